@@ -0,0 +1,68 @@
+//! Hash and verify latency per scheme and cost setting.
+//!
+//! `cargo bench --features argon2` (add `-- argon2` or `-- sha512` to narrow).
+//! Argon2id is measured at each `Argon2Preset`.
+//! `check_pin` pays one verify per attempt, `genpin` one hash per PIN change.
+//! Pick the highest cost whose verify time is acceptable at the PAM prompt.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use pin_auth::policy::PolicyValidator;
+use pin_auth::{hash_pin_with, verify_pin, HashParams, Pin, Scheme};
+
+#[cfg(feature = "argon2")]
+use pin_auth::Argon2Preset;
+#[cfg(any(feature = "sha-crypt", feature = "argon2"))]
+use pin_auth::HashParamsBuilder;
+
+/// sha512-crypt rounds: the crypt(3) default, then 10x steps.
+#[cfg(feature = "sha-crypt")]
+const SHA512_ROUNDS: &[u32] = &[5_000, 50_000, 500_000];
+
+fn bench_scheme(c: &mut Criterion, name: &str, scheme: Scheme, settings: &[(String, HashParams)]) {
+    let pin = Pin::new("246813", &PolicyValidator::default()).unwrap();
+    let wrong = Pin::new("135791", &PolicyValidator::default()).unwrap();
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for (label, params) in settings {
+        let stored = hash_pin_with(&pin, scheme, params).unwrap();
+        group.bench_with_input(BenchmarkId::new("hash", label), params, |b, params| {
+            b.iter(|| hash_pin_with(&pin, scheme, params).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("verify", label), &stored, |b, stored| {
+            b.iter(|| verify_pin(&wrong, stored))
+        });
+    }
+    group.finish();
+}
+
+fn hashing(c: &mut Criterion) {
+    #[cfg(feature = "sha-crypt")]
+    {
+        let settings: Vec<_> = SHA512_ROUNDS
+            .iter()
+            .map(|&rounds| {
+                let params = HashParamsBuilder::new()
+                    .sha512_rounds(rounds)
+                    .build()
+                    .unwrap();
+                (format!("rounds={rounds}"), params)
+            })
+            .collect();
+        bench_scheme(c, "sha512", Scheme::Sha512Crypt, &settings);
+    }
+    #[cfg(feature = "argon2")]
+    {
+        let settings: Vec<_> = Argon2Preset::ALL
+            .iter()
+            .map(|preset| {
+                let params = HashParamsBuilder::new().preset(*preset).build().unwrap();
+                (preset.name().to_string(), params)
+            })
+            .collect();
+        bench_scheme(c, "argon2id", Scheme::Argon2id, &settings);
+    }
+    #[cfg(all(not(feature = "sha-crypt"), not(feature = "argon2")))]
+    let _ = (c, bench_scheme);
+}
+
+criterion_group!(benches, hashing);
+criterion_main!(benches);
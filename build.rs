@@ -1,5 +1,46 @@
-// Build script to ensure linking with libcrypt (libxcrypt / glibc) for crypt(3)
+// Build script: native libraries, baked-in paths and the C header.
 fn main() {
-    // On GNU/Linux, crypt is in libcrypt; some systems fold into libc but this is safe.
+    // Feature `sha-crypt` on dynamically linked GNU targets: link libcrypt
+    // (libxcrypt / glibc) so `$6$` hashes stay interchangeable with the
+    // system's crypt(3). The hashing itself is pure Rust, so musl and
+    // `crt-static` builds skip it and need no C libraries at all.
+    #[cfg(feature = "sha-crypt")]
+    {
+        let target_env = std::env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+        let target_features = std::env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+        let crt_static = target_features.split(',').any(|f| f == "crt-static");
+        if target_env == "gnu" && !crt_static {
+            println!("cargo:rustc-link-lib=crypt");
+        }
+    }
+
+    // Default paths baked in by `pin_auth::paths`.
+    for var in ["PIN_AUTH_DIR", "PIN_AUTH_STATE_DIR", "PIN_AUTH_CONFIG"] {
+        println!("cargo:rerun-if-env-changed={var}");
+    }
+
+    // Feature `fido2`: libfido2 (the runtime .so plus its -dev symlink).
+    #[cfg(feature = "fido2")]
+    println!("cargo:rustc-link-lib=fido2");
+
+    // Feature `legacy-crypt`: crypt(3) for MD5-crypt and DES crypt. musl
+    // keeps it in libc but ships an empty libcrypt, so this links anywhere.
+    #[cfg(feature = "legacy-crypt")]
     println!("cargo:rustc-link-lib=crypt");
+
+    // Feature `ffi`: regenerate the C header from src/ffi.rs.
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml"))
+            .expect("reading cbindgen.toml");
+        cbindgen::Builder::new()
+            .with_src(format!("{crate_dir}/src/ffi.rs"))
+            .with_config(config)
+            .generate()
+            .expect("generating C header")
+            .write_to_file(format!("{crate_dir}/include/pin_auth.h"));
+    }
 }
@@ -0,0 +1,25 @@
+//! `<user>.fail` is parsed as root on every attempt. Parsing must not
+//! panic, must reach a fixed point after one round trip, and the tracker
+//! must cope with whatever state it loads.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pin_auth::fail::{FailState, FailTracker, LockoutPolicy, MemoryFailStore};
+
+const NOW: u64 = 1_700_000_000;
+
+fuzz_target!(|raw: &str| {
+    let state = FailState::parse(raw, NOW);
+    let serialized = state.serialize();
+    assert_eq!(FailState::parse(&serialized, NOW), state);
+
+    let store = MemoryFailStore {
+        contents: raw.to_string(),
+    };
+    let mut tracker = FailTracker::new(store, LockoutPolicy::default()).unwrap();
+    let _ = tracker.check_locked().unwrap();
+    let out = tracker.record_failure().unwrap();
+    assert!(out.count >= 1);
+    tracker.record_success().unwrap();
+    assert_eq!(tracker.into_store().contents, "");
+});
@@ -0,0 +1,38 @@
+//! `check_pin` reads `<user>.passwd` as root and hands it to the hash
+//! backends; nothing in there may panic, whatever the bytes.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pin_auth::policy::{PolicyValidator, MAX_MIN_LEN};
+use pin_auth::{verify_pin_outcome, HashParams, Pin, Scheme, VerifyOutcome};
+
+// Keep each run fast; huge costs are a slow hash, not a bug.
+const MAX_ROUNDS: u32 = 10_000;
+const MAX_M_COST: u32 = 64 * 1024;
+const MAX_T_COST: u32 = 4;
+
+fn cheap(params: &HashParams) -> bool {
+    params.sha512_rounds.map_or(true, |r| r <= MAX_ROUNDS)
+        && params
+            .argon2
+            .map_or(true, |a| a.m_cost <= MAX_M_COST && a.t_cost <= MAX_T_COST)
+}
+
+fuzz_target!(|input: (&str, &str)| {
+    let (candidate, stored) = input;
+    let info = Scheme::from_hash(stored);
+    let Ok(pin) = Pin::candidate(candidate, &PolicyValidator::new(1, MAX_MIN_LEN).unwrap()) else {
+        return;
+    };
+    match info {
+        Ok(info) if cheap(&info.params) => {
+            let _ = verify_pin_outcome(&pin, stored);
+        }
+        Ok(_) => {}
+        // What from_hash rejects must never verify.
+        Err(_) => assert_eq!(
+            verify_pin_outcome(&pin, stored),
+            VerifyOutcome::MalformedHash
+        ),
+    }
+});
@@ -0,0 +1,54 @@
+/* Generated by cbindgen from src/ffi.rs (cargo build --features ffi). Do not edit. */
+
+#ifndef PIN_AUTH_H
+#define PIN_AUTH_H
+
+#include <stddef.h>
+
+#define PIN_AUTH_OK 0
+
+// `pin_auth_verify` only: the PIN did not match.
+#define PIN_AUTH_MISMATCH 1
+
+// A required pointer was NULL.
+#define PIN_AUTH_ERR_NULL -1
+
+// Input was not valid UTF-8.
+#define PIN_AUTH_ERR_UTF8 -2
+
+// The PIN failed the length/charset policy.
+#define PIN_AUTH_ERR_POLICY -3
+
+// The output buffer is too small for the hash and its NUL terminator.
+#define PIN_AUTH_ERR_BUFFER -4
+
+// Hashing failed, or the stored hash is malformed or of an unsupported scheme.
+#define PIN_AUTH_ERR_HASH -5
+
+// The policy configuration in the environment is invalid.
+#define PIN_AUTH_ERR_CONFIG -6
+
+// Hash a new PIN into `out` (NUL-terminated). The full policy applies.
+// Returns `PIN_AUTH_OK` or a negative `PIN_AUTH_ERR_*`.
+//
+// # Safety
+// `pin` must be a valid NUL-terminated string and `out` must point to at
+// least `out_len` writable bytes.
+int pin_auth_hash(const char *pin, char *out, size_t out_len);
+
+// Check a candidate PIN against a stored hash. Returns `PIN_AUTH_OK` on a
+// match, `PIN_AUTH_MISMATCH` on a mismatch or a candidate of invalid format,
+// or a negative `PIN_AUTH_ERR_*`. No lockout state is touched.
+//
+// # Safety
+// `pin` and `stored` must be valid NUL-terminated strings.
+int pin_auth_verify(const char *pin, const char *stored);
+
+// Check a new PIN against the length/charset policy (no blacklist).
+// Returns `PIN_AUTH_OK`, `PIN_AUTH_ERR_POLICY` or another negative error.
+//
+// # Safety
+// `pin` must be a valid NUL-terminated string.
+int pin_auth_check_policy(const char *pin);
+
+#endif  /* PIN_AUTH_H */
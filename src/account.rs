@@ -0,0 +1,252 @@
+//! System account lookups through NSS (`/etc/passwd`, `/etc/group`, LDAP,
+//! SSSD, ... as `nsswitch.conf` says).
+
+use nix::unistd::{Group, User};
+use std::ffi::CString;
+use std::io;
+use std::path::Path;
+
+fn unknown_user(user: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such user {user:?}"))
+}
+
+/// Names of the groups `user` is a member of, primary group included.
+/// `NotFound` if NSS does not know the user.
+pub fn groups_of(user: &str) -> io::Result<Vec<String>> {
+    let entry = User::from_name(user)?.ok_or_else(|| unknown_user(user))?;
+    let name = CString::new(user).map_err(|_| unknown_user(user))?;
+    let mut names = Vec::new();
+    for gid in nix::unistd::getgrouplist(&name, entry.gid)? {
+        // a gid without a name cannot own a record
+        if let Some(group) = Group::from_gid(gid)? {
+            names.push(group.name);
+        }
+    }
+    Ok(names)
+}
+
+/// Whether NSS knows user `name`.
+pub fn user_exists(name: &str) -> io::Result<bool> {
+    Ok(User::from_name(name)?.is_some())
+}
+
+/// `name`'s UID; `None` if NSS does not know the user.
+pub fn uid_of(name: &str) -> io::Result<Option<u32>> {
+    Ok(User::from_name(name)?.map(|u| u.uid.as_raw()))
+}
+
+/// The name NSS has for `uid`.
+pub fn name_of(uid: u32) -> io::Result<Option<String>> {
+    Ok(User::from_uid(nix::unistd::Uid::from_raw(uid))?.map(|u| u.name))
+}
+
+/// Whether NSS knows group `name`.
+pub fn group_exists(name: &str) -> io::Result<bool> {
+    Ok(Group::from_name(name)?.is_some())
+}
+
+/// Why the system has disabled an account (`account_status`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disabled {
+    /// The password hash starts with `!` or `*` (`passwd -l`, `usermod -L`,
+    /// FreeBSD's `pw lock`), or on OpenBSD the shell ends in `-`
+    /// (`usermod -Z`).
+    Locked,
+    /// Past the account expiry date (`chage -E`), or the password expired
+    /// longer ago than its inactivity period (`chage -I`).
+    Expired,
+    /// The login shell is `nologin` or `false`.
+    NoLogin,
+}
+
+impl Disabled {
+    pub fn reason(self) -> &'static str {
+        match self {
+            Disabled::Locked => "account_locked",
+            Disabled::Expired => "account_expired",
+            Disabled::NoLogin => "nologin_shell",
+        }
+    }
+}
+
+/// The shadow fields that decide [`Disabled`]; days since the epoch, `-1`
+/// for an empty field, as in `struct spwd`. On the BSDs they come from
+/// `master.passwd`, which has only the hash and the expiry date.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shadow {
+    pub password: String,
+    pub last_change: i64,
+    pub max_days: i64,
+    pub inactive_days: i64,
+    pub expire: i64,
+}
+
+impl Shadow {
+    /// `user`'s entry; `None` if there is none (e.g. a directory user
+    /// without a shadow map). Needs root.
+    #[cfg(target_os = "linux")]
+    pub fn lookup(user: &str) -> io::Result<Option<Self>> {
+        let name = CString::new(user).map_err(|_| unknown_user(user))?;
+        let mut buf = vec![0 as nix::libc::c_char; 1024];
+        loop {
+            // SAFETY: `entry` and `buf` outlive the call; on success the
+            // strings it points to live in `buf`, read before it is freed.
+            let mut entry = unsafe { std::mem::zeroed::<nix::libc::spwd>() };
+            let mut result = std::ptr::null_mut();
+            let rc = unsafe {
+                nix::libc::getspnam_r(
+                    name.as_ptr(),
+                    &mut entry,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            };
+            match rc {
+                0 if result.is_null() => return Ok(None),
+                0 => {
+                    let password = unsafe { std::ffi::CStr::from_ptr(entry.sp_pwdp) }
+                        .to_string_lossy()
+                        .into_owned();
+                    return Ok(Some(Shadow {
+                        password,
+                        last_change: entry.sp_lstchg as i64,
+                        max_days: entry.sp_max as i64,
+                        inactive_days: entry.sp_inact as i64,
+                        expire: entry.sp_expire as i64,
+                    }));
+                }
+                nix::libc::ENOENT => return Ok(None),
+                nix::libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                errno => return Err(io::Error::from_raw_os_error(errno)),
+            }
+        }
+    }
+
+    /// `user`'s entry; `None` if NSS does not know the user, or when not
+    /// running as root, since getpwnam(3) then hides the hash behind `*`.
+    #[cfg(not(target_os = "linux"))]
+    pub fn lookup(user: &str) -> io::Result<Option<Self>> {
+        const DAY: i64 = 24 * 3600;
+        if !nix::unistd::geteuid().is_root() {
+            return Ok(None);
+        }
+        let Some(entry) = User::from_name(user)? else {
+            return Ok(None);
+        };
+        // time_t is 32 bits on i386
+        #[allow(clippy::unnecessary_cast)]
+        let expire = entry.expire as i64;
+        Ok(Some(Shadow {
+            password: entry.passwd.to_string_lossy().into_owned(),
+            last_change: -1,
+            max_days: -1,
+            inactive_days: -1,
+            // pw_expire is a time, 0 for never
+            expire: if expire == 0 {
+                -1
+            } else {
+                expire.div_euclid(DAY)
+            },
+        }))
+    }
+
+    /// Whether this entry disables the account on day `today`.
+    pub fn disabled(&self, today: i64) -> Option<Disabled> {
+        if self.password.starts_with(['!', '*']) {
+            return Some(Disabled::Locked);
+        }
+        if self.expire >= 0 && today >= self.expire {
+            return Some(Disabled::Expired);
+        }
+        // last_change 0 means "change at next login", not expired
+        if self.last_change > 0 && self.max_days >= 0 && self.inactive_days >= 0 {
+            let unusable = self.last_change + self.max_days + self.inactive_days;
+            if today >= unusable {
+                return Some(Disabled::Expired);
+            }
+        }
+        None
+    }
+}
+
+/// Whether `shell` refuses logins.
+pub fn is_nologin_shell(shell: &Path) -> bool {
+    matches!(
+        shell.file_name().and_then(|n| n.to_str()),
+        Some("nologin" | "false")
+    )
+}
+
+/// Why `user`'s account is disabled on day `today` (days since the
+/// epoch), if it is. Users NSS does not know are not judged here.
+pub fn disabled(user: &str, today: i64) -> io::Result<Option<Disabled>> {
+    let Some(entry) = User::from_name(user)? else {
+        return Ok(None);
+    };
+    #[cfg(target_os = "openbsd")]
+    if entry.shell.to_string_lossy().ends_with('-') {
+        return Ok(Some(Disabled::Locked));
+    }
+    if let Some(shadow) = Shadow::lookup(user)? {
+        if let Some(disabled) = shadow.disabled(today) {
+            return Ok(Some(disabled));
+        }
+    }
+    Ok(is_nologin_shell(&entry.shell).then_some(Disabled::NoLogin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_is_in_its_primary_group() {
+        let groups = groups_of("root").unwrap();
+        let primary = Group::from_gid(nix::unistd::Gid::from_raw(0)).unwrap();
+        if let Some(primary) = primary {
+            assert!(groups.contains(&primary.name), "{groups:?}");
+            assert!(group_exists(&primary.name).unwrap());
+        }
+        assert!(user_exists("root").unwrap());
+        assert!(!user_exists("no-such-user-pin-auth").unwrap());
+        assert_eq!(
+            groups_of("no-such-user-pin-auth").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn shadow_status() {
+        let active = Shadow {
+            password: "$6$salt$hash".into(),
+            last_change: 20_000,
+            max_days: 90,
+            inactive_days: 7,
+            expire: -1,
+        };
+        assert_eq!(active.disabled(20_050), None);
+        assert_eq!(active.disabled(20_097), Some(Disabled::Expired));
+        for password in ["!$6$salt$hash", "!", "*", "!!", "*LOCKED*$6$salt$hash"] {
+            let locked = Shadow {
+                password: password.into(),
+                ..active.clone()
+            };
+            assert_eq!(locked.disabled(20_050), Some(Disabled::Locked));
+        }
+        let expiring = Shadow {
+            expire: 20_010,
+            ..active.clone()
+        };
+        assert_eq!(expiring.disabled(20_009), None);
+        assert_eq!(expiring.disabled(20_010), Some(Disabled::Expired));
+        let must_change = Shadow {
+            last_change: 0,
+            ..active
+        };
+        assert_eq!(must_change.disabled(30_000), None);
+        assert!(is_nologin_shell(Path::new("/usr/sbin/nologin")));
+        assert!(is_nologin_shell(Path::new("/bin/false")));
+        assert!(!is_nologin_shell(Path::new("/bin/bash")));
+    }
+}
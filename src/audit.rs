@@ -0,0 +1,282 @@
+//! Tamper-evident audit log.
+//!
+//! One JSON object per line. Every record carries a sequence number and the
+//! SHA-256 of the previous line (`prev`), so editing or deleting a line breaks
+//! the chain. The digest and sequence of the last line are mirrored in a
+//! `<log>.head` file, which is what exposes truncation of the tail.
+
+use crate::pam::PamContext;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `prev` of the first record.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug)]
+pub enum AuditEvent<'a> {
+    /// A verification attempt and its outcome (`success`, `failure`, `locked`, ...).
+    Auth {
+        user: &'a str,
+        result: &'a str,
+        pam: &'a PamContext,
+    },
+    /// An administrative change, attributed to the real uid that ran the tool.
+    Admin {
+        action: &'a str,
+        user: &'a str,
+        actor_uid: u32,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum AuditError {
+    #[error("audit io: {0}")]
+    Io(#[from] io::Error),
+    /// Line (1-based) is not a JSON object with `seq` and `prev`.
+    #[error("line {0}: malformed record")]
+    Malformed(usize),
+    /// Line's `prev` does not match the digest of the line before it.
+    #[error("line {0}: hash chain broken")]
+    BrokenChain(usize),
+    /// Line's `seq` is not one more than its predecessor's.
+    #[error("line {0}: sequence gap")]
+    BadSequence(usize),
+    /// The head file disagrees with the last line of the log.
+    #[error("log does not end at recorded head (truncated?)")]
+    HeadMismatch,
+}
+
+pub fn digest_line(line: &str) -> String {
+    Sha256::digest(line.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AuditLog { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn head_path(&self) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(".head");
+        PathBuf::from(p)
+    }
+
+    /// Append one record. Holds an exclusive lock on the log for the
+    /// read-tail / append / write-head sequence; the head is replaced
+    /// atomically.
+    pub fn append(&self, event: &AuditEvent<'_>) -> io::Result<()> {
+        let mut log = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+            .open(&self.path)?;
+        crate::lock::lock_exclusive(&log, crate::lock::LOCK_TIMEOUT)?;
+        // Chain from the log's own last line, not the head: the head is
+        // written after the line, so a crash or a full disk in between
+        // leaves it behind, and it is rewritten below either way.
+        let (seq, prev) = last_record(&self.path)?.unwrap_or((0, GENESIS.to_string()));
+        let seq = seq + 1;
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut record = Map::new();
+        record.insert("seq".into(), json!(seq));
+        record.insert("ts".into(), json!(ts));
+        record.insert("prev".into(), json!(prev));
+        match event {
+            AuditEvent::Auth { user, result, pam } => {
+                record.insert("event".into(), json!("auth"));
+                record.insert("user".into(), json!(user));
+                record.insert("result".into(), json!(result));
+                for (key, value) in [
+                    ("service", &pam.service),
+                    ("tty", &pam.tty),
+                    ("rhost", &pam.rhost),
+                ] {
+                    if let Some(v) = value {
+                        record.insert(key.into(), json!(v));
+                    }
+                }
+            }
+            AuditEvent::Admin {
+                action,
+                user,
+                actor_uid,
+            } => {
+                record.insert("event".into(), json!("admin"));
+                record.insert("action".into(), json!(action));
+                record.insert("user".into(), json!(user));
+                record.insert("actor_uid".into(), json!(actor_uid));
+            }
+        }
+        let line = Value::Object(record).to_string();
+        log.write_all(format!("{line}\n").as_bytes())?;
+        self.write_head(seq, &digest_line(&line))
+    }
+
+    fn read_head(&self) -> io::Result<Option<(u64, String)>> {
+        let raw = match fs::read_to_string(self.head_path()) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(raw
+            .trim()
+            .split_once(' ')
+            .and_then(|(seq, digest)| Some((seq.parse().ok()?, digest.to_string()))))
+    }
+
+    fn write_head(&self, seq: u64, digest: &str) -> io::Result<()> {
+        let head = format!("{seq} {digest}\n");
+        crate::store::write_atomic(&self.head_path(), head.as_bytes(), 0o600)
+    }
+
+    /// Walk the whole chain. Returns the number of records on success.
+    pub fn verify(&self) -> Result<u64, AuditError> {
+        let last = match File::open(&self.path) {
+            Ok(f) => verify_chain(BufReader::new(f))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        match (last, self.read_head()?) {
+            (None, None) => Ok(0),
+            (Some(last), Some(head)) if head == last => Ok(last.0),
+            _ => Err(AuditError::HeadMismatch),
+        }
+    }
+}
+
+fn parse_record(line: &str) -> Option<(u64, String)> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    let seq = value.get("seq")?.as_u64()?;
+    let prev = value.get("prev")?.as_str()?.to_string();
+    Some((seq, prev))
+}
+
+/// Check the chain of `reader`; returns the last `(seq, digest)` if any lines.
+pub fn verify_chain(reader: impl BufRead) -> Result<Option<(u64, String)>, AuditError> {
+    let mut last: Option<(u64, String)> = None;
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let n = idx + 1;
+        let (seq, prev) = parse_record(&line).ok_or(AuditError::Malformed(n))?;
+        let (expected_seq, expected_prev) = match &last {
+            Some((s, d)) => (s + 1, d.as_str()),
+            None => (1, GENESIS),
+        };
+        if prev != expected_prev {
+            return Err(AuditError::BrokenChain(n));
+        }
+        if seq != expected_seq {
+            return Err(AuditError::BadSequence(n));
+        }
+        last = Some((seq, digest_line(&line)));
+    }
+    Ok(last)
+}
+
+/// `(seq, digest)` of the last record in the log at `path`. Only the tail
+/// is read unless the last line there does not parse.
+fn last_record(path: &Path) -> io::Result<Option<(u64, String)>> {
+    const TAIL: u64 = 64 * 1024;
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    f.seek(SeekFrom::Start(len.saturating_sub(TAIL)))?;
+    let mut tail = Vec::new();
+    f.read_to_end(&mut tail)?;
+    let tail = String::from_utf8_lossy(&tail);
+    let last_line = tail.lines().rev().find(|l| !l.is_empty());
+    // a whole line only if it starts after a newline (or at the start)
+    let whole = |line: &str| len <= TAIL || tail.len() > line.len() + 1;
+    if let Some(line) = last_line.filter(|l| whole(l)) {
+        if let Some((seq, _)) = parse_record(line) {
+            return Ok(Some((seq, digest_line(line))));
+        }
+    }
+    f.seek(SeekFrom::Start(0))?;
+    let mut last = None;
+    for line in BufReader::new(f).lines() {
+        let line = line?;
+        if let Some((seq, _)) = parse_record(&line) {
+            last = Some((seq, digest_line(&line)));
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_detects_edit_and_truncation() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(tmp.path().join("audit.log"));
+        let pam = PamContext {
+            service: Some("login".into()),
+            tty: Some("tty1".into()),
+            rhost: None,
+        };
+        for result in ["failure", "failure", "success"] {
+            log.append(&AuditEvent::Auth {
+                user: "alice",
+                result,
+                pam: &pam,
+            })
+            .unwrap();
+        }
+        log.append(&AuditEvent::Admin {
+            action: "set_pin",
+            user: "alice",
+            actor_uid: 0,
+        })
+        .unwrap();
+        assert_eq!(log.verify().unwrap(), 4);
+
+        let original = fs::read_to_string(log.path()).unwrap();
+        fs::write(log.path(), original.replacen("failure", "success", 1)).unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::BrokenChain(2))));
+
+        let lines: Vec<&str> = original.lines().collect();
+        fs::write(log.path(), format!("{}\n", lines[..3].join("\n"))).unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::HeadMismatch)));
+    }
+
+    #[test]
+    fn stale_head_is_recomputed_from_the_log() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(tmp.path().join("audit.log"));
+        let event = AuditEvent::Admin {
+            action: "set_pin",
+            user: "alice",
+            actor_uid: 0,
+        };
+        log.append(&event).unwrap();
+        let stale = fs::read(log.head_path()).unwrap();
+        log.append(&event).unwrap();
+        // as if the head write after the second line had failed
+        fs::write(log.head_path(), stale).unwrap();
+        assert!(matches!(log.verify(), Err(AuditError::HeadMismatch)));
+        log.append(&event).unwrap();
+        assert_eq!(log.verify().unwrap(), 3);
+    }
+}
@@ -0,0 +1,833 @@
+//! The verification flow behind `check_pin`, minus process concerns
+//! (environment, logging targets, exit codes).
+
+use crate::fail::{
+    Clock, FailStore, FailTracker, FailureOutcome, LockStatus, LockoutPolicy, MemoryFailStore,
+    WriteErrorPolicy,
+};
+use crate::policy::PolicyValidator;
+use crate::store::PinStore;
+use crate::{
+    hash_pin_configured, verify_pin, verify_pin_outcome, ConfigResolver, Pin, PinHashError,
+    VerifyOutcome,
+};
+use std::io;
+use zeroize::Zeroizing;
+
+/// How one attempt ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Refused before looking at the PIN; `until` is `None` for an indefinite lock.
+    Locked {
+        until: Option<u64>,
+    },
+    /// Candidate failed the length/charset check; not counted as a failure.
+    BadInput,
+    Success,
+    /// Wrong PIN. `locked` is set when this failure reached the threshold.
+    Failure {
+        count: u32,
+        locked: bool,
+    },
+    /// One of the user's decoy PINs: counted as a failure, and `locked` at
+    /// once if [`Decoys::lock`] is set.
+    Decoy {
+        count: u32,
+        locked: bool,
+    },
+    /// The user's emergency PIN, accepted whether or not the user is locked.
+    /// Access must not be granted until [`redeem_emergency`] succeeds.
+    Emergency,
+    /// Recovery code `index` while locked out; `remaining` unused codes are
+    /// left after it. Access must not be granted until [`redeem_recovery`]
+    /// succeeds.
+    Recovery {
+        index: usize,
+        remaining: usize,
+    },
+    /// A code from the user's HOTP token (feature `hotp`), generated at
+    /// `counter`. Access must not be granted until `hotp::redeem_hotp`
+    /// succeeds.
+    #[cfg(feature = "hotp")]
+    Hotp {
+        counter: u64,
+    },
+}
+
+impl Outcome {
+    /// The `result` value used in logs, the audit log and attempt history.
+    pub fn result(&self) -> &'static str {
+        match self {
+            Outcome::Locked { .. } => "locked",
+            Outcome::BadInput => "bad_input",
+            Outcome::Success => "success",
+            Outcome::Failure { .. } => "failure",
+            Outcome::Decoy { .. } => "decoy",
+            Outcome::Emergency => "emergency",
+            Outcome::Recovery { .. } => "recovery",
+            #[cfg(feature = "hotp")]
+            Outcome::Hotp { .. } => "hotp",
+        }
+    }
+}
+
+/// Decoy ("honeypot") PINs for one user: hashes, one per line in
+/// `<user>.decoy`, that never grant access. Typically an old or
+/// deliberately leaked PIN, so that someone replaying it is caught.
+#[derive(Clone, Debug, Default)]
+pub struct Decoys {
+    pub hashes: Vec<String>,
+    /// Lock the user as soon as a decoy is entered.
+    pub lock: bool,
+}
+
+impl Decoys {
+    /// File name of `user`'s decoy hashes.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.decoy")
+    }
+
+    /// `user`'s decoys from `store`; none if the file does not exist.
+    pub fn load(store: &dyn PinStore, user: &str, lock: bool) -> io::Result<Self> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let hashes = raw
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Decoys { hashes, lock })
+    }
+
+    /// Whether `pin` is one of the decoys.
+    pub fn matches(&self, pin: &Pin) -> bool {
+        self.hashes.iter().any(|h| verify_pin(pin, h))
+    }
+}
+
+/// A single-use emergency PIN issued by the helpdesk (`pinctl emergency`):
+/// `<user>.emergency` holds `<expires> <hash>`. It is accepted once, even
+/// while the user is locked out, until it expires. It is
+/// [`Emergency::DIGITS`] random digits whatever the PIN policy, so it cannot
+/// be guessed within its lifetime although a lock does not stop attempts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Emergency {
+    pub hash: String,
+    /// Unix time from which it is no longer accepted.
+    pub expires: u64,
+}
+
+impl Emergency {
+    /// Length of an emergency PIN.
+    pub const DIGITS: usize = 12;
+    /// Lifetime when `emergency_ttl` is not set: one day.
+    pub const DEFAULT_TTL: u64 = 24 * 3600;
+
+    /// File name of `user`'s emergency PIN record.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.emergency")
+    }
+
+    /// A new random emergency PIN valid until `expires`, hashed with the
+    /// configured scheme. The PIN itself is returned to be shown once.
+    pub fn issue(
+        config: &ConfigResolver,
+        expires: u64,
+    ) -> Result<(Zeroizing<String>, Emergency), PinHashError> {
+        let secret = random_digits(Self::DIGITS);
+        let hash = hash_pin_configured(&Pin::unchecked(&secret), config)?;
+        Ok((secret, Emergency { hash, expires }))
+    }
+
+    /// `user`'s emergency PIN from `store`; `None` if none is outstanding.
+    pub fn load(store: &dyn PinStore, user: &str) -> io::Result<Option<Self>> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (expires, hash) = raw
+            .trim()
+            .split_once(' ')
+            .and_then(|(expires, hash)| Some((expires.parse().ok()?, hash.trim())))
+            .filter(|(_, hash)| !hash.is_empty())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed emergency PIN record")
+            })?;
+        Ok(Some(Emergency {
+            hash: hash.to_string(),
+            expires,
+        }))
+    }
+
+    /// The record as written to `<user>.emergency`.
+    pub fn serialize(&self) -> String {
+        format!("{} {}\n", self.expires, self.hash)
+    }
+
+    /// Whether `secret` is this PIN and it is still valid at `now`.
+    pub fn accepts(&self, secret: &str, now: u64) -> bool {
+        now < self.expires
+            && secret.len() == Self::DIGITS
+            && secret.bytes().all(|b| b.is_ascii_digit())
+            && verify_pin(&Pin::unchecked(secret), &self.hash)
+    }
+}
+
+/// One line of `<user>.recovery`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecoveryCode {
+    /// Hash of a code not used yet.
+    Unused(String),
+    /// Crossed off at this Unix time (`used <time>`).
+    Used { at: u64 },
+}
+
+/// Printed recovery codes for one user, generated by `genpin`:
+/// `<user>.recovery` holds one [`RecoveryCode`] per line. Each code stands
+/// in for the PIN once, and only while the user is locked out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryCodes {
+    pub codes: Vec<RecoveryCode>,
+}
+
+impl RecoveryCodes {
+    /// Digits per code, printed in groups of four.
+    pub const DIGITS: usize = 12;
+    /// Codes per set when `recovery_codes` is not set.
+    pub const DEFAULT_COUNT: usize = 10;
+    /// Largest `recovery_codes`: a locked attempt may hash against each.
+    pub const MAX_COUNT: usize = 20;
+
+    /// File name of `user`'s recovery codes.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.recovery")
+    }
+
+    /// `count` new random codes, hashed with the configured scheme. The
+    /// codes themselves are returned to be printed once.
+    pub fn generate(
+        config: &ConfigResolver,
+        count: usize,
+    ) -> Result<(Vec<Zeroizing<String>>, RecoveryCodes), PinHashError> {
+        let mut secrets = Vec::with_capacity(count);
+        let mut codes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let secret = random_digits(Self::DIGITS);
+            codes.push(RecoveryCode::Unused(hash_pin_configured(
+                &Pin::unchecked(&secret),
+                config,
+            )?));
+            secrets.push(secret);
+        }
+        Ok((secrets, RecoveryCodes { codes }))
+    }
+
+    /// `code` as printed: `1234-5678-9012`. Entered codes may keep or drop
+    /// the dashes.
+    pub fn format(code: &str) -> String {
+        let groups: Vec<&str> = code
+            .as_bytes()
+            .chunks(4)
+            .map(|c| std::str::from_utf8(c).unwrap_or_default())
+            .collect();
+        groups.join("-")
+    }
+
+    /// `user`'s codes from `store`; none if the file does not exist.
+    pub fn load(store: &dyn PinStore, user: &str) -> io::Result<Self> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let codes = raw
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|line| match line.strip_prefix("used ") {
+                Some(at) => at
+                    .trim()
+                    .parse()
+                    .map(|at| RecoveryCode::Used { at })
+                    .map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "malformed recovery code record")
+                    }),
+                None => Ok(RecoveryCode::Unused(line.to_string())),
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(RecoveryCodes { codes })
+    }
+
+    /// The file contents for these codes.
+    pub fn serialize(&self) -> String {
+        self.codes
+            .iter()
+            .map(|code| match code {
+                RecoveryCode::Unused(hash) => format!("{hash}\n"),
+                RecoveryCode::Used { at } => format!("used {at}\n"),
+            })
+            .collect()
+    }
+
+    /// Codes not used yet.
+    pub fn remaining(&self) -> usize {
+        self.codes
+            .iter()
+            .filter(|c| matches!(c, RecoveryCode::Unused(_)))
+            .count()
+    }
+
+    /// Index of the unused code `secret` is, if any. Only input shaped like
+    /// a code is hashed.
+    pub fn find(&self, secret: &str) -> Option<usize> {
+        let digits = Zeroizing::new(secret.replace('-', ""));
+        if digits.len() != Self::DIGITS || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let pin = Pin::unchecked(&digits);
+        self.codes.iter().position(|code| match code {
+            RecoveryCode::Unused(hash) => verify_pin(&pin, hash),
+            RecoveryCode::Used { .. } => false,
+        })
+    }
+}
+
+/// What an attempt is checked against besides the PIN hash. Load the
+/// single-use credentials while holding the fail state, so that two
+/// concurrent attempts cannot both use one.
+#[derive(Clone, Debug, Default)]
+pub struct Extras {
+    pub decoys: Decoys,
+    pub emergency: Option<Emergency>,
+    pub recovery: RecoveryCodes,
+    #[cfg(feature = "hotp")]
+    pub hotp: Option<crate::hotp::HotpToken>,
+    /// Look-ahead for [`Extras::hotp`]; see `hotp_window`.
+    #[cfg(feature = "hotp")]
+    pub hotp_window: u64,
+}
+
+/// Cross off recovery code `index` (from [`Outcome::Recovery`]) in `store`,
+/// then clear the fail state. If the file cannot be rewritten nothing is
+/// cleared and access must be refused, so a code never works twice.
+pub fn redeem_recovery<S: FailStore, C: Clock>(
+    store: &dyn PinStore,
+    user: &str,
+    codes: &RecoveryCodes,
+    index: usize,
+    tracker: &mut FailTracker<S, C>,
+) -> io::Result<()> {
+    let mut codes = codes.clone();
+    let Some(code) = codes.codes.get_mut(index) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no such recovery code",
+        ));
+    };
+    *code = RecoveryCode::Used {
+        at: tracker.clock().now(),
+    };
+    store.write_file(&RecoveryCodes::file_name(user), &codes.serialize())?;
+    tracker.record_success()
+}
+
+/// Invalidate `user`'s emergency PIN after [`Outcome::Emergency`], then clear
+/// the fail state. If the record cannot be removed nothing is cleared and
+/// access must be refused, so the PIN never works twice.
+pub fn redeem_emergency<S: FailStore, C: Clock>(
+    store: &dyn PinStore,
+    user: &str,
+    tracker: &mut FailTracker<S, C>,
+) -> io::Result<()> {
+    store.remove_file(&Emergency::file_name(user))?;
+    tracker.record_success()
+}
+
+/// `n` random decimal digits from the OS generator.
+fn random_digits(n: usize) -> Zeroizing<String> {
+    use rand::Rng;
+    let mut rng = rand::rngs::OsRng;
+    Zeroizing::new(
+        (0..n)
+            .map(|_| char::from(b'0' + rng.gen_range(0..10u8)))
+            .collect(),
+    )
+}
+
+/// Open and read `user`'s fail state. If it cannot be opened or read, with
+/// [`WriteErrorPolicy::Open`] the attempt proceeds with an in-memory state
+/// (better to allow auth than hard-fail), so lockout is not enforced for
+/// that attempt; with [`WriteErrorPolicy::Closed`] the error is returned,
+/// and the tracker counts first ([`FailTracker::count_first`]).
+///
+/// A lock timeout ([`crate::lock::is_timeout`]) is returned either way:
+/// another attempt holds the state, and going ahead without it would let
+/// concurrent guesses past the counter.
+pub fn load_fail_state(
+    store: &dyn PinStore,
+    user: &str,
+    policy: LockoutPolicy,
+    on_error: WriteErrorPolicy,
+) -> io::Result<FailTracker<Box<dyn FailStore>>> {
+    let opened = store
+        .open_fail_state(user)
+        .and_then(|fail| FailTracker::new(fail, policy))
+        .map(|tracker| tracker.count_first(on_error == WriteErrorPolicy::Closed));
+    match opened {
+        Ok(tracker) => Ok(tracker),
+        Err(e) if crate::lock::is_timeout(&e) || on_error == WriteErrorPolicy::Closed => Err(e),
+        Err(e) => {
+            tracing::error!(error = %e, "fail state unavailable, lockout not enforced");
+            let fallback: Box<dyn FailStore> = Box::new(MemoryFailStore::default());
+            Ok(FailTracker::new(fallback, policy).expect("in-memory fail store"))
+        }
+    }
+}
+
+/// Run one attempt: lock check, candidate format, then verification against
+/// `stored`, updating the fail state. `candidate` is zeroized in all cases.
+pub fn evaluate_attempt<S: FailStore, C: Clock>(
+    tracker: &mut FailTracker<S, C>,
+    validator: &PolicyValidator,
+    candidate: String,
+    stored: &str,
+) -> Outcome {
+    evaluate_attempt_with(tracker, validator, candidate, stored, &Extras::default())
+}
+
+/// [`evaluate_attempt`] with the user's [`Extras`]: the emergency PIN is
+/// tried before the lock check, recovery codes only while locked, an HOTP
+/// code after it, and a wrong PIN is checked against the decoys. Only failed or locked attempts,
+/// and input shaped like an emergency PIN while one is outstanding, pay for
+/// the extra verifications.
+pub fn evaluate_attempt_with<S: FailStore, C: Clock>(
+    tracker: &mut FailTracker<S, C>,
+    validator: &PolicyValidator,
+    candidate: String,
+    stored: &str,
+    extras: &Extras,
+) -> Outcome {
+    let raw = Zeroizing::new(candidate);
+    let emergency = extras
+        .emergency
+        .as_ref()
+        .is_some_and(|e| e.accepts(&raw, tracker.clock().now()));
+    let candidate = Pin::candidate(raw.as_str(), validator);
+    if emergency {
+        return Outcome::Emergency;
+    }
+    // An error means the lock that the count calls for could not be saved
+    // (kept in `tracker.write_error()`); the user is locked all the same.
+    let status = tracker.check_locked().unwrap_or_else(|_| {
+        let lockout_secs = tracker.policy().lockout_secs;
+        LockStatus::Locked {
+            // 0: locked until reset
+            until: (lockout_secs > 0).then(|| tracker.clock().now().saturating_add(lockout_secs)),
+        }
+    });
+    if let LockStatus::Locked { until } = status {
+        if let Some(index) = extras.recovery.find(&raw) {
+            return Outcome::Recovery {
+                index,
+                remaining: extras.recovery.remaining() - 1,
+            };
+        }
+        return Outcome::Locked { until };
+    }
+    #[cfg(feature = "hotp")]
+    if let Some(token) = &extras.hotp {
+        if let Some(counter) = token.verify(&raw, extras.hotp_window) {
+            return Outcome::Hotp { counter };
+        }
+        // A wrong 7- or 8-digit code fails the PIN policy as well; count it
+        // here, or codes could be guessed without ever locking the user.
+        if candidate.is_err() && token.fits(&raw) {
+            let recorded = tracker.record_failure();
+            let (count, locked) = failure(tracker, recorded);
+            return Outcome::Failure { count, locked };
+        }
+    }
+    let Ok(candidate) = candidate else {
+        // a failed save stays in `tracker.write_error()`
+        let _ = tracker.cancel_pending();
+        return Outcome::BadInput;
+    };
+    match verify_pin_outcome(&candidate, stored) {
+        VerifyOutcome::Match => {
+            // a failed save stays in `tracker.write_error()`
+            let _ = tracker.record_success();
+            return Outcome::Success;
+        }
+        VerifyOutcome::Mismatch => {}
+        // Still counted as a failure so a broken record cannot be probed freely.
+        unusable => tracing::error!(verify = ?unusable, "stored hash unusable"),
+    }
+    let decoys = &extras.decoys;
+    if decoys.matches(&candidate) {
+        let recorded = if decoys.lock {
+            tracker.lock_now()
+        } else {
+            tracker.record_failure()
+        };
+        let (count, locked) = failure(tracker, recorded);
+        return Outcome::Decoy { count, locked };
+    }
+    let recorded = tracker.record_failure();
+    let (count, locked) = failure(tracker, recorded);
+    Outcome::Failure { count, locked }
+}
+
+fn failure<S: FailStore, C: Clock>(
+    tracker: &FailTracker<S, C>,
+    recorded: io::Result<FailureOutcome>,
+) -> (u32, bool) {
+    match recorded {
+        Ok(out) => (out.count, out.locked),
+        // State could not be persisted; report what the tracker holds.
+        Err(_) => (
+            tracker.count(),
+            tracker.count() >= tracker.policy().max_fails,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash_pin, hash_pin_with, HashParams, PinHashError, Scheme};
+
+    fn tracker(max_fails: u32) -> FailTracker<MemoryFailStore> {
+        let policy = LockoutPolicy {
+            max_fails,
+            lockout_secs: 60,
+            fail_window: 0,
+        };
+        FailTracker::new(MemoryFailStore::default(), policy).unwrap()
+    }
+
+    #[test]
+    fn failures_lead_to_lock() {
+        let validator = PolicyValidator::default();
+        let mut t = tracker(2);
+        let mut attempt = |pin: &str| evaluate_attempt(&mut t, &validator, pin.into(), "x");
+        assert_eq!(attempt("12"), Outcome::BadInput);
+        assert_eq!(
+            attempt("1234"),
+            Outcome::Failure {
+                count: 1,
+                locked: false
+            }
+        );
+        assert_eq!(
+            attempt("1234"),
+            Outcome::Failure {
+                count: 2,
+                locked: true
+            }
+        );
+        assert!(matches!(
+            attempt("1234"),
+            Outcome::Locked { until: Some(_) }
+        ));
+    }
+
+    #[test]
+    fn unsaved_lock_still_locks() {
+        struct ReadOnly;
+        impl FailStore for ReadOnly {
+            fn load(&mut self) -> io::Result<String> {
+                Ok("2:1\n".into())
+            }
+            fn save(&mut self, _: &str) -> io::Result<()> {
+                Err(io::Error::from_raw_os_error(nix::libc::EROFS))
+            }
+        }
+        let validator = PolicyValidator::default();
+        for on_error in [WriteErrorPolicy::Open, WriteErrorPolicy::Closed] {
+            let policy = LockoutPolicy {
+                max_fails: 2,
+                lockout_secs: 60,
+                fail_window: 0,
+            };
+            let mut t = FailTracker::new(ReadOnly, policy)
+                .unwrap()
+                .count_first(on_error == WriteErrorPolicy::Closed);
+            assert!(matches!(
+                evaluate_attempt(&mut t, &validator, "1234".into(), "x"),
+                Outcome::Locked { until: Some(_) }
+            ));
+            assert!(t.write_error().is_some());
+        }
+        // an indefinite lock stays indefinite
+        let policy = LockoutPolicy {
+            max_fails: 2,
+            lockout_secs: 0,
+            fail_window: 0,
+        };
+        let mut t = FailTracker::new(ReadOnly, policy).unwrap();
+        assert_eq!(
+            evaluate_attempt(&mut t, &validator, "1234".into(), "x"),
+            Outcome::Locked { until: None }
+        );
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        let validator = PolicyValidator::default();
+        let stored = match hash_pin(&Pin::new("2468", &validator).unwrap()) {
+            Ok(h) => h,
+            Err(PinHashError::BackendUnavailable(_)) => return,
+            Err(e) => panic!("hash: {e:?}"),
+        };
+        let mut t = tracker(5);
+        evaluate_attempt(&mut t, &validator, "1111".into(), &stored);
+        assert_eq!(t.count(), 1);
+        assert_eq!(
+            evaluate_attempt(&mut t, &validator, "2468".into(), &stored),
+            Outcome::Success
+        );
+        assert_eq!(t.count(), 0);
+    }
+
+    #[test]
+    fn timed_lockout_expires() {
+        use crate::fail::ManualClock;
+        let validator = PolicyValidator::default();
+        let pin = Pin::new("1111", &validator).unwrap();
+        let Some(stored) = [Scheme::Argon2id, Scheme::Sha512Crypt]
+            .into_iter()
+            .find_map(|scheme| hash_pin_with(&pin, scheme, &HashParams::default()).ok())
+        else {
+            return; // no backend compiled in
+        };
+        let policy = LockoutPolicy {
+            max_fails: 2,
+            lockout_secs: 2,
+            fail_window: 900,
+        };
+        let clock = ManualClock::new(1_700_000_000);
+        let mut t = FailTracker::with_clock(MemoryFailStore::default(), policy, &clock).unwrap();
+        let mut attempt = |pin: &str| evaluate_attempt(&mut t, &validator, pin.into(), &stored);
+        attempt("0000");
+        assert!(matches!(
+            attempt("0000"),
+            Outcome::Failure { locked: true, .. }
+        ));
+        // still locked, even with the correct PIN
+        clock.advance(1);
+        assert_eq!(
+            attempt("1111"),
+            Outcome::Locked {
+                until: Some(1_700_000_002)
+            }
+        );
+        clock.advance(1);
+        assert_eq!(attempt("1111"), Outcome::Success);
+    }
+
+    #[test]
+    fn decoys_alert_and_lock() {
+        let validator = PolicyValidator::default();
+        let hash = |p: &str| {
+            let pin = Pin::new(p, &validator).unwrap();
+            [Scheme::Argon2id, Scheme::Sha512Crypt]
+                .into_iter()
+                .find_map(|scheme| hash_pin_with(&pin, scheme, &HashParams::default()).ok())
+        };
+        let (Some(stored), Some(decoy)) = (hash("2468"), hash("1357")) else {
+            return; // no backend compiled in
+        };
+        let store =
+            crate::store::MemoryStore::new().with_file("alice.decoy", format!("{decoy}\n\n"));
+        let decoys = Decoys::load(&store, "alice", false).unwrap();
+        assert_eq!(decoys.hashes, [decoy]);
+        assert!(Decoys::load(&store, "bob", true).unwrap().hashes.is_empty());
+        let mut extras = Extras {
+            decoys,
+            ..Extras::default()
+        };
+
+        let mut t = tracker(5);
+        let attempt = |t: &mut FailTracker<MemoryFailStore>, pin: &str, x: &Extras| {
+            evaluate_attempt_with(t, &validator, pin.into(), &stored, x)
+        };
+        assert_eq!(
+            attempt(&mut t, "1357", &extras),
+            Outcome::Decoy {
+                count: 1,
+                locked: false
+            }
+        );
+        assert!(matches!(
+            attempt(&mut t, "0000", &extras),
+            Outcome::Failure { count: 2, .. }
+        ));
+        extras.decoys.lock = true;
+        assert_eq!(
+            attempt(&mut t, "1357", &extras),
+            Outcome::Decoy {
+                count: 5,
+                locked: true
+            }
+        );
+        assert!(matches!(
+            attempt(&mut t, "2468", &extras),
+            Outcome::Locked { .. }
+        ));
+    }
+
+    #[test]
+    fn emergency_pin_is_single_use() {
+        use crate::fail::ManualClock;
+        let Some((secret, emergency)) = ["argon2", "sha-crypt"].into_iter().find_map(|scheme| {
+            let config = ConfigResolver::from_vars([("PIN_SCHEME", scheme)]);
+            Emergency::issue(&config, 1_700_000_100).ok()
+        }) else {
+            return; // no backend compiled in
+        };
+        assert_eq!(secret.len(), Emergency::DIGITS);
+        assert!(secret.bytes().all(|b| b.is_ascii_digit()));
+        let store = crate::store::MemoryStore::new()
+            .with_file("alice.emergency", emergency.serialize())
+            .with_file("bob.emergency", "soon $6$x$y\n");
+        assert_eq!(
+            Emergency::load(&store, "alice").unwrap().as_ref(),
+            Some(&emergency)
+        );
+        assert_eq!(
+            Emergency::load(&store, "bob").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(Emergency::load(&store, "carol").unwrap(), None);
+
+        let validator = PolicyValidator::default();
+        let policy = LockoutPolicy {
+            max_fails: 1,
+            lockout_secs: 0,
+            fail_window: 0,
+        };
+        let clock = ManualClock::new(1_700_000_000);
+        let mut t = FailTracker::with_clock(MemoryFailStore::default(), policy, &clock).unwrap();
+        let extras = Extras {
+            emergency: Emergency::load(&store, "alice").unwrap(),
+            ..Extras::default()
+        };
+        let mut attempt =
+            |pin: &str| evaluate_attempt_with(&mut t, &validator, pin.into(), "x", &extras);
+        assert!(matches!(
+            attempt("0000"),
+            Outcome::Failure { locked: true, .. }
+        ));
+        assert_eq!(attempt("000000000000"), Outcome::Locked { until: None });
+        assert_eq!(attempt(&secret), Outcome::Emergency);
+        redeem_emergency(&store, "alice", &mut t).unwrap();
+        assert_eq!(t.count(), 0);
+        assert_eq!(Emergency::load(&store, "alice").unwrap(), None);
+        assert!(redeem_emergency(&store, "alice", &mut t).is_err());
+
+        // expired
+        assert!(!emergency.accepts(&secret, 1_700_000_100));
+    }
+
+    #[test]
+    fn recovery_codes_only_while_locked() {
+        let Some((secrets, codes)) = ["argon2", "sha-crypt"].into_iter().find_map(|scheme| {
+            let config = ConfigResolver::from_vars([("PIN_SCHEME", scheme)]);
+            RecoveryCodes::generate(&config, 2).ok()
+        }) else {
+            return; // no backend compiled in
+        };
+        assert_eq!((codes.codes.len(), codes.remaining()), (2, 2));
+        let printed = RecoveryCodes::format(&secrets[1]);
+        assert_eq!(printed.len(), 14);
+        assert_eq!(codes.find(&printed), Some(1));
+        assert_eq!(codes.find(&secrets[0]), Some(0));
+        assert_eq!(codes.find("1234"), None);
+
+        let store = crate::store::MemoryStore::new()
+            .with_file("alice.recovery", codes.serialize())
+            .with_file("bob.recovery", "used soon\n");
+        assert_eq!(RecoveryCodes::load(&store, "alice").unwrap(), codes);
+        assert!(RecoveryCodes::load(&store, "bob").is_err());
+        assert_eq!(RecoveryCodes::load(&store, "carol").unwrap().remaining(), 0);
+
+        let validator = PolicyValidator::default();
+        let mut t = tracker(1);
+        let extras = Extras {
+            recovery: codes,
+            ..Extras::default()
+        };
+        let attempt = |t: &mut FailTracker<MemoryFailStore>, pin: &str| {
+            evaluate_attempt_with(t, &validator, pin.into(), "x", &extras)
+        };
+        // not locked: just a wrong (over-long) PIN
+        assert_eq!(attempt(&mut t, &secrets[0]), Outcome::BadInput);
+        assert!(matches!(
+            attempt(&mut t, "0000"),
+            Outcome::Failure { locked: true, .. }
+        ));
+        assert_eq!(
+            attempt(&mut t, &printed),
+            Outcome::Recovery {
+                index: 1,
+                remaining: 1
+            }
+        );
+        redeem_recovery(&store, "alice", &extras.recovery, 1, &mut t).unwrap();
+        assert_eq!(t.count(), 0);
+        let left = RecoveryCodes::load(&store, "alice").unwrap();
+        assert_eq!(left.remaining(), 1);
+        assert!(matches!(left.codes[1], RecoveryCode::Used { .. }));
+        assert_eq!(left.find(&secrets[1]), None);
+    }
+
+    #[cfg(feature = "hotp")]
+    #[test]
+    fn wrong_hotp_codes_lock_like_pins() {
+        let token = crate::hotp::HotpToken {
+            secret: Zeroizing::new(b"12345678901234567890".to_vec()),
+            counter: 0,
+            digits: 8,
+        };
+        let code = token.code(1);
+        let extras = Extras {
+            hotp: Some(token),
+            hotp_window: 10,
+            ..Extras::default()
+        };
+        let validator = PolicyValidator::default();
+        let mut t = tracker(3);
+        let mut attempt =
+            |pin: &str| evaluate_attempt_with(&mut t, &validator, pin.into(), "x", &extras);
+        // other input the policy refuses is still not counted
+        assert_eq!(attempt("1234567"), Outcome::BadInput);
+        // longer than `max_len`, yet counted
+        assert_eq!(
+            attempt("00000000"),
+            Outcome::Failure {
+                count: 1,
+                locked: false
+            }
+        );
+        assert_eq!(
+            attempt("11111111"),
+            Outcome::Failure {
+                count: 2,
+                locked: false
+            }
+        );
+        assert_eq!(
+            attempt("22222222"),
+            Outcome::Failure {
+                count: 3,
+                locked: true
+            }
+        );
+        assert!(matches!(attempt(&code), Outcome::Locked { .. }));
+    }
+}
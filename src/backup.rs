@@ -0,0 +1,231 @@
+//! Encrypted backups of the PIN and state directories (feature `backup`),
+//! so a host can be re-imaged without enrolling everyone again
+//! (`pinctl backup`, `pinctl restore`).
+//!
+//! An archive holds every regular file of both directories (records, fail
+//! and login state, the config file, the audit log, keys) with its mode,
+//! as JSON encrypted with AES-256-GCM under a 32-byte key file:
+//!
+//! ```text
+//! pin-auth backup 1\n  12-byte nonce  ciphertext and tag
+//! ```
+//!
+//! Leftover copies (`*.bak`, `*.tmp`, ...) and symlinks are skipped.
+
+use crate::store::{read_bytes_nofollow, DirStore};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
+const MAGIC: &[u8] = b"pin-auth backup 1\n";
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum BackupError {
+    #[error("backup io: {0}")]
+    Io(#[from] io::Error),
+    /// Not an archive, or one from a newer version.
+    #[error("not a pin-auth backup")]
+    NotABackup,
+    /// Wrong key, or the archive was changed or cut short.
+    #[error("cannot decrypt (wrong key or damaged archive)")]
+    Decrypt,
+    #[error("malformed archive contents")]
+    Malformed,
+    /// A file name that would leave the directory.
+    #[error("unsafe file name {0:?} in archive")]
+    UnsafeName(String),
+}
+
+/// One backed-up file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupFile {
+    pub name: String,
+    pub mode: u32,
+    pub contents: Vec<u8>,
+}
+
+/// What an archive holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backup {
+    /// Host name at the time of the backup.
+    pub host: String,
+    /// Unix time of the backup.
+    pub created: u64,
+    pub files: Vec<BackupFile>,
+}
+
+impl Backup {
+    /// Read every file to back up from `store`.
+    pub fn collect(store: &DirStore, created: u64) -> io::Result<Self> {
+        let mut names = BTreeSet::new();
+        for dir in [store.dir(), store.state_dir()] {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                if let Some(name) = entry.file_name().to_str() {
+                    if !crate::gc::is_backup_copy(name) {
+                        names.insert(name.to_string());
+                    }
+                }
+            }
+        }
+        let mut files = Vec::with_capacity(names.len());
+        for name in names {
+            // by name, as DirStore places it
+            let path = store.path(&name);
+            let mode = match fs::symlink_metadata(&path) {
+                Ok(md) if md.is_file() => md.permissions().mode() & 0o7777,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            files.push(BackupFile {
+                contents: read_bytes_nofollow(&path)?,
+                name,
+                mode,
+            });
+        }
+        let host = nix::unistd::gethostname()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Backup {
+            host,
+            created,
+            files,
+        })
+    }
+
+    /// The archive, encrypted under `key`.
+    pub fn seal(&self, key: &[u8; 32]) -> Vec<u8> {
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|f| {
+                json!({
+                    "name": f.name,
+                    "mode": f.mode,
+                    "contents": crate::hex(&f.contents).as_str(),
+                })
+            })
+            .collect();
+        let plain = zeroize::Zeroizing::new(
+            json!({ "host": self.host, "created": self.created, "files": files }).to_string(),
+        );
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut nonce);
+        let sealed = Aes256Gcm::new(key.into())
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plain.as_bytes(),
+                    aad: MAGIC,
+                },
+            )
+            .expect("AES-GCM encryption of an in-memory buffer");
+        [MAGIC, &nonce, &sealed].concat()
+    }
+
+    /// Decrypt and parse an archive.
+    pub fn open(archive: &[u8], key: &[u8; 32]) -> Result<Self, BackupError> {
+        let rest = archive
+            .strip_prefix(MAGIC)
+            .filter(|rest| rest.len() > NONCE_LEN)
+            .ok_or(BackupError::NotABackup)?;
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+        let plain = zeroize::Zeroizing::new(
+            Aes256Gcm::new(key.into())
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: sealed,
+                        aad: MAGIC,
+                    },
+                )
+                .map_err(|_| BackupError::Decrypt)?,
+        );
+        let value: Value = serde_json::from_slice(&plain).map_err(|_| BackupError::Malformed)?;
+        let mut files = Vec::new();
+        for f in value["files"].as_array().ok_or(BackupError::Malformed)? {
+            let name = f["name"].as_str().ok_or(BackupError::Malformed)?;
+            if !safe_name(name) {
+                return Err(BackupError::UnsafeName(name.to_string()));
+            }
+            files.push(BackupFile {
+                name: name.to_string(),
+                mode: f["mode"]
+                    .as_u64()
+                    .and_then(|m| u32::try_from(m).ok())
+                    .ok_or(BackupError::Malformed)?,
+                contents: match f["contents"].as_str().ok_or(BackupError::Malformed)? {
+                    "" => Vec::new(),
+                    hex => crate::parse_hex(hex)
+                        .ok_or(BackupError::Malformed)?
+                        .to_vec(),
+                },
+            });
+        }
+        Ok(Backup {
+            host: value["host"].as_str().unwrap_or_default().to_string(),
+            created: value["created"].as_u64().unwrap_or(0),
+            files,
+        })
+    }
+}
+
+/// A plain file name: no path separators, not `.` or `..`.
+fn safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_then_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let state = tmp.path().join("state");
+        fs::create_dir(&state).unwrap();
+        let store = DirStore::new(tmp.path()).with_state_dir(&state);
+        fs::write(tmp.path().join("alice.passwd"), "$6$x$y\n").unwrap();
+        fs::write(state.join("alice.fail"), "lock:2000000000\n").unwrap();
+        fs::write(state.join("log.key"), [0u8, 1, 255]).unwrap();
+        fs::write(tmp.path().join("alice.passwd.bak"), "$6$x$old\n").unwrap();
+        fs::write(tmp.path().join("pin-blacklist"), "").unwrap();
+
+        let backup = Backup::collect(&store, 1_700_000_000).unwrap();
+        let names: Vec<_> = backup.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["alice.fail", "alice.passwd", "log.key", "pin-blacklist"]
+        );
+        let key = [9u8; 32];
+        let archive = backup.seal(&key);
+        assert_eq!(Backup::open(&archive, &key).unwrap(), backup);
+        assert!(matches!(
+            Backup::open(&archive, &[8u8; 32]),
+            Err(BackupError::Decrypt)
+        ));
+        let mut damaged = archive.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            Backup::open(&damaged, &key),
+            Err(BackupError::Decrypt)
+        ));
+        assert!(matches!(
+            Backup::open(b"tar", &key),
+            Err(BackupError::NotABackup)
+        ));
+        assert!(!safe_name("../shadow"));
+        assert!(!safe_name(".."));
+        assert!(safe_name("@ops.passwd"));
+    }
+}
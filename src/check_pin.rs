@@ -1,319 +1,900 @@
-use anyhow::{Context, Result};
-use nix::libc; // for O_NOFOLLOW / O_CLOEXEC
-use pin_auth::verify_pin;
+use anyhow::Result;
+use pin_auth::audit::{AuditEvent, AuditLog};
+use pin_auth::auth::{
+    evaluate_attempt_with, load_fail_state, redeem_emergency, redeem_recovery, Decoys, Emergency,
+    Extras, Outcome, RecoveryCodes,
+};
+use pin_auth::exit::{
+    CheckResult, EXIT_CODES, EXIT_CONFIG, EXIT_INPUT, EXIT_LOCKED, EXIT_MISMATCH, EXIT_OK,
+};
+use pin_auth::fail::{FailStore, FailTracker, LockoutPolicy, WriteErrorPolicy};
+use pin_auth::gc;
+#[cfg(feature = "sqlite")]
+use pin_auth::history::{Attempt, History};
+use pin_auth::hours::{AccessHours, LocalTime};
+use pin_auth::i18n::Messages;
+use pin_auth::lastlogin::LastLogin;
+use pin_auth::logging::{self, fail2ban_failure};
+use pin_auth::man::{self, ManPage};
+use pin_auth::metrics;
+use pin_auth::notify::{self, LockoutNotice};
+use pin_auth::pam::{AuthtokFraming, PamContext};
+use pin_auth::policy::{PolicyValidator, UsernameError, UsernamePolicy};
+use pin_auth::record::{HashRecord, RecordError};
+use pin_auth::store::{read_group_hash, resolve_store_with, DirStore, PinStore};
+use pin_auth::{ConfigResolver, HashFloor, Scheme};
 use std::env;
-use std::fs::{self, OpenOptions};
 use std::io::{self, Read};
-use std::io::{Seek, SeekFrom, Write as IoWrite};
-use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
-use std::os::unix::io::AsRawFd;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
-#[cfg(feature = "syslog")]
-use syslog::{Facility, Formatter3164};
-
-// Exit codes
-const EXIT_OK: i32 = 0; // success
-const EXIT_MISMATCH: i32 = 1; // wrong pin / generic failure
-const EXIT_LOCKED: i32 = 2; // locked out
-const EXIT_INPUT: i32 = 3; // bad input format / empty
-const EXIT_CONFIG: i32 = 4; // config error (length policy, etc.)
+use tracing::{error, info, warn};
+use zeroize::Zeroize;
 
 fn main() -> Result<()> {
+    if env::args().nth(1).as_deref() == Some(man::GENERATE_MAN) {
+        print!("{}", man_page().render());
+        return Ok(());
+    }
+    // Fixed directory in release; allow override only in debug/test builds for isolation.
+    let requested_dir = pin_auth::paths::pin_dir();
+    // Settings: <dir>/pin-auth.conf over the (deprecated) environment.
+    let (config, config_error) = match ConfigResolver::load_dir(&requested_dir) {
+        Ok(config) => (config, None),
+        Err(e) => (ConfigResolver::from_env(), Some(e)),
+    };
+    let default_log = if cfg!(feature = "syslog") {
+        "syslog"
+    } else {
+        "stderr"
+    };
+    logging::init_with("check_pin", default_log, &config);
     // Enforce root effective UID; debug build allows ALLOW_NON_ROOT=1 for tests.
     let euid = nix::unistd::geteuid().as_raw();
     if euid != 0 {
         #[cfg(not(debug_assertions))]
         {
-            eprintln!("denied: requires root (effective uid 0)");
+            error!("denied: requires root (effective uid 0)");
             std::process::exit(EXIT_CONFIG);
         }
         #[cfg(debug_assertions)]
         {
             if env::var("ALLOW_NON_ROOT").ok().as_deref() != Some("1") {
-                eprintln!(
-                    "denied: requires root (set ALLOW_NON_ROOT=1 in debug to bypass for tests)"
-                );
+                error!("denied: requires root (set ALLOW_NON_ROOT=1 in debug to bypass for tests)");
                 std::process::exit(EXIT_CONFIG);
             }
         }
     }
+    if let Some(e) = config_error {
+        error!(error = %e, "configuration");
+        std::process::exit(EXIT_CONFIG);
+    }
+    config.report();
     let user = env::var("PAM_USER")
         .or_else(|_| env::var("USER"))
         .unwrap_or_default();
     if user.is_empty() {
         std::process::exit(EXIT_CONFIG);
     }
-    if !validate_username(&user) {
-        // Reject suspicious usernames early
-        std::process::exit(EXIT_CONFIG);
-    }
-
-    #[cfg(feature = "syslog")]
-    let mut logger = syslog::unix(Formatter3164 {
-        facility: Facility::LOG_AUTH,
-        hostname: None,
-        process: "check_pin".into(),
-        pid: 0,
-    })
-    .ok();
-
-    // Fixed directory in release; allow override only in debug/test builds for isolation.
-    let requested_dir = if cfg!(debug_assertions) {
-        env::var("PIN_DIR").unwrap_or_else(|_| "/etc/pin.d".to_string())
-    } else {
-        "/etc/pin.d".to_string()
+    let pam = PamContext::from_env();
+    // The name, or its UID with `record_key = uid`, names the user's files.
+    let file_key = match UsernamePolicy::from_config(&config).record_key(&user) {
+        Ok(key) => Some(key),
+        // no UID: refused below, once it can be audited
+        Err(UsernameError::UnknownUser) => None,
+        Err(e) => {
+            // Reject suspicious usernames early
+            let result = CheckResult::InvalidUser;
+            warn!(result = result.reason(), error = %e, "rejected invalid username");
+            audit_early(&requested_dir, &config, &user, &pam, result);
+            std::process::exit(result.exit_code())
+        }
     };
-    let base_dir = secure_resolve_pin_dir(&requested_dir).unwrap_or_else(|_e| {
-        #[cfg(feature = "syslog")]
-        if let Some(ref mut l) = logger {
-            let _ = l.err("pin-auth: dir validation failed".to_string());
+    // Users NSS does not know get the answer to a wrong PIN, so callers
+    // cannot probe for accounts; only the log tells them apart. Looked up
+    // before the store is, and answered once it is open (`None`).
+    let known = match &file_key {
+        None => Ok(false),
+        Some(_) if config.flag("require_known_user").unwrap_or(false) => {
+            pin_auth::account::user_exists(&user)
         }
-        std::process::exit(EXIT_CONFIG)
-    });
-    let path = format!("{}/{}.passwd", base_dir, user);
-    let stored = match read_file_nofollow(&path) {
-        Ok(s) => s.trim().to_string(),
-        Err(_) => std::process::exit(EXIT_MISMATCH),
+        Some(_) => Ok(true),
+    };
+    let file_key = match (file_key, known) {
+        (Some(key), Ok(true)) => Some(key),
+        (_, Err(e)) => {
+            error!(error = %e, "user lookup failed");
+            std::process::exit(EXIT_CONFIG)
+        }
+        _ => None,
     };
 
-    // Fail counter / lockout
-    let max_fails: u32 = env::var("PIN_MAX_FAILS")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(5);
-    let fail_file: PathBuf = [base_dir.as_str(), &format!("{}.fail", user)]
-        .iter()
-        .collect();
-    let lockout_secs: u64 = env::var("PIN_LOCKOUT_SECS")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(300); // 5 minutes default
-    let fail_window: u64 = env::var("PIN_FAIL_WINDOW")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(900); // 15 minutes aggregation window
-                         // Syslog failure sampling: log only every Nth failure (plus first & lock events)
-    let _fail_sample: u32 = env::var("PIN_SYSLOG_FAIL_SAMPLE")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(1);
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-
-    // File formats:
-    //  - "count:first_ts"  (e.g. "2:1700000000")
-    //  - "lock:until_ts"   (e.g. "lock:1700000300")
-    //  - legacy: just number (treated as count with first_ts=now)
-    let mut fail_count: u32 = 0;
-    let mut first_ts: u64 = now;
-    // Open (create if missing) fail file securely and obtain advisory lock to avoid races.
-    let mut fail_fh = match OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
-        .open(&fail_file)
+    let store = resolve_store_with(&requested_dir, &config).unwrap_or_else(|e| {
+        let result = CheckResult::Config;
+        error!(result = result.reason(), error = %e, "dir validation failed");
+        audit_early(&requested_dir, &config, &user, &pam, result);
+        std::process::exit(result.exit_code())
+    });
+    // Daily sweep of fail state nobody needs any more (`state_gc_age`); an
+    // unknown user's attempt only gets as far as the audit log.
+    if let Some(age) = config
+        .duration("state_gc_age")
+        .filter(|&age| age > 0 && file_key.is_some())
     {
-        Ok(f) => f,
-        Err(_) => {
-            // Could not open fail tracking file. Proceed without persistent fail/lockout state
-            // (better to allow auth than hard-fail). Use /dev/null so later writes are no-ops.
-            if let Ok(devnull) = OpenOptions::new().read(true).open("/dev/null") {
-                devnull
-            } else {
-                return Err(anyhow::anyhow!("failed to open fail state"));
+        collect_stale_state(&store, &config, age);
+    }
+    // Privacy mode: logs carry a per-host HMAC of the name instead of the name itself.
+    let log_user = if config.flag("log_pseudonymize").unwrap_or(false) {
+        match logging::load_or_create_key(store.path("log.key")) {
+            Ok(key) => logging::pseudonym(&key, &user),
+            Err(e) => {
+                error!(error = %e, "pseudonym key unavailable");
+                "-".to_string()
             }
         }
+    } else {
+        user.clone()
     };
-    unsafe {
-        libc::flock(fail_fh.as_raw_fd(), libc::LOCK_EX);
-    }
-    // Read existing content
-    let mut raw_state = String::new();
-    if std::io::Read::read_to_string(&mut fail_fh, &mut raw_state).is_ok() {
-        let line = raw_state.trim();
-        if let Some(rest) = line.strip_prefix("lock:") {
-            if let Ok(until) = rest.parse::<u64>() {
-                if now < until {
-                    #[cfg(feature = "syslog")]
-                    if let Some(ref mut l) = logger {
-                        let _ = l.err(format!("pin-auth: user={user} locked (until {until})"));
-                    }
-                    std::process::exit(EXIT_LOCKED);
-                } else {
-                    // expired: overwrite below
-                }
+    let _span = tracing::info_span!(
+        "attempt",
+        user = %log_user,
+        service = pam.service.as_deref(),
+        tty = pam.tty.as_deref(),
+        rhost = pam.rhost.as_deref()
+    )
+    .entered();
+    let audit_log = config
+        .flag("audit")
+        .unwrap_or(false)
+        .then(|| AuditLog::new(store.path("audit.log")));
+    #[cfg(feature = "sqlite")]
+    let history = config
+        .flag("history")
+        .unwrap_or(false)
+        .then(|| History::open(store.path("history.sqlite")))
+        .and_then(|r| {
+            r.map_err(|e| error!(error = %e, "opening attempt history"))
+                .ok()
+        });
+    let metrics_file = config.string("metrics_file");
+    // Record the attempt outcome in the audit log / attempt history /
+    // metrics textfile (when enabled); `lockout` when it locked the user.
+    let audit_with = |result: &str, lockout: bool| {
+        if let Some(path) = &metrics_file {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if let Err(e) = metrics::record(Path::new(path), result, lockout, now) {
+                error!(error = %e, "metrics update failed");
             }
-        } else if let Some((cnt, ts)) = line.split_once(':') {
-            if let (Ok(c), Ok(t)) = (cnt.parse::<u32>(), ts.parse::<u64>()) {
-                fail_count = c;
-                first_ts = t;
+        }
+        if let Some(log) = &audit_log {
+            let event = AuditEvent::Auth {
+                user: &user,
+                result,
+                pam: &pam,
+            };
+            if let Err(e) = log.append(&event) {
+                error!(error = %e, "audit append failed");
             }
-        } else if let Ok(c) = line.parse::<u32>() {
-            // legacy
-            fail_count = c;
-            first_ts = now;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &history {
+            let attempt = Attempt {
+                ts: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                user: user.clone(),
+                result: result.to_string(),
+                rhost: pam.rhost.clone(),
+                service: pam.service.clone(),
+                tty: pam.tty.clone(),
+            };
+            if let Err(e) = db.record(&attempt) {
+                error!(error = %e, "history insert failed");
+            }
+        }
+    };
+    let audit = |result: &str| audit_with(result, false);
+    let Some(file_key) = file_key else {
+        // failures counted under the name's pseudonym, not the name
+        let code = match logging::load_or_create_key(store.path("log.key")) {
+            Ok(key) => answer_without_record(&store, &config, &logging::pseudonym(&key, &user)),
+            Err(e) => {
+                error!(error = %e, "pseudonym key unavailable");
+                CheckResult::StateError.exit_code()
+            }
+        };
+        warn!(result = CheckResult::UnknownUser.reason(), "no such user");
+        audit(CheckResult::UnknownUser.reason());
+        std::process::exit(code)
+    };
+    // On every success: show the previous login (pam_exec `stdout`), then
+    // record this one.
+    let show_last_login = config.flag("last_login_message").unwrap_or(false);
+    let messages = Messages::load(store.dir(), &config);
+    let logged_in = || {
+        if show_last_login {
+            match LastLogin::load(&store, &file_key) {
+                Ok(Some(last)) => {
+                    println!(
+                        "{}",
+                        messages.get("last_login", &[("last", &last.describe())])
+                    )
+                }
+                Ok(None) => {}
+                Err(e) => error!(error = %e, "last login unavailable"),
+            }
+        }
+        let login = LastLogin {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            pam: pam.clone(),
+        };
+        if let Err(e) = login.save(&store, &file_key) {
+            error!(error = %e, "last login not recorded");
+        }
+    };
+    // Only members of `require_group` may use a PIN at all.
+    if let Some(group) = config.string("require_group") {
+        let member = pin_auth::account::groups_of(&user)
+            .inspect_err(|e| info!(error = %e, "group lookup failed"))
+            .is_ok_and(|groups| groups.iter().any(|g| g == group));
+        if !member {
+            let result = CheckResult::NotInGroup;
+            warn!(result = result.reason(), group, "not in the required group");
+            audit(result.reason());
+            std::process::exit(result.exit_code());
         }
     }
-
-    // Reset window if expired (unless window==0 meaning infinite accumulation)
-    if fail_window > 0 && now.saturating_sub(first_ts) > fail_window {
-        fail_count = 0;
-        first_ts = now;
-    }
-    if fail_count >= max_fails {
-        if lockout_secs > 0 {
-            let until = now.saturating_add(lockout_secs);
-            let _ = fail_fh.set_len(0);
-            let _ = fail_fh.seek(SeekFrom::Start(0));
-            let _ = IoWrite::write_all(&mut fail_fh, format!("lock:{}\n", until).as_bytes());
-        }
-        #[cfg(feature = "syslog")]
-        if let Some(ref mut l) = logger {
-            let _ = l.err(format!("pin-auth: user={user} locked (threshold reached)"));
-        }
-        std::process::exit(EXIT_LOCKED);
+    let hours = AccessHours::load(&store).unwrap_or_else(|e| {
+        error!(error = %e, "access hours");
+        std::process::exit(EXIT_CONFIG)
+    });
+    if !hours.allows(&user, pam.service.as_deref(), LocalTime::now()) {
+        let result = CheckResult::OutsideHours;
+        warn!(result = result.reason(), "outside the allowed hours");
+        audit(result.reason());
+        std::process::exit(result.exit_code());
     }
-
-    let mut input = String::new();
-    io::stdin().read_to_string(&mut input).ok();
-    let mut candidate = input.trim_end_matches('\n').to_string();
-    if candidate.is_empty() {
-        use zeroize::Zeroize;
-        candidate.zeroize();
-        input.zeroize();
-        std::process::exit(EXIT_INPUT);
+    // A PIN must not get around `passwd -l`, `chage -E` or a nologin shell.
+    if config.flag("account_status").unwrap_or(false) {
+        let today = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 86_400;
+        match pin_auth::account::disabled(&user, today as i64) {
+            Ok(None) => {}
+            Ok(Some(disabled)) => {
+                let result = CheckResult::AccountDisabled;
+                warn!(
+                    result = result.reason(),
+                    status = disabled.reason(),
+                    "system account disabled"
+                );
+                audit(result.reason());
+                std::process::exit(result.exit_code());
+            }
+            // fail closed: the check was asked for
+            Err(e) => {
+                error!(error = %e, "account status unavailable");
+                std::process::exit(EXIT_CONFIG);
+            }
+        }
     }
-    // Enforce digit-only and max length policy similar to generation step (defense in depth)
-    let min_len: usize = env::var("PIN_MIN_LEN")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(4);
-    let max_len: usize = env::var("PIN_MAX_LEN")
+    // A TOML record may set this user's policy (a corrupt one is reported
+    // with the hash below).
+    #[cfg(feature = "toml-records")]
+    let config = match pin_auth::toml_record::TomlRecord::load(&store, &file_key) {
+        Ok(Some(record)) => config
+            .clone()
+            .with_user_settings(
+                record.policy,
+                &store
+                    .path(&format!("{file_key}.toml"))
+                    .display()
+                    .to_string(),
+            )
+            .unwrap_or_else(|e| {
+                error!(error = %e, "pin record policy");
+                std::process::exit(EXIT_CONFIG)
+            }),
+        _ => config.clone(),
+    };
+    // So may `key=value` lines in the `.passwd` record (`pinctl policy`).
+    let passwd = store.path(&format!("{file_key}.passwd"));
+    let settings = store
+        .read_file(&format!("{file_key}.passwd"))
         .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(6);
-    if candidate.len() < min_len
-        || candidate.len() > max_len
-        || !candidate.chars().all(|c| c.is_ascii_digit())
-    {
-        use zeroize::Zeroize;
-        candidate.zeroize();
-        input.zeroize();
-        std::process::exit(EXIT_INPUT);
+        .and_then(|raw| HashRecord::parse(&raw).ok())
+        .map(|record| record.settings())
+        .unwrap_or_default();
+    let config = config
+        .clone()
+        .with_user_settings(settings, &passwd.display().to_string())
+        .unwrap_or_else(|e| {
+            error!(error = %e, "pin record policy");
+            std::process::exit(EXIT_CONFIG)
+        });
+    let validator = PolicyValidator::from_config(&config).unwrap_or_else(|e| {
+        error!(error = %e, "pin policy");
+        std::process::exit(EXIT_CONFIG)
+    });
+    // No record of their own: a group they belong to may share one.
+    let own = match store.read_hash(&file_key) {
+        Err(e) if e.get_ref().is_some_and(|e| e.is::<RecordError>()) => {
+            let result = CheckResult::CorruptRecord;
+            error!(result = result.reason(), error = %e, "pin record");
+            audit(result.reason());
+            std::process::exit(result.exit_code())
+        }
+        // sealed without the key, or otherwise unreadable
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            error!(error = %e, "pin record");
+            std::process::exit(EXIT_CONFIG)
+        }
+        read => read.ok(),
+    };
+    let stored = match own.or_else(|| {
+        let groups = pin_auth::account::groups_of(&user)
+            .inspect_err(|e| info!(error = %e, "group lookup failed"))
+            .ok()?;
+        let (group, hash) = read_group_hash(&store, &groups)?;
+        info!(group = %group, "using the group's shared pin");
+        Some(hash)
+    }) {
+        Some(s) => s,
+        None => {
+            #[cfg(feature = "fido2")]
+            if try_fido2(&store, &file_key, pin_auth::fido2::Fallback::when_no_pin) {
+                info!(
+                    result = "fido2",
+                    "fido2 assertion accepted, no pin enrolled"
+                );
+                audit("fido2");
+                logged_in();
+                std::process::exit(EXIT_OK);
+            }
+            let code = answer_without_record(&store, &config, &file_key);
+            audit("no_record");
+            std::process::exit(code)
+        }
+    };
+    // Below the floor the hash is not even tried: the PIN must be set again.
+    if let Some(floor) = config.parse::<HashFloor>("min_hash") {
+        if Scheme::from_hash(&stored).is_ok_and(|info| !floor.allows(&info)) {
+            let result = CheckResult::WeakHash;
+            warn!(
+                result = result.reason(),
+                min_hash = %floor,
+                "pin hash below min_hash, set the pin again with genpin"
+            );
+            audit(result.reason());
+            std::process::exit(result.exit_code());
+        }
     }
 
-    if verify_pin(&mut candidate, &stored) {
-        // success → reset fail counter / lock
-        let _ = fail_fh.set_len(0);
-        let _ = fail_fh.seek(SeekFrom::Start(0));
-        #[cfg(feature = "syslog")]
-        if let Some(ref mut l) = logger {
-            let _ = l.info(format!("pin-auth: user={user} success"));
-        }
-        std::process::exit(EXIT_OK);
-    } else {
-        fail_count += 1;
-        // persist update
-        let _ = fail_fh.set_len(0);
-        let _ = fail_fh.seek(SeekFrom::Start(0));
-        if fail_count >= max_fails {
-            if lockout_secs > 0 {
-                let until = now.saturating_add(lockout_secs);
-                let _ = IoWrite::write_all(&mut fail_fh, format!("lock:{}\n", until).as_bytes());
+    let policy = LockoutPolicy::from_config(&config);
+    // Failure log sampling: log only every Nth failure (plus first & lock events)
+    let fail_sample: u32 = config.parse("syslog_fail_sample").unwrap_or(1);
+    // fail2ban stream: one unsampled line per rejected attempt
+    let fail2ban = config.flag("fail2ban").unwrap_or(false);
+    let rejected = || {
+        if fail2ban {
+            fail2ban_failure(&log_user, pam.rhost.as_deref(), pam.service.as_deref());
+        }
+    };
+    let decoy_lock = config.flag("decoy_lock").unwrap_or(false);
+    let decoys = Decoys::load(&store, &file_key, decoy_lock).unwrap_or_else(|e| {
+        error!(error = %e, "decoy PINs unavailable");
+        Decoys::default()
+    });
+    let on_write_error: WriteErrorPolicy = config.parse("on_write_error").unwrap_or_default();
+    // Held (and locked) until exit. Going on without it would let parallel
+    // attempts past the fail counter.
+    let mut tracker =
+        load_fail_state(&store, &file_key, policy, on_write_error).unwrap_or_else(|e| {
+            let result = if pin_auth::lock::is_timeout(&e) {
+                CheckResult::LockTimeout
             } else {
-                let _ = IoWrite::write_all(
-                    &mut fail_fh,
-                    format!("{}:{}\n", fail_count, first_ts).as_bytes(),
+                CheckResult::StateError
+            };
+            error!(result = result.reason(), error = %e, "fail state unavailable");
+            audit(result.reason());
+            std::process::exit(result.exit_code())
+        });
+    // Read under the fail state lock: a concurrent attempt waits until this
+    // one has used (and crossed off) them.
+    let emergency = Emergency::load(&store, &file_key).unwrap_or_else(|e| {
+        error!(error = %e, "emergency pin unavailable");
+        None
+    });
+    let recovery = RecoveryCodes::load(&store, &file_key).unwrap_or_else(|e| {
+        error!(error = %e, "recovery codes unavailable");
+        RecoveryCodes::default()
+    });
+    #[cfg(feature = "hotp")]
+    let hotp = pin_auth::hotp::HotpToken::load(&store, &file_key).unwrap_or_else(|e| {
+        error!(error = %e, "hotp token unavailable");
+        None
+    });
+    let extras = Extras {
+        decoys,
+        emergency,
+        recovery,
+        #[cfg(feature = "hotp")]
+        hotp,
+        #[cfg(feature = "hotp")]
+        hotp_window: config
+            .parse("hotp_window")
+            .unwrap_or(pin_auth::hotp::DEFAULT_WINDOW),
+    };
+
+    let framing: AuthtokFraming = config.parse("authtok_framing").unwrap_or_default();
+    let Some(candidate) = read_candidate(&config) else {
+        warn!(result = "bad_input", framing = ?framing, "input is not a framed token");
+        audit("bad_input");
+        std::process::exit(EXIT_INPUT);
+    };
+    // Enrolled YubiKey: an empty PIN asks the key instead, in local
+    // sessions only.
+    #[cfg(feature = "yubikey")]
+    if candidate.is_empty() {
+        try_yubikey(
+            &store,
+            &file_key,
+            pin_auth::yubikey::session_refusal(&config, &pam),
+            &mut tracker,
+            on_write_error,
+            &audit,
+            &logged_in,
+        );
+    }
+    let outcome = evaluate_attempt_with(&mut tracker, &validator, candidate, &stored, &extras);
+    refuse_unsaved(&tracker, on_write_error, &audit);
+    let lock_until = || {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        (policy.lockout_secs > 0).then(|| now.saturating_add(policy.lockout_secs))
+    };
+    match outcome {
+        Outcome::Locked { until } => {
+            #[cfg(feature = "fido2")]
+            if try_fido2(&store, &file_key, pin_auth::fido2::Fallback::when_locked) {
+                if tracker.record_success().is_err() {
+                    refuse_unsaved(&tracker, on_write_error, &audit);
+                }
+                warn!(
+                    result = "fido2",
+                    "fido2 assertion accepted, lockout cleared"
                 );
+                audit("fido2");
+                logged_in();
+                std::process::exit(EXIT_OK);
+            }
+            error!(result = "locked", until, "account locked");
+            rejected();
+            audit(outcome.result());
+            std::process::exit(EXIT_LOCKED);
+        }
+        Outcome::BadInput => {
+            audit(outcome.result());
+            std::process::exit(EXIT_INPUT);
+        }
+        Outcome::Success => {
+            info!(result = "success", "pin accepted");
+            audit(outcome.result());
+            warn_weak_hash(&store, &file_key, &stored, &config);
+            logged_in();
+            std::process::exit(EXIT_OK);
+        }
+        Outcome::Emergency => {
+            if let Err(e) = redeem_emergency(&store, &file_key, &mut tracker) {
+                error!(error = %e, "emergency pin could not be invalidated, refused");
+                std::process::exit(EXIT_CONFIG);
             }
-        } else {
-            let _ = IoWrite::write_all(
-                &mut fail_fh,
-                format!("{}:{}\n", fail_count, first_ts).as_bytes(),
+            warn!(
+                result = "emergency",
+                "emergency pin accepted, lockout cleared"
             );
+            audit(outcome.result());
+            logged_in();
+            std::process::exit(EXIT_OK);
         }
-        #[cfg(feature = "syslog")]
-        if let Some(ref mut l) = logger {
+        Outcome::Recovery { index, remaining } => {
+            if let Err(e) =
+                redeem_recovery(&store, &file_key, &extras.recovery, index, &mut tracker)
+            {
+                error!(error = %e, "recovery code could not be crossed off, refused");
+                std::process::exit(EXIT_CONFIG);
+            }
+            warn!(
+                result = "recovery",
+                remaining, "recovery code accepted, lockout cleared"
+            );
+            audit(outcome.result());
+            logged_in();
+            std::process::exit(EXIT_OK);
+        }
+        #[cfg(feature = "hotp")]
+        Outcome::Hotp { counter } => {
+            if let Err(e) = pin_auth::hotp::redeem_hotp(
+                &store,
+                &file_key,
+                extras.hotp.as_ref(),
+                counter,
+                &mut tracker,
+            ) {
+                error!(error = %e, "hotp counter could not be stored, refused");
+                std::process::exit(EXIT_CONFIG);
+            }
+            info!(result = "hotp", counter, "hotp code accepted");
+            audit(outcome.result());
+            logged_in();
+            std::process::exit(EXIT_OK);
+        }
+        Outcome::Failure {
+            count: fail_count,
+            locked,
+        } => {
+            let max_fails = policy.max_fails;
             // Never log candidate PINs; only metadata.
-            let fs = _fail_sample; // local copy
             if fail_count == 1
                 || fail_count == max_fails
-                || fs == 1
-                || (fs > 1 && fail_count % fs == 0)
+                || fail_sample == 1
+                || (fail_sample > 1 && fail_count.is_multiple_of(fail_sample))
             {
-                let _ = l.warning(format!("pin-auth: user={user} failure count={fail_count}"));
+                warn!(result = "failure", failcount = fail_count, "pin rejected");
+            }
+            rejected();
+            let lockout = locked && fail_count == max_fails;
+            audit_with(outcome.result(), lockout);
+            if !locked {
+                std::process::exit(EXIT_MISMATCH);
+            }
+            if lockout {
+                notify_admin(
+                    &store,
+                    &config,
+                    "lockout-notify.tmpl",
+                    notify::DEFAULT_TEMPLATE,
+                    &LockoutNotice {
+                        user: &user,
+                        pam: &pam,
+                        failcount: fail_count,
+                        until: lock_until(),
+                    },
+                );
             }
-        }
-        if fail_count >= max_fails {
             std::process::exit(EXIT_LOCKED);
         }
-        std::process::exit(EXIT_MISMATCH);
+        Outcome::Decoy {
+            count: fail_count,
+            locked,
+        } => {
+            // The alert itself: never sampled.
+            error!(
+                result = "decoy",
+                failcount = fail_count,
+                locked,
+                "decoy pin entered"
+            );
+            rejected();
+            audit_with(outcome.result(), locked);
+            notify_admin(
+                &store,
+                &config,
+                "decoy-notify.tmpl",
+                notify::DECOY_TEMPLATE,
+                &LockoutNotice {
+                    user: &user,
+                    pam: &pam,
+                    failcount: fail_count,
+                    until: lock_until(),
+                },
+            );
+            std::process::exit(if locked { EXIT_LOCKED } else { EXIT_MISMATCH });
+        }
     }
 }
 
-fn validate_username(u: &str) -> bool {
-    // Conservative policy: 1..32 chars, [a-zA-Z0-9_-], must start alnum/underscore, not all digits.
-    if u.is_empty() || u.len() > 32 {
-        return false;
+/// Audit a refusal that comes before the store is resolved, in the audit
+/// log the store would use.
+fn audit_early(
+    dir: &str,
+    config: &ConfigResolver,
+    user: &str,
+    pam: &PamContext,
+    result: CheckResult,
+) {
+    if !config.flag("audit").unwrap_or(false) {
+        return;
     }
-    let mut chars = u.chars();
-    if let Some(first) = chars.next() {
-        if !first.is_ascii_alphanumeric() && first != '_' {
-            return false;
-        }
+    let state_dir = config
+        .string("state_dir")
+        .or_else(|| pin_auth::paths::default_state_dir(dir))
+        .unwrap_or(dir);
+    let event = AuditEvent::Auth {
+        user,
+        result: result.reason(),
+        pam,
+    };
+    if let Err(e) = AuditLog::new(Path::new(state_dir).join("audit.log")).append(&event) {
+        error!(error = %e, "audit append failed");
+    }
+}
+
+/// The token from stdin, framed as `authtok_framing` says; `None` if the
+/// input does not fit the framing.
+fn read_candidate(config: &ConfigResolver) -> Option<String> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).ok();
+    let framing: AuthtokFraming = config.parse("authtok_framing").unwrap_or_default();
+    let candidate = if config.flag("normalize_input").unwrap_or(false) {
+        framing.normalized_token(&input)
     } else {
-        return false;
+        framing.token(&input)
     }
-    if u.contains('/') {
-        return false;
+    .map(str::to_string);
+    input.zeroize();
+    candidate
+}
+
+/// Exit code for an attempt with no PIN to check it against (an unknown
+/// user, or one without a record): what a wrong PIN would get. The input is
+/// read and verified against a [`pin_auth::dummy_hash`], and the failure
+/// counts toward the lockout of `key`, so neither the answer nor its timing
+/// differs, not even after `max_fails` attempts.
+fn answer_without_record(store: &DirStore, config: &ConfigResolver, key: &str) -> i32 {
+    let Some(candidate) = read_candidate(config) else {
+        return EXIT_INPUT;
+    };
+    let validator = PolicyValidator::from_config(config).unwrap_or_default();
+    let on_write_error: WriteErrorPolicy = config.parse("on_write_error").unwrap_or_default();
+    let policy = LockoutPolicy::from_config(config);
+    let mut tracker = match load_fail_state(store, key, policy, on_write_error) {
+        Ok(tracker) => tracker,
+        Err(e) if pin_auth::lock::is_timeout(&e) => return CheckResult::LockTimeout.exit_code(),
+        Err(e) => {
+            error!(error = %e, "fail state unavailable");
+            return CheckResult::StateError.exit_code();
+        }
+    };
+    let dummy = pin_auth::dummy_hash(config);
+    let outcome = evaluate_attempt_with(
+        &mut tracker,
+        &validator,
+        candidate,
+        &dummy,
+        &Extras::default(),
+    );
+    if on_write_error == WriteErrorPolicy::Closed && tracker.write_error().is_some() {
+        return CheckResult::StateError.exit_code();
     }
-    if !u
-        .chars()
-        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
-    {
-        return false;
+    match outcome {
+        Outcome::BadInput => EXIT_INPUT,
+        Outcome::Locked { .. } | Outcome::Failure { locked: true, .. } => EXIT_LOCKED,
+        _ => EXIT_MISMATCH,
     }
-    true
 }
 
-fn secure_resolve_pin_dir(input: &str) -> Result<String> {
-    // Always require absolute path when running setuid root; otherwise allow relative for tests.
-    let euid_root = nix::unistd::geteuid().as_raw() == 0;
-    let path = Path::new(input);
-    if euid_root && !path.is_absolute() {
-        anyhow::bail!("PIN_DIR must be absolute under root");
+fn man_page() -> ManPage {
+    ManPage {
+        name: "check_pin",
+        about: "check a user's PIN for pam_exec",
+        synopsis: vec!["auth sufficient pam_exec.so expose_authtok seteuid quiet check_pin".into()],
+        description: vec![
+            "check_pin reads the token pam_exec writes to its standard input and checks it \
+             against the PIN record of PAM_USER, counting failures and locking the user out \
+             after max_fails of them. Decoy PINs, emergency PINs, recovery codes and enrolled \
+             tokens are accepted as configured in pin-auth.conf."
+                .into(),
+            "PAM_SERVICE, PAM_TTY and PAM_RHOST, when set, are logged and audited with every \
+             attempt."
+                .into(),
+            "With an enrolled YubiKey, an empty PIN makes check_pin challenge the YubiKeys \
+             attached to this host. Whoever starts the PAM conversation chooses the empty PIN, \
+             over sshd too, so this happens only with yubikey_require_touch set (the keys are \
+             programmed to need a touch), never when PAM_RHOST is set, and, with \
+             yubikey_services, only for the PAM services listed."
+                .into(),
+        ],
+        lists: vec![(
+            "EXIT STATUS",
+            EXIT_CODES
+                .iter()
+                .map(|(code, text)| (code.to_string(), text.to_string()))
+                .collect(),
+        )],
     }
-    // Canonicalize (best effort); if it fails we still attempt metadata on original.
-    let meta_path = path;
-    if euid_root {
-        let md = fs::metadata(meta_path).with_context(|| format!("stat {:?}", meta_path))?;
-        if md.file_type().is_symlink() {
-            anyhow::bail!("PIN_DIR may not be a symlink");
-        }
-        if md.uid() != 0 {
-            anyhow::bail!("PIN_DIR must be owned by root");
-        }
-        // Mode check (0700 expected; allow 0710 for group traverse if desired?)
-        let mode = md.mode() & 0o7777;
-        if mode & 0o022 != 0 {
-            // group/world write bits
-            anyhow::bail!("PIN_DIR must not be group/world writable");
+}
+
+/// Log a fail state that could not be written during this attempt (a full
+/// disk, say) and, with `on_write_error = closed`, refuse the attempt
+/// (exits).
+fn refuse_unsaved(
+    tracker: &FailTracker<Box<dyn FailStore>>,
+    on_write_error: WriteErrorPolicy,
+    audit: &dyn Fn(&str),
+) {
+    let Some(e) = tracker.write_error() else {
+        return;
+    };
+    if on_write_error == WriteErrorPolicy::Open {
+        error!(error = %e, "fail state not saved, lockout not enforced");
+        return;
+    }
+    let result = CheckResult::StateError;
+    error!(result = result.reason(), error = %e, "fail state not saved, refused");
+    audit(result.reason());
+    std::process::exit(result.exit_code());
+}
+
+/// Warns that `user`'s hash is weaker than what `genpin` writes now, so
+/// admins can plan the migration: on their first login of the (UTC) day,
+/// going by the `.last` file this login is about to replace.
+fn warn_weak_hash(store: &dyn PinStore, user: &str, stored: &str, config: &ConfigResolver) {
+    let Some(gap) = Scheme::from_hash(stored)
+        .ok()
+        .and_then(|info| info.shortfall(config))
+    else {
+        return;
+    };
+    let today = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400;
+    let warned = LastLogin::load(store, user)
+        .ok()
+        .flatten()
+        .is_some_and(|last| last.at / 86_400 == today);
+    if !warned {
+        warn!(
+            gap = %gap,
+            "pin hash weaker than configured, set the pin again with genpin to upgrade it"
+        );
+    }
+}
+
+/// Accept `user`'s enrolled YubiKey (exits), unless locked or the session
+/// may not ask it (`refusal`). A key that answers wrongly is logged but not
+/// counted: it may simply be someone else's.
+#[cfg(feature = "yubikey")]
+fn try_yubikey(
+    store: &dyn PinStore,
+    user: &str,
+    refusal: Option<&str>,
+    tracker: &mut FailTracker<Box<dyn FailStore>>,
+    on_write_error: WriteErrorPolicy,
+    audit: &dyn Fn(&str),
+    logged_in: &dyn Fn(),
+) {
+    use pin_auth::fail::LockStatus;
+    use pin_auth::yubikey::YubikeyRecord;
+    let record = match YubikeyRecord::load(store, user) {
+        Ok(Some(record)) => record,
+        Ok(None) => return,
+        Err(e) => {
+            error!(error = %e, "yubikey record unavailable");
+            return;
+        }
+    };
+    if let Some(reason) = refusal {
+        info!(reason, "yubikey not asked");
+        return;
+    }
+    if let Ok(LockStatus::Locked { .. }) = tracker.check_locked() {
+        return;
+    }
+    match record.authenticate() {
+        Ok(true) => {
+            if tracker.record_success().is_err() {
+                refuse_unsaved(tracker, on_write_error, audit);
+            }
+            info!(result = "yubikey", "yubikey accepted");
+            audit("yubikey");
+            logged_in();
+            std::process::exit(EXIT_OK);
+        }
+        Ok(false) => warn!("yubikey response did not match"),
+        Err(e) => info!(error = %e, "yubikey unavailable"),
+    }
+}
+
+/// Whether `user` has a FIDO2 credential whose fallback mode passes `when`
+/// and an attached key produced a touched assertion for it.
+#[cfg(feature = "fido2")]
+fn try_fido2(
+    store: &dyn PinStore,
+    user: &str,
+    when: fn(pin_auth::fido2::Fallback) -> bool,
+) -> bool {
+    use pin_auth::fido2::Fido2Record;
+    let record = match Fido2Record::load(store, user) {
+        Ok(Some(record)) if when(record.when) => record,
+        Ok(_) => return false,
+        Err(e) => {
+            error!(error = %e, "fido2 record unavailable");
+            return false;
+        }
+    };
+    info!("waiting for fido2 assertion");
+    match record.authenticate() {
+        Ok(true) => true,
+        Ok(false) => {
+            warn!("fido2 assertion did not verify");
+            false
+        }
+        Err(e) => {
+            info!(error = %e, "fido2 unavailable");
+            false
         }
     }
-    Ok(path.to_string_lossy().into_owned())
 }
 
-fn read_file_nofollow(path: &str) -> io::Result<String> {
-    let mut f = OpenOptions::new()
-        .read(true)
-        .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
-        .open(path)?;
-    let mut buf = String::new();
-    use std::io::Read as _;
-    f.read_to_string(&mut buf)?;
-    Ok(buf)
+/// Remove stale `.fail` files if the daily run is due. Failures are logged
+/// and otherwise ignored: the attempt goes ahead either way.
+fn collect_stale_state(store: &DirStore, config: &ConfigResolver, age: u64) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match gc::due(store, now) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            error!(error = %e, "state gc stamp");
+            return;
+        }
+    }
+    let policy = LockoutPolicy::from_config(config);
+    let record_key = UsernamePolicy::from_config(config).key;
+    // unknown only if NSS says so; lookup errors keep the file
+    let exists = |key: &str| record_key.account_exists(key).unwrap_or(true);
+    match gc::collect(store, &policy, Some(age), now, exists, false) {
+        Ok(removed) => {
+            for (user, stale) in &removed {
+                info!(file_user = %user, reason = stale.reason(), "removed stale fail state");
+            }
+        }
+        Err(e) => error!(error = %e, "state gc"),
+    }
+}
+
+/// Mail a lockout or decoy alert to `notify_mail` (if set) through sendmail.
+/// `default_template` may be overridden by `<pin dir>/<template_file>`.
+fn notify_admin(
+    store: &dyn PinStore,
+    config: &ConfigResolver,
+    template_file: &str,
+    default_template: &str,
+    notice: &LockoutNotice<'_>,
+) {
+    let Some(to) = config.string("notify_mail") else {
+        return;
+    };
+    if !notify::valid_recipient(to) {
+        warn!("notify_mail is not a plain address, notification skipped");
+        return;
+    }
+    // Fixed mailer in release; the command may be swapped only in debug/test builds.
+    let command = if cfg!(debug_assertions) {
+        env::var("PIN_NOTIFY_COMMAND").unwrap_or_else(|_| notify::DEFAULT_COMMAND.to_string())
+    } else {
+        notify::DEFAULT_COMMAND.to_string()
+    };
+    let template = store
+        .read_file(template_file)
+        .unwrap_or_else(|_| default_template.to_string());
+    let message = notify::render(&template, to, notice);
+    if let Err(e) = notify::send(&command, &message) {
+        warn!(error = %e, "notification failed");
+    }
 }
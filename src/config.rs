@@ -0,0 +1,829 @@
+//! Where settings come from: built-in defaults, then the `PIN_*` environment
+//! variables, then `<pin dir>/pin-auth.conf`; a later source wins.
+//!
+//! The file holds `key = value` lines; lines starting with `#` are comments.
+//! Keys are the variable names without `PIN_`, in lower case:
+//!
+//! ```text
+//! # /etc/pin.d/pin-auth.conf
+//! max_fails = 3
+//! lockout_secs = 600
+//! scheme = argon2id
+//! ```
+//!
+//! The environment variables still work but are deprecated: the binaries
+//! warn once per variable that is set. The file wins because it lives in the
+//! validated, root-owned PIN directory, whereas a helper's environment is
+//! partly up to whoever started it.
+//!
+//! Debug/test hooks (`PIN_DIR`, `ALLOW_NON_ROOT`, `GENPIN_NONINTERACTIVE`,
+//! `PIN_NOTIFY_COMMAND`, `PIN_LOG_FILE`) and the `PAM_*` variables are not
+//! configuration and stay environment only.
+
+use crate::store::{resolve_store, PinStore};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+
+/// Name of the config file inside the PIN directory.
+pub const CONFIG_FILE: &str = "pin-auth.conf";
+
+/// A configuration key and the environment variable it replaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    /// Name in `pin-auth.conf`.
+    pub name: &'static str,
+    /// Deprecated environment variable; keys added since the file was
+    /// introduced have none.
+    pub env: Option<&'static str>,
+}
+
+const fn key(name: &'static str, env: &'static str) -> Key {
+    Key {
+        name,
+        env: Some(env),
+    }
+}
+
+const fn file_only(name: &'static str) -> Key {
+    Key { name, env: None }
+}
+
+/// Every key the file may set.
+pub const KEYS: &[Key] = &[
+    key("scheme", "PIN_SCHEME"),
+    key("min_len", "PIN_MIN_LEN"),
+    key("max_len", "PIN_MAX_LEN"),
+    file_only("charset"),
+    file_only("min_entropy_bits"),
+    key("user_max_len", "PIN_USER_MAX_LEN"),
+    key("user_extra_chars", "PIN_USER_EXTRA_CHARS"),
+    key("user_leading_digit", "PIN_USER_LEADING_DIGIT"),
+    key("max_fails", "PIN_MAX_FAILS"),
+    key("fail_window", "PIN_FAIL_WINDOW"),
+    key("lockout_secs", "PIN_LOCKOUT_SECS"),
+    key("argon2_m_cost", "PIN_ARGON2_M_COST"),
+    key("argon2_t_cost", "PIN_ARGON2_T_COST"),
+    key("argon2_p_cost", "PIN_ARGON2_P_COST"),
+    key("argon2_preset", "PIN_ARGON2_PRESET"),
+    key("argon2_target_ms", "PIN_ARGON2_TARGET_MS"),
+    file_only("min_hash"),
+    key("syslog_fail_sample", "PIN_SYSLOG_FAIL_SAMPLE"),
+    key("log", "PIN_LOG"),
+    key("log_level", "PIN_LOG_LEVEL"),
+    key("syslog_format", "PIN_SYSLOG_FORMAT"),
+    key("log_file_max_bytes", "PIN_LOG_FILE_MAX_BYTES"),
+    key("log_file_max_age", "PIN_LOG_FILE_MAX_AGE"),
+    key("log_file_keep", "PIN_LOG_FILE_KEEP"),
+    key("log_pseudonymize", "PIN_LOG_PSEUDONYMIZE"),
+    key("audit", "PIN_AUDIT"),
+    key("history", "PIN_HISTORY"),
+    key("notify_mail", "PIN_NOTIFY_MAIL"),
+    key("fail2ban", "PIN_FAIL2BAN"),
+    file_only("decoy_lock"),
+    file_only("emergency_ttl"),
+    file_only("recovery_codes"),
+    file_only("hotp_window"),
+    file_only("yubikey_require_touch"),
+    file_only("yubikey_services"),
+    file_only("require_group"),
+    file_only("last_login_message"),
+    file_only("state_gc_age"),
+    file_only("metrics_file"),
+    file_only("record_key"),
+    file_only("user_ignore_case"),
+    file_only("account_status"),
+    file_only("require_known_user"),
+    file_only("state_dir"),
+    file_only("on_write_error"),
+    file_only("immutable_hashes"),
+    file_only("manifest_pubkey"),
+    file_only("encryption_key_file"),
+    file_only("require_sealed"),
+    file_only("reject_pins"),
+    file_only("prompt_tries"),
+    file_only("authtok_framing"),
+    file_only("normalize_input"),
+    file_only("locale"),
+];
+
+/// Keys a user's record may set for that user alone: `key=value` lines
+/// in `<user>.passwd`, or `[policy]` in a TOML record (feature
+/// `toml-records`).
+pub const USER_KEYS: &[&str] = &[
+    "min_len",
+    "max_len",
+    "max_fails",
+    "fail_window",
+    "lockout_secs",
+    "decoy_lock",
+];
+
+/// The registry entry for `name`.
+pub fn lookup(name: &str) -> Option<&'static Key> {
+    KEYS.iter().find(|k| k.name == name)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("{path}: {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    /// Line (1-based) is not `key = value`, or repeats a key.
+    #[error("{path}:{line}: {msg}")]
+    Syntax {
+        path: String,
+        line: usize,
+        msg: String,
+    },
+    /// A per-user setting that is not in [`USER_KEYS`] or not valid.
+    #[error("{path}: {name}: {msg}")]
+    User {
+        path: String,
+        name: String,
+        msg: String,
+    },
+}
+
+/// Where a value came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Env(&'static str),
+    File {
+        path: String,
+        line: usize,
+    },
+    /// A user's record (see [`ConfigResolver::with_user_settings`]).
+    User {
+        path: String,
+    },
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Env(var) => f.write_str(var),
+            Source::File { path, line } => write!(f, "{path}:{line}"),
+            Source::User { path } => f.write_str(path),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setting {
+    pub value: String,
+    pub source: Source,
+}
+
+/// Resolved settings; nothing set means the built-in default applies.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigResolver {
+    settings: HashMap<&'static str, Setting>,
+    /// Registered variables found in the environment, with their key.
+    env_vars: Vec<(&'static str, &'static Key)>,
+    /// Keys in the file that are not in [`KEYS`], with their source.
+    unknown: Vec<(String, Source)>,
+    /// Registered variables ignored because the process runs setuid or
+    /// setgid (see [`ConfigResolver::from_env`]).
+    untrusted_env: Vec<&'static str>,
+}
+
+/// Variables already warned about in this process.
+static WARNED: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+
+impl ConfigResolver {
+    /// Defaults and the environment. In a setuid or setgid run (real and
+    /// effective ids differ) the caller chose the environment, so none of
+    /// it is used: lockout, audit and logging come from the file alone.
+    pub fn from_env() -> Self {
+        use nix::unistd::{getegid, geteuid, getgid, getuid};
+        Self::from_caller_vars(
+            std::env::vars_os()
+                .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?))),
+            getuid() != geteuid() || getgid() != getegid(),
+        )
+    }
+
+    /// Defaults and the given `(variable, value)` pairs in place of the
+    /// environment.
+    pub fn from_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        Self::from_caller_vars(vars, false)
+    }
+
+    /// [`ConfigResolver::from_vars`], ignoring every variable if `setid`.
+    fn from_caller_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>, setid: bool) -> Self
+    where
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut config = ConfigResolver::default();
+        for (var, value) in vars {
+            let Some((env, key)) = KEYS
+                .iter()
+                .find_map(|k| Some((k.env?, k)).filter(|(env, _)| *env == var.as_ref()))
+            else {
+                continue;
+            };
+            if setid {
+                config.untrusted_env.push(env);
+                continue;
+            }
+            config.env_vars.push((env, key));
+            config.settings.insert(
+                key.name,
+                Setting {
+                    value: value.into(),
+                    source: Source::Env(env),
+                },
+            );
+        }
+        config
+    }
+
+    /// The environment plus `<dir>/pin-auth.conf` (or the built-in
+    /// [`crate::paths::CONFIG`]), as the binaries use it. The file is read
+    /// only from a directory that passes [`resolve_store`]; if its directory
+    /// does not (or does not exist yet) only the environment applies, and
+    /// the caller's own `resolve_store` reports why.
+    pub fn load_dir(dir: &str) -> Result<Self, ConfigError> {
+        let config = Self::from_env();
+        let file = crate::paths::config_file(dir);
+        let (Some(parent), Some(name)) = (
+            file.parent().and_then(|p| p.to_str()),
+            file.file_name().and_then(|n| n.to_str()),
+        ) else {
+            return Ok(config);
+        };
+        let Ok(store) = resolve_store(parent) else {
+            return Ok(config);
+        };
+        let path = file.display().to_string();
+        match store.read_file(name) {
+            Ok(text) => config.with_file(&text, &path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(config),
+            Err(source) => Err(ConfigError::Read { path, source }),
+        }
+    }
+
+    /// Overlay the contents of a config file; `path` is used in messages.
+    pub fn with_file(mut self, text: &str, path: &str) -> Result<Self, ConfigError> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let syntax = |msg: String| ConfigError::Syntax {
+                path: path.to_string(),
+                line: line_no,
+                msg,
+            };
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(syntax("expected key = value".to_string()));
+            };
+            let (name, value) = (name.trim(), value.trim());
+            if name.is_empty() {
+                return Err(syntax("missing key".to_string()));
+            }
+            if let Some(first) = seen.insert(name.to_string(), line_no) {
+                return Err(syntax(format!("{name} already set on line {first}")));
+            }
+            let source = Source::File {
+                path: path.to_string(),
+                line: line_no,
+            };
+            match lookup(name) {
+                Some(key) => {
+                    let value = value.to_string();
+                    self.settings.insert(key.name, Setting { value, source });
+                }
+                None => self.unknown.push((name.to_string(), source)),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Overlay settings for one user, from their record at `path`. Only
+    /// [`USER_KEYS`] may be set, each checked as in the file.
+    pub fn with_user_settings(
+        mut self,
+        settings: impl IntoIterator<Item = (String, String)>,
+        path: &str,
+    ) -> Result<Self, ConfigError> {
+        for (name, value) in settings {
+            let error = |msg: String| ConfigError::User {
+                path: path.to_string(),
+                name: name.clone(),
+                msg,
+            };
+            let Some(key) = lookup(&name).filter(|key| USER_KEYS.contains(&key.name)) else {
+                return Err(error("not a per-user setting".to_string()));
+            };
+            check_value(key.name, &value).map_err(error)?;
+            let source = Source::User {
+                path: path.to_string(),
+            };
+            self.settings.insert(key.name, Setting { value, source });
+        }
+        Ok(self)
+    }
+
+    /// The value of `name` and where it came from, if set anywhere.
+    pub fn get(&self, name: &str) -> Option<&Setting> {
+        debug_assert!(lookup(name).is_some(), "unregistered config key {name}");
+        self.settings.get(name)
+    }
+
+    pub fn string(&self, name: &str) -> Option<&str> {
+        self.get(name).map(|s| s.value.as_str())
+    }
+
+    /// `name` parsed as `T`. A value that does not parse is logged and
+    /// treated as unset.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let setting = self.get(name)?;
+        setting
+            .value
+            .parse()
+            .map_err(|e| {
+                tracing::warn!(source = %setting.source, error = %e, "ignoring invalid {name}");
+            })
+            .ok()
+    }
+
+    /// A yes/no setting: `1`/`true`/`yes`/`on` or `0`/`false`/`no`/`off`.
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        self.parse::<Flag>(name).map(|f| f.0)
+    }
+
+    /// A duration in seconds, as accepted by [`crate::parse_duration`].
+    pub fn duration(&self, name: &str) -> Option<u64> {
+        self.parse::<Seconds>(name).map(|d| d.0)
+    }
+
+    /// Keys in the file that this build does not know.
+    pub fn unknown_keys(&self) -> &[(String, Source)] {
+        &self.unknown
+    }
+
+    /// Deprecated environment variables that are set, with their key.
+    pub fn deprecated(&self) -> impl Iterator<Item = (&'static str, &'static Key)> + '_ {
+        self.env_vars.iter().copied()
+    }
+
+    /// Log what deserves a warning: unknown keys, and each deprecated
+    /// variable in use (once per process, however often this is called).
+    pub fn report(&self) {
+        for (name, source) in &self.unknown {
+            tracing::warn!(%source, "ignoring unknown config key {name:?}");
+        }
+        let mut warned = WARNED.lock().unwrap_or_else(PoisonError::into_inner);
+        let warned = warned.get_or_insert_with(HashSet::new);
+        for &env in &self.untrusted_env {
+            if warned.insert(env) {
+                tracing::warn!("{env} is ignored in a setuid run; set it in {CONFIG_FILE}");
+            }
+        }
+        for &(env, key) in &self.env_vars {
+            if !warned.insert(env) {
+                continue;
+            }
+            match self.settings.get(key.name).map(|s| &s.source) {
+                Some(Source::Env(_)) => tracing::warn!(
+                    "{} is deprecated; set {} in {CONFIG_FILE} instead",
+                    env,
+                    key.name
+                ),
+                Some(source) => tracing::warn!(
+                    %source,
+                    "{} is deprecated and ignored: {} is set in {CONFIG_FILE}",
+                    env,
+                    key.name
+                ),
+                None => {}
+            }
+        }
+    }
+}
+
+/// A setting that is invalid, unknown, or cancelled by another one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// Where the offending value was set; `None` when nothing was.
+    pub source: Option<Source>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{source}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl ConfigResolver {
+    /// Everything wrong with the resolved settings, for `pinctl
+    /// check-config`: unknown keys, values of the wrong type or out of
+    /// range, and combinations where one setting silently cancels another.
+    /// At run time these values are ignored (with a warning) instead.
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems: Vec<Problem> = self
+            .unknown
+            .iter()
+            .map(|(name, source)| Problem {
+                source: Some(source.clone()),
+                message: format!("unknown key {name:?}"),
+            })
+            .collect();
+        for key in KEYS {
+            if let Some(setting) = self.settings.get(key.name) {
+                if let Err(msg) = check_value(key.name, &setting.value) {
+                    problems.push(Problem {
+                        source: Some(setting.source.clone()),
+                        message: format!("{}: {msg}", key.name),
+                    });
+                }
+            }
+        }
+        self.check_combinations(&mut problems);
+        problems
+    }
+
+    fn check_combinations(&self, problems: &mut Vec<Problem>) {
+        let mut report = |name: &str, message: String| {
+            problems.push(Problem {
+                source: self.get(name).map(|s| s.source.clone()),
+                message,
+            });
+        };
+        if let Err(e) = crate::policy::PolicyValidator::from_config(self) {
+            report("max_len", e.to_string());
+        }
+
+        let scheme = crate::scheme_from_config(self);
+        if !scheme.is_available() {
+            report(
+                "scheme",
+                format!("{scheme:?} is not compiled into this build"),
+            );
+        }
+        let costs = ["argon2_m_cost", "argon2_t_cost", "argon2_p_cost"];
+        let set: Vec<&str> = costs
+            .into_iter()
+            .filter(|name| self.get(name).is_some())
+            .collect();
+        if !set.is_empty() && set.len() < costs.len() {
+            report(
+                set[0],
+                "argon2 costs are ignored unless argon2_m_cost, argon2_t_cost and \
+                 argon2_p_cost are all set"
+                    .to_string(),
+            );
+        }
+        let explicit = crate::HashParams::argon2_from_config(self);
+        if let Some(c) = explicit.filter(|c| c.m_cost < 8 * c.p_cost) {
+            report(
+                "argon2_m_cost",
+                format!("argon2 needs at least 8 KiB per lane ({})", 8 * c.p_cost),
+            );
+        }
+        if set.len() == costs.len() && self.get("argon2_preset").is_some() {
+            report(
+                "argon2_preset",
+                "argon2_preset is ignored: explicit argon2 costs are set".to_string(),
+            );
+        }
+        if explicit.is_some() && self.get("argon2_target_ms").is_some() {
+            report(
+                "argon2_target_ms",
+                "argon2_target_ms is ignored: argon2 costs or a preset are set".to_string(),
+            );
+        }
+        if scheme != crate::Scheme::Argon2id {
+            if let Some(key) = KEYS
+                .iter()
+                .find(|k| k.name.starts_with("argon2_") && self.get(k.name).is_some())
+            {
+                report(
+                    key.name,
+                    format!("{} has no effect: scheme is {scheme:?}", key.name),
+                );
+            }
+        }
+
+        if self.flag("history") == Some(true) && !cfg!(feature = "sqlite") {
+            report("history", "history needs the `sqlite` feature".to_string());
+        }
+        if self.get("hotp_window").is_some() && !cfg!(feature = "hotp") {
+            report(
+                "hotp_window",
+                "hotp_window needs the `hotp` feature".to_string(),
+            );
+        }
+        let targets = crate::logging::parse_targets(self.string("log").unwrap_or_default());
+        for (target, built) in [
+            (crate::logging::LogTarget::Syslog, cfg!(feature = "syslog")),
+            (
+                crate::logging::LogTarget::Journald,
+                cfg!(feature = "journald"),
+            ),
+        ] {
+            if targets.contains(&target) && !built {
+                report("log", format!("{target:?} logging is not compiled in"));
+            }
+        }
+    }
+}
+
+/// Type and range check of one value; the message omits the key.
+fn check_value(name: &str, value: &str) -> Result<(), String> {
+    fn int(value: &str, min: u64, max: u64) -> Result<(), String> {
+        let n: u64 = value
+            .parse()
+            .map_err(|_| format!("{value:?} is not a whole number"))?;
+        if n < min || n > max {
+            return Err(format!("{n} is outside {min}..={max}"));
+        }
+        Ok(())
+    }
+    let u32_max = u64::from(u32::MAX);
+    match name {
+        "scheme" => match value.to_lowercase().as_str() {
+            "argon2" | "argon2id" | "sha-crypt" | "sha512" | "sha512crypt" | "sha512-crypt" => {
+                Ok(())
+            }
+            _ => Err(format!("unknown scheme {value:?}")),
+        },
+        "min_len" => int(value, 1, crate::policy::MAX_MIN_LEN as u64),
+        "max_len" | "user_max_len" => int(value, 1, u64::MAX),
+        "user_extra_chars" => match value.chars().find(|&c| c == '/' || !c.is_ascii_graphic()) {
+            Some(c) => Err(format!("{c:?} is never allowed in usernames")),
+            None => Ok(()),
+        },
+        "user_leading_digit"
+        | "log_pseudonymize"
+        | "audit"
+        | "history"
+        | "fail2ban"
+        | "decoy_lock"
+        | "last_login_message"
+        | "user_ignore_case"
+        | "account_status"
+        | "require_known_user"
+        | "immutable_hashes"
+        | "normalize_input"
+        | "require_sealed"
+        | "yubikey_require_touch" => value.parse::<Flag>().map(drop),
+        "max_fails" | "argon2_m_cost" | "argon2_t_cost" | "argon2_p_cost" => int(value, 1, u32_max),
+        "syslog_fail_sample" | "log_file_keep" => int(value, 0, u32_max),
+        "hotp_window" => int(value, 0, 100),
+        "min_entropy_bits" => int(value, 0, 256),
+        "prompt_tries" => int(value, 1, 100),
+        "recovery_codes" => int(value, 0, crate::auth::RecoveryCodes::MAX_COUNT as u64),
+        "fail_window" | "lockout_secs" | "argon2_target_ms" | "log_file_max_bytes" => {
+            int(value, 0, u64::MAX)
+        }
+        "log_file_max_age" | "emergency_ttl" | "state_gc_age" => value.parse::<Seconds>().map(drop),
+        "argon2_preset" => value
+            .parse::<crate::Argon2Preset>()
+            .map(drop)
+            .map_err(|e| e.to_string()),
+        "min_hash" => value
+            .parse::<crate::HashFloor>()
+            .map(drop)
+            .map_err(|e| e.to_string()),
+        "log" => match value
+            .split(',')
+            .map(|t| t.trim().to_lowercase())
+            .find(|t| !["stderr", "syslog", "journald", "file", "none"].contains(&t.as_str()))
+        {
+            Some(t) => Err(format!("unknown log target {t:?}")),
+            None => Ok(()),
+        },
+        "log_level" => value
+            .parse::<tracing_subscriber::filter::LevelFilter>()
+            .map(drop)
+            .map_err(|_| format!("unknown level {value:?}")),
+        "syslog_format" => match value.to_lowercase().as_str() {
+            "rfc5424" | "rfc3164" => Ok(()),
+            _ => Err(format!("unknown format {value:?}")),
+        },
+        "require_group" => match value.chars().find(|&c| c == '/' || !c.is_ascii_graphic()) {
+            Some(c) => Err(format!("{c:?} is not allowed in a group name")),
+            None if value.is_empty() => Err("empty group name".to_string()),
+            None => Ok(()),
+        },
+        "yubikey_services" => match value
+            .split(',')
+            .map(str::trim)
+            .find(|s| s.is_empty() || s.chars().any(|c| !c.is_ascii_graphic()))
+        {
+            Some(s) => Err(format!("{s:?} is not a PAM service name")),
+            None => Ok(()),
+        },
+        "record_key" => value.parse::<crate::policy::RecordKey>().map(drop),
+        "charset" => value.parse::<crate::policy::Charset>().map(drop),
+        "reject_pins" => value.parse::<crate::policy::DerivedPins>().map(drop),
+        "authtok_framing" => value.parse::<crate::pam::AuthtokFraming>().map(drop),
+        "on_write_error" => value.parse::<crate::fail::WriteErrorPolicy>().map(drop),
+        "metrics_file" | "state_dir" | "encryption_key_file" if !value.starts_with('/') => {
+            Err(format!("{value:?} is not an absolute path"))
+        }
+        "encryption_key_file" if !cfg!(feature = "encryption") => {
+            Err("needs a build with --features encryption".to_string())
+        }
+        "locale" => match value
+            .chars()
+            .find(|&c| !c.is_ascii_alphanumeric() && !"_-.@".contains(c))
+        {
+            Some(c) => Err(format!("{c:?} is not allowed in a locale name")),
+            None => Ok(()),
+        },
+        "manifest_pubkey" if crate::parse_hex(value).is_none_or(|key| key.len() != 32) => {
+            Err(format!("{value:?} is not 64 hex digits"))
+        }
+        "notify_mail" if !crate::notify::valid_recipient(value) => {
+            Err(format!("{value:?} is not a plain address"))
+        }
+        _ => Ok(()),
+    }
+}
+
+struct Flag(bool);
+
+impl FromStr for Flag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Flag(true)),
+            "0" | "false" | "no" | "off" => Ok(Flag(false)),
+            _ => Err(format!("{s:?} is not a yes/no value")),
+        }
+    }
+}
+
+struct Seconds(u64);
+
+impl FromStr for Seconds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::parse_duration(s)
+            .map(Seconds)
+            .ok_or_else(|| format!("{s:?} is not a duration"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_and_sources() {
+        let config = ConfigResolver::from_vars([
+            ("PIN_MAX_FAILS", "7"),
+            ("PIN_LOCKOUT_SECS", "60"),
+            ("PIN_DIR", "/tmp"),
+        ]);
+        assert_eq!(config.parse::<u32>("max_fails"), Some(7));
+        assert_eq!(config.parse::<u32>("fail_window"), None);
+        assert_eq!(
+            config.get("lockout_secs").unwrap().source,
+            Source::Env("PIN_LOCKOUT_SECS")
+        );
+
+        let file = "# lockout\nmax_fails = 3\n\n  audit=yes\nfuture_key = 1\n";
+        let config = config.with_file(file, "pin-auth.conf").unwrap();
+        assert_eq!(config.parse::<u32>("max_fails"), Some(3));
+        assert_eq!(
+            config.get("max_fails").unwrap().source.to_string(),
+            "pin-auth.conf:2"
+        );
+        assert_eq!(config.parse::<u64>("lockout_secs"), Some(60));
+        assert_eq!(config.flag("audit"), Some(true));
+        assert_eq!(config.unknown_keys()[0].0, "future_key");
+        config.report();
+    }
+
+    #[test]
+    fn setuid_runs_ignore_a_hostile_environment() {
+        let hostile = [
+            ("PIN_MAX_FAILS", "0"),
+            ("PIN_LOCKOUT_SECS", "0"),
+            ("PIN_AUDIT", "0"),
+            ("PIN_HISTORY", "0"),
+            ("PIN_NOTIFY_MAIL", "nobody"),
+            ("PIN_LOG", "stderr"),
+            ("PIN_LOG_FILE_MAX_BYTES", "1"),
+            ("PIN_SYSLOG_FAIL_SAMPLE", "1000000"),
+        ];
+        let config = ConfigResolver::from_caller_vars(hostile, true)
+            .with_file("audit = 1\n", "pin-auth.conf")
+            .unwrap();
+        assert_eq!(config.flag("audit"), Some(true));
+        for (env, _) in hostile {
+            let key = KEYS.iter().find(|k| k.env == Some(env)).unwrap();
+            if key.name != "audit" {
+                assert_eq!(config.get(key.name), None, "{env}");
+            }
+        }
+        config.report();
+        // the same environment is honoured when it is the user's own
+        let config = ConfigResolver::from_caller_vars(hostile, false);
+        assert_eq!(config.parse::<u32>("max_fails"), Some(0));
+    }
+
+    #[test]
+    fn values_and_syntax() {
+        let config = ConfigResolver::default()
+            .with_file(
+                "max_fails = lots\nlog_file_max_age = 7d\nfail2ban = off\nuser_extra_chars = _-.\n",
+                "c",
+            )
+            .unwrap();
+        assert_eq!(config.parse::<u32>("max_fails"), None);
+        assert_eq!(config.duration("log_file_max_age"), Some(7 * 86400));
+        assert_eq!(config.flag("fail2ban"), Some(false));
+        assert_eq!(config.string("user_extra_chars"), Some("_-."));
+
+        let err = |text| {
+            ConfigResolver::default()
+                .with_file(text, "c")
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(err("max_fails 3"), "c:1: expected key = value");
+        assert_eq!(err("a = 1\n = 2"), "c:2: missing key");
+        assert_eq!(
+            err("audit = 1\n#\naudit = 0"),
+            "c:3: audit already set on line 1"
+        );
+    }
+
+    #[test]
+    fn check_reports_values_and_conflicts() {
+        let config = ConfigResolver::from_vars([("PIN_LOG", "stderr,sylog")])
+            .with_file(
+                "min_len = 8\nmax_len = 6\nmax_fails = 0\naudit = maybe\n\
+                 argon2_m_cost = 65536\nmax_failz = 3\n",
+                "c",
+            )
+            .unwrap();
+        let problems: Vec<String> = config.check().iter().map(ToString::to_string).collect();
+        for expected in [
+            "c:6: unknown key \"max_failz\"",
+            "c:3: max_fails: 0 is outside 1..=4294967295",
+            "c:4: audit: \"maybe\" is not a yes/no value",
+            "PIN_LOG: log: unknown log target \"sylog\"",
+            "c:2: max_len (6) is less than min_len (8)",
+            "c:5: argon2 costs are ignored unless argon2_m_cost, argon2_t_cost and \
+             argon2_p_cost are all set",
+        ] {
+            assert!(
+                problems.iter().any(|p| p == expected),
+                "{expected}\n{problems:#?}"
+            );
+        }
+
+        let config = ConfigResolver::default()
+            .with_file("scheme = argon2id\nargon2_preset = server\n", "c")
+            .unwrap();
+        let ok = cfg!(feature = "argon2");
+        assert_eq!(config.check().is_empty(), ok, "{:?}", config.check());
+        assert!(ConfigResolver::default()
+            .with_file("argon2_preset = server\n", "c")
+            .unwrap()
+            .check()
+            .iter()
+            .any(|p| p.message.contains("has no effect")));
+    }
+
+    #[test]
+    fn file_in_pin_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let config = ConfigResolver::load_dir(dir).unwrap();
+        assert!(config.unknown_keys().is_empty());
+        std::fs::write(tmp.path().join(CONFIG_FILE), "min_len = 6\n").unwrap();
+        let config = ConfigResolver::load_dir(dir).unwrap();
+        assert_eq!(config.parse::<usize>("min_len"), Some(6));
+        assert!(ConfigResolver::load_dir(&format!("{dir}/missing")).is_ok());
+    }
+}
@@ -0,0 +1,157 @@
+//! `check_pin` exit statuses, for wrappers, tests and PAM configuration
+//! (e.g. `pam_exec` success/ignore mappings).
+
+use crate::auth::Outcome;
+
+/// PIN accepted.
+pub const EXIT_OK: i32 = 0;
+/// Wrong PIN, or no PIN record for the user.
+pub const EXIT_MISMATCH: i32 = 1;
+/// Locked out (already, or by this failure).
+pub const EXIT_LOCKED: i32 = 2;
+/// Empty or malformed input (length/charset).
+pub const EXIT_INPUT: i32 = 3;
+/// Configuration or environment error (not root, bad policy, bad directory,
+/// corrupt PIN record, fail state locked by another process for too long,
+/// or not written with `on_write_error = closed`).
+pub const EXIT_CONFIG: i32 = 4;
+/// Refused by access policy before any PIN was checked (`require_group`,
+/// `access-hours`, `account_status`, a hash below `min_hash`).
+pub const EXIT_DENIED: i32 = 5;
+
+/// Every exit status with a one-line meaning, for the man page.
+pub const EXIT_CODES: &[(i32, &str)] = &[
+    (EXIT_OK, "PIN accepted"),
+    (EXIT_MISMATCH, "wrong PIN, or no PIN record for the user"),
+    (EXIT_LOCKED, "locked out, already or by this failure"),
+    (EXIT_INPUT, "empty or malformed input"),
+    (
+        EXIT_CONFIG,
+        "configuration, directory or record error; fail state not usable",
+    ),
+    (
+        EXIT_DENIED,
+        "refused by access policy before any PIN was checked",
+    ),
+];
+
+/// Why `check_pin` exited the way it did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CheckResult {
+    Success,
+    Mismatch,
+    /// No PIN enrolled; indistinguishable from `Mismatch` by exit status.
+    NoRecord,
+    /// NSS does not know the user (`require_known_user`, `record_key =
+    /// uid`); also indistinguishable from `Mismatch`.
+    UnknownUser,
+    /// `PAM_USER` fails the username policy.
+    InvalidUser,
+    Locked,
+    BadInput,
+    Config,
+    /// Another process held the fail state past `lock::LOCK_TIMEOUT`.
+    LockTimeout,
+    /// The fail state could not be read or written, and `on_write_error =
+    /// closed`.
+    StateError,
+    /// Not a member of `require_group`.
+    NotInGroup,
+    /// Outside the user's or service's `access-hours`.
+    OutsideHours,
+    /// Locked, expired or nologin system account (`account_status`).
+    AccountDisabled,
+    /// The PIN record has content that is neither the hash nor a
+    /// `key=value` line ([`crate::record::RecordError`]).
+    CorruptRecord,
+    /// The stored hash is below `min_hash`; the PIN must be set again.
+    WeakHash,
+}
+
+impl CheckResult {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            CheckResult::Success => EXIT_OK,
+            CheckResult::Mismatch | CheckResult::NoRecord | CheckResult::UnknownUser => {
+                EXIT_MISMATCH
+            }
+            CheckResult::Locked => EXIT_LOCKED,
+            CheckResult::BadInput => EXIT_INPUT,
+            CheckResult::Config
+            | CheckResult::InvalidUser
+            | CheckResult::LockTimeout
+            | CheckResult::StateError
+            | CheckResult::CorruptRecord => EXIT_CONFIG,
+            CheckResult::NotInGroup
+            | CheckResult::OutsideHours
+            | CheckResult::AccountDisabled
+            | CheckResult::WeakHash => EXIT_DENIED,
+        }
+    }
+
+    /// Short machine-readable reason.
+    pub fn reason(self) -> &'static str {
+        match self {
+            CheckResult::Success => "success",
+            CheckResult::Mismatch => "mismatch",
+            CheckResult::NoRecord => "no_record",
+            CheckResult::UnknownUser => "unknown_user",
+            CheckResult::InvalidUser => "invalid_user",
+            CheckResult::Locked => "locked",
+            CheckResult::BadInput => "bad_input",
+            CheckResult::Config => "config",
+            CheckResult::LockTimeout => "lock_timeout",
+            CheckResult::StateError => "state_error",
+            CheckResult::NotInGroup => "not_in_group",
+            CheckResult::OutsideHours => "outside_hours",
+            CheckResult::AccountDisabled => "account_disabled",
+            CheckResult::CorruptRecord => "corrupt_record",
+            CheckResult::WeakHash => "weak_hash",
+        }
+    }
+}
+
+impl From<Outcome> for CheckResult {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            // Only once redeemed; `check_pin` refuses if that fails.
+            Outcome::Success | Outcome::Emergency | Outcome::Recovery { .. } => {
+                CheckResult::Success
+            }
+            #[cfg(feature = "hotp")]
+            Outcome::Hotp { .. } => CheckResult::Success,
+            Outcome::BadInput => CheckResult::BadInput,
+            // A decoy looks like any other wrong PIN to the caller.
+            Outcome::Locked { .. }
+            | Outcome::Failure { locked: true, .. }
+            | Outcome::Decoy { locked: true, .. } => CheckResult::Locked,
+            Outcome::Failure { locked: false, .. } | Outcome::Decoy { locked: false, .. } => {
+                CheckResult::Mismatch
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcome_exit_codes() {
+        let failure = |locked| Outcome::Failure { count: 1, locked };
+        assert_eq!(CheckResult::from(Outcome::Success).exit_code(), 0);
+        assert_eq!(CheckResult::from(failure(false)).exit_code(), 1);
+        assert_eq!(CheckResult::from(failure(true)).exit_code(), 2);
+        assert_eq!(CheckResult::from(Outcome::BadInput).exit_code(), 3);
+        assert_eq!(CheckResult::NoRecord.exit_code(), EXIT_MISMATCH);
+        assert_eq!(CheckResult::UnknownUser.exit_code(), EXIT_MISMATCH);
+        assert_eq!(CheckResult::Config.reason(), "config");
+        assert_eq!(CheckResult::LockTimeout.exit_code(), EXIT_CONFIG);
+        assert_eq!(CheckResult::StateError.exit_code(), EXIT_CONFIG);
+        assert_eq!(CheckResult::CorruptRecord.exit_code(), EXIT_CONFIG);
+        assert_eq!(CheckResult::NotInGroup.exit_code(), EXIT_DENIED);
+        assert_eq!(CheckResult::AccountDisabled.exit_code(), EXIT_DENIED);
+        assert_eq!(CheckResult::WeakHash.exit_code(), EXIT_DENIED);
+    }
+}
@@ -0,0 +1,304 @@
+//! Per-user records as JSON, for moving them to another host or into
+//! another backend (`pinctl export --json`, `pinctl import`).
+//!
+//! ```json
+//! {"version": 1, "records": [
+//!   {"key": "alice", "files": {"passwd": "$argon2id$...\n", "fail": "lock:1700000000\n"},
+//!    "changed": {"passwd": 1690000000, "fail": 1699999100}}
+//! ]}
+//! ```
+//!
+//! `files` maps each extension in [`RECORD_EXTS`] the key has a file for
+//! to that file's contents, unchanged: hashes, decoys, tokens, the fail
+//! counter or lock and the last login. `changed` has each file's
+//! modification time, which `pinctl sync` compares. Keys are exported as
+//! stored (a name, `@group`, a UID or an escaped name, per `record_key`).
+
+use crate::store::{DirStore, PinStore, RECORD_EXTS, STATE_EXTS};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::UNIX_EPOCH;
+
+const VERSION: u64 = 1;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ImportError {
+    #[error("not a pin-auth export: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported export version {0}")]
+    Version(u64),
+    /// A key that would leave the directory or is not a record key.
+    #[error("invalid record key {0:?}")]
+    BadKey(String),
+    #[error("{0:?} is not a record type")]
+    BadExt(String),
+}
+
+/// The records of one key: extension to file contents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+    pub key: String,
+    pub files: BTreeMap<String, String>,
+    /// Extension to modification time (Unix seconds), where known.
+    pub changed: BTreeMap<String, u64>,
+}
+
+impl Record {
+    /// The file names and contents to write.
+    pub fn file_names(&self) -> impl Iterator<Item = (String, &str)> + '_ {
+        self.files
+            .iter()
+            .map(|(ext, contents)| (format!("{}.{ext}", self.key), contents.as_str()))
+    }
+}
+
+/// Every key with a record in `store`, or only those in `only` if it is
+/// not empty.
+pub fn collect(store: &DirStore, only: &[String]) -> io::Result<Vec<Record>> {
+    let mut records: BTreeMap<String, Record> = BTreeMap::new();
+    for ext in RECORD_EXTS {
+        for key in store.names(ext)? {
+            if !only.is_empty() && !only.contains(&key) {
+                continue;
+            }
+            let name = format!("{key}.{ext}");
+            let path = store.path(&name);
+            // decrypted if sealed at rest, so another host can seal it anew
+            let contents = match store.read_file(&name) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let changed = path
+                .symlink_metadata()?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let record = records.entry(key.clone()).or_default();
+            record.key = key;
+            record.files.insert(ext.to_string(), contents);
+            record.changed.insert(ext.to_string(), changed);
+        }
+    }
+    Ok(records.into_values().collect())
+}
+
+pub fn to_json(records: &[Record]) -> Value {
+    let records: Vec<Value> = records
+        .iter()
+        .map(|r| {
+            let files: Map<String, Value> = r
+                .files
+                .iter()
+                .map(|(ext, contents)| (ext.clone(), Value::from(contents.as_str())))
+                .collect();
+            json!({ "key": r.key, "files": files, "changed": r.changed })
+        })
+        .collect();
+    json!({ "version": VERSION, "records": records })
+}
+
+pub fn from_json(value: &Value) -> Result<Vec<Record>, ImportError> {
+    let version = value["version"]
+        .as_u64()
+        .ok_or(ImportError::Malformed("no version"))?;
+    if version != VERSION {
+        return Err(ImportError::Version(version));
+    }
+    let mut records = Vec::new();
+    for r in value["records"]
+        .as_array()
+        .ok_or(ImportError::Malformed("no records"))?
+    {
+        let key = r["key"]
+            .as_str()
+            .ok_or(ImportError::Malformed("record without key"))?;
+        if !valid_key(key) {
+            return Err(ImportError::BadKey(key.to_string()));
+        }
+        let mut files = BTreeMap::new();
+        for (ext, contents) in r["files"]
+            .as_object()
+            .ok_or(ImportError::Malformed("record without files"))?
+        {
+            if !RECORD_EXTS.contains(&ext.as_str()) {
+                return Err(ImportError::BadExt(ext.clone()));
+            }
+            let contents = contents
+                .as_str()
+                .ok_or(ImportError::Malformed("file contents must be a string"))?;
+            files.insert(ext.clone(), contents.to_string());
+        }
+        // optional: exports made by hand need not carry times
+        let changed = r["changed"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(ext, _)| files.contains_key(*ext))
+            .filter_map(|(ext, ts)| Some((ext.clone(), ts.as_u64()?)))
+            .collect();
+        records.push(Record {
+            key: key.to_string(),
+            files,
+            changed,
+        });
+    }
+    Ok(records)
+}
+
+/// Only the files `pinctl sync` shares between hosts: those an
+/// administrator sets, not the per-host state `check_pin` keeps
+/// ([`STATE_EXTS`]).
+pub fn shared_only(records: Vec<Record>) -> Vec<Record> {
+    records
+        .into_iter()
+        .filter_map(|mut r| {
+            r.files.retain(|ext, _| !STATE_EXTS.contains(&ext.as_str()));
+            r.changed.retain(|ext, _| r.files.contains_key(ext));
+            (!r.files.is_empty()).then_some(r)
+        })
+        .collect()
+}
+
+/// The files of `incoming` that should replace those in `current`: ones
+/// `current` lacks and ones changed later. Files with the same time but
+/// other contents are conflicts, returned by name and left alone.
+pub fn newer(current: &[Record], incoming: &[Record]) -> (Vec<Record>, Vec<String>) {
+    let mut updates = Vec::new();
+    let mut conflicts = Vec::new();
+    for r in incoming {
+        let mine = current.iter().find(|c| c.key == r.key);
+        let mut update = Record {
+            key: r.key.clone(),
+            ..Record::default()
+        };
+        for (ext, contents) in &r.files {
+            let theirs = r.changed.get(ext).copied().unwrap_or(0);
+            let take = match mine.and_then(|m| Some((m.files.get(ext)?, m.changed.get(ext)))) {
+                None => true,
+                Some((same, _)) if same == contents => false,
+                Some((_, ours)) => {
+                    let ours = ours.copied().unwrap_or(0);
+                    if theirs == ours {
+                        conflicts.push(format!("{}.{ext}", r.key));
+                    }
+                    theirs > ours
+                }
+            };
+            if take {
+                update.files.insert(ext.clone(), contents.clone());
+                update.changed.insert(ext.clone(), theirs);
+            }
+        }
+        if !update.files.is_empty() {
+            updates.push(update);
+        }
+    }
+    (updates, conflicts)
+}
+
+/// Apply `updates` (from [`newer`]) to `records`.
+pub fn merge(records: &mut Vec<Record>, updates: Vec<Record>) {
+    for update in updates {
+        match records.iter_mut().find(|r| r.key == update.key) {
+            Some(r) => {
+                r.files.extend(update.files);
+                r.changed.extend(update.changed);
+            }
+            None => records.push(update),
+        }
+    }
+    records.sort_by(|a, b| a.key.cmp(&b.key));
+}
+
+/// A plain file stem, as [`crate::policy::UsernamePolicy`] allows them:
+/// printable ASCII without `/`, not starting with `.`.
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('.')
+        && key.chars().all(|c| c.is_ascii_graphic() && c != '/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn export_then_import() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = DirStore::new(tmp.path());
+        fs::write(tmp.path().join("alice.passwd"), "$6$x$y\n").unwrap();
+        fs::write(tmp.path().join("alice.fail"), "lock:1700000000\n").unwrap();
+        fs::write(tmp.path().join("@ops.passwd"), "$6$x$z\n").unwrap();
+        fs::write(tmp.path().join("bob.last"), "1 - - -\n").unwrap();
+        fs::write(tmp.path().join("pin-blacklist"), "1234\n").unwrap();
+
+        let records = collect(&store, &[]).unwrap();
+        let keys: Vec<_> = records.iter().map(|r| r.key.as_str()).collect();
+        assert_eq!(keys, ["@ops", "alice", "bob"]);
+        let json = to_json(&records);
+        assert_eq!(json["records"][1]["files"]["fail"], "lock:1700000000\n");
+        assert_eq!(from_json(&json).unwrap(), records);
+        let names: Vec<_> = records[1].file_names().map(|(name, _)| name).collect();
+        assert_eq!(names, ["alice.fail", "alice.passwd"]);
+
+        let only = collect(&store, &["bob".to_string()]).unwrap();
+        assert_eq!(only.len(), 1);
+
+        let bad = |key: &str, ext: &str| {
+            from_json(&json!({"version": 1, "records": [{"key": key, "files": {ext: ""}}]}))
+        };
+        assert!(matches!(bad("../x", "passwd"), Err(ImportError::BadKey(_))));
+        assert!(matches!(bad("..", "passwd"), Err(ImportError::BadKey(_))));
+        assert!(matches!(bad("alice", "conf"), Err(ImportError::BadExt(_))));
+        assert!(matches!(bad(".x", "passwd"), Err(ImportError::BadKey(_))));
+        assert!(bad("john.doe", "passwd").is_ok());
+        assert!(matches!(
+            from_json(&json!({"version": 2, "records": []})),
+            Err(ImportError::Version(2))
+        ));
+    }
+
+    #[test]
+    fn newer_files_win() {
+        let record = |key: &str, files: &[(&str, &str, u64)]| Record {
+            key: key.to_string(),
+            files: files
+                .iter()
+                .map(|(ext, c, _)| (ext.to_string(), c.to_string()))
+                .collect(),
+            changed: files
+                .iter()
+                .map(|(ext, _, t)| (ext.to_string(), *t))
+                .collect(),
+        };
+        let mut here = vec![
+            record("alice", &[("passwd", "old", 100), ("fail", "1:5", 300)]),
+            record("bob", &[("passwd", "mine", 200)]),
+        ];
+        let there = shared_only(vec![
+            record("alice", &[("passwd", "new", 150), ("fail", "3:9", 400)]),
+            record("bob", &[("passwd", "theirs", 200)]),
+            record("carol", &[("passwd", "hers", 50)]),
+            record("dave", &[("last", "1 - - -", 50)]),
+        ]);
+        assert_eq!(there.len(), 3);
+        let (updates, conflicts) = newer(&here, &there);
+        assert_eq!(conflicts, ["bob.passwd"]);
+        assert_eq!(
+            updates,
+            [
+                record("alice", &[("passwd", "new", 150)]),
+                record("carol", &[("passwd", "hers", 50)]),
+            ]
+        );
+        merge(&mut here, updates);
+        assert_eq!(here[0].files["passwd"], "new");
+        assert_eq!(here[0].files["fail"], "1:5");
+        assert_eq!(here[2].key, "carol");
+        assert!(newer(&here, &there).0.is_empty());
+    }
+}
@@ -0,0 +1,667 @@
+//! Per-user fail counter and timed lockout.
+//!
+//! The state machine that used to live inline in `check_pin`: failures are
+//! counted inside a rolling window, reaching `max_fails` locks the user for
+//! `lockout_secs` (or indefinitely when that is 0), and a success clears
+//! everything. Persistence is behind the [`FailStore`] trait and time
+//! behind [`Clock`].
+
+use crate::config::ConfigResolver;
+use crate::lock::{lock_exclusive, LOCK_TIMEOUT};
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Persisted fail state. On disk (see [`FailState::parse`]):
+///  - `""`               nothing recorded
+///  - `count:first_ts`   e.g. `2:1700000000`
+///  - `lock:until_ts`    e.g. `lock:1700000300`
+///  - legacy: a bare number, treated as a count starting now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailState {
+    Clear,
+    Counting { count: u32, first_ts: u64 },
+    Locked { until: u64 },
+}
+
+impl FailState {
+    /// Lenient parse: anything unrecognised reads as `Clear`, matching the
+    /// historical behaviour of `check_pin`.
+    pub fn parse(raw: &str, now: u64) -> FailState {
+        let line = raw.trim();
+        if let Some(rest) = line.strip_prefix("lock:") {
+            return match rest.parse::<u64>() {
+                Ok(until) => FailState::Locked { until },
+                Err(_) => FailState::Clear,
+            };
+        }
+        if let Some((cnt, ts)) = line.split_once(':') {
+            if let (Ok(count), Ok(first_ts)) = (cnt.parse::<u32>(), ts.parse::<u64>()) {
+                return FailState::Counting { count, first_ts };
+            }
+            return FailState::Clear;
+        }
+        match line.parse::<u32>() {
+            Ok(count) => FailState::Counting {
+                count,
+                first_ts: now,
+            },
+            Err(_) => FailState::Clear,
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        match self {
+            FailState::Clear => String::new(),
+            FailState::Counting { count, first_ts } => format!("{count}:{first_ts}\n"),
+            FailState::Locked { until } => format!("lock:{until}\n"),
+        }
+    }
+}
+
+/// Lockout parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Failures before the user is locked.
+    pub max_fails: u32,
+    /// Lock duration; 0 = locked until the fail file is reset.
+    pub lockout_secs: u64,
+    /// Failures older than this no longer count; 0 = never forget.
+    pub fail_window: u64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            max_fails: 5,
+            lockout_secs: 300, // 5 minutes
+            fail_window: 900,  // 15 minutes aggregation window
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// [`LockoutPolicy::from_config`] over the environment alone.
+    pub fn from_env() -> Self {
+        Self::from_config(&ConfigResolver::from_env())
+    }
+
+    /// `max_fails`, `lockout_secs`, `fail_window` over the defaults.
+    pub fn from_config(config: &ConfigResolver) -> Self {
+        let d = LockoutPolicy::default();
+        LockoutPolicy {
+            max_fails: config.parse("max_fails").unwrap_or(d.max_fails),
+            lockout_secs: config.parse("lockout_secs").unwrap_or(d.lockout_secs),
+            fail_window: config.parse("fail_window").unwrap_or(d.fail_window),
+        }
+    }
+}
+
+/// What an attempt does when its fail state cannot be written, e.g. on a
+/// full disk (`on_write_error`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteErrorPolicy {
+    /// Carry on with the state in memory, logging every error. Lockout is
+    /// not enforced across attempts until writes succeed again.
+    #[default]
+    Open,
+    /// Refuse the attempt, whatever its outcome, so no PIN can be tried
+    /// without its failure being counted.
+    Closed,
+}
+
+impl std::str::FromStr for WriteErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "open" => Ok(WriteErrorPolicy::Open),
+            "closed" => Ok(WriteErrorPolicy::Closed),
+            _ => Err(format!("{s:?} is not open or closed")),
+        }
+    }
+}
+
+/// Storage for one user's [`FailState`]. Implementations are expected to
+/// hold whatever lock they need for the lifetime of the value, so a
+/// load/modify/save sequence is atomic with respect to other attempts.
+pub trait FailStore {
+    fn load(&mut self) -> io::Result<String>;
+    fn save(&mut self, serialized: &str) -> io::Result<()>;
+}
+
+impl<S: FailStore + ?Sized> FailStore for Box<S> {
+    fn load(&mut self) -> io::Result<String> {
+        (**self).load()
+    }
+    fn save(&mut self, serialized: &str) -> io::Result<()> {
+        (**self).save(serialized)
+    }
+}
+
+/// The `<user>.fail` file, opened without following symlinks and held under
+/// an exclusive lock (see [`crate::lock`]) until dropped. [`FailFile::open`]
+/// gives up with [`io::ErrorKind::TimedOut`] if another process holds it
+/// for longer than [`LOCK_TIMEOUT`].
+pub struct FailFile {
+    file: File,
+}
+
+impl FailFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+                .open(path)?;
+            lock_exclusive(&file, LOCK_TIMEOUT)?;
+            // Removed (e.g. by garbage collection) while we waited: state
+            // written now would be lost, so start over on the new file.
+            let held = file.metadata()?;
+            match std::fs::symlink_metadata(path) {
+                Ok(current) if current.ino() == held.ino() && current.dev() == held.dev() => {
+                    return Ok(FailFile { file })
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Delete the file while holding its lock, so a waiting attempt notices
+    /// and reopens a fresh one.
+    pub fn remove(self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+}
+
+impl FailStore for FailFile {
+    fn load(&mut self) -> io::Result<String> {
+        let mut raw = String::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_string(&mut raw)?;
+        Ok(raw)
+    }
+
+    fn save(&mut self, serialized: &str) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(serialized.as_bytes())
+    }
+}
+
+/// Non-persistent store: state lives only as long as the value.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFailStore {
+    pub contents: String,
+}
+
+impl FailStore for MemoryFailStore {
+    fn load(&mut self) -> io::Result<String> {
+        Ok(self.contents.clone())
+    }
+    fn save(&mut self, serialized: &str) -> io::Result<()> {
+        self.contents = serialized.to_string();
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockStatus {
+    Open,
+    /// `until` is `None` for an indefinite lock (`lockout_secs == 0`).
+    Locked {
+        until: Option<u64>,
+    },
+}
+
+/// Result of [`FailTracker::record_failure`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FailureOutcome {
+    /// Failures in the current window, including this one.
+    pub count: u32,
+    /// This failure reached the threshold (the user is now locked).
+    pub locked: bool,
+}
+
+/// Source of the current time, in seconds since the Unix epoch.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+/// Wall-clock time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// A clock that only moves when told to, for tests. Pass it by reference
+/// (`&clock`) to keep advancing it while a tracker holds it.
+#[derive(Debug, Default)]
+pub struct ManualClock(Cell<u64>);
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        ManualClock(Cell::new(now))
+    }
+
+    pub fn set(&self, now: u64) {
+        self.0.set(now);
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.0.set(self.0.get().saturating_add(secs));
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> u64 {
+        (**self).now()
+    }
+}
+
+pub struct FailTracker<S: FailStore, C: Clock = SystemClock> {
+    store: S,
+    policy: LockoutPolicy,
+    clock: C,
+    count: u32,
+    first_ts: u64,
+    /// A `lock:` record that had not expired when last checked.
+    locked_until: Option<u64>,
+    /// The first save that failed.
+    write_error: Option<io::Error>,
+    /// See [`FailTracker::count_first`].
+    count_first: bool,
+    /// `count_first` has written a failure this attempt has not made yet.
+    pending: bool,
+}
+
+impl<S: FailStore> FailTracker<S> {
+    /// Load the current state from `store`, using wall-clock time.
+    pub fn new(store: S, policy: LockoutPolicy) -> io::Result<Self> {
+        FailTracker::with_clock(store, policy, SystemClock)
+    }
+}
+
+impl<S: FailStore, C: Clock> FailTracker<S, C> {
+    /// Load the current state from `store`, taking time from `clock`.
+    pub fn with_clock(mut store: S, policy: LockoutPolicy, clock: C) -> io::Result<Self> {
+        let now = clock.now();
+        let state = FailState::parse(&store.load()?, now);
+        let mut tracker = FailTracker {
+            store,
+            policy,
+            clock,
+            count: 0,
+            first_ts: now,
+            locked_until: None,
+            write_error: None,
+            count_first: false,
+            pending: false,
+        };
+        match state {
+            FailState::Clear => {}
+            FailState::Counting { count, first_ts } => {
+                tracker.count = count;
+                tracker.first_ts = first_ts;
+            }
+            // An expired lock starts over from zero.
+            FailState::Locked { until } if now < until => tracker.locked_until = Some(until),
+            FailState::Locked { .. } => {}
+        }
+        Ok(tracker)
+    }
+
+    /// With [`WriteErrorPolicy::Closed`]: an open [`FailTracker::check_locked`]
+    /// first writes the failure the attempt may turn out to be, and reports
+    /// the user locked if that fails. Nothing is then verified without its
+    /// failure on disk. Success and failure overwrite it as usual;
+    /// [`FailTracker::cancel_pending`] takes it back for attempts that
+    /// count neither way.
+    pub fn count_first(mut self, on: bool) -> Self {
+        self.count_first = on;
+        self
+    }
+
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    /// Current failure count within the window.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Decide whether an attempt may proceed. Expired windows are reset;
+    /// a count already at the threshold (e.g. after lowering `max_fails`)
+    /// turns into a fresh timed lock.
+    pub fn check_locked(&mut self) -> io::Result<LockStatus> {
+        let now = self.clock.now();
+        if let Some(until) = self.locked_until {
+            if now < until {
+                return Ok(LockStatus::Locked { until: Some(until) });
+            }
+            // Expired since it was loaded: start over from zero.
+            self.locked_until = None;
+            self.count = 0;
+            self.first_ts = now;
+        }
+        // Reset window if expired (unless window==0 meaning infinite accumulation)
+        if self.policy.fail_window > 0
+            && now.saturating_sub(self.first_ts) > self.policy.fail_window
+        {
+            self.count = 0;
+            self.first_ts = now;
+        }
+        if self.count >= self.policy.max_fails {
+            if self.policy.lockout_secs > 0 {
+                let until = now.saturating_add(self.policy.lockout_secs);
+                self.save(FailState::Locked { until })?;
+                self.locked_until = Some(until);
+                return Ok(LockStatus::Locked { until: Some(until) });
+            }
+            return Ok(LockStatus::Locked { until: None });
+        }
+        if self.count_first && !self.pending {
+            let (_, state) = self.next_failure();
+            if self.save(state).is_err() {
+                return Ok(LockStatus::Locked { until: None });
+            }
+            self.pending = true;
+        }
+        Ok(LockStatus::Open)
+    }
+
+    pub fn record_failure(&mut self) -> io::Result<FailureOutcome> {
+        let (count, state) = self.next_failure();
+        self.count = count;
+        let locked = count >= self.policy.max_fails;
+        if let FailState::Locked { until } = state {
+            self.locked_until = Some(until);
+        }
+        self.pending = false;
+        self.save(state)?;
+        Ok(FailureOutcome {
+            count: self.count,
+            locked,
+        })
+    }
+
+    /// Undo what [`FailTracker::count_first`] wrote, for an attempt that
+    /// turned out to count neither way (malformed input, say).
+    pub fn cancel_pending(&mut self) -> io::Result<()> {
+        if !std::mem::take(&mut self.pending) {
+            return Ok(());
+        }
+        let state = match self.count {
+            0 => FailState::Clear,
+            count => FailState::Counting {
+                count,
+                first_ts: self.first_ts,
+            },
+        };
+        self.save(state)
+    }
+
+    /// The count and state one more failure leads to.
+    fn next_failure(&self) -> (u32, FailState) {
+        let count = self.count.saturating_add(1);
+        let state = if count >= self.policy.max_fails && self.policy.lockout_secs > 0 {
+            FailState::Locked {
+                until: self.clock.now().saturating_add(self.policy.lockout_secs),
+            }
+        } else {
+            FailState::Counting {
+                count,
+                first_ts: self.first_ts,
+            }
+        };
+        (count, state)
+    }
+
+    /// Record a failure that locks the user regardless of the count so far
+    /// (e.g. a decoy PIN), as if the threshold had just been reached.
+    pub fn lock_now(&mut self) -> io::Result<FailureOutcome> {
+        self.count = self.count.max(self.policy.max_fails.saturating_sub(1));
+        self.record_failure()
+    }
+
+    pub fn record_success(&mut self) -> io::Result<()> {
+        self.count = 0;
+        self.locked_until = None;
+        self.pending = false;
+        self.save(FailState::Clear)
+    }
+
+    /// The first error saving the state through this tracker, if any. Some
+    /// callers carry on past one (see [`WriteErrorPolicy`]); this is where
+    /// it is not lost.
+    pub fn write_error(&self) -> Option<&io::Error> {
+        self.write_error.as_ref()
+    }
+
+    fn save(&mut self, state: FailState) -> io::Result<()> {
+        self.store.save(&state.serialize()).inspect_err(|e| {
+            self.write_error
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        })
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(contents: &str, policy: LockoutPolicy) -> FailTracker<MemoryFailStore> {
+        let store = MemoryFailStore {
+            contents: contents.to_string(),
+        };
+        FailTracker::new(store, policy).unwrap()
+    }
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(FailState::parse("", 7), FailState::Clear);
+        assert_eq!(
+            FailState::parse("2:1700000000\n", 7),
+            FailState::Counting {
+                count: 2,
+                first_ts: 1700000000
+            }
+        );
+        assert_eq!(
+            FailState::parse("lock:99", 7),
+            FailState::Locked { until: 99 }
+        );
+        assert_eq!(
+            FailState::parse("3", 7),
+            FailState::Counting {
+                count: 3,
+                first_ts: 7
+            }
+        );
+        assert_eq!(FailState::parse("lock:x", 7), FailState::Clear);
+        assert_eq!(FailState::parse("garbage", 7), FailState::Clear);
+    }
+
+    #[test]
+    fn threshold_locks_and_success_clears() {
+        let policy = LockoutPolicy {
+            max_fails: 3,
+            lockout_secs: 60,
+            fail_window: 0,
+        };
+        let mut t = tracker("", policy);
+        for n in 1..=2 {
+            assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+            let out = t.record_failure().unwrap();
+            assert_eq!((out.count, out.locked), (n, false));
+        }
+        assert!(t.record_failure().unwrap().locked);
+        assert!(matches!(
+            t.check_locked().unwrap(),
+            LockStatus::Locked { until: Some(_) }
+        ));
+        let mut store = t.into_store();
+        assert!(store.load().unwrap().starts_with("lock:"));
+
+        let mut t = tracker("2:0", policy);
+        t.record_success().unwrap();
+        assert_eq!(t.into_store().contents, "");
+    }
+
+    #[test]
+    fn expired_window_and_lock_reset() {
+        let policy = LockoutPolicy::default();
+        // first failure long ago: window expired, count starts over
+        let mut t = tracker("4:1", policy);
+        assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+        assert_eq!(t.record_failure().unwrap().count, 1);
+        // expired lock
+        let mut t = tracker("lock:1", policy);
+        assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+    }
+
+    #[test]
+    fn window_and_lock_expire_with_clock() {
+        let policy = LockoutPolicy {
+            max_fails: 2,
+            lockout_secs: 300,
+            fail_window: 900,
+        };
+        let clock = ManualClock::new(1_000);
+        let mut t = FailTracker::with_clock(MemoryFailStore::default(), policy, &clock).unwrap();
+        t.record_failure().unwrap();
+        // window runs out: the earlier failure is forgotten
+        clock.advance(901);
+        assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+        assert_eq!(t.record_failure().unwrap().count, 1);
+        clock.advance(10);
+        assert!(t.record_failure().unwrap().locked);
+        assert_eq!(
+            t.check_locked().unwrap(),
+            LockStatus::Locked {
+                until: Some(1_911 + 300)
+            }
+        );
+        clock.advance(299);
+        assert!(matches!(
+            t.check_locked().unwrap(),
+            LockStatus::Locked { .. }
+        ));
+        clock.advance(1);
+        assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+        assert_eq!(t.record_failure().unwrap().count, 1);
+
+        // a lock loaded from the store expires the same way
+        let store = MemoryFailStore {
+            contents: "lock:2000\n".into(),
+        };
+        clock.set(1_999);
+        let mut t = FailTracker::with_clock(store, policy, &clock).unwrap();
+        assert!(matches!(
+            t.check_locked().unwrap(),
+            LockStatus::Locked { .. }
+        ));
+        clock.set(2_000);
+        assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+    }
+
+    #[test]
+    fn indefinite_lock_without_lockout_secs() {
+        let policy = LockoutPolicy {
+            max_fails: 1,
+            lockout_secs: 0,
+            fail_window: 0,
+        };
+        let mut t = tracker("", policy);
+        assert!(t.record_failure().unwrap().locked);
+        assert_eq!(
+            t.check_locked().unwrap(),
+            LockStatus::Locked { until: None }
+        );
+    }
+
+    #[test]
+    fn count_first_writes_the_failure_up_front() {
+        let clock = ManualClock::new(1_000);
+        let policy = LockoutPolicy {
+            max_fails: 2,
+            lockout_secs: 60,
+            fail_window: 0,
+        };
+        let mut t = FailTracker::with_clock(MemoryFailStore::default(), policy, &clock)
+            .unwrap()
+            .count_first(true);
+        assert_eq!(t.check_locked().unwrap(), LockStatus::Open);
+        assert_eq!(t.store.contents, "1:1000\n");
+        t.cancel_pending().unwrap();
+        assert_eq!(t.store.contents, "");
+        t.check_locked().unwrap();
+        t.record_success().unwrap();
+        assert_eq!(t.store.contents, "");
+        t.check_locked().unwrap();
+        assert_eq!(t.record_failure().unwrap().count, 1);
+        // the next failure would lock, so that is what goes first
+        t.check_locked().unwrap();
+        assert_eq!(t.store.contents, "lock:1060\n");
+        t.cancel_pending().unwrap();
+        assert_eq!(t.store.contents, "1:1000\n");
+    }
+
+    #[test]
+    fn failed_saves_are_kept() {
+        struct FullDisk;
+        impl FailStore for FullDisk {
+            fn load(&mut self) -> io::Result<String> {
+                Ok(String::new())
+            }
+            fn save(&mut self, _: &str) -> io::Result<()> {
+                Err(io::Error::from_raw_os_error(nix::libc::ENOSPC))
+            }
+        }
+        let mut t = FailTracker::new(FullDisk, LockoutPolicy::default()).unwrap();
+        assert!(t.write_error().is_none());
+        assert!(t.record_failure().is_err());
+        assert!(t.record_success().is_err());
+        assert_eq!(t.write_error().unwrap().kind(), io::ErrorKind::StorageFull);
+        let mut t = FailTracker::new(FullDisk, LockoutPolicy::default())
+            .unwrap()
+            .count_first(true);
+        assert_eq!(
+            t.check_locked().unwrap(),
+            LockStatus::Locked { until: None }
+        );
+        assert_eq!("Closed".parse(), Ok(WriteErrorPolicy::Closed));
+        assert!("never".parse::<WriteErrorPolicy>().is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! C interface (feature `ffi`), built into `libpin_auth.so` / `.a`.
+//!
+//! The header is `include/pin_auth.h`, generated by cbindgen from this file
+//! when the feature is enabled. Policy and hash scheme come from the `PIN_*`
+//! environment variables (`PIN_MIN_LEN`, `PIN_SCHEME`, ...); `pin-auth.conf`
+//! is not read.
+
+use crate::policy::PolicyValidator;
+use crate::{hash_pin, verify_pin_outcome, Pin, VerifyOutcome};
+use std::ffi::{c_char, c_int, CStr};
+
+pub const PIN_AUTH_OK: c_int = 0;
+/// `pin_auth_verify` only: the PIN did not match.
+pub const PIN_AUTH_MISMATCH: c_int = 1;
+/// A required pointer was NULL.
+pub const PIN_AUTH_ERR_NULL: c_int = -1;
+/// Input was not valid UTF-8.
+pub const PIN_AUTH_ERR_UTF8: c_int = -2;
+/// The PIN failed the length/charset policy.
+pub const PIN_AUTH_ERR_POLICY: c_int = -3;
+/// The output buffer is too small for the hash and its NUL terminator.
+pub const PIN_AUTH_ERR_BUFFER: c_int = -4;
+/// Hashing failed, or the stored hash is malformed or of an unsupported scheme.
+pub const PIN_AUTH_ERR_HASH: c_int = -5;
+/// The policy configuration in the environment is invalid.
+pub const PIN_AUTH_ERR_CONFIG: c_int = -6;
+
+/// Borrow a C string as UTF-8.
+unsafe fn as_str<'a>(p: *const c_char) -> Result<&'a str, c_int> {
+    if p.is_null() {
+        return Err(PIN_AUTH_ERR_NULL);
+    }
+    CStr::from_ptr(p).to_str().map_err(|_| PIN_AUTH_ERR_UTF8)
+}
+
+fn validator() -> Result<PolicyValidator, c_int> {
+    PolicyValidator::from_env().map_err(|_| PIN_AUTH_ERR_CONFIG)
+}
+
+/// Hash a new PIN into `out` (NUL-terminated). The full policy applies.
+/// Returns `PIN_AUTH_OK` or a negative `PIN_AUTH_ERR_*`.
+///
+/// # Safety
+/// `pin` must be a valid NUL-terminated string and `out` must point to at
+/// least `out_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pin_auth_hash(
+    pin: *const c_char,
+    out: *mut c_char,
+    out_len: usize,
+) -> c_int {
+    let run = || -> Result<(), c_int> {
+        let secret = as_str(pin)?;
+        if out.is_null() {
+            return Err(PIN_AUTH_ERR_NULL);
+        }
+        let pin = Pin::new(secret, &validator()?).map_err(|_| PIN_AUTH_ERR_POLICY)?;
+        let hash = hash_pin(&pin).map_err(|_| PIN_AUTH_ERR_HASH)?;
+        if hash.len() >= out_len {
+            return Err(PIN_AUTH_ERR_BUFFER);
+        }
+        std::ptr::copy_nonoverlapping(hash.as_ptr(), out.cast::<u8>(), hash.len());
+        *out.add(hash.len()) = 0;
+        Ok(())
+    };
+    run().map_or_else(|code| code, |()| PIN_AUTH_OK)
+}
+
+/// Check a candidate PIN against a stored hash. Returns `PIN_AUTH_OK` on a
+/// match, `PIN_AUTH_MISMATCH` on a mismatch or a candidate of invalid format,
+/// or a negative `PIN_AUTH_ERR_*`. No lockout state is touched.
+///
+/// # Safety
+/// `pin` and `stored` must be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn pin_auth_verify(pin: *const c_char, stored: *const c_char) -> c_int {
+    let run = || -> Result<c_int, c_int> {
+        let secret = as_str(pin)?;
+        let stored = as_str(stored)?;
+        let Ok(candidate) = Pin::candidate(secret, &validator()?) else {
+            return Ok(PIN_AUTH_MISMATCH);
+        };
+        match verify_pin_outcome(&candidate, stored.trim()) {
+            VerifyOutcome::Match => Ok(PIN_AUTH_OK),
+            VerifyOutcome::Mismatch => Ok(PIN_AUTH_MISMATCH),
+            _ => Err(PIN_AUTH_ERR_HASH),
+        }
+    };
+    run().unwrap_or_else(|code| code)
+}
+
+/// Check a new PIN against the length/charset policy (no blacklist).
+/// Returns `PIN_AUTH_OK`, `PIN_AUTH_ERR_POLICY` or another negative error.
+///
+/// # Safety
+/// `pin` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pin_auth_check_policy(pin: *const c_char) -> c_int {
+    let run = || -> Result<(), c_int> {
+        let secret = as_str(pin)?;
+        validator()?
+            .check_format(secret)
+            .map_err(|_| PIN_AUTH_ERR_POLICY)
+    };
+    run().map_or_else(|code| code, |()| PIN_AUTH_OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn c_round_trip() {
+        let pin = CString::new("2468").unwrap();
+        unsafe {
+            assert_eq!(pin_auth_check_policy(pin.as_ptr()), PIN_AUTH_OK);
+            let short = CString::new("12").unwrap();
+            assert_eq!(pin_auth_check_policy(short.as_ptr()), PIN_AUTH_ERR_POLICY);
+            assert_eq!(pin_auth_check_policy(std::ptr::null()), PIN_AUTH_ERR_NULL);
+
+            let mut buf = [0 as c_char; 256];
+            let rc = pin_auth_hash(pin.as_ptr(), buf.as_mut_ptr(), buf.len());
+            // PIN_AUTH_ERR_HASH: default scheme not compiled into this build
+            assert!(rc == PIN_AUTH_OK || rc == PIN_AUTH_ERR_HASH, "{rc}");
+            if rc == PIN_AUTH_OK {
+                let mut tiny = [0 as c_char; 8];
+                assert_eq!(
+                    pin_auth_hash(pin.as_ptr(), tiny.as_mut_ptr(), tiny.len()),
+                    PIN_AUTH_ERR_BUFFER
+                );
+            }
+
+            let policy = PolicyValidator::default();
+            let hash = crate::hash_pin_with(
+                &Pin::new("2468", &policy).unwrap(),
+                crate::Scheme::Argon2id,
+                &crate::HashParams::default(),
+            );
+            let Ok(hash) = hash else { return };
+            let hash = CString::new(hash).unwrap();
+            let stored = hash.as_ptr();
+            assert_eq!(pin_auth_verify(pin.as_ptr(), stored), PIN_AUTH_OK);
+            let wrong = CString::new("1357").unwrap();
+            assert_eq!(pin_auth_verify(wrong.as_ptr(), stored), PIN_AUTH_MISMATCH);
+            let garbage = CString::new("$argon2id$garbage").unwrap();
+            assert_eq!(
+                pin_auth_verify(pin.as_ptr(), garbage.as_ptr()),
+                PIN_AUTH_ERR_HASH
+            );
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! FIDO2 security keys as a fallback for the PIN (feature `fido2`), through
+//! the system libfido2.
+//!
+//! `pinctl fido2 USER` makes a resident ES256 credential for relying party
+//! [`RP_ID`] on the attached key. `<user>.fido2` holds
+//! `<when> <hex credential id> <hex public key>`, where `<when>` says when
+//! `check_pin` may ask the key instead of the PIN: `no_pin` (the user has no
+//! PIN record), `locked` (the PIN is locked out) or `both`. The assertion
+//! must carry user presence (a touch) and verify against the stored key.
+
+use crate::store::PinStore;
+use sha2::{Digest, Sha256};
+use std::ffi::{c_int, CStr, CString};
+use std::fmt;
+use std::io;
+use std::ptr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Relying party ID the credentials are made for.
+pub const RP_ID: &str = "pin-auth";
+/// How long to wait for the touch.
+pub const TOUCH_TIMEOUT: Duration = Duration::from_secs(15);
+/// Most keys attached at once that are tried.
+const MAX_DEVICES: usize = 16;
+
+/// When the key may stand in for the PIN.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fallback {
+    /// Only while the user has no PIN record.
+    NoPin,
+    /// Only while the PIN is locked out.
+    Locked,
+    /// In either case.
+    Both,
+}
+
+impl Fallback {
+    pub fn when_no_pin(self) -> bool {
+        matches!(self, Fallback::NoPin | Fallback::Both)
+    }
+
+    pub fn when_locked(self) -> bool {
+        matches!(self, Fallback::Locked | Fallback::Both)
+    }
+}
+
+impl FromStr for Fallback {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "no_pin" => Ok(Fallback::NoPin),
+            "locked" => Ok(Fallback::Locked),
+            "both" => Ok(Fallback::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Fallback::NoPin => "no_pin",
+            Fallback::Locked => "locked",
+            Fallback::Both => "both",
+        })
+    }
+}
+
+/// One user's enrolled credential.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fido2Record {
+    pub when: Fallback,
+    pub credential: Vec<u8>,
+    /// Uncompressed P-256 point, `x || y`.
+    pub public_key: Vec<u8>,
+}
+
+impl Fido2Record {
+    /// File name of `user`'s record.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.fido2")
+    }
+
+    /// `user`'s record from `store`; `None` if not enrolled.
+    pub fn load(store: &dyn PinStore, user: &str) -> io::Result<Option<Self>> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut fields = raw.split_whitespace();
+        let when = fields.next().and_then(|w| w.parse().ok());
+        let credential = fields
+            .next()
+            .and_then(crate::parse_hex)
+            .filter(|c| !c.is_empty());
+        let public_key = fields
+            .next()
+            .and_then(crate::parse_hex)
+            .filter(|k| k.len() == 64);
+        match (when, credential, public_key, fields.next()) {
+            (Some(when), Some(credential), Some(public_key), None) => Ok(Some(Fido2Record {
+                when,
+                credential: credential.to_vec(),
+                public_key: public_key.to_vec(),
+            })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed FIDO2 record",
+            )),
+        }
+    }
+
+    /// The record as written to `<user>.fido2`.
+    pub fn serialize(&self) -> String {
+        format!(
+            "{} {} {}\n",
+            self.when,
+            crate::hex(&self.credential).as_str(),
+            crate::hex(&self.public_key).as_str()
+        )
+    }
+
+    /// Ask each attached key for a touched assertion over a fresh challenge.
+    /// `Ok(false)` if none produced one that verifies; `Err` if no key
+    /// could be reached at all.
+    pub fn authenticate(&self) -> io::Result<bool> {
+        let paths = device_paths()?;
+        if paths.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no FIDO2 device attached",
+            ));
+        }
+        let pk = sys::Es256Pk::from_point(&self.public_key)?;
+        let rp = CString::new(RP_ID).expect("no NUL in RP_ID");
+        let mut last_err = None;
+        for path in &paths {
+            let dev = match sys::Dev::open(path) {
+                Ok(dev) => dev,
+                Err(e) => {
+                    last_err = Some(e);
+                    continue;
+                }
+            };
+            let assert = sys::Assert::new()?;
+            let hash = client_data_hash();
+            // SAFETY: all pointers are live for the calls; libfido2 copies
+            // the buffers it is given.
+            unsafe {
+                check(
+                    sys::fido_assert_set_rp(assert.0, rp.as_ptr()),
+                    "set relying party",
+                )?;
+                check(
+                    sys::fido_assert_set_clientdata_hash(assert.0, hash.as_ptr(), hash.len()),
+                    "set client data",
+                )?;
+                check(
+                    sys::fido_assert_allow_cred(
+                        assert.0,
+                        self.credential.as_ptr(),
+                        self.credential.len(),
+                    ),
+                    "set credential",
+                )?;
+                check(
+                    sys::fido_assert_set_up(assert.0, sys::FIDO_OPT_TRUE),
+                    "require presence",
+                )?;
+                // a key without the credential answers NO_CREDENTIALS
+                if sys::fido_dev_get_assert(dev.0, assert.0, ptr::null()) != sys::FIDO_OK {
+                    continue;
+                }
+                if sys::fido_assert_count(assert.0) == 1
+                    && sys::fido_assert_verify(assert.0, 0, sys::COSE_ES256, pk.0.cast())
+                        == sys::FIDO_OK
+                {
+                    return Ok(true);
+                }
+            }
+        }
+        match last_err {
+            Some(e) if paths.len() == 1 => Err(e),
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Make a resident ES256 credential for `user` on the first attached key
+/// (touch required). `device_pin` is the key's own PIN, if it has one.
+pub fn enroll(user: &str, device_pin: Option<&str>, when: Fallback) -> io::Result<Fido2Record> {
+    let path = device_paths()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no FIDO2 device attached"))?;
+    let dev = sys::Dev::open(&path)?;
+    let cred = sys::Cred::new()?;
+    let rp = CString::new(RP_ID).expect("no NUL in RP_ID");
+    let name = CString::new(user)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in user name"))?;
+    let pin = device_pin
+        .map(CString::new)
+        .transpose()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in device PIN"))?;
+    let hash = client_data_hash();
+    // SAFETY: all pointers are live for the calls; the id and key pointers
+    // are copied out before `cred` is freed.
+    unsafe {
+        check(sys::fido_cred_set_type(cred.0, sys::COSE_ES256), "set type")?;
+        check(
+            sys::fido_cred_set_clientdata_hash(cred.0, hash.as_ptr(), hash.len()),
+            "set client data",
+        )?;
+        check(
+            sys::fido_cred_set_rp(cred.0, rp.as_ptr(), rp.as_ptr()),
+            "set relying party",
+        )?;
+        check(
+            sys::fido_cred_set_user(
+                cred.0,
+                user.as_ptr(),
+                user.len(),
+                name.as_ptr(),
+                name.as_ptr(),
+                ptr::null(),
+            ),
+            "set user",
+        )?;
+        check(
+            sys::fido_cred_set_rk(cred.0, sys::FIDO_OPT_TRUE),
+            "request resident key",
+        )?;
+        check(
+            sys::fido_dev_make_cred(
+                dev.0,
+                cred.0,
+                pin.as_ref().map_or(ptr::null(), |p| p.as_ptr()),
+            ),
+            "make credential",
+        )?;
+        let id = std::slice::from_raw_parts(
+            sys::fido_cred_id_ptr(cred.0),
+            sys::fido_cred_id_len(cred.0),
+        );
+        let key = std::slice::from_raw_parts(
+            sys::fido_cred_pubkey_ptr(cred.0),
+            sys::fido_cred_pubkey_len(cred.0),
+        );
+        if id.is_empty() || key.len() != 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "device returned no ES256 credential",
+            ));
+        }
+        Ok(Fido2Record {
+            when,
+            credential: id.to_vec(),
+            public_key: key.to_vec(),
+        })
+    }
+}
+
+/// SHA-256 of a random challenge, in place of WebAuthn client data.
+fn client_data_hash() -> [u8; 32] {
+    let mut challenge = [0u8; 32];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut challenge);
+    Sha256::digest(challenge).into()
+}
+
+/// Paths of the attached FIDO2 devices.
+fn device_paths() -> io::Result<Vec<CString>> {
+    // SAFETY: the list is sized MAX_DEVICES and only indexed below `found`;
+    // paths are copied before it is freed.
+    unsafe {
+        sys::fido_init(0);
+        let mut list = sys::fido_dev_info_new(MAX_DEVICES);
+        if list.is_null() {
+            return Err(io::Error::other("libfido2: out of memory"));
+        }
+        let mut found = 0;
+        let rc = sys::fido_dev_info_manifest(list, MAX_DEVICES, &mut found);
+        let paths = (0..found)
+            .map(|i| sys::fido_dev_info_path(sys::fido_dev_info_ptr(list, i)))
+            .filter(|p| !p.is_null())
+            .map(|p| CStr::from_ptr(p).to_owned())
+            .collect();
+        sys::fido_dev_info_free(&mut list, MAX_DEVICES);
+        check(rc, "list devices")?;
+        Ok(paths)
+    }
+}
+
+fn check(rc: c_int, what: &str) -> io::Result<()> {
+    if rc == sys::FIDO_OK {
+        return Ok(());
+    }
+    // SAFETY: fido_strerr returns a static string for any code.
+    let msg = unsafe { CStr::from_ptr(sys::fido_strerr(rc)) };
+    Err(io::Error::other(format!(
+        "libfido2: {what}: {}",
+        msg.to_string_lossy()
+    )))
+}
+
+/// The parts of libfido2's C API used here, with owning wrappers.
+/// Linked by build.rs.
+mod sys {
+    #![allow(non_camel_case_types)]
+    use super::{check, TOUCH_TIMEOUT};
+    use std::ffi::{c_char, c_int, c_uchar, c_void, CStr};
+    use std::io;
+
+    #[repr(C)]
+    pub struct fido_dev_info_t {
+        _opaque: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct fido_dev_t {
+        _opaque: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct fido_assert_t {
+        _opaque: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct fido_cred_t {
+        _opaque: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct es256_pk_t {
+        _opaque: [u8; 0],
+    }
+
+    pub const FIDO_OK: c_int = 0;
+    /// `fido_opt_t`: OMIT, FALSE, TRUE.
+    pub const FIDO_OPT_TRUE: c_int = 2;
+    pub const COSE_ES256: c_int = -7;
+
+    extern "C" {
+        pub fn fido_init(flags: c_int);
+        pub fn fido_strerr(n: c_int) -> *const c_char;
+
+        pub fn fido_dev_info_new(n: usize) -> *mut fido_dev_info_t;
+        pub fn fido_dev_info_manifest(
+            devlist: *mut fido_dev_info_t,
+            ilen: usize,
+            olen: *mut usize,
+        ) -> c_int;
+        pub fn fido_dev_info_ptr(
+            devlist: *const fido_dev_info_t,
+            i: usize,
+        ) -> *const fido_dev_info_t;
+        pub fn fido_dev_info_path(di: *const fido_dev_info_t) -> *const c_char;
+        pub fn fido_dev_info_free(devlist_p: *mut *mut fido_dev_info_t, n: usize);
+
+        pub fn fido_dev_new() -> *mut fido_dev_t;
+        pub fn fido_dev_open(dev: *mut fido_dev_t, path: *const c_char) -> c_int;
+        pub fn fido_dev_set_timeout(dev: *mut fido_dev_t, ms: c_int) -> c_int;
+        pub fn fido_dev_close(dev: *mut fido_dev_t) -> c_int;
+        pub fn fido_dev_free(dev_p: *mut *mut fido_dev_t);
+
+        pub fn fido_assert_new() -> *mut fido_assert_t;
+        pub fn fido_assert_set_rp(assert: *mut fido_assert_t, id: *const c_char) -> c_int;
+        pub fn fido_assert_set_clientdata_hash(
+            assert: *mut fido_assert_t,
+            ptr: *const c_uchar,
+            len: usize,
+        ) -> c_int;
+        pub fn fido_assert_allow_cred(
+            assert: *mut fido_assert_t,
+            ptr: *const c_uchar,
+            len: usize,
+        ) -> c_int;
+        pub fn fido_assert_set_up(assert: *mut fido_assert_t, up: c_int) -> c_int;
+        pub fn fido_dev_get_assert(
+            dev: *mut fido_dev_t,
+            assert: *mut fido_assert_t,
+            pin: *const c_char,
+        ) -> c_int;
+        pub fn fido_assert_count(assert: *const fido_assert_t) -> usize;
+        pub fn fido_assert_verify(
+            assert: *const fido_assert_t,
+            idx: usize,
+            cose_alg: c_int,
+            pk: *const c_void,
+        ) -> c_int;
+        pub fn fido_assert_free(assert_p: *mut *mut fido_assert_t);
+
+        pub fn es256_pk_new() -> *mut es256_pk_t;
+        pub fn es256_pk_from_ptr(pk: *mut es256_pk_t, ptr: *const c_void, len: usize) -> c_int;
+        pub fn es256_pk_free(pk_p: *mut *mut es256_pk_t);
+
+        pub fn fido_cred_new() -> *mut fido_cred_t;
+        pub fn fido_cred_set_type(cred: *mut fido_cred_t, cose_alg: c_int) -> c_int;
+        pub fn fido_cred_set_clientdata_hash(
+            cred: *mut fido_cred_t,
+            ptr: *const c_uchar,
+            len: usize,
+        ) -> c_int;
+        pub fn fido_cred_set_rp(
+            cred: *mut fido_cred_t,
+            id: *const c_char,
+            name: *const c_char,
+        ) -> c_int;
+        pub fn fido_cred_set_user(
+            cred: *mut fido_cred_t,
+            user_id: *const c_uchar,
+            user_id_len: usize,
+            name: *const c_char,
+            display_name: *const c_char,
+            icon: *const c_char,
+        ) -> c_int;
+        pub fn fido_cred_set_rk(cred: *mut fido_cred_t, rk: c_int) -> c_int;
+        pub fn fido_dev_make_cred(
+            dev: *mut fido_dev_t,
+            cred: *mut fido_cred_t,
+            pin: *const c_char,
+        ) -> c_int;
+        pub fn fido_cred_id_ptr(cred: *const fido_cred_t) -> *const c_uchar;
+        pub fn fido_cred_id_len(cred: *const fido_cred_t) -> usize;
+        pub fn fido_cred_pubkey_ptr(cred: *const fido_cred_t) -> *const c_uchar;
+        pub fn fido_cred_pubkey_len(cred: *const fido_cred_t) -> usize;
+        pub fn fido_cred_free(cred_p: *mut *mut fido_cred_t);
+    }
+
+    fn oom() -> io::Error {
+        io::Error::other("libfido2: out of memory")
+    }
+
+    /// An open device, with the touch timeout set.
+    pub struct Dev(pub *mut fido_dev_t);
+
+    impl Dev {
+        pub fn open(path: &CStr) -> io::Result<Self> {
+            // SAFETY: `dev` is owned by the wrapper from here on.
+            unsafe {
+                let dev = Dev(fido_dev_new());
+                if dev.0.is_null() {
+                    return Err(oom());
+                }
+                check(fido_dev_open(dev.0, path.as_ptr()), "open device")?;
+                let ms = TOUCH_TIMEOUT.as_millis() as c_int;
+                check(fido_dev_set_timeout(dev.0, ms), "set timeout")?;
+                Ok(dev)
+            }
+        }
+    }
+
+    impl Drop for Dev {
+        fn drop(&mut self) {
+            // SAFETY: closing an unopened device is a no-op error.
+            unsafe {
+                fido_dev_close(self.0);
+                fido_dev_free(&mut self.0);
+            }
+        }
+    }
+
+    pub struct Assert(pub *mut fido_assert_t);
+
+    impl Assert {
+        pub fn new() -> io::Result<Self> {
+            // SAFETY: no preconditions.
+            let assert = unsafe { fido_assert_new() };
+            if assert.is_null() {
+                return Err(oom());
+            }
+            Ok(Assert(assert))
+        }
+    }
+
+    impl Drop for Assert {
+        fn drop(&mut self) {
+            // SAFETY: the pointer came from fido_assert_new.
+            unsafe { fido_assert_free(&mut self.0) }
+        }
+    }
+
+    pub struct Cred(pub *mut fido_cred_t);
+
+    impl Cred {
+        pub fn new() -> io::Result<Self> {
+            // SAFETY: no preconditions.
+            let cred = unsafe { fido_cred_new() };
+            if cred.is_null() {
+                return Err(oom());
+            }
+            Ok(Cred(cred))
+        }
+    }
+
+    impl Drop for Cred {
+        fn drop(&mut self) {
+            // SAFETY: the pointer came from fido_cred_new.
+            unsafe { fido_cred_free(&mut self.0) }
+        }
+    }
+
+    pub struct Es256Pk(pub *mut es256_pk_t);
+
+    impl Es256Pk {
+        /// From the 64-byte `x || y` form libfido2 hands out at enrollment.
+        pub fn from_point(point: &[u8]) -> io::Result<Self> {
+            // SAFETY: `pk` is owned by the wrapper; the point is copied.
+            unsafe {
+                let pk = Es256Pk(es256_pk_new());
+                if pk.0.is_null() {
+                    return Err(oom());
+                }
+                check(
+                    es256_pk_from_ptr(pk.0, point.as_ptr().cast(), point.len()),
+                    "load public key",
+                )?;
+                Ok(pk)
+            }
+        }
+    }
+
+    impl Drop for Es256Pk {
+        fn drop(&mut self) {
+            // SAFETY: the pointer came from es256_pk_new.
+            unsafe { es256_pk_free(&mut self.0) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let record = Fido2Record {
+            when: Fallback::Locked,
+            credential: vec![0xab; 48],
+            public_key: vec![0x04; 64],
+        };
+        let store = crate::store::MemoryStore::new()
+            .with_file("alice.fido2", record.serialize())
+            .with_file("bob.fido2", "sometimes abcd 0404\n");
+        assert_eq!(Fido2Record::load(&store, "alice").unwrap(), Some(record));
+        assert!(Fido2Record::load(&store, "bob").is_err());
+        assert_eq!(Fido2Record::load(&store, "carol").unwrap(), None);
+        assert!(Fallback::Both.when_no_pin() && Fallback::Both.when_locked());
+        assert!(!Fallback::NoPin.when_locked());
+        assert!(!Fallback::Locked.when_no_pin());
+    }
+}
@@ -0,0 +1,268 @@
+//! Removing `.fail` files that no longer hold anything: the user is gone
+//! from NSS, or their count or lock ran out more than `state_gc_age` ago.
+//! Those `check_pin` keeps for names NSS never knew (under their
+//! [`pseudonym`](crate::logging::pseudonym)) go once they ran out.
+//!
+//! `check_pin` runs this at most once per [`GC_INTERVAL`] when
+//! `state_gc_age` is set; the last run is kept in `<dir>/state-gc.stamp`.
+//! `pinctl cleanup` also finds records of deleted accounts and old backup
+//! copies.
+
+use crate::fail::{FailFile, FailState, FailStore, LockoutPolicy};
+use crate::logging::PSEUDONYM_PREFIX;
+use crate::store::{DirStore, PinStore, GROUP_PREFIX, RECORD_EXTS};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// How often `check_pin` collects.
+pub const GC_INTERVAL: u64 = 24 * 3600;
+const STAMP: &str = "state-gc.stamp";
+/// Leftover copies: editor and admin backups, interrupted writes.
+const BACKUP_SUFFIXES: &[&str] = &[".bak", ".old", ".orig", "~", ".tmp"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stale {
+    /// NSS does not know the user.
+    UnknownUser,
+    /// Nothing recorded, or the window or lock ended long enough ago.
+    Expired,
+}
+
+impl Stale {
+    pub fn reason(self) -> &'static str {
+        match self {
+            Stale::UnknownUser => "unknown_user",
+            Stale::Expired => "expired",
+        }
+    }
+}
+
+/// Whether `state` has had nothing left to enforce for more than `age`
+/// seconds at `now`.
+pub fn expired(state: FailState, policy: &LockoutPolicy, age: u64, now: u64) -> bool {
+    match state {
+        FailState::Clear => true,
+        // fail_window = 0: failures add up forever
+        FailState::Counting { first_ts, .. } => {
+            policy.fail_window > 0
+                && now
+                    > first_ts
+                        .saturating_add(policy.fail_window)
+                        .saturating_add(age)
+        }
+        FailState::Locked { until } => now > until.saturating_add(age),
+    }
+}
+
+/// Find (and unless `dry_run`, remove) stale `.fail` files: those of users
+/// `exists` does not know, and with `age`, those [`expired`] that long. A
+/// file is removed under its lock, after checking again. Returns the users
+/// whose files were (or would be) removed.
+pub fn collect(
+    store: &DirStore,
+    policy: &LockoutPolicy,
+    age: Option<u64>,
+    now: u64,
+    exists: impl Fn(&str) -> bool,
+    dry_run: bool,
+) -> io::Result<Vec<(String, Stale)>> {
+    let mut removed = Vec::new();
+    for user in store.names("fail")? {
+        let path = store.path(&format!("{user}.fail"));
+        let verdict = |raw: &str| {
+            if user.starts_with(PSEUDONYM_PREFIX) {
+                let state = FailState::parse(raw, now);
+                return expired(state, policy, age.unwrap_or(0), now).then_some(Stale::Expired);
+            }
+            if !exists(&user) {
+                Some(Stale::UnknownUser)
+            } else if age.is_some_and(|age| expired(FailState::parse(raw, now), policy, age, now)) {
+                Some(Stale::Expired)
+            } else {
+                None
+            }
+        };
+        if dry_run {
+            if let Some(stale) = store
+                .read_file(&format!("{user}.fail"))
+                .ok()
+                .and_then(|raw| verdict(&raw))
+            {
+                removed.push((user, stale));
+            }
+            continue;
+        }
+        let mut file = FailFile::open(&path)?;
+        if let Some(stale) = verdict(&file.load()?) {
+            file.remove(&path)?;
+            removed.push((user, stale));
+        }
+    }
+    Ok(removed)
+}
+
+/// Keys (`alice`, `@operators`) with a PIN record whose account or group
+/// no longer exists.
+pub fn orphaned_records(
+    store: &DirStore,
+    user_exists: impl Fn(&str) -> bool,
+    group_exists: impl Fn(&str) -> bool,
+) -> io::Result<Vec<String>> {
+    let mut keys = store.names("passwd")?;
+    keys.extend(store.names("toml")?);
+    keys.sort();
+    keys.dedup();
+    Ok(keys
+        .into_iter()
+        .filter(|key| match key.strip_prefix(GROUP_PREFIX) {
+            Some(group) => !group_exists(group),
+            None => !user_exists(key),
+        })
+        .collect())
+}
+
+/// The files kept for `key` that exist, `.fail` excluded (see [`collect`]).
+pub fn record_files(store: &DirStore, key: &str) -> Vec<PathBuf> {
+    RECORD_EXTS
+        .iter()
+        .filter(|&&ext| ext != "fail")
+        .map(|ext| store.path(&format!("{key}.{ext}")))
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect()
+}
+
+/// Backup copies in the PIN and state directories last modified more than
+/// `older_than` ago.
+pub fn old_backups(store: &DirStore, older_than: Duration) -> io::Result<Vec<PathBuf>> {
+    let cutoff = SystemTime::now()
+        .checked_sub(older_than)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut dirs = vec![store.dir()];
+    if store.state_dir() != store.dir() {
+        dirs.push(store.state_dir());
+    }
+    let mut found = Vec::new();
+    for dir in dirs {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if !is_backup_copy(name) {
+                continue;
+            }
+            let md = entry.metadata()?;
+            if md.is_file() && md.modified()? < cutoff {
+                found.push(entry.path());
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Whether `name` is a leftover copy (`*.bak`, `*~`, `*.tmp`, ...).
+pub fn is_backup_copy(name: &str) -> bool {
+    BACKUP_SUFFIXES.iter().any(|s| name.ends_with(s))
+}
+
+/// Whether a collection is due, recording this one if so.
+pub fn due(store: &DirStore, now: u64) -> io::Result<bool> {
+    let last = match store.read_file(STAMP) {
+        Ok(raw) => raw.trim().parse().unwrap_or(0),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    if now.saturating_sub(last) < GC_INTERVAL {
+        return Ok(false);
+    }
+    store.write_file(STAMP, &format!("{now}\n"))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn stale_fail_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = DirStore::new(tmp.path());
+        let policy = LockoutPolicy {
+            fail_window: 100,
+            ..LockoutPolicy::default()
+        };
+        let now = 10_000;
+        for (user, state) in [
+            ("fresh", "2:9950\n"),
+            ("old", "2:5000\n"),
+            ("locked", "lock:10100\n"),
+            ("unlocked", "lock:5000\n"),
+            ("empty", ""),
+            ("gone", "1:9990\n"),
+            // names NSS never knew, kept while locked
+            ("u:0123456789abcdef", "lock:10100\n"),
+            ("u:fedcba9876543210", "lock:9000\n"),
+        ] {
+            fs::write(tmp.path().join(format!("{user}.fail")), state).unwrap();
+        }
+        let exists = |user: &str| user != "gone";
+        let found = collect(&store, &policy, Some(1000), now, exists, true).unwrap();
+        assert_eq!(
+            found,
+            [
+                ("empty".to_string(), Stale::Expired),
+                ("gone".to_string(), Stale::UnknownUser),
+                ("old".to_string(), Stale::Expired),
+                ("unlocked".to_string(), Stale::Expired),
+            ]
+        );
+        assert_eq!(
+            collect(&store, &policy, None, now, exists, true).unwrap(),
+            [
+                ("gone".to_string(), Stale::UnknownUser),
+                ("u:fedcba9876543210".to_string(), Stale::Expired),
+            ]
+        );
+        assert_eq!(store.names("fail").unwrap().len(), 8);
+        collect(&store, &policy, Some(1000), now, exists, false).unwrap();
+        assert_eq!(
+            store.names("fail").unwrap(),
+            [
+                "fresh",
+                "locked",
+                "u:0123456789abcdef",
+                "u:fedcba9876543210"
+            ]
+        );
+
+        fs::write(tmp.path().join("gone.passwd"), "$6$x$y\n").unwrap();
+        fs::write(tmp.path().join("gone.last"), "1 - - -\n").unwrap();
+        fs::write(tmp.path().join("fresh.passwd"), "$6$x$y\n").unwrap();
+        fs::write(tmp.path().join("@lab.passwd"), "$6$x$y\n").unwrap();
+        fs::write(tmp.path().join("fresh.passwd.bak"), "$6$x$y\n").unwrap();
+        assert_eq!(
+            orphaned_records(&store, exists, |group| group != "lab").unwrap(),
+            ["@lab", "gone"]
+        );
+        assert_eq!(
+            record_files(&store, "gone"),
+            [tmp.path().join("gone.passwd"), tmp.path().join("gone.last")]
+        );
+        assert!(old_backups(&store, Duration::from_secs(3600))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            old_backups(&store, Duration::ZERO).unwrap(),
+            [tmp.path().join("fresh.passwd.bak")]
+        );
+
+        let later = 100 * GC_INTERVAL;
+        assert!(due(&store, later).unwrap());
+        assert!(!due(&store, later + 60).unwrap());
+        assert!(due(&store, later + GC_INTERVAL).unwrap());
+    }
+}
@@ -1,33 +1,85 @@
 use anyhow::{bail, Context, Result};
 use nix::unistd::{chown, Gid, Uid};
-use pin_auth::hash_pin;
+use pin_auth::account;
+use pin_auth::audit::{AuditEvent, AuditLog};
+use pin_auth::auth::{Decoys, RecoveryCodes};
+use pin_auth::i18n::Messages;
+use pin_auth::immutable::{make_mutable, set_immutable};
+use pin_auth::man::{self, ManPage};
+use pin_auth::policy::{self, PolicyValidator, UsernamePolicy};
+use pin_auth::record::HashRecord;
+use pin_auth::store::{resolve_store_with, write_atomic, DirStore, PinStore, GROUP_PREFIX};
+use pin_auth::{hash_pin_configured, verify_pin, ConfigResolver, Pin, PinHashError};
 use rpassword::prompt_password;
 use std::env;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
-use zeroize::Zeroize;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// PINs `genpin` asks for before giving up, unless `prompt_tries` says.
+const PROMPT_TRIES: usize = 3;
+
+/// Options, for the man page.
+const OPTIONS: &[(&str, &str)] = &[
+    (
+        "--decoy",
+        "add a decoy PIN for USER: it never grants access, and entering it is logged and audited",
+    ),
+    (
+        "--recovery-codes",
+        "replace USER's single-use recovery codes and print them; the PIN is kept",
+    ),
+    (
+        "--print",
+        "print the hash instead of writing it, e.g. for a USER.toml record",
+    ),
+    (
+        "--pin-stdin",
+        "read the PIN from standard input instead of prompting: the first line, and the second, if any, as its confirmation",
+    ),
+    ("--pin-fd N", "as --pin-stdin, from file descriptor N"),
+];
 
 fn main() -> Result<()> {
+    if env::args().nth(1).as_deref() == Some(man::GENERATE_MAN) {
+        print!("{}", man_page().render());
+        return Ok(());
+    }
+    // Directory is fixed at build time for release builds. In debug/test builds we allow PIN_DIR for test isolation only.
+    let dir = pin_auth::paths::pin_dir();
+    // Settings: <dir>/pin-auth.conf over the (deprecated) environment.
+    let (config, config_error) = match ConfigResolver::load_dir(&dir) {
+        Ok(config) => (config, None),
+        Err(e) => (ConfigResolver::from_env(), Some(e)),
+    };
+    pin_auth::logging::init_with("genpin", "stderr", &config);
     // Require effective UID 0 (root). In debug builds a test override ALLOW_NON_ROOT=1 permits execution.
     let euid = nix::unistd::geteuid().as_raw();
     if euid != 0 {
         #[cfg(not(debug_assertions))]
         {
-            eprintln!("denied: requires root (effective uid 0)");
+            tracing::error!("denied: requires root (effective uid 0)");
             std::process::exit(1);
         }
         #[cfg(debug_assertions)]
         {
             if std::env::var("ALLOW_NON_ROOT").ok().as_deref() != Some("1") {
-                eprintln!(
+                tracing::error!(
                     "denied: requires root (set ALLOW_NON_ROOT=1 in debug to bypass for tests)"
                 );
                 std::process::exit(1);
             }
         }
     }
-    // Usage: genpin <username> [--dir /etc/pin.d]
+    if let Some(e) = config_error {
+        return Err(e).context("configuration");
+    }
+    config.report();
+    // Usage: genpin <username> [--decoy | --recovery-codes | --print]
+    //        [--pin-stdin | --pin-fd N] | genpin @<group>
     let mut args = env::args().skip(1);
     let user = if let Some(u) = args.next() {
         u
@@ -35,95 +87,376 @@ fn main() -> Result<()> {
         // No username supplied: silently do nothing (success exit)
         return Ok(());
     };
-    // Directory is fixed at /etc/pin.d for release builds. In debug/test builds we allow PIN_DIR for test isolation only.
-    let dir = if cfg!(debug_assertions) {
-        std::env::var("PIN_DIR").unwrap_or_else(|_| "/etc/pin.d".to_string())
+    let flags: Vec<String> = args.collect();
+    // --decoy: add a PIN that never grants access but raises an alert.
+    let decoy = flags.iter().any(|a| a == "--decoy");
+    // --recovery-codes: replace the user's recovery codes; the PIN is kept.
+    let recovery = flags.iter().any(|a| a == "--recovery-codes");
+    // --print: write the hash to stdout instead, e.g. for a `.toml` record.
+    let print = flags.iter().any(|a| a == "--print");
+    if [decoy, recovery, print].iter().filter(|&&f| f).count() > 1 {
+        bail!("--decoy, --recovery-codes and --print are exclusive");
+    }
+    // --pin-stdin / --pin-fd N: read the PIN instead of prompting, for
+    // scripts; unlike GENPIN_NONINTERACTIVE it is not in /proc/<pid>/environ.
+    let pin_fd = match flags.iter().position(|a| a == "--pin-fd") {
+        Some(i) => Some(
+            flags
+                .get(i + 1)
+                .and_then(|fd| fd.parse::<RawFd>().ok())
+                .filter(|&fd| fd >= 0)
+                .context("--pin-fd needs a file descriptor number")?,
+        ),
+        None => flags.iter().any(|a| a == "--pin-stdin").then_some(0),
+    };
+    // @group: one PIN for every member of the group
+    let group = user.strip_prefix(GROUP_PREFIX);
+    // Files are named after `key`: the name, a UID (`record_key = uid`) or
+    // `@group`.
+    let key = if let Some(group) = group {
+        UsernamePolicy::from_config(&config)
+            .validate(group)
+            .with_context(|| format!("invalid group name {group:?}"))?;
+        if !account::group_exists(group).context("looking up group")? {
+            bail!("no such group {group:?}");
+        }
+        if decoy || recovery {
+            bail!("decoy PINs and recovery codes are per user, not per group");
+        }
+        user.clone()
     } else {
-        "/etc/pin.d".to_string()
+        UsernamePolicy::from_config(&config)
+            .record_key(&user)
+            .with_context(|| format!("invalid username {user:?}"))?
     };
+    let recovery_count = config.parse::<usize>("recovery_codes").unwrap_or(0);
+
+    if recovery {
+        let store = resolve_store_with(&dir, &config).context("insecure PIN directory")?;
+        store
+            .read_hash(&key)
+            .with_context(|| format!("no PIN record for {user}"))?;
+        let count = match recovery_count {
+            0 => RecoveryCodes::DEFAULT_COUNT,
+            n => n,
+        };
+        return issue_recovery_codes(&store, &config, &key, &user, count);
+    }
 
-    println!("Creating/Updating PIN for user: {user}");
+    if decoy {
+        println!("Adding decoy PIN for user: {user}");
+    } else if !print {
+        println!("Creating/Updating PIN for user: {user}");
+    }
+    let (name, uid) = match group {
+        Some(group) => (group, None),
+        None => (user.as_str(), account::uid_of(&user).ok().flatten()),
+    };
+    let validator = PolicyValidator::from_config(&config)?
+        .for_account(name, uid)
+        .load_blacklist(format!("{}/pin-blacklist", dir))
+        .context("reading pin-blacklist")?;
     let non_interactive = std::env::var("GENPIN_NONINTERACTIVE").ok();
-    let (pin1, pin2) = if let Some(val) = non_interactive {
+    let entered = if let Some(fd) = pin_fd {
+        read_pin(fd)?
+    } else if let Some(val) = non_interactive {
+        tracing::warn!(
+            "GENPIN_NONINTERACTIVE is deprecated: other processes can read the PIN from \
+             /proc/<pid>/environ; use --pin-stdin or --pin-fd"
+        );
         // Expect form PIN[:CONFIRM]; if only one provided reuse it.
+        let val = Zeroizing::new(val);
         let mut parts = val.splitn(2, ':');
-        let p1 = parts.next().unwrap().to_string();
-        let p2 = parts.next().unwrap_or(&p1).to_string();
-        (p1, p2)
+        let p1 = Zeroizing::new(parts.next().unwrap().to_string());
+        if parts.next().is_some_and(|p2| p2 != p1.as_str()) {
+            bail!("PINs do not match");
+        }
+        p1
     } else {
-        let p1 = prompt_password("Enter new PIN: ")?;
-        let p2 = prompt_password("Repeat new PIN: ")?;
-        (p1, p2)
+        let tries = config
+            .parse::<usize>("prompt_tries")
+            .unwrap_or(PROMPT_TRIES);
+        let messages = Messages::load(Path::new(&dir), &config);
+        prompt_new_pin(&validator, &messages, tries)?
     };
-    if pin1 != pin2 {
-        bail!("PINs do not match");
-    }
-    let min_len: usize = std::env::var("PIN_MIN_LEN")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(4);
-    let max_len: usize = std::env::var("PIN_MAX_LEN")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(6);
-    if min_len == 0 || min_len > 32 {
-        bail!("Unreasonable PIN_MIN_LEN");
+    let pin = Pin::new(entered.as_str(), &validator)?;
+    // Only `pin` is needed from here; the entered copy is wiped now.
+    drop(entered);
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("creating {}", dir))?;
+    let store = resolve_store_with(&dir, &config).context("insecure PIN directory")?;
+    // a .passwd file would make a configuration-managed record corrupt
+    let toml = format!("{key}.toml");
+    if !decoy && !print && store.path(&toml).symlink_metadata().is_ok() {
+        bail!("{toml} holds this PIN record; put the hash from `genpin {user} --print` there");
     }
-    if max_len < min_len {
-        bail!("PIN_MAX_LEN ({max_len}) is less than PIN_MIN_LEN ({min_len})");
+    if decoy {
+        if let Ok(current) = store.read_hash(&key) {
+            if verify_pin(&pin, &current) {
+                bail!("a decoy PIN must differ from the current PIN");
+            }
+        }
+    } else if Decoys::load(&store, &key, false)?.matches(&pin) {
+        bail!("PIN is registered as a decoy for {user}");
     }
-    if pin1.len() < min_len {
-        bail!("PIN shorter than minimum ({min_len})");
+    let hash = hash_new_pin(&pin, &config).context("hashing pin")?;
+    // check_pin would refuse it
+    if let Some(floor) = config.parse::<pin_auth::HashFloor>("min_hash") {
+        if !pin_auth::Scheme::from_hash(&hash).is_ok_and(|info| floor.allows(&info)) {
+            bail!("the configured scheme and costs hash below min_hash ({floor}); raise them");
+        }
     }
-    if pin1.len() > max_len {
-        bail!("PIN longer than allowed maximum ({max_len})");
+    drop(pin);
+    if print {
+        println!("{hash}");
+        return Ok(());
     }
-    if !pin1.chars().all(|c| c.is_ascii_digit()) {
-        bail!("PIN must contain only digits (0-9)");
+
+    if decoy {
+        let name = Decoys::file_name(&key);
+        let record = store.path(&name);
+        let path = record.display().to_string();
+        let mut hashes = match store.read_file(&name) {
+            Ok(hashes) => hashes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("reading {path}")),
+        };
+        hashes.push_str(&format!("{hash}\n"));
+        make_mutable(&record).with_context(|| format!("clearing immutable on {path}"))?;
+        write_atomic(&record, store.seal(&name, &hashes).as_bytes(), 0o600)
+            .with_context(|| format!("writing {path}"))?;
+        protect(&config, &record);
+        audit(&config, &store, "add_decoy", &user)?;
+        println!("Decoy PIN hash added to {}", path);
+        return Ok(());
     }
 
-    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir))?;
-    // Hash (consumes & zeroizes mutable PIN copy)
-    let hash = {
-        let mut working = pin1.clone();
-        let res = hash_pin(&mut working).map_err(|e| anyhow::anyhow!("hashing pin: {e}"))?;
-        working.zeroize();
-        res
-    };
-    // Zeroize original PIN buffers now that hashing is complete.
-    // (They might still be in terminal input buffers, but we clear our copies.)
-    let mut pin1_owned = pin1; // move then zeroize
-    let mut pin2_owned = pin2;
-    pin1_owned.zeroize();
-    pin2_owned.zeroize();
-
-    let path = format!("{}/{}.passwd", dir, user);
+    let name = format!("{key}.passwd");
+    let record = store.path(&name);
+    let path = record.display().to_string();
     // Reset fail counter on new PIN
-    let fail_path = format!("{}/{}.fail", dir, user);
+    let fail_path = store.path(&format!("{key}.fail"));
     let _ = fs::remove_file(&fail_path);
-    let mut f = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .mode(0o600)
-        .open(&path)?;
-    f.write_all(hash.as_bytes())?;
-    f.write_all(b"\n")?;
-    drop(f);
+    // keep when the first PIN was set, and keys a newer version wrote
+    let mut contents = store
+        .read_file(&name)
+        .ok()
+        .and_then(|old| HashRecord::parse(&old).ok())
+        .unwrap_or_default();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    contents.created_at.get_or_insert(now);
+    contents.changed_at = Some(now);
+    contents.scheme = pin_auth::Scheme::from_hash(&hash)
+        .ok()
+        .map(|info| info.scheme.name().to_string());
+    contents.hash = hash;
+    make_mutable(&record).with_context(|| format!("clearing immutable on {path}"))?;
+    write_atomic(
+        &record,
+        store.seal(&name, &contents.serialize()).as_bytes(),
+        0o600,
+    )
+    .with_context(|| format!("writing {path}"))?;
 
     // best-effort ownership/perms
     if Uid::effective().as_raw() == 0 {
-        let _ = chown(
-            std::path::Path::new(&path),
-            Some(Uid::from_raw(0)),
-            Some(Gid::from_raw(0)),
-        );
+        let _ = chown(&record, Some(Uid::from_raw(0)), Some(Gid::from_raw(0)));
         let _ = fs::set_permissions(&path, fs::Permissions::from_mode(0o600));
         let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o700));
     } else {
-        eprintln!("(Not root) Wrote {}. Consider:\n  sudo chown root:root {}\n  sudo chmod 0600 {}\n  sudo chmod 0700 {}\n", path, path, path, dir);
+        tracing::warn!("(Not root) Wrote {}. Consider:\n  sudo chown root:root {}\n  sudo chmod 0600 {}\n  sudo chmod 0700 {}\n", path, path, path, dir);
     }
+    protect(&config, &record);
+
+    audit(&config, &store, "set_pin", &user)?;
 
     println!("PIN hash saved to {}", path);
+    // First provisioning (or all used up): hand out a set with the PIN.
+    if group.is_none() && recovery_count > 0 && RecoveryCodes::load(&store, &key)?.remaining() == 0
+    {
+        issue_recovery_codes(&store, &config, &key, &user, recovery_count)?;
+    }
     Ok(())
 }
+
+/// Most a `--pin-fd` input is read, so a wrong descriptor cannot make
+/// `genpin` read without end.
+const PIN_INPUT_MAX: u64 = 4096;
+
+/// The PIN from file descriptor `fd` (`--pin-stdin` is 0): the first line,
+/// and the second, if any, as its confirmation.
+fn read_pin(fd: RawFd) -> Result<Zeroizing<String>> {
+    let mut input = Zeroizing::new(String::new());
+    if fd == 0 {
+        io::stdin().take(PIN_INPUT_MAX).read_to_string(&mut input)
+    } else {
+        nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_GETFD)
+            .with_context(|| format!("--pin-fd {fd}"))?;
+        // SAFETY: `fd` is open (checked above), and nothing else in genpin
+        // uses it; the file closes it when dropped.
+        let file = unsafe { fs::File::from_raw_fd(fd) };
+        file.take(PIN_INPUT_MAX).read_to_string(&mut input)
+    }
+    .context("reading the PIN")?;
+    let mut lines = input.split('\n').map(|l| l.trim_end_matches('\r'));
+    let pin = Zeroizing::new(lines.next().unwrap_or_default().to_string());
+    if let Some(confirm) = lines.next().filter(|l| !l.is_empty()) {
+        if confirm != pin.as_str() {
+            bail!("PINs do not match");
+        }
+    }
+    Ok(pin)
+}
+
+/// Ask for the new PIN until one passes the policy and is repeated
+/// correctly, at most `tries` times. What is wrong with a refused PIN is
+/// said before asking again, and an easily guessed one has to be
+/// confirmed. Every entry is wiped as soon as it is done with; the texts
+/// are in the user's language.
+fn prompt_new_pin(
+    validator: &PolicyValidator,
+    messages: &Messages,
+    tries: usize,
+) -> Result<Zeroizing<String>> {
+    for _ in 0..tries {
+        let pin = Zeroizing::new(prompt_password(messages.get("enter_pin", &[]))?);
+        if let Err(violation) = validator.validate(&pin) {
+            eprintln!("{}", messages.violation(&violation));
+            continue;
+        }
+        if let Some(weakness) = policy::weakness(&pin) {
+            let weakness = messages.weakness(weakness);
+            eprint!(
+                "{}",
+                messages.get("easy_to_guess", &[("weakness", &weakness)])
+            );
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer)?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                continue;
+            }
+        }
+        let repeated = Zeroizing::new(prompt_password(messages.get("repeat_pin", &[]))?);
+        if repeated != pin {
+            eprintln!("{}", messages.get("pins_differ", &[]));
+            continue;
+        }
+        return Ok(pin);
+    }
+    bail!("{}", messages.get("gave_up", &[("tries", &tries)]));
+}
+
+fn man_page() -> ManPage {
+    ManPage {
+        name: "genpin",
+        about: "set a user's or group's PIN",
+        synopsis: vec![
+            "genpin USER [--decoy | --recovery-codes | --print] [--pin-stdin | --pin-fd N]".into(),
+            "genpin @GROUP [--print] [--pin-stdin | --pin-fd N]".into(),
+        ],
+        description: vec![
+            "genpin asks for a new PIN, checks it against the policy in pin-auth.conf \
+             (length, charset, blacklist, reject_pins, min_entropy_bits) and writes its hash \
+             to the PIN directory, resetting the user's fail counter. A @GROUP PIN is shared \
+             by every member of the group."
+                .into(),
+            "Run as root. Without USER, genpin does nothing.".into(),
+        ],
+        lists: vec![(
+            "OPTIONS",
+            OPTIONS
+                .iter()
+                .map(|(option, text)| (option.to_string(), text.to_string()))
+                .collect(),
+        )],
+    }
+}
+
+/// With `immutable_hashes`, make the hash file at `path` immutable again
+/// after a change. A file system without the attribute gets a warning.
+fn protect(config: &ConfigResolver, path: &Path) {
+    if !config.flag("immutable_hashes").unwrap_or(false) {
+        return;
+    }
+    if let Err(e) = set_immutable(path, true) {
+        tracing::warn!("could not make {} immutable: {e}", path.display());
+    }
+}
+
+/// Replace `user`'s recovery codes (stored under `key`) with `count` new
+/// ones and print them; they are not shown again.
+fn issue_recovery_codes(
+    store: &DirStore,
+    config: &ConfigResolver,
+    key: &str,
+    user: &str,
+    count: usize,
+) -> Result<()> {
+    let (secrets, codes) =
+        RecoveryCodes::generate(config, count).context("hashing recovery codes")?;
+    let name = RecoveryCodes::file_name(key);
+    store
+        .write_file(&name, &codes.serialize())
+        .with_context(|| format!("writing {}", store.path(&name).display()))?;
+    audit(config, store, "set_recovery_codes", user)?;
+    println!("Recovery codes for {user} (each works once, only while the PIN is locked out):");
+    for secret in &secrets {
+        println!("    {}", RecoveryCodes::format(secret));
+    }
+    Ok(())
+}
+
+/// Append an admin record to the audit log, if enabled.
+fn audit(
+    config: &ConfigResolver,
+    store: &DirStore,
+    action: &'static str,
+    user: &str,
+) -> Result<()> {
+    if config.flag("audit").unwrap_or(false) {
+        let log = AuditLog::new(store.path("audit.log"));
+        log.append(&AuditEvent::Admin {
+            action,
+            user,
+            actor_uid: Uid::current().as_raw(),
+        })
+        .context("writing audit record")?;
+    }
+    Ok(())
+}
+
+/// `hash_pin_configured`, except that with `argon2_target_ms` set (and no
+/// explicit Argon2 costs) Argon2 costs are calibrated on this host first.
+fn hash_new_pin(pin: &Pin, config: &ConfigResolver) -> Result<String, PinHashError> {
+    #[cfg(feature = "argon2")]
+    {
+        use pin_auth::{hash_pin_with, scheme_from_config, tune, HashParams, Scheme};
+        if let Some(budget) = tune::target_from_config(config) {
+            if scheme_from_config(config) == Scheme::Argon2id
+                && HashParams::argon2_from_config(config).is_none()
+            {
+                let costs = tune::calibrate_argon2(budget)?;
+                tracing::info!(
+                    m_cost = costs.m_cost,
+                    t_cost = costs.t_cost,
+                    p_cost = costs.p_cost,
+                    budget_ms = budget.as_millis() as u64,
+                    "calibrated argon2 costs"
+                );
+                let params = HashParams {
+                    argon2: Some(costs),
+                    ..HashParams::default()
+                };
+                return hash_pin_with(pin, Scheme::Argon2id, &params);
+            }
+        }
+    }
+    hash_pin_configured(pin, config)
+}
@@ -0,0 +1,127 @@
+//! Attempt history in a local SQLite database (feature `sqlite`).
+//!
+//! One row per verification attempt; queried by `pinctl audit`.
+
+use rusqlite::{params, Connection};
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attempt {
+    pub ts: u64,
+    pub user: String,
+    pub result: String,
+    pub rhost: Option<String>,
+    pub service: Option<String>,
+    pub tty: Option<String>,
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// Open (creating with mode 0600 if needed) and migrate the database.
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let path = path.as_ref();
+        // Create the file ourselves so it never exists with a umask-derived mode.
+        let _ = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .custom_flags(nix::libc::O_NOFOLLOW)
+            .open(path);
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(2))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS attempts (
+                 id INTEGER PRIMARY KEY,
+                 ts INTEGER NOT NULL,
+                 user TEXT NOT NULL,
+                 result TEXT NOT NULL,
+                 rhost TEXT,
+                 service TEXT,
+                 tty TEXT
+             );
+             CREATE INDEX IF NOT EXISTS attempts_user_ts ON attempts(user, ts);",
+        )?;
+        // Databases created before the tty column existed.
+        let has_tty = conn
+            .prepare("SELECT 1 FROM pragma_table_info('attempts') WHERE name = 'tty'")?
+            .exists([])?;
+        if !has_tty {
+            conn.execute_batch("ALTER TABLE attempts ADD COLUMN tty TEXT")?;
+        }
+        Ok(History { conn })
+    }
+
+    pub fn record(&self, attempt: &Attempt) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO attempts (ts, user, result, rhost, service, tty)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                attempt.ts as i64,
+                attempt.user,
+                attempt.result,
+                attempt.rhost,
+                attempt.service,
+                attempt.tty
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Attempts at or after `since` (epoch seconds), optionally for one user,
+    /// oldest first.
+    pub fn query(&self, user: Option<&str>, since: u64) -> rusqlite::Result<Vec<Attempt>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ts, user, result, rhost, service, tty FROM attempts
+             WHERE ts >= ?1 AND (?2 IS NULL OR user = ?2)
+             ORDER BY ts, id",
+        )?;
+        let rows = stmt.query_map(params![since as i64, user], |row| {
+            Ok(Attempt {
+                ts: row.get::<_, i64>(0)? as u64,
+                user: row.get(1)?,
+                result: row.get(2)?,
+                rhost: row.get(3)?,
+                service: row.get(4)?,
+                tty: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_query() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = History::open(tmp.path().join("history.sqlite")).unwrap();
+        for (ts, user, result) in [
+            (100, "alice", "failure"),
+            (200, "bob", "success"),
+            (300, "alice", "success"),
+        ] {
+            db.record(&Attempt {
+                ts,
+                user: user.into(),
+                result: result.into(),
+                rhost: None,
+                service: Some("login".into()),
+                tty: Some("tty1".into()),
+            })
+            .unwrap();
+        }
+        let alice = db.query(Some("alice"), 0).unwrap();
+        assert_eq!(alice.len(), 2);
+        assert_eq!(alice[1].result, "success");
+        assert_eq!(db.query(None, 150).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,233 @@
+//! What the host allows a hash computation: CPUs and memory, after cgroup
+//! limits.
+//!
+//! cgroup limits are read for the process's own cgroup and every ancestor
+//! (a parent's limit applies to its children); the smallest wins. Only
+//! cgroup v2 and the v1 `cpu` and `memory` controllers are understood;
+//! anything else counts as unlimited.
+
+use crate::Argon2Costs;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Upper bound on automatically chosen Argon2 lanes (RFC 9106 uses 4).
+pub const MAX_AUTO_LANES: u32 = 4;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// CPUs this process may use: scheduler affinity and cgroup CPU quota.
+pub fn available_cpus() -> usize {
+    let affinity = std::thread::available_parallelism().map_or(1, |n| n.get());
+    match cgroup_cpu_limit() {
+        Some(quota) => affinity.min(quota),
+        None => affinity,
+    }
+}
+
+/// Argon2 `p_cost` when none is configured: available CPUs, capped at
+/// [`MAX_AUTO_LANES`].
+pub fn argon2_lanes() -> u32 {
+    u32::try_from(available_cpus())
+        .unwrap_or(u32::MAX)
+        .clamp(1, MAX_AUTO_LANES)
+}
+
+/// cgroup v2 `cpu.max` (`"<quota> <period>"` or `"max <period>"`) as whole
+/// CPUs, rounded up.
+pub fn parse_cpu_max(contents: &str) -> Option<usize> {
+    let mut fields = contents.split_whitespace();
+    let quota = fields.next()?;
+    let period = fields
+        .next()
+        .map_or(Some(100_000), |p| p.parse::<u64>().ok())?;
+    cpus_from_quota(quota.parse::<i64>().ok()?, period)
+}
+
+fn cpus_from_quota(quota: i64, period: u64) -> Option<usize> {
+    // v1 uses -1 for "no limit"
+    let quota = u64::try_from(quota).ok().filter(|&q| q > 0)?;
+    if period == 0 {
+        return None;
+    }
+    usize::try_from(quota.div_ceil(period).max(1)).ok()
+}
+
+fn cgroup_cpu_limit() -> Option<usize> {
+    let v2 = cgroup_min(&cgroup_dirs(CGROUP_ROOT, "")?, |dir| {
+        parse_cpu_max(&fs::read_to_string(dir.join("cpu.max")).ok()?)
+    });
+    v2.or_else(|| {
+        let root = format!("{CGROUP_ROOT}/cpu");
+        cgroup_min(&cgroup_dirs(&root, "cpu")?, |dir| {
+            let read = |name| fs::read_to_string(dir.join(name)).ok();
+            let quota = read("cpu.cfs_quota_us")?.trim().parse::<i64>().ok()?;
+            let period = read("cpu.cfs_period_us")?.trim().parse::<u64>().ok()?;
+            cpus_from_quota(quota, period)
+        })
+    })
+}
+
+/// cgroup v2 `memory.max` or v1 `memory.limit_in_bytes`, in bytes; `max`
+/// and v1's "unlimited" (close to `i64::MAX`) are `None`.
+pub fn parse_memory_limit(contents: &str) -> Option<u64> {
+    contents
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&bytes| bytes < 1 << 60)
+}
+
+/// Bytes this process's cgroup may still allocate (limit minus current
+/// usage, smallest along the hierarchy); `None` when unlimited.
+pub fn memory_headroom() -> Option<u64> {
+    let headroom = |dir: &Path, limit: &str, usage: &str| {
+        let limit = parse_memory_limit(&fs::read_to_string(dir.join(limit)).ok()?)?;
+        let usage = fs::read_to_string(dir.join(usage))
+            .ok()
+            .and_then(|u| u.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        Some(limit.saturating_sub(usage))
+    };
+    let v2 = cgroup_dirs(CGROUP_ROOT, "")
+        .and_then(|dirs| cgroup_min(&dirs, |d| headroom(d, "memory.max", "memory.current")));
+    v2.or_else(|| {
+        let root = format!("{CGROUP_ROOT}/memory");
+        cgroup_min(&cgroup_dirs(&root, "memory")?, |d| {
+            headroom(d, "memory.limit_in_bytes", "memory.usage_in_bytes")
+        })
+    })
+}
+
+/// Largest Argon2 `m_cost` (KiB) for `headroom` bytes: half of it, so the
+/// rest of the login process and anything else in the cgroup keep room.
+/// Whole MiB where possible, never below the backend minimum of 8 KiB per lane.
+pub fn max_m_cost(headroom: u64, p_cost: u32) -> u32 {
+    let kib = headroom / 2 / 1024;
+    let kib = if kib >= 1024 { kib / 1024 * 1024 } else { kib };
+    u32::try_from(kib)
+        .unwrap_or(u32::MAX)
+        .max(8 * p_cost.max(1))
+}
+
+/// Lower `costs.m_cost` to fit the cgroup memory limit, logging a warning
+/// when it does. Returns whether it clamped.
+pub fn clamp_argon2_memory(costs: &mut Argon2Costs) -> bool {
+    let Some(headroom) = memory_headroom() else {
+        return false;
+    };
+    clamp_m_cost(costs, headroom)
+}
+
+fn clamp_m_cost(costs: &mut Argon2Costs, headroom: u64) -> bool {
+    let max = max_m_cost(headroom, costs.p_cost);
+    if costs.m_cost <= max {
+        return false;
+    }
+    tracing::warn!(
+        requested_kib = costs.m_cost,
+        clamped_kib = max,
+        headroom_bytes = headroom,
+        "argon2 memory cost clamped to the cgroup memory limit"
+    );
+    costs.m_cost = max;
+    true
+}
+
+/// The process's cgroup directory under `root` and each ancestor up to
+/// `root`, innermost first. `controller` selects the v1 hierarchy; `""`
+/// is the v2 unified one.
+pub(crate) fn cgroup_dirs(root: &str, controller: &str) -> Option<Vec<PathBuf>> {
+    let membership = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let rel = own_cgroup(&membership, controller)?;
+    let root = Path::new(root);
+    let mut dir = root.join(rel.trim_start_matches('/'));
+    let mut dirs = vec![dir.clone()];
+    while dir != root && dir.pop() {
+        dirs.push(dir.clone());
+    }
+    Some(dirs)
+}
+
+/// The cgroup path for `controller` from `/proc/self/cgroup` contents
+/// (`hierarchy-id:controllers:path` lines; v2 is `0::path`).
+pub(crate) fn own_cgroup<'a>(membership: &'a str, controller: &str) -> Option<&'a str> {
+    membership.lines().find_map(|line| {
+        let mut parts = line.splitn(3, ':');
+        let (_, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+        let matches = if controller.is_empty() {
+            controllers.is_empty()
+        } else {
+            controllers.split(',').any(|c| c == controller)
+        };
+        matches.then_some(path)
+    })
+}
+
+/// Smallest limit found along `dirs`.
+pub(crate) fn cgroup_min<T: Ord>(
+    dirs: &[PathBuf],
+    limit: impl Fn(&Path) -> Option<T>,
+) -> Option<T> {
+    dirs.iter().filter_map(|d| limit(d)).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_quota_parsing() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("200000 100000\n"), Some(2));
+        assert_eq!(parse_cpu_max("150000 100000"), Some(2));
+        assert_eq!(parse_cpu_max("50000 100000"), Some(1));
+        assert_eq!(parse_cpu_max("garbage"), None);
+        assert_eq!(cpus_from_quota(-1, 100_000), None);
+        assert!((1..=MAX_AUTO_LANES).contains(&argon2_lanes()));
+        assert!(available_cpus() >= 1);
+    }
+
+    #[test]
+    fn memory_limits() {
+        assert_eq!(parse_memory_limit("max\n"), None);
+        assert_eq!(parse_memory_limit("9223372036854771712\n"), None);
+        assert_eq!(parse_memory_limit("67108864\n"), Some(64 << 20));
+
+        let mut costs = Argon2Costs {
+            m_cost: 65_536,
+            t_cost: 3,
+            p_cost: 4,
+        };
+        // 256 MiB free: 64 MiB fits
+        assert!(!clamp_m_cost(&mut costs, 256 << 20));
+        // 64 MiB free: half of it
+        assert!(clamp_m_cost(&mut costs, 64 << 20));
+        assert_eq!(costs.m_cost, 32_768);
+        // nearly nothing: backend minimum
+        assert!(clamp_m_cost(&mut costs, 4096));
+        assert_eq!(costs.m_cost, 32);
+        assert_eq!(max_m_cost(1 << 20, 1), 512);
+    }
+
+    #[test]
+    fn cgroup_membership() {
+        let v2 = "0::/system.slice/sshd.service\n";
+        assert_eq!(own_cgroup(v2, ""), Some("/system.slice/sshd.service"));
+        let v1 =
+            "12:memory:/user.slice\n4:cpu,cpuacct:/user.slice/user-0.slice\n1:name=systemd:/x\n";
+        assert_eq!(own_cgroup(v1, "cpu"), Some("/user.slice/user-0.slice"));
+        assert_eq!(own_cgroup(v1, "memory"), Some("/user.slice"));
+        assert_eq!(own_cgroup(v1, ""), None);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let leaf = tmp.path().join("a/b");
+        fs::create_dir_all(&leaf).unwrap();
+        fs::write(tmp.path().join("a/cpu.max"), "100000 100000").unwrap();
+        fs::write(leaf.join("cpu.max"), "max 100000").unwrap();
+        let dirs = [leaf, tmp.path().join("a"), tmp.path().to_path_buf()];
+        let min = cgroup_min(&dirs, |d| {
+            parse_cpu_max(&fs::read_to_string(d.join("cpu.max")).ok()?)
+        });
+        assert_eq!(min, Some(1));
+    }
+}
@@ -0,0 +1,248 @@
+//! HOTP (RFC 4226) one-time codes from tokens without a clock (feature
+//! `hotp`).
+//!
+//! An enrolled user (`pinctl hotp USER`) may enter the token's current code
+//! instead of the PIN. `<user>.hotp` holds `<counter> <digits> <hex secret>`;
+//! the counter is the next value the token is expected to show. Codes up to
+//! `hotp_window` presses ahead are accepted, and the counter moves past the
+//! one used, so no code works twice. A token pressed further ahead than that
+//! is resynchronized with two consecutive codes (`pinctl hotp USER --resync`).
+
+use crate::fail::{Clock, FailStore, FailTracker};
+use crate::store::PinStore;
+use crate::{hex, parse_hex};
+use hmac::{Hmac, Mac};
+use std::io;
+use zeroize::Zeroizing;
+
+/// Look-ahead when `hotp_window` is not set.
+pub const DEFAULT_WINDOW: u64 = 10;
+/// How far `--resync` searches for the two codes.
+pub const RESYNC_WINDOW: u64 = 1000;
+/// Secret length `pinctl hotp` generates (the RFC's recommended 160 bits).
+pub const SECRET_LEN: usize = 20;
+
+/// The RFC 4226 value for `counter`: HMAC-SHA1, dynamic truncation, then
+/// the low `digits` decimal digits.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac =
+        <Hmac<sha1::Sha1> as Mac>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(&counter.to_be_bytes());
+    let tag = mac.finalize().into_bytes();
+    let offset = usize::from(tag[19] & 0x0f);
+    let binary = u32::from_be_bytes([
+        tag[offset] & 0x7f,
+        tag[offset + 1],
+        tag[offset + 2],
+        tag[offset + 3],
+    ]);
+    binary % 10u32.pow(digits)
+}
+
+/// One user's token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HotpToken {
+    pub secret: Zeroizing<Vec<u8>>,
+    /// Next counter value expected from the token.
+    pub counter: u64,
+    /// Code length: 6, 7 or 8.
+    pub digits: u32,
+}
+
+impl HotpToken {
+    /// File name of `user`'s token.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.hotp")
+    }
+
+    /// A token with a new random secret, counter 0.
+    pub fn generate(digits: u32) -> Self {
+        let mut secret = Zeroizing::new(vec![0u8; SECRET_LEN]);
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret);
+        HotpToken {
+            secret,
+            counter: 0,
+            digits,
+        }
+    }
+
+    /// `user`'s token from `store`; `None` if not enrolled.
+    pub fn load(store: &dyn PinStore, user: &str) -> io::Result<Option<Self>> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => Zeroizing::new(raw),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HOTP record");
+        let mut fields = raw.split_whitespace();
+        let counter = fields.next().and_then(|c| c.parse().ok());
+        let digits = fields
+            .next()
+            .and_then(|d| d.parse().ok())
+            .filter(|d| (6..=8).contains(d));
+        let secret = fields.next().and_then(parse_hex);
+        match (counter, digits, secret, fields.next()) {
+            (Some(counter), Some(digits), Some(secret), None) => Ok(Some(HotpToken {
+                secret,
+                counter,
+                digits,
+            })),
+            _ => Err(malformed()),
+        }
+    }
+
+    /// The record as written to `<user>.hotp`.
+    pub fn serialize(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{} {} {}\n",
+            self.counter,
+            self.digits,
+            hex(&self.secret).as_str()
+        ))
+    }
+
+    /// The code the token shows at `counter`, zero-padded.
+    pub fn code(&self, counter: u64) -> String {
+        format!(
+            "{:0width$}",
+            hotp(&self.secret, counter, self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Whether `code` has the shape of this token's codes: `digits` digits.
+    pub fn fits(&self, code: &str) -> bool {
+        code.len() == self.digits as usize && code.bytes().all(|b| b.is_ascii_digit())
+    }
+
+    /// Counter value `code` was generated at, looking `window` presses
+    /// ahead of the expected one.
+    pub fn verify(&self, code: &str, window: u64) -> Option<u64> {
+        if !self.fits(code) {
+            return None;
+        }
+        // every candidate is computed, so timing does not reveal the offset
+        (self.counter..=self.counter.saturating_add(window))
+            .filter(|&c| crate::constant_time_eq(self.code(c).as_bytes(), code.as_bytes()))
+            .min()
+    }
+
+    /// Counter after `first` and `second`, consecutive codes within
+    /// [`RESYNC_WINDOW`] presses ahead.
+    pub fn resync(&self, first: &str, second: &str) -> Option<u64> {
+        let mut at = self.counter;
+        let end = self.counter.saturating_add(RESYNC_WINDOW);
+        while at < end {
+            let ahead = HotpToken {
+                counter: at,
+                ..self.clone()
+            };
+            let found = ahead.verify(first, end - at)?;
+            if self.code(found + 1) == second {
+                return Some(found + 2);
+            }
+            at = found + 1;
+        }
+        None
+    }
+
+    /// The secret in unpadded RFC 4648 base32, as authenticator apps take it.
+    pub fn secret_base32(&self) -> Zeroizing<String> {
+        const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+        let mut out = Zeroizing::new(String::new());
+        for chunk in self.secret.chunks(5) {
+            let mut buf = [0u8; 5];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let bits = buf.iter().fold(0u64, |acc, &b| acc << 8 | u64::from(b));
+            let chars = (chunk.len() * 8).div_ceil(5);
+            for i in 0..chars {
+                let index = (bits >> (35 - i * 5)) & 0x1f;
+                out.push(char::from(ALPHABET[index as usize]));
+            }
+        }
+        out
+    }
+
+    /// `otpauth://` URI for enrolling a soft token (e.g. as a QR code).
+    pub fn otpauth_uri(&self, user: &str) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "otpauth://hotp/pin-auth:{user}?secret={}&counter={}&digits={}&issuer=pin-auth",
+            self.secret_base32().as_str(),
+            self.counter,
+            self.digits
+        ))
+    }
+}
+
+/// Store the counter past `matched` (from [`crate::auth::Outcome::Hotp`]),
+/// then clear the fail state. If the record cannot be written nothing is
+/// cleared and access must be refused, so the code cannot be replayed.
+pub fn redeem_hotp<S: FailStore, C: Clock>(
+    store: &dyn PinStore,
+    user: &str,
+    token: Option<&HotpToken>,
+    matched: u64,
+    tracker: &mut FailTracker<S, C>,
+) -> io::Result<()> {
+    let Some(token) = token else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no HOTP token"));
+    };
+    let advanced = HotpToken {
+        counter: matched.saturating_add(1),
+        ..token.clone()
+    };
+    store.write_file(&HotpToken::file_name(user), &advanced.serialize())?;
+    tracker.record_success()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4226 appendix D
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+    const RFC_CODES: [&str; 10] = [
+        "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871",
+        "520489",
+    ];
+
+    #[test]
+    fn rfc4226_vectors() {
+        let token = HotpToken {
+            secret: Zeroizing::new(RFC_SECRET.to_vec()),
+            counter: 0,
+            digits: 6,
+        };
+        for (counter, code) in RFC_CODES.iter().enumerate() {
+            assert_eq!(token.code(counter as u64), *code);
+        }
+        assert_eq!(
+            token.secret_base32().as_str(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+    }
+
+    #[test]
+    fn window_and_resync() {
+        let token = HotpToken {
+            secret: Zeroizing::new(RFC_SECRET.to_vec()),
+            counter: 2,
+            digits: 6,
+        };
+        assert_eq!(token.verify("359152", 0), Some(2));
+        assert_eq!(token.verify("399871", 10), Some(8));
+        assert_eq!(token.verify("399871", 3), None);
+        // behind the counter: already used
+        assert_eq!(token.verify("755224", 10), None);
+        assert_eq!(token.verify("35915", 10), None);
+        assert_eq!(token.resync("162583", "399871"), Some(9));
+        assert_eq!(token.resync("162583", "520489"), None);
+
+        let store = crate::store::MemoryStore::new()
+            .with_file("alice.hotp", token.serialize().as_str())
+            .with_file("bob.hotp", "1 6 xyz\n");
+        assert_eq!(HotpToken::load(&store, "alice").unwrap(), Some(token));
+        assert!(HotpToken::load(&store, "bob").is_err());
+        assert_eq!(HotpToken::load(&store, "carol").unwrap(), None);
+    }
+}
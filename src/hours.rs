@@ -0,0 +1,259 @@
+//! Time-of-day access windows from `<dir>/access-hours`.
+//!
+//! One rule per line, `#` comments allowed:
+//!
+//! ```text
+//! kiosk           Mon-Fri 07:30-19:00  Sat 09:00-13:00
+//! service=gdm     * 06:00-23:00
+//! night-shift     Mon-Fri 22:00-06:00
+//! ```
+//!
+//! The first field is a user name or `service=<PAM service>`; the rest are
+//! `DAYS HH:MM-HH:MM` pairs in local time. DAYS is `*`, a day (`Mon`), a
+//! range (`Mon-Fri`) or a comma list of those. A window ending before it
+//! starts runs past midnight into the next day. When a user rule and a
+//! service rule both match an attempt, it must fall inside both. Users and
+//! services without a rule are not restricted.
+
+use std::io;
+use std::str::FromStr;
+
+/// File name in the PIN directory.
+pub const FILE_NAME: &str = "access-hours";
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Why `access-hours` could not be used.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum HoursError {
+    #[error("access-hours line {line}: {msg}")]
+    Syntax { line: usize, msg: String },
+    #[error("reading access-hours: {0}")]
+    Read(#[from] io::Error),
+}
+
+/// A moment in local time, as the rules see it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalTime {
+    /// 0 = Monday.
+    pub weekday: u8,
+    /// Minutes since midnight.
+    pub minute: u16,
+}
+
+impl LocalTime {
+    /// Now, in the system time zone.
+    pub fn now() -> Self {
+        // SAFETY: time(NULL) has no preconditions; localtime_r only writes
+        // the `tm` it is given.
+        let tm = unsafe {
+            let now = nix::libc::time(std::ptr::null_mut());
+            let mut tm = std::mem::zeroed::<nix::libc::tm>();
+            nix::libc::localtime_r(&now, &mut tm);
+            tm
+        };
+        LocalTime {
+            // tm_wday: 0 = Sunday
+            weekday: ((tm.tm_wday + 6) % 7) as u8,
+            minute: (tm.tm_hour * 60 + tm.tm_min) as u16,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Window {
+    /// Bit `d` set: the window opens on weekday `d`.
+    days: u8,
+    start: u16,
+    end: u16,
+}
+
+impl Window {
+    fn contains(&self, at: LocalTime) -> bool {
+        let opens = |day: u8| self.days & (1 << day) != 0;
+        if self.start < self.end {
+            opens(at.weekday) && (self.start..self.end).contains(&at.minute)
+        } else {
+            // past midnight: the tail belongs to the previous day's window
+            (opens(at.weekday) && at.minute >= self.start)
+                || (opens((at.weekday + 6) % 7) && at.minute < self.end)
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    /// `DAYS HH:MM-HH:MM`, with the two parts separated by one space.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (days, times) = s.split_once(' ').ok_or("expected DAYS HH:MM-HH:MM")?;
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| format!("{times:?} is not HH:MM-HH:MM"))?;
+        let (start, end) = (parse_minute(start)?, parse_minute(end)?);
+        if start == end {
+            return Err(format!("{times:?} is empty"));
+        }
+        Ok(Window {
+            days: parse_days(days)?,
+            start,
+            end,
+        })
+    }
+}
+
+fn parse_minute(s: &str) -> Result<u16, String> {
+    let bad = || format!("{s:?} is not HH:MM");
+    let (h, m) = s.split_once(':').ok_or_else(bad)?;
+    let (h, m): (u16, u16) = (h.parse().map_err(|_| bad())?, m.parse().map_err(|_| bad())?);
+    // 24:00 closes a window at midnight
+    if m > 59 || h > 24 || (h == 24 && m != 0) {
+        return Err(bad());
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_days(s: &str) -> Result<u8, String> {
+    if s == "*" {
+        return Ok(0x7f);
+    }
+    let day = |name: &str| {
+        DAY_NAMES
+            .iter()
+            .position(|d| d.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown day {name:?}"))
+    };
+    let mut days = 0u8;
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (day(from)?, day(to)?);
+                // Fri-Mon wraps over the weekend
+                let mut d = from;
+                loop {
+                    days |= 1 << d;
+                    if d == to {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days |= 1 << day(part)?,
+        }
+    }
+    Ok(days)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Subject {
+    User(String),
+    Service(String),
+}
+
+/// The parsed rules.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessHours {
+    rules: Vec<(Subject, Vec<Window>)>,
+}
+
+impl AccessHours {
+    pub fn parse(text: &str) -> Result<Self, HoursError> {
+        let mut rules = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let syntax = |msg: String| HoursError::Syntax { line: n + 1, msg };
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut fields = line.split_whitespace();
+            let Some(who) = fields.next() else {
+                continue;
+            };
+            let subject = match who.strip_prefix("service=") {
+                Some(service) => Subject::Service(service.to_string()),
+                None => Subject::User(who.to_string()),
+            };
+            let rest: Vec<&str> = fields.collect();
+            if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                return Err(syntax(format!("{who}: expected DAYS HH:MM-HH:MM pairs")));
+            }
+            let windows = rest
+                .chunks(2)
+                .map(|pair| pair.join(" ").parse())
+                .collect::<Result<_, _>>()
+                .map_err(syntax)?;
+            rules.push((subject, windows));
+        }
+        Ok(AccessHours { rules })
+    }
+
+    /// The rules in `store`; none if the file does not exist.
+    pub fn load(store: &dyn crate::store::PinStore) -> Result<Self, HoursError> {
+        match store.read_file(FILE_NAME) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether `user` may authenticate through `service` at `at`.
+    pub fn allows(&self, user: &str, service: Option<&str>, at: LocalTime) -> bool {
+        self.rules
+            .iter()
+            .filter(|(subject, _)| match subject {
+                Subject::User(u) => u == user,
+                Subject::Service(s) => Some(s.as_str()) == service,
+            })
+            .all(|(_, windows)| windows.iter().any(|w| w.contains(at)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(weekday: u8, hh: u16, mm: u16) -> LocalTime {
+        LocalTime {
+            weekday,
+            minute: hh * 60 + mm,
+        }
+    }
+
+    #[test]
+    fn windows() {
+        let hours = AccessHours::parse(
+            "# kiosk accounts\n\
+             kiosk  Mon-Fri 07:30-19:00  Sat 09:00-13:00\n\
+             night  Fri-Mon 22:00-06:00\n\
+             service=gdm * 06:00-24:00\n",
+        )
+        .unwrap();
+        assert!(hours.allows("kiosk", None, at(0, 7, 30)));
+        assert!(!hours.allows("kiosk", None, at(0, 19, 0)));
+        assert!(hours.allows("kiosk", None, at(5, 12, 59)));
+        assert!(!hours.allows("kiosk", None, at(6, 12, 0)));
+        // both rules apply
+        assert!(!hours.allows("kiosk", Some("gdm"), at(2, 5, 0)));
+        assert!(hours.allows("kiosk", Some("gdm"), at(2, 8, 0)));
+        assert!(hours.allows("alice", Some("gdm"), at(6, 23, 59)));
+        assert!(hours.allows("alice", Some("sshd"), at(6, 3, 0)));
+        // Fri-Mon nights: Friday evening through Tuesday early morning
+        assert!(hours.allows("night", None, at(4, 23, 0)));
+        assert!(hours.allows("night", None, at(1, 5, 59)));
+        assert!(!hours.allows("night", None, at(2, 5, 0)));
+        assert!(!hours.allows("night", None, at(4, 5, 0)));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for bad in [
+            "kiosk",
+            "kiosk Mon-Fri",
+            "kiosk Mon-Fri 08:00",
+            "kiosk Funday 08:00-09:00",
+            "kiosk * 08:00-08:00",
+            "kiosk * 25:00-26:00",
+        ] {
+            let err = AccessHours::parse(&format!("# header\n{bad}\n")).unwrap_err();
+            assert!(err.to_string().contains("line 2"), "{bad}: {err}");
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Translations of what users see: `genpin`'s prompts and `check_pin`'s
+//! login-screen text (shown with `pam_exec ... stdout`).
+//!
+//! A catalog is a file `messages.<lang>` in the PIN directory, one
+//! `id = text` line per message, gettext-style: ids missing from it stay
+//! English. Quote a text to keep blanks at its ends; `{name}` is replaced
+//! by the value the message is given:
+//!
+//! ```text
+//! # /etc/pin.d/messages.de
+//! enter_pin = "Neue PIN eingeben: "
+//! pins_differ = Die PINs stimmen nicht überein
+//! ```
+//!
+//! The language is `locale` from `pin-auth.conf`, else the first of
+//! `LC_ALL`, `LC_MESSAGES` and `LANG` that is set; `de_AT.UTF-8` looks for
+//! `messages.de_AT`, then `messages.de`.
+
+use crate::config::ConfigResolver;
+use crate::policy::{PinWeakness, PolicyViolation};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+/// Every message id, with its English text.
+pub const ENGLISH: &[(&str, &str)] = &[
+    ("enter_pin", "Enter new PIN: "),
+    ("repeat_pin", "Repeat new PIN: "),
+    ("pins_differ", "PINs do not match"),
+    (
+        "easy_to_guess",
+        "This PIN is easy to guess ({weakness}). Use it anyway? [y/N] ",
+    ),
+    ("gave_up", "no PIN set after {tries} tries"),
+    ("too_short", "PIN shorter than minimum ({min})"),
+    ("too_long", "PIN longer than allowed maximum ({max})"),
+    ("non_digit", "PIN must contain only digits (0-9)"),
+    ("outside_charset", "PIN must contain only {charset}"),
+    ("blacklisted", "PIN is on the blacklist"),
+    ("account_uid", "PIN is the account's UID"),
+    ("keyboard_run", "PIN is a row or column of the keypad"),
+    ("from_username", "PIN is taken from the username"),
+    (
+        "low_entropy",
+        "PIN is too easy to guess (less than {min_bits} bits)",
+    ),
+    ("weak_repeated", "the same digit repeated"),
+    ("weak_sequential", "sequential digits"),
+    ("weak_pattern", "a repeated pattern"),
+    ("last_login", "Last PIN login: {last}"),
+];
+
+/// The messages in the configured language.
+#[derive(Clone, Debug, Default)]
+pub struct Messages {
+    catalog: HashMap<String, String>,
+}
+
+impl Messages {
+    /// The catalog for the configured language from `dir`. English if there
+    /// is none; one that cannot be read is logged and English used.
+    pub fn load(dir: &Path, config: &ConfigResolver) -> Self {
+        let Some(locale) = config
+            .string("locale")
+            .map(str::to_string)
+            .or_else(env_locale)
+        else {
+            return Messages::default();
+        };
+        for lang in candidates(&locale) {
+            let path = dir.join(format!("messages.{lang}"));
+            match crate::store::read_bytes_nofollow(&path) {
+                Ok(raw) => return Messages::parse(&String::from_utf8_lossy(&raw)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    tracing::warn!(error = %e, "cannot read {}", path.display());
+                    break;
+                }
+            }
+        }
+        Messages::default()
+    }
+
+    /// A catalog's `id = text` lines; `#` comments and blank lines are
+    /// skipped, as are lines without `=`.
+    pub fn parse(text: &str) -> Self {
+        let catalog = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| l.split_once('='))
+            .map(|(id, text)| {
+                let text = text.trim();
+                let text = text
+                    .strip_prefix('"')
+                    .and_then(|t| t.strip_suffix('"'))
+                    .unwrap_or(text);
+                (id.trim().to_string(), text.to_string())
+            })
+            .collect();
+        Messages { catalog }
+    }
+
+    /// Message `id` with each `{name}` replaced by its value in `args`.
+    pub fn get(&self, id: &str, args: &[(&str, &dyn Display)]) -> String {
+        let text = self
+            .catalog
+            .get(id)
+            .map(String::as_str)
+            .or_else(|| ENGLISH.iter().find(|(i, _)| *i == id).map(|(_, t)| *t))
+            .unwrap_or(id);
+        args.iter().fold(text.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), &value.to_string())
+        })
+    }
+
+    pub fn violation(&self, violation: &PolicyViolation) -> String {
+        match violation {
+            PolicyViolation::TooShort { min } => self.get("too_short", &[("min", min)]),
+            PolicyViolation::TooLong { max } => self.get("too_long", &[("max", max)]),
+            PolicyViolation::NonDigit => self.get("non_digit", &[]),
+            PolicyViolation::OutsideCharset(charset) => {
+                self.get("outside_charset", &[("charset", charset)])
+            }
+            PolicyViolation::Blacklisted => self.get("blacklisted", &[]),
+            PolicyViolation::AccountUid => self.get("account_uid", &[]),
+            PolicyViolation::KeyboardRun => self.get("keyboard_run", &[]),
+            PolicyViolation::FromUsername => self.get("from_username", &[]),
+            PolicyViolation::LowEntropy { min_bits } => {
+                self.get("low_entropy", &[("min_bits", min_bits)])
+            }
+        }
+    }
+
+    pub fn weakness(&self, weakness: PinWeakness) -> String {
+        self.get(
+            match weakness {
+                PinWeakness::Repeated => "weak_repeated",
+                PinWeakness::Sequential => "weak_sequential",
+                PinWeakness::Pattern => "weak_pattern",
+            },
+            &[],
+        )
+    }
+}
+
+/// The locale from the environment, as gettext picks it.
+fn env_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+}
+
+/// Catalog names to try for `locale`, most specific first: `de_AT.UTF-8@euro`
+/// gives `de_AT`, `de`. None for `C`, `POSIX` or anything not a plain
+/// language tag.
+fn candidates(locale: &str) -> Vec<String> {
+    let lang = locale
+        .split(['.', '@'])
+        .next()
+        .unwrap_or_default()
+        .replace('-', "_");
+    let valid = !lang.is_empty()
+        && lang != "C"
+        && lang != "POSIX"
+        && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Vec::new();
+    }
+    let mut names = vec![lang.clone()];
+    if let Some((base, _)) = lang.split_once('_') {
+        names.push(base.to_string());
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogs() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(
+            tmp.path().join("messages.de"),
+            "# Deutsch\nenter_pin = \"Neue PIN eingeben: \"\n\
+             too_short = PIN kürzer als {min} Stellen\n",
+        )
+        .unwrap();
+        let config = ConfigResolver::default()
+            .with_file("locale = de_AT.UTF-8\n", "pin-auth.conf")
+            .unwrap();
+        let de = Messages::load(tmp.path(), &config);
+        assert_eq!(de.get("enter_pin", &[]), "Neue PIN eingeben: ");
+        assert_eq!(
+            de.violation(&PolicyViolation::TooShort { min: 4 }),
+            "PIN kürzer als 4 Stellen"
+        );
+        // not translated: English
+        assert_eq!(de.get("pins_differ", &[]), "PINs do not match");
+        assert_eq!(
+            de.get("last_login", &[("last", &"yesterday")]),
+            "Last PIN login: yesterday"
+        );
+
+        let config = ConfigResolver::default()
+            .with_file("locale = fr_FR\n", "pin-auth.conf")
+            .unwrap();
+        let fr = Messages::load(tmp.path(), &config);
+        assert_eq!(fr.get("enter_pin", &[]), "Enter new PIN: ");
+        assert_eq!(candidates("C.UTF-8"), Vec::<String>::new());
+        assert_eq!(candidates("../../x"), Vec::<String>::new());
+        assert_eq!(candidates("pt-BR"), ["pt_BR", "pt"]);
+
+        let shipped = Messages::parse(include_str!("../contrib/locale/messages.de"));
+        for (id, _) in ENGLISH {
+            assert!(shipped.catalog.contains_key(*id), "messages.de lacks {id}");
+        }
+
+        // the English texts are the errors' own
+        for v in [
+            PolicyViolation::TooLong { max: 6 },
+            PolicyViolation::OutsideCharset(crate::policy::Charset::Hex),
+            PolicyViolation::LowEntropy { min_bits: 16 },
+        ] {
+            assert_eq!(Messages::default().violation(&v), v.to_string());
+        }
+        assert_eq!(
+            Messages::default().weakness(PinWeakness::Pattern),
+            PinWeakness::Pattern.to_string()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The immutable file attribute (`chattr +i`) on hash files
+//! (`immutable_hashes`).
+//!
+//! An immutable file cannot be written, renamed over or removed, even by
+//! root, until the attribute is cleared again, which takes
+//! `CAP_LINUX_IMMUTABLE`. `genpin` and `pinctl` clear it before they
+//! replace or remove a record and set it again afterwards, so a stray
+//! `echo > alice.passwd` or a script that does not know about it fails
+//! instead of silently changing a PIN. It is a guard against mistakes and
+//! careless tooling, not against an attacker with root, who can clear it
+//! just as well. Only Linux file systems with `FS_IMMUTABLE_FL` (ext4, XFS,
+//! Btrfs, ...) have it; elsewhere setting it fails with
+//! [`io::ErrorKind::Unsupported`].
+
+use std::io;
+use std::path::Path;
+
+/// `FS_IMMUTABLE_FL` from `linux/fs.h`.
+#[cfg(target_os = "linux")]
+const FS_IMMUTABLE_FL: nix::libc::c_int = 0x10;
+
+/// Whether `path` has the immutable attribute. `false` where the file
+/// system has no such attribute.
+#[cfg(target_os = "linux")]
+pub fn is_immutable(path: &Path) -> io::Result<bool> {
+    match flags(&open(path)?) {
+        Ok(flags) => Ok(flags & FS_IMMUTABLE_FL != 0),
+        Err(e) if unsupported(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Set or clear the immutable attribute on `path`.
+#[cfg(target_os = "linux")]
+pub fn set_immutable(path: &Path, on: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = open(path)?;
+    let old = flags(&file).map_err(|e| {
+        if unsupported(&e) {
+            io::Error::new(io::ErrorKind::Unsupported, e)
+        } else {
+            e
+        }
+    })?;
+    let new = if on {
+        old | FS_IMMUTABLE_FL
+    } else {
+        old & !FS_IMMUTABLE_FL
+    };
+    if new == old {
+        return Ok(());
+    }
+    // SAFETY: the kernel reads one int from `new`
+    if unsafe { nix::libc::ioctl(file.as_raw_fd(), nix::libc::FS_IOC_SETFLAGS, &new) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn open(path: &Path) -> io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // read-only: an immutable file cannot be opened for writing
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC | nix::libc::O_NONBLOCK)
+        .open(path)
+}
+
+#[cfg(target_os = "linux")]
+fn flags(file: &std::fs::File) -> io::Result<nix::libc::c_int> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut flags: nix::libc::c_int = 0;
+    // SAFETY: the kernel writes one int to `flags`
+    if unsafe { nix::libc::ioctl(file.as_raw_fd(), nix::libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags)
+}
+
+#[cfg(target_os = "linux")]
+fn unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(nix::libc::ENOTTY | nix::libc::EOPNOTSUPP | nix::libc::EINVAL)
+    )
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_immutable(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_immutable(_path: &Path, _on: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no immutable attribute on this platform",
+    ))
+}
+
+/// Clear the attribute from `path` if it has it, before the file is
+/// replaced or removed. A missing file is fine.
+pub fn make_mutable(path: &Path) -> io::Result<()> {
+    match is_immutable(path) {
+        Ok(true) => set_immutable(path, false),
+        Ok(false) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_clear() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("alice.passwd");
+        make_mutable(&path).unwrap();
+        std::fs::write(&path, "$6$x$y\n").unwrap();
+        assert!(!is_immutable(&path).unwrap());
+        match set_immutable(&path, true) {
+            Ok(()) => {}
+            // tmpfs, or no CAP_LINUX_IMMUTABLE
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => return,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("{e}"),
+        }
+        assert!(is_immutable(&path).unwrap());
+        assert!(std::fs::remove_file(&path).is_err());
+        make_mutable(&path).unwrap();
+        assert!(!is_immutable(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! The last successful PIN authentication per user, in `<user>.last`:
+//! `<unix time> <service> <tty> <rhost>`, `-` for an absent item.
+
+use crate::pam::PamContext;
+use crate::store::PinStore;
+use std::io;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LastLogin {
+    pub at: u64,
+    pub pam: PamContext,
+}
+
+impl LastLogin {
+    /// File name of `user`'s record.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.last")
+    }
+
+    /// `user`'s last login from `store`; `None` if there has been none.
+    pub fn load(store: &dyn PinStore, user: &str) -> io::Result<Option<Self>> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let item = |field: &str| (field != "-").then(|| field.to_string());
+        match raw.split_whitespace().collect::<Vec<_>>()[..] {
+            [at, service, tty, rhost] => {
+                let at = at.parse().map_err(|_| malformed())?;
+                Ok(Some(LastLogin {
+                    at,
+                    pam: PamContext {
+                        service: item(service),
+                        tty: item(tty),
+                        rhost: item(rhost),
+                    },
+                }))
+            }
+            _ => Err(malformed()),
+        }
+    }
+
+    /// Store as `user`'s last login.
+    pub fn save(&self, store: &dyn PinStore, user: &str) -> io::Result<()> {
+        store.write_file(&Self::file_name(user), &self.serialize())
+    }
+
+    /// The record as written to `<user>.last`.
+    pub fn serialize(&self) -> String {
+        // PAM items come from the caller; keep them to one field each
+        let item = |value: &Option<String>| match value {
+            Some(v) if !v.is_empty() => v
+                .chars()
+                .map(|c| if c.is_ascii_graphic() { c } else { '?' })
+                .collect(),
+            _ => "-".to_string(),
+        };
+        format!(
+            "{} {} {} {}\n",
+            self.at,
+            item(&self.pam.service),
+            item(&self.pam.tty),
+            item(&self.pam.rhost)
+        )
+    }
+
+    /// One line for humans: `2026-10-16 08:12:00 UTC via sshd on pts/0 from
+    /// 10.0.0.5`.
+    pub fn describe(&self) -> String {
+        let mut line = format!("{} UTC", crate::format_utc(self.at));
+        for (word, value) in [
+            ("via", &self.pam.service),
+            ("on", &self.pam.tty),
+            ("from", &self.pam.rhost),
+        ] {
+            if let Some(value) = value {
+                line.push_str(&format!(" {word} {value}"));
+            }
+        }
+        line
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed last login record")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let store = crate::store::MemoryStore::new();
+        assert_eq!(LastLogin::load(&store, "alice").unwrap(), None);
+        let login = LastLogin {
+            at: 1_700_000_000,
+            pam: PamContext {
+                service: Some("sshd".into()),
+                tty: None,
+                rhost: Some("10.0.0.5".into()),
+            },
+        };
+        login.save(&store, "alice").unwrap();
+        assert_eq!(
+            store.read_file("alice.last").unwrap(),
+            "1700000000 sshd - 10.0.0.5\n"
+        );
+        assert_eq!(
+            LastLogin::load(&store, "alice").unwrap(),
+            Some(login.clone())
+        );
+        assert_eq!(
+            login.describe(),
+            "2023-11-14 22:13:20 UTC via sshd from 10.0.0.5"
+        );
+        let odd = LastLogin {
+            pam: PamContext {
+                tty: Some("a b".into()),
+                ..PamContext::default()
+            },
+            ..login
+        };
+        assert_eq!(odd.serialize(), "1700000000 - a?b -\n");
+    }
+}
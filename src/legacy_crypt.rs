@@ -0,0 +1,58 @@
+//! Legacy crypt(3) hashes (feature `legacy-crypt`): MD5-crypt
+//! (`$1$salt$hash`) and traditional DES crypt (13 characters, the salt
+//! first), verified through the system libcrypt so PIN files carried over
+//! from older kiosks keep working during a migration.
+//!
+//! They are verified only: [`hash_pin_with`](crate::hash_pin_with) refuses
+//! to write them, and [`HashInfo::shortfall`](crate::HashInfo::shortfall)
+//! always reports them, so `check_pin` warns until the PIN is set again
+//! and `pinctl stats` counts them. DES crypt looks at the first 8
+//! characters of the PIN only. Whether libcrypt still has either method
+//! depends on how it was built; `pinctl self-test` tells.
+
+use crate::VerifyOutcome;
+use std::ffi::{c_char, CStr};
+use std::sync::Mutex;
+
+extern "C" {
+    fn crypt(key: *const c_char, setting: *const c_char) -> *mut c_char;
+}
+
+/// crypt(3) returns a static buffer; `crypt_r` has no portable signature.
+static CRYPT: Mutex<()> = Mutex::new(());
+
+/// Check `secret` against the legacy hash `stored`.
+/// [`VerifyOutcome::UnsupportedScheme`] if libcrypt cannot compute it.
+pub fn verify(secret: &str, stored: &str) -> VerifyOutcome {
+    if secret.contains('\0') {
+        return VerifyOutcome::Mismatch;
+    }
+    if stored.contains('\0') {
+        return VerifyOutcome::MalformedHash;
+    }
+    let nul_terminated = |s: &str| {
+        let mut bytes = zeroize::Zeroizing::new(Vec::with_capacity(s.len() + 1));
+        bytes.extend_from_slice(s.as_bytes());
+        bytes.push(0);
+        bytes
+    };
+    let (key, setting) = (nul_terminated(secret), nul_terminated(stored));
+    let _guard = CRYPT.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: both arguments are NUL-terminated and outlive the call; the
+    // result is read before the lock is released.
+    let out = unsafe { crypt(key.as_ptr().cast(), setting.as_ptr().cast()) };
+    if out.is_null() {
+        return VerifyOutcome::UnsupportedScheme;
+    }
+    // SAFETY: a non-null result is a NUL-terminated string.
+    let out = unsafe { CStr::from_ptr(out) }.to_bytes();
+    // libxcrypt fails with "*0" or "*1" rather than NULL
+    if out.starts_with(b"*") {
+        return VerifyOutcome::UnsupportedScheme;
+    }
+    if crate::constant_time_eq(out, stored.as_bytes()) {
+        VerifyOutcome::Match
+    } else {
+        VerifyOutcome::Mismatch
+    }
+}
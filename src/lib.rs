@@ -1,4 +1,58 @@
-use zeroize::Zeroize;
+pub mod account;
+pub mod audit;
+pub mod auth;
+#[cfg(feature = "backup")]
+pub mod backup;
+pub mod config;
+pub mod exit;
+pub mod export;
+pub mod fail;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fido2")]
+pub mod fido2;
+pub mod gc;
+#[cfg(feature = "sqlite")]
+pub mod history;
+pub mod host;
+#[cfg(feature = "hotp")]
+pub mod hotp;
+pub mod hours;
+pub mod i18n;
+pub mod immutable;
+pub mod lastlogin;
+#[cfg(feature = "legacy-crypt")]
+pub mod legacy_crypt;
+pub mod lock;
+pub mod logging;
+pub mod man;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+pub mod metrics;
+pub mod notify;
+pub mod pam;
+pub mod paths;
+pub mod pin;
+pub mod policy;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod record;
+#[cfg(feature = "encryption")]
+pub mod seal;
+pub mod selftest;
+pub mod store;
+#[cfg(feature = "toml-records")]
+pub mod toml_record;
+#[cfg(feature = "argon2")]
+pub mod tune;
+#[cfg(feature = "yubikey")]
+pub mod yubikey;
+
+pub use config::ConfigResolver;
+pub use pin::Pin;
+#[cfg(feature = "secrecy")]
+pub use pin::{hash_secret, verify_secret};
+pub use selftest::self_test;
 
 #[cfg(feature = "argon2")]
 use argon2::{
@@ -8,32 +62,324 @@ use argon2::{
 #[cfg(feature = "sha-crypt")]
 use sha_crypt::{sha512_check, sha512_simple, Sha512Params};
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum PinHashError {
-    UnsupportedScheme,
+    /// The scheme's backend was not compiled in (cargo feature disabled).
+    #[error("{0:?} support not compiled in")]
+    BackendUnavailable(Scheme),
+    /// A legacy scheme ([`Scheme::is_legacy`]): hashes are verified, never
+    /// written.
+    #[error("{0:?} is only verified, not used for new hashes")]
+    VerifyOnly(Scheme),
+    /// Cost parameters rejected by the backend.
+    #[error("invalid hash parameters: {0}")]
+    InvalidParams(String),
+    #[error("hash failure: {0}")]
     HashFailure(String),
+    #[error("parse failure: {0}")]
     ParseFailure(String),
+    #[error("pin policy: {0}")]
+    Policy(#[from] policy::PolicyViolation),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
-impl std::fmt::Display for PinHashError {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    Sha512Crypt,
+    Argon2id,
+    /// `$1$` MD5-crypt; verified only (feature `legacy-crypt`).
+    Md5Crypt,
+    /// Traditional 13-character DES crypt; verified only (feature
+    /// `legacy-crypt`).
+    DesCrypt,
+}
+
+/// sha512-crypt rounds when the hash carries no `rounds=` field.
+pub const SHA512_DEFAULT_ROUNDS: u32 = 5000;
+
+/// What a stored hash says about itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashInfo {
+    pub scheme: Scheme,
+    /// Effective parameters; only the field for `scheme` is set.
+    pub params: HashParams,
+}
+
+/// `argon2id m=19456,t=2,p=1`, `sha512crypt rounds=5000` or `md5crypt`.
+impl std::fmt::Display for HashInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.scheme.name())?;
+        let params = self.params.to_string();
+        if !params.is_empty() {
+            write!(f, " {params}")?;
+        }
+        Ok(())
+    }
+}
+
+impl HashInfo {
+    /// How this hash falls short of what `genpin` writes with `config`,
+    /// e.g. `sha512crypt < argon2id` or `m=19456 < 65536`; `None` if it
+    /// does not. Argon2 costs are compared only when configured (or left
+    /// at the defaults), not when tuned per host with `argon2_target_ms`.
+    /// Legacy hashes ([`Scheme::is_legacy`]) always fall short.
+    pub fn shortfall(&self, config: &ConfigResolver) -> Option<String> {
+        let target = scheme_from_config(config);
+        match (self.scheme, target) {
+            (Scheme::Md5Crypt | Scheme::DesCrypt, _) => {
+                Some(format!("{} < {}", self.scheme.name(), target.name()))
+            }
+            // never configured for writing
+            (_, Scheme::Md5Crypt | Scheme::DesCrypt) => None,
+            (Scheme::Sha512Crypt, Scheme::Argon2id) => {
+                Some(format!("{} < {}", self.scheme.name(), target.name()))
+            }
+            (Scheme::Argon2id, Scheme::Sha512Crypt) => None,
+            (Scheme::Sha512Crypt, Scheme::Sha512Crypt) => {
+                let rounds = self.params.sha512_rounds.unwrap_or(SHA512_DEFAULT_ROUNDS);
+                (rounds < SHA512_DEFAULT_ROUNDS)
+                    .then(|| format!("rounds={rounds} < {SHA512_DEFAULT_ROUNDS}"))
+            }
+            (Scheme::Argon2id, Scheme::Argon2id) => {
+                let have = self.params.argon2?;
+                let want = HashParams::argon2_from_config(config).or_else(|| {
+                    config
+                        .get("argon2_target_ms")
+                        .is_none()
+                        .then_some(ARGON2_DEFAULT_COSTS)
+                })?;
+                let gaps: Vec<String> = [
+                    ("m", have.m_cost, want.m_cost),
+                    ("t", have.t_cost, want.t_cost),
+                ]
+                .into_iter()
+                .filter(|(_, have, want)| have < want)
+                .map(|(name, have, want)| format!("{name}={have} < {want}"))
+                .collect();
+                (!gaps.is_empty()).then(|| gaps.join(", "))
+            }
+        }
+    }
+}
+
+impl Scheme {
+    /// Lowercase name, as in a record's `scheme=` line.
+    pub fn name(self) -> &'static str {
         match self {
-            PinHashError::UnsupportedScheme => write!(f, "unsupported hash scheme"),
-            PinHashError::HashFailure(e) => write!(f, "hash failure: {e}"),
-            PinHashError::ParseFailure(e) => write!(f, "parse failure: {e}"),
+            Scheme::Sha512Crypt => "sha512crypt",
+            Scheme::Argon2id => "argon2id",
+            Scheme::Md5Crypt => "md5crypt",
+            Scheme::DesCrypt => "descrypt",
+        }
+    }
+
+    /// Whether this scheme's backend is compiled in (cargo features).
+    pub fn is_available(self) -> bool {
+        match self {
+            Scheme::Sha512Crypt => cfg!(feature = "sha-crypt"),
+            Scheme::Argon2id => cfg!(feature = "argon2"),
+            Scheme::Md5Crypt | Scheme::DesCrypt => cfg!(feature = "legacy-crypt"),
+        }
+    }
+
+    /// A scheme hashes are only verified with, never written: MD5-crypt
+    /// and DES crypt, for PIN files carried over from older systems.
+    pub fn is_legacy(self) -> bool {
+        matches!(self, Scheme::Md5Crypt | Scheme::DesCrypt)
+    }
+
+    /// Detect the scheme of a stored hash and decode its cost parameters.
+    /// Pure string parsing; works whether or not the backend is compiled in.
+    pub fn from_hash(stored: &str) -> Result<HashInfo, PinHashError> {
+        let bad = |why: &str| PinHashError::ParseFailure(why.to_string());
+        let legacy = |scheme| HashInfo {
+            scheme,
+            params: HashParams::default(),
+        };
+        if stored.len() == 13 && stored.bytes().all(is_crypt64) {
+            return Ok(legacy(Scheme::DesCrypt));
+        }
+        let mut fields = stored.split('$');
+        if fields.next() != Some("") {
+            return Err(bad("not a $-prefixed hash"));
+        }
+        let ident = fields.next().unwrap_or_default();
+        let rest: Vec<&str> = fields.collect();
+        match ident {
+            "6" => {
+                let (rounds, rest) = match rest.split_first() {
+                    Some((f, tail)) if f.starts_with("rounds=") => {
+                        let n = f["rounds=".len()..]
+                            .parse::<u32>()
+                            .map_err(|_| bad("bad rounds"))?;
+                        (n, tail)
+                    }
+                    _ => (SHA512_DEFAULT_ROUNDS, &rest[..]),
+                };
+                match rest {
+                    [_salt, hash] if !hash.is_empty() => Ok(HashInfo {
+                        scheme: Scheme::Sha512Crypt,
+                        params: HashParams {
+                            sha512_rounds: Some(rounds),
+                            ..HashParams::default()
+                        },
+                    }),
+                    _ => Err(bad("expected $6$[rounds=N$]salt$hash")),
+                }
+            }
+            "1" => match rest[..] {
+                [salt, hash] if salt.len() <= 8 && hash.len() == 22 => Ok(legacy(Scheme::Md5Crypt)),
+                _ => Err(bad("expected $1$salt$hash")),
+            },
+            "argon2id" => {
+                let rest = match rest.split_first() {
+                    Some((f, tail)) if f.starts_with("v=") => tail,
+                    _ => &rest[..],
+                };
+                let [costs, _salt, hash] = rest else {
+                    return Err(bad("expected $argon2id$[v=N$]m=,t=,p=$salt$hash"));
+                };
+                if hash.is_empty() {
+                    return Err(bad("missing hash output"));
+                }
+                let (mut m, mut t, mut p) = (None, None, None);
+                for kv in costs.split(',') {
+                    let (k, v) = kv.split_once('=').ok_or_else(|| bad("bad cost field"))?;
+                    let v = v.parse::<u32>().map_err(|_| bad("bad cost value"))?;
+                    match k {
+                        "m" => m = Some(v),
+                        "t" => t = Some(v),
+                        "p" => p = Some(v),
+                        _ => {}
+                    }
+                }
+                match (m, t, p) {
+                    (Some(m_cost), Some(t_cost), Some(p_cost)) => Ok(HashInfo {
+                        scheme: Scheme::Argon2id,
+                        params: HashParams {
+                            argon2: Some(Argon2Costs {
+                                m_cost,
+                                t_cost,
+                                p_cost,
+                            }),
+                            ..HashParams::default()
+                        },
+                    }),
+                    _ => Err(bad("missing m, t or p")),
+                }
+            }
+            other => Err(bad(&format!("unknown scheme {other:?}"))),
         }
     }
 }
-impl std::error::Error for PinHashError {}
 
-#[derive(Clone, Copy, Debug)]
-pub enum Scheme {
-    Sha512Crypt,
-    Argon2id,
+/// A character of crypt(3)'s base-64 alphabet (`./0-9A-Za-z`).
+fn is_crypt64(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'.' || b == b'/'
+}
+
+/// The weakest stored hash `check_pin` still verifies (`min_hash`): a
+/// scheme and, optionally, the least cost for it, e.g.
+/// `argon2id,m=65536,t=3` or `sha512crypt,rounds=100000`. Any Argon2id
+/// hash is stronger than any sha512-crypt hash, which is stronger than any
+/// legacy one (`md5crypt`, then `descrypt`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashFloor {
+    pub scheme: Scheme,
+    /// Least sha512-crypt rounds (0: any).
+    pub sha512_rounds: u32,
+    /// Least Argon2 memory in KiB (0: any).
+    pub argon2_m_cost: u32,
+    /// Least Argon2 iterations (0: any).
+    pub argon2_t_cost: u32,
+}
+
+impl HashFloor {
+    /// Whether a hash described by `info` is at least this strong.
+    pub fn allows(&self, info: &HashInfo) -> bool {
+        match (self.scheme, info.scheme) {
+            (floor, Scheme::DesCrypt) => floor == Scheme::DesCrypt,
+            (floor, Scheme::Md5Crypt) => floor.is_legacy(),
+            (Scheme::Md5Crypt | Scheme::DesCrypt, _) => true,
+            (Scheme::Argon2id, Scheme::Sha512Crypt) => false,
+            (Scheme::Sha512Crypt, Scheme::Argon2id) => true,
+            (Scheme::Sha512Crypt, Scheme::Sha512Crypt) => {
+                info.params.sha512_rounds.unwrap_or(SHA512_DEFAULT_ROUNDS) >= self.sha512_rounds
+            }
+            (Scheme::Argon2id, Scheme::Argon2id) => info
+                .params
+                .argon2
+                .is_some_and(|c| c.m_cost >= self.argon2_m_cost && c.t_cost >= self.argon2_t_cost),
+        }
+    }
 }
 
+impl std::str::FromStr for HashFloor {
+    type Err = PinHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = |why: String| PinHashError::InvalidParams(format!("min_hash {s:?}: {why}"));
+        let mut fields = s.split(',').map(str::trim);
+        let scheme = match fields.next().unwrap_or_default().to_lowercase().as_str() {
+            "argon2" | "argon2id" => Scheme::Argon2id,
+            "sha-crypt" | "sha512crypt" => Scheme::Sha512Crypt,
+            "md5crypt" => Scheme::Md5Crypt,
+            "descrypt" => Scheme::DesCrypt,
+            other => return Err(bad(format!("unknown scheme {other:?}"))),
+        };
+        let mut floor = HashFloor {
+            scheme,
+            sha512_rounds: 0,
+            argon2_m_cost: 0,
+            argon2_t_cost: 0,
+        };
+        for field in fields {
+            let (name, value) = field
+                .split_once('=')
+                .ok_or_else(|| bad(format!("{field:?} is not name=value")))?;
+            let value = value
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| bad(format!("{field:?} is not a whole number")))?;
+            let cost = match (scheme, name.trim()) {
+                (Scheme::Sha512Crypt, "rounds") => &mut floor.sha512_rounds,
+                (Scheme::Argon2id, "m") => &mut floor.argon2_m_cost,
+                (Scheme::Argon2id, "t") => &mut floor.argon2_t_cost,
+                _ => return Err(bad(format!("{name:?} is not a {scheme:?} cost"))),
+            };
+            *cost = value;
+        }
+        Ok(floor)
+    }
+}
+
+impl std::fmt::Display for HashFloor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.scheme.name())?;
+        for (name, value) in [
+            ("rounds", self.sha512_rounds),
+            ("m", self.argon2_m_cost),
+            ("t", self.argon2_t_cost),
+        ] {
+            if value > 0 {
+                write!(f, ",{name}={value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `PIN_SCHEME` over the default; see [`scheme_from_config`].
 pub fn scheme_from_env() -> Scheme {
-    match std::env::var("PIN_SCHEME")
+    scheme_from_config(&ConfigResolver::from_env())
+}
+
+/// The `scheme` setting: `argon2`/`argon2id`, anything else sha512-crypt.
+pub fn scheme_from_config(config: &ConfigResolver) -> Scheme {
+    match config
+        .string("scheme")
         .unwrap_or_default()
         .to_lowercase()
         .as_str()
@@ -43,136 +389,941 @@ pub fn scheme_from_env() -> Scheme {
     }
 }
 
-pub fn hash_pin(pin: &mut String) -> Result<String, PinHashError> {
-    // If neither hashing backend is compiled in, fail early without unreachable code.
-    #[cfg(all(not(feature = "sha-crypt"), not(feature = "argon2")))]
-    {
-        let _ = pin; // silence unused warning
-        Err(PinHashError::UnsupportedScheme)
+/// Cost parameters for [`hash_pin_with`]; `None` keeps the backend default.
+/// [`HashParamsBuilder`] builds checked ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HashParams {
+    /// sha512-crypt rounds.
+    pub sha512_rounds: Option<u32>,
+    pub argon2: Option<Argon2Costs>,
+    /// Argon2 output length in bytes (the backend's default is 32). Not
+    /// decoded from stored hashes.
+    pub argon2_output_len: Option<usize>,
+}
+
+/// Builds [`HashParams`], checking each value against the ranges below
+/// rather than leaving it to the backend at hashing time. Argon2 costs not
+/// set keep [`ARGON2_DEFAULT_COSTS`]:
+///
+/// ```
+/// use pin_auth::{Argon2Preset, HashParamsBuilder};
+/// let params = HashParamsBuilder::new()
+///     .preset(Argon2Preset::Server)
+///     .time_cost(3)
+///     .build()
+///     .unwrap();
+/// assert_eq!(params.argon2.unwrap().t_cost, 3);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HashParamsBuilder {
+    params: HashParams,
+}
+
+impl HashParamsBuilder {
+    /// sha512-crypt rounds, as the backend accepts them.
+    pub const SHA512_ROUNDS: std::ops::RangeInclusive<u32> = 1_000..=999_999_999;
+    /// Argon2 passes.
+    pub const ARGON2_T_COST: std::ops::RangeInclusive<u32> = 1..=u32::MAX;
+    /// Argon2 lanes.
+    pub const ARGON2_P_COST: std::ops::RangeInclusive<u32> = 1..=0xff_ffff;
+    /// Argon2 output bytes: at least a 128-bit tag, at most a 512-bit one.
+    pub const ARGON2_OUTPUT_LEN: std::ops::RangeInclusive<usize> = 16..=64;
+
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    #[cfg(any(feature = "sha-crypt", feature = "argon2"))]
-    {
-        let scheme = scheme_from_env();
-        let out = match scheme {
-            Scheme::Sha512Crypt => {
-                #[cfg(feature = "sha-crypt")]
-                {
-                    let params = Sha512Params::default();
-                    sha512_simple(pin, &params)
-                        .map_err(|e| PinHashError::HashFailure(format!("{e:?}")))?
-                }
-                #[cfg(not(feature = "sha-crypt"))]
-                {
-                    return Err(PinHashError::UnsupportedScheme);
-                }
+    /// The configured costs: all of `argon2_m_cost`, `_t_cost` and
+    /// `_p_cost` when set and non-zero, otherwise `argon2_preset` if set.
+    pub fn from_config(config: &ConfigResolver) -> Self {
+        let cost = |name: &str| config.parse::<u32>(name).filter(|&n| n > 0);
+        let builder = Self::new();
+        match (
+            cost("argon2_m_cost"),
+            cost("argon2_t_cost"),
+            cost("argon2_p_cost"),
+        ) {
+            (Some(m_cost), Some(t_cost), Some(p_cost)) => builder
+                .memory_kib(m_cost)
+                .time_cost(t_cost)
+                .parallelism(p_cost),
+            _ => match config.parse::<Argon2Preset>("argon2_preset") {
+                Some(preset) => builder.preset(preset),
+                None => builder,
+            },
+        }
+    }
+
+    /// All three Argon2 costs of `preset`.
+    pub fn preset(mut self, preset: Argon2Preset) -> Self {
+        self.params.argon2 = Some(preset.costs());
+        self
+    }
+
+    /// Argon2 memory in KiB; at least 8 per lane.
+    pub fn memory_kib(self, m_cost: u32) -> Self {
+        self.with_argon2(|c| c.m_cost = m_cost)
+    }
+
+    pub fn time_cost(self, t_cost: u32) -> Self {
+        self.with_argon2(|c| c.t_cost = t_cost)
+    }
+
+    pub fn parallelism(self, p_cost: u32) -> Self {
+        self.with_argon2(|c| c.p_cost = p_cost)
+    }
+
+    pub fn output_len(mut self, bytes: usize) -> Self {
+        self.params.argon2_output_len = Some(bytes);
+        self
+    }
+
+    pub fn sha512_rounds(mut self, rounds: u32) -> Self {
+        self.params.sha512_rounds = Some(rounds);
+        self
+    }
+
+    /// The Argon2 costs set so far, unchecked.
+    pub fn argon2_costs(&self) -> Option<Argon2Costs> {
+        self.params.argon2
+    }
+
+    fn with_argon2(mut self, set: impl FnOnce(&mut Argon2Costs)) -> Self {
+        set(self.params.argon2.get_or_insert(ARGON2_DEFAULT_COSTS));
+        self
+    }
+
+    pub fn build(self) -> Result<HashParams, PinHashError> {
+        let bad = |msg: String| Err(PinHashError::InvalidParams(msg));
+        let p = self.params;
+        if let Some(rounds) = p.sha512_rounds.filter(|r| !Self::SHA512_ROUNDS.contains(r)) {
+            return bad(format!(
+                "sha512-crypt rounds {rounds} outside {:?}",
+                Self::SHA512_ROUNDS
+            ));
+        }
+        if let Some(c) = p.argon2 {
+            if !Self::ARGON2_T_COST.contains(&c.t_cost) {
+                return bad(format!(
+                    "argon2 t_cost {} outside {:?}",
+                    c.t_cost,
+                    Self::ARGON2_T_COST
+                ));
             }
-            Scheme::Argon2id => {
-                #[cfg(feature = "argon2")]
-                {
-                    let salt = SaltString::generate(&mut OsRng);
-                    let argon = {
-                        let base = Argon2::default();
-                        if let (Ok(m), Ok(t), Ok(p)) = (
-                            std::env::var("PIN_ARGON2_M_COST")
-                                .unwrap_or_default()
-                                .parse::<u32>(),
-                            std::env::var("PIN_ARGON2_T_COST")
-                                .unwrap_or_default()
-                                .parse::<u32>(),
-                            std::env::var("PIN_ARGON2_P_COST")
-                                .unwrap_or_default()
-                                .parse::<u32>(),
-                        ) {
-                            if m > 0 && t > 0 && p > 0 {
-                                use argon2::{Algorithm, Params, Version};
-                                if let Ok(params) = Params::new(m, t, p, None) {
-                                    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
-                                } else {
-                                    base
-                                }
-                            } else {
-                                base
-                            }
-                        } else {
-                            base
-                        }
-                    };
-                    argon
-                        .hash_password(pin.as_bytes(), &salt)
-                        .map_err(|e| PinHashError::HashFailure(e.to_string()))?
-                        .to_string()
-                }
-                #[cfg(not(feature = "argon2"))]
-                {
-                    return Err(PinHashError::UnsupportedScheme);
-                }
+            if !Self::ARGON2_P_COST.contains(&c.p_cost) {
+                return bad(format!(
+                    "argon2 p_cost {} outside {:?}",
+                    c.p_cost,
+                    Self::ARGON2_P_COST
+                ));
             }
+            if u64::from(c.m_cost) < 8 * u64::from(c.p_cost) {
+                return bad(format!(
+                    "argon2 m_cost {} below 8 KiB per lane ({})",
+                    c.m_cost,
+                    8 * u64::from(c.p_cost)
+                ));
+            }
+        }
+        if let Some(len) = p
+            .argon2_output_len
+            .filter(|len| !Self::ARGON2_OUTPUT_LEN.contains(len))
+        {
+            return bad(format!(
+                "argon2 output length {len} outside {:?}",
+                Self::ARGON2_OUTPUT_LEN
+            ));
+        }
+        Ok(p)
+    }
+}
+
+impl From<HashParams> for HashParamsBuilder {
+    fn from(params: HashParams) -> Self {
+        HashParamsBuilder { params }
+    }
+}
+
+/// `rounds=5000` or `m=19456,t=2,p=1`, whichever is set; both if both
+/// are. A set output length follows as `len=N`.
+impl std::fmt::Display for HashParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(rounds) = self.sha512_rounds {
+            fields.push(format!("rounds={rounds}"));
+        }
+        if let Some(c) = self.argon2 {
+            fields.push(format!("m={},t={},p={}", c.m_cost, c.t_cost, c.p_cost));
+        }
+        if let Some(len) = self.argon2_output_len {
+            fields.push(format!("len={len}"));
+        }
+        f.write_str(&fields.join(","))
+    }
+}
+
+/// The argon2 crate's defaults (19 MiB, t=2, p=1), used when
+/// [`HashParams::argon2`] is `None`.
+pub const ARGON2_DEFAULT_COSTS: Argon2Costs = Argon2Costs {
+    m_cost: 19_456,
+    t_cost: 2,
+    p_cost: 1,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Argon2Costs {
+    /// Memory in KiB.
+    pub m_cost: u32,
+    /// Iterations.
+    pub t_cost: u32,
+    /// Parallelism.
+    pub p_cost: u32,
+}
+
+/// Named Argon2id cost sets, sized by how much memory a verification may
+/// take on the host. All but `Desktop` follow the OWASP equivalence ladder
+/// (equal strength, less memory traded for more passes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Argon2Preset {
+    /// 64 MiB, t=3, p=4 (RFC 9106 low-memory recommendation).
+    Desktop,
+    /// 19 MiB, t=2, p=1; many verifications may run at once.
+    Server,
+    /// 12 MiB, t=3, p=1, for devices with about 64 MB of RAM.
+    Embedded64Mb,
+    /// 7 MiB, t=5, p=1, for devices with about 16 MB of RAM.
+    Embedded16Mb,
+}
+
+impl Argon2Preset {
+    pub const ALL: [Argon2Preset; 4] = [
+        Argon2Preset::Desktop,
+        Argon2Preset::Server,
+        Argon2Preset::Embedded64Mb,
+        Argon2Preset::Embedded16Mb,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Argon2Preset::Desktop => "desktop",
+            Argon2Preset::Server => "server",
+            Argon2Preset::Embedded64Mb => "embedded-64mb",
+            Argon2Preset::Embedded16Mb => "embedded-16mb",
+        }
+    }
+
+    pub fn costs(self) -> Argon2Costs {
+        let (m_cost, t_cost, p_cost) = match self {
+            Argon2Preset::Desktop => (65_536, 3, 4),
+            Argon2Preset::Server => (19_456, 2, 1),
+            Argon2Preset::Embedded64Mb => (12_288, 3, 1),
+            Argon2Preset::Embedded16Mb => (7_168, 5, 1),
         };
-        pin.zeroize();
-        Ok(out)
+        Argon2Costs {
+            m_cost,
+            t_cost,
+            p_cost,
+        }
+    }
+}
+
+impl std::str::FromStr for Argon2Preset {
+    type Err = PinHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Argon2Preset::ALL
+            .into_iter()
+            .find(|p| p.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| PinHashError::InvalidParams(format!("unknown argon2 preset {s:?}")))
+    }
+}
+
+impl HashParams {
+    /// [`HashParams::from_config`] over the environment alone.
+    pub fn from_env() -> Self {
+        Self::from_config(&ConfigResolver::from_env())
     }
+
+    /// [`HashParams::argon2_from_config`], or when nothing is configured the
+    /// backend's default memory and passes with one lane per available CPU
+    /// (see [`host::argon2_lanes`]).
+    pub fn from_config(config: &ConfigResolver) -> Self {
+        let argon2 = Self::argon2_from_config(config).unwrap_or(Argon2Costs {
+            p_cost: host::argon2_lanes(),
+            ..ARGON2_DEFAULT_COSTS
+        });
+        HashParams {
+            argon2: Some(argon2),
+            ..HashParams::default()
+        }
+    }
+
+    /// [`HashParams::argon2_from_config`] over the environment alone.
+    pub fn argon2_from_env() -> Option<Argon2Costs> {
+        Self::argon2_from_config(&ConfigResolver::from_env())
+    }
+
+    /// Explicitly configured Argon2 costs ([`HashParamsBuilder::from_config`]),
+    /// unchecked.
+    pub fn argon2_from_config(config: &ConfigResolver) -> Option<Argon2Costs> {
+        HashParamsBuilder::from_config(config).argon2_costs()
+    }
+}
+
+/// [`hash_pin_configured`] with the environment alone (`PIN_SCHEME`,
+/// `PIN_ARGON2_*`). Library users should call [`hash_pin_with`].
+pub fn hash_pin(pin: &Pin) -> Result<String, PinHashError> {
+    hash_pin_configured(pin, &ConfigResolver::from_env())
+}
+
+/// Hash with the configured scheme and costs, as the binaries do. Argon2
+/// memory is clamped to the cgroup memory limit (see
+/// [`host::clamp_argon2_memory`]). Invalid costs fall back to the backend
+/// defaults with a warning.
+pub fn hash_pin_configured(pin: &Pin, config: &ConfigResolver) -> Result<String, PinHashError> {
+    let (scheme, params) = configured_params(config);
+    match hash_pin_with(pin, scheme, &params) {
+        Err(PinHashError::InvalidParams(e)) => {
+            tracing::warn!(error = %e, "invalid Argon2 costs, using defaults");
+            hash_pin_with(pin, scheme, &HashParams::default())
+        }
+        other => other,
+    }
+}
+
+/// The configured scheme and its costs, Argon2 memory clamped.
+fn configured_params(config: &ConfigResolver) -> (Scheme, HashParams) {
+    let scheme = scheme_from_config(config);
+    let mut params = HashParams::from_config(config);
+    if let Some(costs) = params
+        .argon2
+        .as_mut()
+        .filter(|_| scheme == Scheme::Argon2id)
+    {
+        host::clamp_argon2_memory(costs);
+    }
+    (scheme, params)
 }
 
-pub fn verify_pin(candidate: &mut String, stored: &str) -> bool {
-    let scheme = if stored.starts_with("$6$") {
-        Scheme::Sha512Crypt
-    } else if stored.starts_with("$argon2") {
-        Scheme::Argon2id
-    } else {
-        scheme_from_env()
+/// A well-formed hash of the configured scheme and costs that no PIN
+/// matches. Verifying against it takes as long as against a real record,
+/// for answering users who have none without a timing difference.
+pub fn dummy_hash(config: &ConfigResolver) -> String {
+    let (scheme, params) = configured_params(config);
+    match scheme {
+        Scheme::Argon2id => {
+            let costs = params.argon2.unwrap_or(ARGON2_DEFAULT_COSTS);
+            let len = params.argon2_output_len.unwrap_or(32);
+            format!(
+                "$argon2id$v=19$m={},t={},p={}${}${}",
+                costs.m_cost,
+                costs.t_cost,
+                costs.p_cost,
+                "A".repeat(22),
+                "A".repeat((len * 4).div_ceil(3))
+            )
+        }
+        _ => format!(
+            "$6$rounds={}$pinauthdummy${}",
+            params.sha512_rounds.unwrap_or(SHA512_DEFAULT_ROUNDS),
+            "A".repeat(86)
+        ),
+    }
+}
+
+/// Hash `pin` with an explicit scheme and parameters; reads no environment.
+/// Parameters outside [`HashParamsBuilder`]'s ranges are refused.
+pub fn hash_pin_with(
+    pin: &Pin,
+    scheme: Scheme,
+    params: &HashParams,
+) -> Result<String, PinHashError> {
+    let params = &HashParamsBuilder::from(*params).build()?;
+    #[cfg(all(not(feature = "sha-crypt"), not(feature = "argon2")))]
+    let _ = (pin, params); // silence unused warning
+    tracing::debug!(?scheme, "hashing pin");
+    match scheme {
+        Scheme::Sha512Crypt => {
+            #[cfg(feature = "sha-crypt")]
+            {
+                let sp = match params.sha512_rounds {
+                    Some(rounds) => Sha512Params::new(rounds as usize)
+                        .map_err(|e| PinHashError::InvalidParams(format!("{e:?}")))?,
+                    None => Sha512Params::default(),
+                };
+                sha512_simple(pin.expose_secret(), &sp)
+                    .map_err(|e| PinHashError::HashFailure(format!("{e:?}")))
+            }
+            #[cfg(not(feature = "sha-crypt"))]
+            {
+                Err(PinHashError::BackendUnavailable(scheme))
+            }
+        }
+        Scheme::Argon2id => {
+            #[cfg(feature = "argon2")]
+            {
+                use argon2::{Algorithm, Params, Version};
+                let argon = match (params.argon2, params.argon2_output_len) {
+                    (None, None) => Argon2::default(),
+                    (costs, len) => {
+                        let c = costs.unwrap_or(ARGON2_DEFAULT_COSTS);
+                        let p = Params::new(c.m_cost, c.t_cost, c.p_cost, len)
+                            .map_err(|e| PinHashError::InvalidParams(e.to_string()))?;
+                        Argon2::new(Algorithm::Argon2id, Version::V0x13, p)
+                    }
+                };
+                let salt = SaltString::generate(&mut OsRng);
+                argon
+                    .hash_password(pin.expose_secret().as_bytes(), &salt)
+                    .map(|h| h.to_string())
+                    .map_err(|e| PinHashError::HashFailure(e.to_string()))
+            }
+            #[cfg(not(feature = "argon2"))]
+            {
+                Err(PinHashError::BackendUnavailable(scheme))
+            }
+        }
+        Scheme::Md5Crypt | Scheme::DesCrypt => Err(PinHashError::VerifyOnly(scheme)),
+    }
+}
+
+/// Result of checking a candidate against a stored hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Match,
+    Mismatch,
+    /// The stored string is not a hash we can parse (corrupted record).
+    MalformedHash,
+    /// The hash names a scheme whose backend is not compiled in.
+    UnsupportedScheme,
+}
+
+pub fn verify_pin(candidate: &Pin, stored: &str) -> bool {
+    verify_pin_outcome(candidate, stored) == VerifyOutcome::Match
+}
+
+/// Like [`verify_pin`], but tells a wrong PIN apart from an unusable hash.
+pub fn verify_pin_outcome(candidate: &Pin, stored: &str) -> VerifyOutcome {
+    let scheme = match Scheme::from_hash(stored) {
+        Ok(info) => info.scheme,
+        Err(_) => return VerifyOutcome::MalformedHash,
     };
-    let ok = match scheme {
+    #[cfg(not(any(feature = "sha-crypt", feature = "argon2", feature = "legacy-crypt")))]
+    let _ = candidate; // silence unused warning
+    match scheme {
         Scheme::Sha512Crypt => {
             #[cfg(feature = "sha-crypt")]
             {
-                sha512_check(candidate, stored).is_ok()
+                match sha512_check(candidate.expose_secret(), stored) {
+                    Ok(()) => VerifyOutcome::Match,
+                    // `from_hash` already vetted the format, and sha-crypt
+                    // does not export its error type to tell more apart.
+                    Err(_) => VerifyOutcome::Mismatch,
+                }
             }
             #[cfg(not(feature = "sha-crypt"))]
             {
-                false
+                VerifyOutcome::UnsupportedScheme
             }
         }
         Scheme::Argon2id => {
             #[cfg(feature = "argon2")]
             {
-                if let Ok(ph) = PasswordHash::new(stored) {
-                    Argon2::default()
-                        .verify_password(candidate.as_bytes(), &ph)
-                        .is_ok()
-                } else {
-                    false
+                match PasswordHash::new(stored) {
+                    // No output: verification would just report a mismatch.
+                    Ok(ph) if ph.hash.is_none() => VerifyOutcome::MalformedHash,
+                    Ok(ph) => match Argon2::default()
+                        .verify_password(candidate.expose_secret().as_bytes(), &ph)
+                    {
+                        Ok(()) => VerifyOutcome::Match,
+                        Err(argon2::password_hash::Error::Password) => VerifyOutcome::Mismatch,
+                        Err(_) => VerifyOutcome::MalformedHash,
+                    },
+                    Err(_) => VerifyOutcome::MalformedHash,
                 }
             }
             #[cfg(not(feature = "argon2"))]
             {
-                false
+                VerifyOutcome::UnsupportedScheme
             }
         }
+        Scheme::Md5Crypt | Scheme::DesCrypt => {
+            #[cfg(feature = "legacy-crypt")]
+            {
+                legacy_crypt::verify(candidate.expose_secret(), stored)
+            }
+            #[cfg(not(feature = "legacy-crypt"))]
+            {
+                VerifyOutcome::UnsupportedScheme
+            }
+        }
+    }
+}
+
+/// Parse a duration such as `90`, `90s`, `30m`, `24h` or `7d` into seconds.
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = num.parse().ok()?;
+    let mult = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return None,
     };
-    candidate.zeroize();
-    ok
+    n.checked_mul(mult)
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC, without pulling in a date crate.
+pub fn format_utc(ts: u64) -> String {
+    let days = (ts / 86400) as i64;
+    let secs = ts % 86400;
+    // Civil-from-days (H. Hinnant), proleptic Gregorian.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!(
+        "{y:04}-{m:02}-{d:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Lowercase hex of `bytes`.
+pub fn hex(bytes: &[u8]) -> zeroize::Zeroizing<String> {
+    use std::fmt::Write;
+    let mut out = zeroize::Zeroizing::new(String::with_capacity(bytes.len() * 2));
+    for b in bytes {
+        let _ = write!(out, "{b:02x}");
+    }
+    out
+}
+
+/// Bytes from hex, as token vendors ship secrets; `None` unless even-length
+/// and non-empty.
+pub fn parse_hex(s: &str) -> Option<zeroize::Zeroizing<Vec<u8>>> {
+    if s.is_empty() || !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(zeroize::Zeroizing::new(bytes))
+}
+
+/// Compare secrets without an early exit on the first difference.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn pin(s: &str) -> Pin {
+        Pin::candidate(s, &policy::PolicyValidator::default()).unwrap()
+    }
+
     #[test]
     fn round_trip_pin() {
-        let mut pin = String::from("1234");
-        match hash_pin(&mut pin) {
+        match hash_pin(&pin("1234")) {
             Ok(hash) => {
-                let mut good = String::from("1234");
-                assert!(verify_pin(&mut good, &hash));
-                let mut bad = String::from("9999");
-                assert!(!verify_pin(&mut bad, &hash));
+                assert!(verify_pin(&pin("1234"), &hash));
+                assert!(!verify_pin(&pin("9999"), &hash));
             }
-            Err(PinHashError::UnsupportedScheme) => {
-                // Feature set provides neither hashing backend; skip.
+            Err(PinHashError::BackendUnavailable(_)) => {
+                // Feature set lacks the selected hashing backend; skip.
                 eprintln!("Skipping round_trip_pin: unsupported scheme");
             }
             Err(e) => panic!("hash: {e:?}"),
         }
     }
+
+    #[cfg(feature = "sha-crypt")]
+    #[test]
+    fn sha512_check_errors_on_well_formed_hash_are_mismatches() {
+        let hash =
+            hash_pin_with(&pin("1234"), Scheme::Sha512Crypt, &HashParams::default()).unwrap();
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), &hash),
+            VerifyOutcome::Match
+        );
+        assert_eq!(
+            verify_pin_outcome(&pin("9999"), &hash),
+            VerifyOutcome::Mismatch
+        );
+        // parses as $6$salt$hash, but sha-crypt rejects the digest encoding
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), "$6$saltsalt$not-base64!"),
+            VerifyOutcome::Mismatch
+        );
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), "$6$saltonly"),
+            VerifyOutcome::MalformedHash
+        );
+    }
+
+    #[test]
+    fn dummy_hash_costs_as_configured() {
+        let config = ConfigResolver::default()
+            .with_file(
+                "scheme = argon2id\nargon2_m_cost = 1024\nargon2_t_cost = 1\nargon2_p_cost = 1\n",
+                "c",
+            )
+            .unwrap();
+        let hash = dummy_hash(&config);
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"), "{hash}");
+        // parses, so verifying runs the hash function in full
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), &hash),
+            if cfg!(feature = "argon2") {
+                VerifyOutcome::Mismatch
+            } else {
+                VerifyOutcome::UnsupportedScheme
+            }
+        );
+        let hash = dummy_hash(&ConfigResolver::default());
+        assert!(hash.starts_with("$6$rounds=5000$"), "{hash}");
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), &hash),
+            if cfg!(feature = "sha-crypt") {
+                VerifyOutcome::Mismatch
+            } else {
+                VerifyOutcome::UnsupportedScheme
+            }
+        );
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn explicit_argon2_params() {
+        let costs = Argon2Costs {
+            m_cost: 1024,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let params = HashParams {
+            argon2: Some(costs),
+            ..Default::default()
+        };
+        let hash = hash_pin_with(&pin("1234"), Scheme::Argon2id, &params).unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(verify_pin(&pin("1234"), &hash));
+
+        let bad = HashParams {
+            argon2: Some(Argon2Costs { m_cost: 1, ..costs }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            hash_pin_with(&pin("1234"), Scheme::Argon2id, &bad),
+            Err(PinHashError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn hash_params_builder() {
+        let params = HashParamsBuilder::new()
+            .preset(Argon2Preset::Server)
+            .time_cost(3)
+            .output_len(48)
+            .sha512_rounds(10_000)
+            .build()
+            .unwrap();
+        let costs = params.argon2.unwrap();
+        assert_eq!(costs.m_cost, Argon2Preset::Server.costs().m_cost);
+        assert_eq!(costs.t_cost, 3);
+        assert_eq!(params.sha512_rounds, Some(10_000));
+        assert!(params.to_string().ends_with(",len=48"), "{params}");
+        // unset costs are the defaults
+        let params = HashParamsBuilder::new().parallelism(2).build().unwrap();
+        assert_eq!(
+            params.argon2,
+            Some(Argon2Costs {
+                p_cost: 2,
+                ..ARGON2_DEFAULT_COSTS
+            })
+        );
+        assert_eq!(
+            HashParamsBuilder::new().build().unwrap(),
+            HashParams::default()
+        );
+
+        for bad in [
+            HashParamsBuilder::new().sha512_rounds(999),
+            HashParamsBuilder::new().time_cost(0),
+            HashParamsBuilder::new().parallelism(0),
+            HashParamsBuilder::new().memory_kib(15).parallelism(2),
+            HashParamsBuilder::new().output_len(8),
+            HashParamsBuilder::new().output_len(65),
+        ] {
+            assert!(
+                matches!(bad.build(), Err(PinHashError::InvalidParams(_))),
+                "{bad:?}"
+            );
+        }
+
+        let config = ConfigResolver::default()
+            .with_file(
+                "argon2_m_cost = 4096\nargon2_t_cost = 1\nargon2_p_cost = 0\n\
+                 argon2_preset = embedded-16mb\n",
+                "pin-auth.conf",
+            )
+            .unwrap();
+        // a zero cost: the preset instead
+        assert_eq!(
+            HashParamsBuilder::from_config(&config).argon2_costs(),
+            Some(Argon2Preset::Embedded16Mb.costs())
+        );
+        #[cfg(feature = "argon2")]
+        {
+            let params = HashParamsBuilder::new()
+                .memory_kib(1024)
+                .time_cost(1)
+                .parallelism(1)
+                .output_len(16)
+                .build()
+                .unwrap();
+            let hash = hash_pin_with(&pin("1234"), Scheme::Argon2id, &params).unwrap();
+            assert!(verify_pin(&pin("1234"), &hash));
+            // 16 bytes: 22 base64 characters
+            assert_eq!(hash.rsplit('$').next().unwrap().len(), 22, "{hash}");
+        }
+    }
+
+    #[test]
+    fn argon2_presets() {
+        for preset in Argon2Preset::ALL {
+            assert_eq!(preset.name().parse::<Argon2Preset>().unwrap(), preset);
+            let c = preset.costs();
+            // valid for the backend (m >= 8 * p)
+            assert!(c.m_cost >= 8 * c.p_cost && c.t_cost >= 1);
+        }
+        assert_eq!(
+            "Embedded-16MB".parse::<Argon2Preset>().unwrap(),
+            Argon2Preset::Embedded16Mb
+        );
+        assert!("laptop".parse::<Argon2Preset>().is_err());
+        // leaves more than half of a 16 MB device free
+        assert!(Argon2Preset::Embedded16Mb.costs().m_cost * 1024 < 8 << 20);
+
+        #[cfg(feature = "argon2")]
+        {
+            let params = HashParams {
+                argon2: Some(Argon2Preset::Embedded16Mb.costs()),
+                ..Default::default()
+            };
+            let hash = hash_pin_with(&pin("1234"), Scheme::Argon2id, &params).unwrap();
+            assert!(hash.starts_with("$argon2id$v=19$m=7168,t=5,p=1$"));
+        }
+    }
+
+    #[test]
+    fn verify_outcomes() {
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), "not-a-hash"),
+            VerifyOutcome::MalformedHash
+        );
+        assert_eq!(
+            verify_pin_outcome(&pin("1234"), "$argon2id$garbage"),
+            VerifyOutcome::MalformedHash
+        );
+        let expected = if cfg!(feature = "argon2") {
+            VerifyOutcome::Mismatch
+        } else {
+            VerifyOutcome::UnsupportedScheme
+        };
+        assert_eq!(
+            verify_pin_outcome(
+                &pin("1234"),
+                "$argon2id$v=19$m=1024,t=1,p=1$c29tZXNhbHQ$0b7ZTfjs4HbkW1WJTZTR6Q"
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn hash_introspection() {
+        let info = Scheme::from_hash("$6$rounds=10000$salt$abc").unwrap();
+        assert_eq!(info.scheme, Scheme::Sha512Crypt);
+        assert_eq!(info.params.sha512_rounds, Some(10000));
+        let info = Scheme::from_hash("$6$salt$abc").unwrap();
+        assert_eq!(info.params.sha512_rounds, Some(SHA512_DEFAULT_ROUNDS));
+        assert_eq!(info.to_string(), "sha512crypt rounds=5000");
+        let info = Scheme::from_hash("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(info.to_string(), "argon2id m=19456,t=2,p=1");
+        assert_eq!(info.scheme, Scheme::Argon2id);
+        assert_eq!(
+            info.params.argon2,
+            Some(Argon2Costs {
+                m_cost: 19456,
+                t_cost: 2,
+                p_cost: 1
+            })
+        );
+        for bad in [
+            "",
+            "1234",
+            "$1$salt$hash",
+            "$6$salt",
+            "$argon2id$v=19$m=1$s$h",
+        ] {
+            assert!(Scheme::from_hash(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn hash_floor() {
+        let info = |hash: &str| Scheme::from_hash(hash).unwrap();
+        let legacy = info("$6$salt$abc");
+        let slow_sha = info("$6$rounds=200000$salt$abc");
+        let server = info("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA");
+        let desktop = info("$argon2id$v=19$m=65536,t=3,p=4$c2FsdA$aGFzaA");
+
+        let floor: HashFloor = "argon2id,m=65536".parse().unwrap();
+        assert_eq!(floor.to_string(), "argon2id,m=65536");
+        assert!(!floor.allows(&legacy));
+        assert!(!floor.allows(&server));
+        assert!(floor.allows(&desktop));
+        let floor: HashFloor = "sha-crypt, rounds=100000".parse().unwrap();
+        assert!(!floor.allows(&legacy));
+        assert!(floor.allows(&slow_sha));
+        assert!(floor.allows(&server));
+        let floor: HashFloor = "argon2".parse().unwrap();
+        assert!(floor.allows(&server) && !floor.allows(&slow_sha));
+
+        for bad in [
+            "",
+            "md5",
+            "argon2id,rounds=5000",
+            "sha512crypt,m=1",
+            "argon2id,m",
+        ] {
+            assert!(bad.parse::<HashFloor>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn hash_shortfall() {
+        let config = |text: &str| ConfigResolver::default().with_file(text, "test").unwrap();
+        let info = |hash: &str| Scheme::from_hash(hash).unwrap();
+        let legacy = info("$6$salt$abc");
+        let server = info("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA");
+
+        assert_eq!(legacy.shortfall(&config("")), None);
+        assert_eq!(
+            legacy.shortfall(&config("scheme = argon2id\n")).as_deref(),
+            Some("sha512crypt < argon2id")
+        );
+        assert_eq!(
+            info("$6$rounds=1000$salt$abc")
+                .shortfall(&config(""))
+                .as_deref(),
+            Some("rounds=1000 < 5000")
+        );
+        let argon2 = "scheme = argon2id\n";
+        assert_eq!(server.shortfall(&config(argon2)), None);
+        assert_eq!(
+            server
+                .shortfall(&config(&format!("{argon2}argon2_preset = desktop\n")))
+                .as_deref(),
+            Some("m=19456 < 65536, t=2 < 3")
+        );
+        // tuned per host: nothing to compare with
+        assert_eq!(
+            server.shortfall(&config(&format!("{argon2}argon2_target_ms = 500\n"))),
+            None
+        );
+        assert_eq!(server.shortfall(&config("")), None);
+    }
+
+    #[test]
+    fn legacy_hashes() {
+        let md5 = Scheme::from_hash("$1$pinauth$wsT3iFNswov/cS.sZkjB00").unwrap();
+        let des = Scheme::from_hash("panx27Di8nqrA").unwrap();
+        assert_eq!(
+            (md5.scheme, des.scheme),
+            (Scheme::Md5Crypt, Scheme::DesCrypt)
+        );
+        assert_eq!(md5.to_string(), "md5crypt");
+        for bad in [
+            "$1$pinauth$short",
+            "$1$toolongsalt$wsT3iFNswov/cS.sZkjB00",
+            "panx27Di8nqr!",
+        ] {
+            assert!(Scheme::from_hash(bad).is_err(), "{bad}");
+        }
+
+        // always to be set again
+        let config = ConfigResolver::default();
+        assert_eq!(
+            des.shortfall(&config).as_deref(),
+            Some("descrypt < sha512crypt")
+        );
+        let floor = |s: &str| s.parse::<HashFloor>().unwrap();
+        assert!(!floor("sha512crypt").allows(&md5));
+        assert!(floor("md5crypt").allows(&md5) && !floor("md5crypt").allows(&des));
+        assert!(floor("descrypt").allows(&Scheme::from_hash("$6$salt$abc").unwrap()));
+
+        assert!(matches!(
+            hash_pin_with(&pin("2468"), Scheme::Md5Crypt, &HashParams::default()),
+            Err(PinHashError::VerifyOnly(Scheme::Md5Crypt))
+        ));
+        let expected = |outcome| {
+            if cfg!(feature = "legacy-crypt") {
+                outcome
+            } else {
+                VerifyOutcome::UnsupportedScheme
+            }
+        };
+        for stored in ["$1$pinauth$wsT3iFNswov/cS.sZkjB00", "panx27Di8nqrA"] {
+            assert_eq!(
+                verify_pin_outcome(&pin("2468"), stored),
+                expected(VerifyOutcome::Match)
+            );
+            assert_eq!(
+                verify_pin_outcome(&pin("2469"), stored),
+                expected(VerifyOutcome::Mismatch)
+            );
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("24h"), Some(86400));
+        assert_eq!(parse_duration("7d"), Some(604800));
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("h"), None);
+    }
+
+    #[test]
+    fn utc_format() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951782400), "2000-02-29 00:00:00");
+        assert_eq!(format_utc(1700000000), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(parse_hex("0aFF").unwrap().as_slice(), [10, 255]);
+        assert_eq!(hex(&[10, 255]).as_str(), "0aff");
+        assert!(parse_hex("0a0").is_none());
+        assert!(parse_hex("").is_none());
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
 }
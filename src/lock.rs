@@ -0,0 +1,104 @@
+//! Exclusive whole-file locks with a bounded wait.
+//!
+//! Linux uses open file description locks (`F_OFD_SETLK`): like `flock`
+//! they belong to the open file rather than the process and go away with
+//! its last descriptor, but they are POSIX record locks, so NFS honours
+//! them too. Elsewhere `flock` is used. Either way the lock is retried
+//! without blocking until [`LOCK_TIMEOUT`], so one hung process holding a
+//! fail file cannot stall every later login for that user.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a lock held by another process.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest pause between two tries.
+const MAX_PAUSE: Duration = Duration::from_millis(50);
+
+/// Take an exclusive lock on `file`, waiting at most `timeout` for another
+/// holder to let go. A wait that runs out fails with
+/// [`io::ErrorKind::TimedOut`]; see [`is_timeout`].
+pub fn lock_exclusive(file: &File, timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut pause = Duration::from_millis(1);
+    loop {
+        match try_lock(file) {
+            Ok(()) => return Ok(()),
+            Err(e) if held_elsewhere(&e) => {}
+            Err(e) => return Err(e),
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("lock still held by another process after {timeout:?}"),
+            ));
+        }
+        std::thread::sleep(pause.min(left));
+        pause = (pause * 2).min(MAX_PAUSE);
+    }
+}
+
+/// Whether `e` is a [`lock_exclusive`] timeout.
+pub fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut
+}
+
+fn held_elsewhere(e: &io::Error) -> bool {
+    // EWOULDBLOCK is EAGAIN here; POSIX allows EACCES from fcntl
+    matches!(
+        e.raw_os_error(),
+        Some(nix::libc::EAGAIN | nix::libc::EACCES)
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn try_lock(file: &File) -> io::Result<()> {
+    // SAFETY: an all-zero `struct flock` is valid; l_start = l_len = 0
+    // covers the whole file and l_pid must be 0 for OFD locks.
+    let mut lock = unsafe { std::mem::zeroed::<nix::libc::flock>() };
+    lock.l_type = nix::libc::F_WRLCK as _;
+    lock.l_whence = nix::libc::SEEK_SET as _;
+    if unsafe { nix::libc::fcntl(file.as_raw_fd(), nix::libc::F_OFD_SETLK, &lock) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn try_lock(file: &File) -> io::Result<()> {
+    let op = nix::libc::LOCK_EX | nix::libc::LOCK_NB;
+    if unsafe { nix::libc::flock(file.as_raw_fd(), op) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_lock_times_out() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let open = || {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(tmp.path())
+                .unwrap()
+        };
+        let holder = open();
+        lock_exclusive(&holder, Duration::ZERO).unwrap();
+        // a second open file description, as another process would have
+        let other = open();
+        let start = Instant::now();
+        let err = lock_exclusive(&other, Duration::from_millis(100)).unwrap_err();
+        assert!(is_timeout(&err), "{err}");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        drop(holder);
+        lock_exclusive(&other, Duration::ZERO).unwrap();
+    }
+}
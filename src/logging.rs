@@ -0,0 +1,825 @@
+//! Logging setup shared by the binaries, built on `tracing`.
+//!
+//! Events are rendered as a single line: span fields first, then the message,
+//! then event fields (`user=alice failure count=2`). Where the line goes is
+//! selected with the `log` setting, a comma separated list of targets (`stderr`,
+//! `syslog`, `journald`, `file`, `none`). Never record PIN material as a field.
+
+use crate::config::ConfigResolver;
+use std::fmt::{self, Write as _};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogTarget {
+    Stderr,
+    Syslog,
+    Journald,
+    File,
+}
+
+/// Parse a `log` style target list. Unknown names are ignored so that a
+/// config written for a newer build does not silence logging entirely.
+pub fn parse_targets(spec: &str) -> Vec<LogTarget> {
+    let mut out = Vec::new();
+    for name in spec.split(',').map(|s| s.trim().to_lowercase()) {
+        let target = match name.as_str() {
+            "stderr" => LogTarget::Stderr,
+            "syslog" => LogTarget::Syslog,
+            "journald" => LogTarget::Journald,
+            "file" => LogTarget::File,
+            _ => continue,
+        };
+        if !out.contains(&target) {
+            out.push(target);
+        }
+    }
+    out
+}
+
+/// [`init_with`] over the environment alone.
+pub fn init(ident: &'static str, default_targets: &str) {
+    init_with(ident, default_targets, &ConfigResolver::from_env());
+}
+
+/// Install the global subscriber. `ident` names the process in log lines;
+/// `default_targets` applies when `log` is unset. Calling this twice is
+/// harmless (the second call is ignored).
+pub fn init_with(ident: &'static str, default_targets: &str, config: &ConfigResolver) {
+    let targets = parse_targets(config.string("log").unwrap_or(default_targets));
+    let level = config
+        .parse::<LevelFilter>("log_level")
+        .unwrap_or(LevelFilter::INFO);
+
+    let stderr = targets
+        .contains(&LogTarget::Stderr)
+        .then_some(StderrLayer { ident });
+    #[cfg(feature = "syslog")]
+    let syslog = if targets.contains(&LogTarget::Syslog) {
+        let legacy = config
+            .string("syslog_format")
+            .is_some_and(|v| v.eq_ignore_ascii_case("rfc3164"));
+        rfc5424::SyslogLayer::connect(ident, legacy)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "syslog"))]
+    let syslog: Option<tracing_subscriber::layer::Identity> = None;
+    #[cfg(feature = "journald")]
+    let journald = targets
+        .contains(&LogTarget::Journald)
+        .then(journald::JournaldLayer::connect)
+        .flatten();
+    #[cfg(not(feature = "journald"))]
+    let journald: Option<tracing_subscriber::layer::Identity> = None;
+
+    let file = targets
+        .contains(&LogTarget::File)
+        .then(|| file::FileLayer::from_config(ident, config));
+
+    let subscriber = tracing_subscriber::registry()
+        .with(level)
+        .with(SpanFieldsLayer)
+        .with(stderr)
+        .with(syslog)
+        .with(journald)
+        .with(file);
+    let _ = tracing::subscriber::set_global_default(subscriber);
+}
+
+/// tracing target of the fail2ban stream.
+pub const FAIL2BAN_TARGET: &str = "pin_auth::fail2ban";
+
+/// The stable fail2ban line (see `contrib/fail2ban/filter.d/pin-auth.conf`):
+/// `authentication failure; user=<user> rhost=<host|-> service=<service|->`.
+/// Whitespace inside values is replaced so the line always splits the same way.
+pub fn fail2ban_line(user: &str, rhost: Option<&str>, service: Option<&str>) -> String {
+    fn clean(v: Option<&str>) -> String {
+        match v {
+            Some(v) if !v.is_empty() => v
+                .chars()
+                .map(|c| if c.is_whitespace() { '_' } else { c })
+                .collect(),
+            _ => "-".to_string(),
+        }
+    }
+    format!(
+        "authentication failure; user={} rhost={} service={}",
+        clean(Some(user)),
+        clean(rhost),
+        clean(service)
+    )
+}
+
+/// Emit one failure on the fail2ban target. The event is a root event so no
+/// span fields are prepended and the line keeps its documented shape.
+pub fn fail2ban_failure(user: &str, rhost: Option<&str>, service: Option<&str>) {
+    tracing::warn!(
+        target: FAIL2BAN_TARGET,
+        parent: None,
+        "{}",
+        fail2ban_line(user, rhost, service)
+    );
+}
+
+/// Load the per-host pseudonym key, creating 32 random bytes (mode 0600) on
+/// first use.
+pub fn load_or_create_key(path: impl AsRef<std::path::Path>) -> std::io::Result<Vec<u8>> {
+    use std::io::{Read, Write};
+    use std::os::unix::fs::OpenOptionsExt;
+    let path = path.as_ref();
+    let open = |create: bool| {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(create)
+            .create_new(create)
+            .mode(0o600)
+            .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+            .open(path)
+    };
+    match open(true) {
+        Ok(mut f) => {
+            let mut key = vec![0u8; 32];
+            rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut key);
+            f.write_all(&key)?;
+            f.sync_all()?;
+            Ok(key)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            let mut key = Vec::new();
+            open(false)?.read_to_end(&mut key)?;
+            if key.len() < 16 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "pseudonym key too short",
+                ));
+            }
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// How a [`pseudonym`] starts. No account name contains the `:`.
+pub const PSEUDONYM_PREFIX: &str = "u:";
+
+/// Stable per-host stand-in for a username: `u:` plus the first 16 hex
+/// digits of HMAC-SHA256(key, name). Correlatable within a host, not across.
+pub fn pseudonym(key: &[u8], user: &str) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac =
+        <Hmac<sha2::Sha256> as Mac>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(user.as_bytes());
+    let tag = mac.finalize().into_bytes();
+    let mut out = String::from(PSEUDONYM_PREFIX);
+    for b in &tag[..8] {
+        let _ = write!(out, "{b:02x}");
+    }
+    out
+}
+
+/// Collects the message and named fields of an event or span.
+#[derive(Default)]
+struct FieldText {
+    message: String,
+    pairs: Vec<(&'static str, String)>,
+}
+
+impl FieldText {
+    fn pairs_text(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in &self.pairs {
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            let _ = write!(out, "{name}={value}");
+        }
+        out
+    }
+}
+
+impl Visit for FieldText {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.pairs.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            self.pairs.push((field.name(), format!("{value:?}")));
+        }
+    }
+}
+
+/// Fields of a span, stored in its extensions by `SpanFieldsLayer`.
+struct SpanFields(FieldText);
+
+struct SpanFieldsLayer;
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = FieldText::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+}
+
+/// Visit the fields of every span enclosing `event`, outermost first.
+fn for_each_span_field<S>(event: &Event<'_>, ctx: &Context<'_, S>, mut f: impl FnMut(&FieldText))
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if let Some(scope) = ctx.event_scope(event) {
+        for span in scope.from_root() {
+            if let Some(fields) = span.extensions().get::<SpanFields>() {
+                f(&fields.0);
+            }
+        }
+    }
+}
+
+fn render<S>(event: &Event<'_>, ctx: &Context<'_, S>) -> String
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn push(out: &mut String, part: &str) {
+        if part.is_empty() {
+            return;
+        }
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(part);
+    }
+    let mut out = String::new();
+    for_each_span_field(event, ctx, |fields| push(&mut out, &fields.pairs_text()));
+    let mut fields = FieldText::default();
+    event.record(&mut fields);
+    push(&mut out, &fields.message);
+    push(&mut out, &fields.pairs_text());
+    out
+}
+
+struct StderrLayer {
+    ident: &'static str,
+}
+
+impl<S> Layer<S> for StderrLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let level = event.metadata().level().as_str().to_lowercase();
+        eprintln!("{}: {level}: {}", self.ident, render(event, &ctx));
+    }
+}
+
+#[cfg(feature = "syslog")]
+mod rfc5424 {
+    //! Syslog output. RFC 5424 by default, with the fields operators filter
+    //! on carried as structured data (`[pinauth@32473 user="alice" ...]`);
+    //! `syslog_format = rfc3164` keeps the legacy plain format.
+
+    use super::{for_each_span_field, render, FieldText};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use syslog::{Facility, Formatter3164, Formatter5424, Logger, LoggerBackend};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// SD-ID for our element; 32473 is the documentation enterprise number (RFC 5612).
+    pub(super) const SD_ID: &str = "pinauth@32473";
+    /// Fields lifted into the structured-data element.
+    const SD_FIELDS: &[&str] = &["user", "service", "tty", "rhost", "result"];
+
+    enum Sink {
+        Rfc5424(Logger<LoggerBackend, Formatter5424>),
+        Rfc3164(Logger<LoggerBackend, Formatter3164>),
+    }
+
+    pub(super) struct SyslogLayer {
+        sink: Mutex<Sink>,
+    }
+
+    impl SyslogLayer {
+        pub(super) fn connect(ident: &str, legacy: bool) -> Option<Self> {
+            let sink = if legacy {
+                Sink::Rfc3164(
+                    syslog::unix(Formatter3164 {
+                        facility: Facility::LOG_AUTH,
+                        hostname: None,
+                        process: ident.into(),
+                        pid: std::process::id(),
+                    })
+                    .ok()?,
+                )
+            } else {
+                let hostname = nix::unistd::gethostname()
+                    .ok()
+                    .and_then(|h| h.into_string().ok());
+                Sink::Rfc5424(
+                    syslog::unix(Formatter5424 {
+                        facility: Facility::LOG_AUTH,
+                        hostname,
+                        process: ident.into(),
+                        pid: std::process::id(),
+                    })
+                    .ok()?,
+                )
+            };
+            Some(SyslogLayer {
+                sink: Mutex::new(sink),
+            })
+        }
+    }
+
+    /// Escape a PARAM-VALUE per RFC 5424 section 6.3.3.
+    pub(super) fn escape_param(value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    pub(super) fn structured_data(
+        pairs: &[(&'static str, String)],
+    ) -> HashMap<String, HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (name, value) in pairs {
+            if SD_FIELDS.contains(name) {
+                params.insert(name.to_string(), escape_param(value));
+            }
+        }
+        let mut data = HashMap::new();
+        if !params.is_empty() {
+            data.insert(SD_ID.to_string(), params);
+        }
+        data
+    }
+
+    impl<S> Layer<S> for SyslogLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let line = format!("pin-auth: {}", render(event, &ctx));
+            let Ok(mut sink) = self.sink.lock() else {
+                return;
+            };
+            let level = *event.metadata().level();
+            let _ = match &mut *sink {
+                Sink::Rfc3164(logger) => match level {
+                    Level::ERROR => logger.err(line),
+                    Level::WARN => logger.warning(line),
+                    Level::INFO => logger.info(line),
+                    _ => logger.debug(line),
+                },
+                Sink::Rfc5424(logger) => {
+                    let mut pairs = Vec::new();
+                    for_each_span_field(event, &ctx, |f| pairs.extend(f.pairs.iter().cloned()));
+                    let mut fields = FieldText::default();
+                    event.record(&mut fields);
+                    pairs.extend(fields.pairs);
+                    let msg = (0, structured_data(&pairs), line);
+                    match level {
+                        Level::ERROR => logger.err(msg),
+                        Level::WARN => logger.warning(msg),
+                        Level::INFO => logger.info(msg),
+                        _ => logger.debug(msg),
+                    }
+                }
+            };
+        }
+    }
+}
+
+mod file {
+    //! Append-only log file with size/age based rotation.
+    //!
+    //! The file is opened, locked and closed for every line, so rotation by
+    //! another process (or an external logrotate) never leaves us writing to
+    //! a renamed file and no reopen signal is needed.
+    //!
+    //! Its age counts from the mtime of `<path>.started`, touched whenever a
+    //! new file is begun: file systems need not record a birth time.
+
+    use super::{render, ConfigResolver};
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+    use std::path::{Path, PathBuf};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub const DEFAULT_PATH: &str = "/var/log/pin-auth.log";
+
+    pub(super) struct FileLayer {
+        ident: &'static str,
+        path: PathBuf,
+        max_bytes: u64,
+        max_age_secs: u64,
+        keep: u32,
+    }
+
+    impl FileLayer {
+        pub(super) fn from_config(ident: &'static str, config: &ConfigResolver) -> Self {
+            // Fixed path in release builds (this runs setuid root); debug/test builds may redirect.
+            let path = if cfg!(debug_assertions) {
+                std::env::var("PIN_LOG_FILE").unwrap_or_else(|_| DEFAULT_PATH.to_string())
+            } else {
+                DEFAULT_PATH.to_string()
+            };
+            let max_bytes = config
+                .parse("log_file_max_bytes")
+                .unwrap_or(10 * 1024 * 1024);
+            let max_age_secs = config.duration("log_file_max_age").unwrap_or(0);
+            let keep = config.parse("log_file_keep").unwrap_or(5);
+            FileLayer {
+                ident,
+                path: PathBuf::from(path),
+                max_bytes,
+                max_age_secs,
+                keep,
+            }
+        }
+
+        fn open(&self) -> io::Result<fs::File> {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .mode(0o600)
+                .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+                .open(&self.path)
+        }
+
+        /// [`FileLayer::open`], locked. A writer that waited for the lock
+        /// while another rotated holds `<path>.1` and must start over.
+        fn open_locked(&self) -> io::Result<fs::File> {
+            loop {
+                let f = self.open()?;
+                crate::lock::lock_exclusive(&f, crate::lock::LOCK_TIMEOUT)?;
+                let held = f.metadata()?;
+                if fs::symlink_metadata(&self.path)
+                    .is_ok_and(|md| md.dev() == held.dev() && md.ino() == held.ino())
+                {
+                    return Ok(f);
+                }
+            }
+        }
+
+        fn needs_rotation(&self, f: &fs::File, incoming: u64) -> io::Result<bool> {
+            let md = f.metadata()?;
+            if md.len() == 0 {
+                // new, or rotated by logrotate: its age starts now
+                if self.max_age_secs > 0 {
+                    touch(&started(&self.path))?;
+                }
+                return Ok(false);
+            }
+            if self.max_bytes > 0 && md.len() + incoming > self.max_bytes {
+                return Ok(true);
+            }
+            if self.max_age_secs > 0 {
+                let stamp = started(&self.path);
+                match fs::metadata(&stamp).and_then(|md| md.modified()) {
+                    Ok(t) => {
+                        let age = t.elapsed().unwrap_or_default();
+                        return Ok(age.as_secs() > self.max_age_secs);
+                    }
+                    // written before ages were tracked: count from now
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => touch(&stamp)?,
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(false)
+        }
+
+        pub(super) fn write_line(&self, line: &str) -> io::Result<()> {
+            let mut f = self.open_locked()?;
+            if self.needs_rotation(&f, line.len() as u64)? {
+                rotate(&self.path, self.keep)?;
+                // Our fd now points at `<path>.1`; continue in a fresh file,
+                // holding the old lock until the new one is taken.
+                f = self.open_locked()?;
+                if self.max_age_secs > 0 {
+                    touch(&started(&self.path))?;
+                }
+            }
+            f.write_all(line.as_bytes())
+        }
+    }
+
+    /// `<path>.started`, whose mtime is when the current file was begun.
+    fn started(path: &Path) -> PathBuf {
+        let mut p = path.as_os_str().to_os_string();
+        p.push(".started");
+        PathBuf::from(p)
+    }
+
+    fn touch(path: &Path) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+            .open(path)?
+            .set_modified(SystemTime::now())
+    }
+
+    fn numbered(path: &Path, n: u32) -> PathBuf {
+        let mut p = path.as_os_str().to_os_string();
+        p.push(format!(".{n}"));
+        PathBuf::from(p)
+    }
+
+    /// `log.(keep-1)` -> `log.keep`, ..., `log` -> `log.1`. With `keep == 0`
+    /// the current file is simply removed.
+    pub(super) fn rotate(path: &Path, keep: u32) -> io::Result<()> {
+        if keep == 0 {
+            return fs::remove_file(path);
+        }
+        let _ = fs::remove_file(numbered(path, keep));
+        for n in (1..keep).rev() {
+            let _ = fs::rename(numbered(path, n), numbered(path, n + 1));
+        }
+        fs::rename(path, numbered(path, 1))
+    }
+
+    impl<S> Layer<S> for FileLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let line = format!(
+                "{} {}[{}] {}: {}\n",
+                crate::format_utc(now),
+                self.ident,
+                std::process::id(),
+                event.metadata().level().as_str().to_lowercase(),
+                render(event, &ctx)
+            );
+            let _ = self.write_line(&line);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn rotates_by_size() {
+            let tmp = tempfile::tempdir().unwrap();
+            let layer = FileLayer {
+                ident: "test",
+                path: tmp.path().join("pin.log"),
+                max_bytes: 16,
+                max_age_secs: 0,
+                keep: 2,
+            };
+            for line in [
+                "aaaaaaaaaa\n",
+                "bbbbbbbbbb\n",
+                "cccccccccc\n",
+                "dddddddddd\n",
+            ] {
+                layer.write_line(line).unwrap();
+            }
+            let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+            assert_eq!(read(layer.path.clone()), "dddddddddd\n");
+            assert_eq!(read(numbered(&layer.path, 1)), "cccccccccc\n");
+            assert_eq!(read(numbered(&layer.path, 2)), "bbbbbbbbbb\n");
+            assert!(!numbered(&layer.path, 3).exists());
+        }
+
+        #[test]
+        fn rotates_by_age_without_birth_time() {
+            let tmp = tempfile::tempdir().unwrap();
+            let layer = FileLayer {
+                ident: "test",
+                path: tmp.path().join("pin.log"),
+                max_bytes: 0,
+                max_age_secs: 3600,
+                keep: 1,
+            };
+            layer.write_line("old\n").unwrap();
+            layer.write_line("old\n").unwrap();
+            assert!(!numbered(&layer.path, 1).exists());
+            let stamp = started(&layer.path);
+            let backdate = |secs| {
+                fs::File::options()
+                    .write(true)
+                    .open(&stamp)
+                    .unwrap()
+                    .set_modified(SystemTime::now() - std::time::Duration::from_secs(secs))
+                    .unwrap()
+            };
+            backdate(7200);
+            layer.write_line("new\n").unwrap();
+            let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+            assert_eq!(read(numbered(&layer.path, 1)), "old\nold\n");
+            assert_eq!(read(layer.path.clone()), "new\n");
+            // the new file's age starts at the rotation
+            layer.write_line("new\n").unwrap();
+            assert_eq!(read(layer.path.clone()), "new\nnew\n");
+        }
+
+        #[test]
+        fn waiting_writer_follows_rotation() {
+            let tmp = tempfile::tempdir().unwrap();
+            let layer = FileLayer {
+                ident: "test",
+                path: tmp.path().join("pin.log"),
+                max_bytes: 0,
+                max_age_secs: 0,
+                keep: 1,
+            };
+            layer.write_line("before\n").unwrap();
+            let held = layer.open().unwrap();
+            crate::lock::lock_exclusive(&held, crate::lock::LOCK_TIMEOUT).unwrap();
+            std::thread::scope(|s| {
+                let writer = s.spawn(|| layer.write_line("after\n"));
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                rotate(&layer.path, layer.keep).unwrap();
+                drop(held);
+                writer.join().unwrap().unwrap();
+            });
+            let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+            assert_eq!(read(numbered(&layer.path, 1)), "before\n");
+            assert_eq!(read(layer.path.clone()), "after\n");
+        }
+    }
+}
+
+#[cfg(feature = "journald")]
+mod journald {
+    //! Native journal protocol: one datagram per entry, fields as
+    //! `KEY=value` lines, with the length-prefixed form for values that
+    //! contain newlines.
+
+    use super::{for_each_span_field, render, FieldText};
+    use std::os::unix::net::UnixDatagram;
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+    pub(super) struct JournaldLayer {
+        socket: UnixDatagram,
+    }
+
+    impl JournaldLayer {
+        pub(super) fn connect() -> Option<Self> {
+            let socket = UnixDatagram::unbound().ok()?;
+            socket.connect(JOURNAL_SOCKET).ok()?;
+            Some(JournaldLayer { socket })
+        }
+    }
+
+    /// Map a tracing field name to a journal field: `user` -> `PIN_USER`.
+    fn field_name(name: &str) -> String {
+        let mut out = String::from("PIN_");
+        out.extend(name.chars().map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        }));
+        out
+    }
+
+    pub(super) fn put_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+        buf.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+
+    impl<S> Layer<S> for JournaldLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let priority = match *event.metadata().level() {
+                Level::ERROR => "3",
+                Level::WARN => "4",
+                Level::INFO => "6",
+                _ => "7",
+            };
+            let mut buf = Vec::new();
+            put_field(&mut buf, "MESSAGE", &render(event, &ctx));
+            put_field(&mut buf, "PRIORITY", priority);
+            put_field(&mut buf, "SYSLOG_FACILITY", "4");
+            put_field(&mut buf, "SYSLOG_IDENTIFIER", "pin-auth");
+            put_field(&mut buf, "SYSLOG_PID", &std::process::id().to_string());
+            let mut emit = |fields: &FieldText| {
+                for (name, value) in &fields.pairs {
+                    put_field(&mut buf, &field_name(name), value);
+                }
+            };
+            for_each_span_field(event, &ctx, &mut emit);
+            let mut fields = FieldText::default();
+            event.record(&mut fields);
+            emit(&fields);
+            let _ = self.socket.send(&buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_parse_and_dedupe() {
+        assert_eq!(
+            parse_targets("syslog, STDERR,syslog,bogus"),
+            vec![LogTarget::Syslog, LogTarget::Stderr]
+        );
+        assert!(parse_targets("none").is_empty());
+    }
+
+    #[test]
+    fn fail2ban_line_is_stable() {
+        assert_eq!(
+            fail2ban_line("alice", Some("10.0.0.1"), Some("sshd")),
+            "authentication failure; user=alice rhost=10.0.0.1 service=sshd"
+        );
+        assert_eq!(
+            fail2ban_line("bob", Some("evil host"), None),
+            "authentication failure; user=bob rhost=evil_host service=-"
+        );
+    }
+
+    #[test]
+    fn pseudonyms_are_keyed_and_stable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("log.key");
+        let key = load_or_create_key(&path).unwrap();
+        assert_eq!(key, load_or_create_key(&path).unwrap());
+        let a = pseudonym(&key, "alice");
+        assert_eq!(a, pseudonym(&key, "alice"));
+        assert_eq!(a.len(), 18);
+        assert_ne!(a, pseudonym(&key, "bob"));
+        assert_ne!(a, pseudonym(b"another host key", "alice"));
+    }
+
+    #[cfg(feature = "syslog")]
+    #[test]
+    fn structured_data_picks_known_fields() {
+        let pairs = vec![
+            ("user", "alice".to_string()),
+            ("failcount", "2".to_string()),
+            ("rhost", "a]b\"c".to_string()),
+        ];
+        let data = rfc5424::structured_data(&pairs);
+        let params = &data[rfc5424::SD_ID];
+        assert_eq!(params.len(), 2);
+        assert_eq!(params["user"], "alice");
+        assert_eq!(params["rhost"], "a\\]b\\\"c");
+    }
+
+    #[cfg(feature = "journald")]
+    #[test]
+    fn journal_fields_encode() {
+        let mut buf = Vec::new();
+        journald::put_field(&mut buf, "PIN_USER", "alice");
+        journald::put_field(&mut buf, "MESSAGE", "a\nb");
+        let mut expected = b"PIN_USER=alice\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(buf, expected);
+    }
+}
@@ -0,0 +1,174 @@
+//! Man pages for the binaries (`genpin --generate-man` and so on), for
+//! packagers to run at build time. The pages are roff written from the
+//! same usage text, option tables and exit codes the binaries use, so they
+//! cannot drift from the programs.
+
+use crate::config::KEYS;
+use std::fmt::Write;
+
+/// The (unlisted) argument that prints the page and exits.
+pub const GENERATE_MAN: &str = "--generate-man";
+
+/// A section 8 page.
+#[derive(Clone, Debug, Default)]
+pub struct ManPage {
+    pub name: &'static str,
+    /// One line, for NAME.
+    pub about: &'static str,
+    pub synopsis: Vec<String>,
+    pub description: Vec<String>,
+    /// Further sections of terms and their texts: OPTIONS, COMMANDS, ...
+    pub lists: Vec<(&'static str, Vec<(String, String)>)>,
+}
+
+impl ManPage {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            ".TH {} 8 \"\" \"pin-auth {}\" \"System Administration\"",
+            self.name.to_uppercase(),
+            env!("CARGO_PKG_VERSION")
+        );
+        let _ = writeln!(out, ".SH NAME\n{} \\- {}", self.name, escape(self.about));
+        out.push_str(".SH SYNOPSIS\n.nf\n");
+        for line in &self.synopsis {
+            let _ = writeln!(out, "{}", escape(line));
+        }
+        out.push_str(".fi\n.SH DESCRIPTION\n");
+        for (i, paragraph) in self.description.iter().enumerate() {
+            if i > 0 {
+                out.push_str(".PP\n");
+            }
+            let _ = writeln!(out, "{}", escape(paragraph));
+        }
+        let environment = ("ENVIRONMENT", environment());
+        for (title, entries) in self.lists.iter().chain([&environment]) {
+            let _ = writeln!(out, ".SH {title}");
+            for (term, text) in entries {
+                let _ = writeln!(out, ".TP\n.B {}\n{}", escape(term), escape(text));
+            }
+        }
+        let _ = writeln!(
+            out,
+            ".SH FILES\n.TP\n.B {dir}/pin-auth.conf\nsettings\n.TP\n.B {dir}\nPIN records and, \
+             without state_dir, fail state and logs",
+            dir = crate::paths::PIN_DIR
+        );
+        out.push_str(".SH SEE ALSO\n");
+        for page in ["check_pin", "genpin", "pinctl", "pam_exec"] {
+            if page != self.name {
+                let _ = writeln!(out, ".BR {page} (8)");
+            }
+        }
+        out
+    }
+}
+
+/// The commands of a usage text like `pinctl`'s: after a `commands:`
+/// line, one command per line, its text after a gap of two or more
+/// blanks. Lines indented further continue the previous text; a command
+/// too long for the gap ends before its first plain lowercase word.
+pub fn usage_entries(usage: &str) -> Vec<(String, String)> {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    let lines = usage
+        .lines()
+        .skip_while(|l| l.trim() != "commands:")
+        .skip(1);
+    for line in lines {
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match entries.last_mut() {
+            Some((_, text)) if indent > 2 => {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(line);
+            }
+            _ => {
+                let (term, text) = line.split_once("  ").unwrap_or_else(|| split_command(line));
+                entries.push((term.trim().to_string(), text.trim().to_string()));
+            }
+        }
+    }
+    entries
+}
+
+/// `line` split before the first word after the command that is not an
+/// option or an argument placeholder (`--x`, `[...]`, `USER`).
+fn split_command(line: &str) -> (&str, &str) {
+    let argument = |word: &str| {
+        word.starts_with(['-', '[', '<']) || !word.contains(|c: char| c.is_ascii_lowercase())
+    };
+    let mut at = line.find(' ').unwrap_or(line.len());
+    while let Some(word) = line[at..].split_whitespace().next() {
+        if !argument(word) {
+            return (&line[..at], &line[at..]);
+        }
+        at = line[at..].find(word).unwrap_or_default() + at + word.len();
+    }
+    (line, "")
+}
+
+/// The deprecated `PIN_*` variables, from the configuration keys.
+fn environment() -> Vec<(String, String)> {
+    KEYS.iter()
+        .filter_map(|key| {
+            let env = key.env?;
+            Some((
+                env.to_string(),
+                format!("deprecated: set {} in pin-auth.conf", key.name),
+            ))
+        })
+        .collect()
+}
+
+/// `text` safe as roff text: backslashes and hyphens escaped, and no
+/// line starting with a control character.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{text}")
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let usage = "usage: tool <command>\n\ncommands:\n  \
+                     status USER     show USER\n  \
+                     cleanup [--apply]\n                  remove stale\n                  files\n  \
+                     enroll USER [--x N] let USER in\n  \
+                     self-test       check it\n";
+        let entries = usage_entries(usage);
+        assert_eq!(
+            entries,
+            [
+                ("status USER".into(), "show USER".into()),
+                ("cleanup [--apply]".into(), "remove stale files".into()),
+                ("enroll USER [--x N]".into(), "let USER in".into()),
+                ("self-test".into(), "check it".into()),
+            ]
+        );
+        let page = ManPage {
+            name: "tool",
+            about: "do things",
+            synopsis: vec!["tool <command>".into()],
+            description: vec![".hidden \\ text".into()],
+            lists: vec![("COMMANDS", entries)],
+        }
+        .render();
+        assert!(page.starts_with(".TH TOOL 8 "), "{page}");
+        assert!(page.contains("\n\\&.hidden \\e text\n"), "{page}");
+        assert!(page.contains(".TP\n.B cleanup [\\-\\-apply]\nremove stale files\n"));
+        assert!(page.contains(".B PIN_MAX_FAILS\n"));
+    }
+}
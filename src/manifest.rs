@@ -0,0 +1,255 @@
+//! A signed manifest of the PIN directory (feature `manifest`), to notice
+//! records changed while the system was offline (`pinctl sign`,
+//! `pinctl verify`).
+//!
+//! `pin-auth.manifest` lists the size and SHA-256 of every file that only
+//! changes when an administrator runs `genpin` or `pinctl`: PIN and decoy
+//! hashes, YubiKey and FIDO2 records and `pin-auth.conf`. Files `check_pin`
+//! rewrites itself ([`STATE_EXTS`]) are left out. The list is signed with
+//! an ed25519 key that is best kept off the host; verifying needs only the
+//! public half.
+//!
+//! ```text
+//! pin-auth manifest 1
+//! alice.passwd 107 <sha256 hex>
+//! sig <ed25519 signature over the lines above, hex>
+//! ```
+
+use crate::config::CONFIG_FILE;
+use crate::store::{
+    load_or_create_key_file, read_bytes_nofollow, DirStore, RECORD_EXTS, STATE_EXTS,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+
+/// The manifest's file name in the PIN directory.
+pub const MANIFEST_FILE: &str = "pin-auth.manifest";
+const HEADER: &str = "pin-auth manifest 1";
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ManifestError {
+    /// Line (1-based) is not a header, entry or signature line.
+    #[error("line {0}: malformed manifest")]
+    Malformed(usize),
+    /// The signature does not match the key, or anything it covers changed.
+    #[error("bad signature")]
+    BadSignature,
+}
+
+/// One covered file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    pub digest: [u8; 32],
+}
+
+/// How the directory differs from a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Modified(String),
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(name) => write!(f, "{name}: not in manifest"),
+            Change::Removed(name) => write!(f, "{name}: missing"),
+            Change::Modified(name) => write!(f, "{name}: changed"),
+        }
+    }
+}
+
+/// Covered files, sorted by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// The covered files as they are now.
+    pub fn scan(store: &DirStore) -> io::Result<Self> {
+        let mut names = Vec::new();
+        for ext in RECORD_EXTS.iter().filter(|ext| !STATE_EXTS.contains(ext)) {
+            names.extend(
+                store
+                    .names(ext)?
+                    .into_iter()
+                    .map(|key| format!("{key}.{ext}")),
+            );
+        }
+        if store.dir().join(CONFIG_FILE).exists() {
+            names.push(CONFIG_FILE.to_string());
+        }
+        names.sort();
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            let contents = read_bytes_nofollow(&store.dir().join(&name))?;
+            entries.push(Entry {
+                size: contents.len() as u64,
+                digest: Sha256::digest(&contents).into(),
+                name,
+            });
+        }
+        Ok(Manifest { entries })
+    }
+
+    fn body(&self) -> String {
+        let mut body = format!("{HEADER}\n");
+        for entry in &self.entries {
+            body.push_str(&format!(
+                "{} {} {}\n",
+                entry.name,
+                entry.size,
+                crate::hex(&entry.digest).as_str()
+            ));
+        }
+        body
+    }
+
+    /// The manifest file, signed with `key`.
+    pub fn sign(&self, key: &SigningKey) -> String {
+        let body = self.body();
+        let sig = key.sign(body.as_bytes());
+        format!("{body}sig {}\n", crate::hex(&sig.to_bytes()).as_str())
+    }
+
+    /// Parse a manifest file, checking its signature against `key`.
+    pub fn verify(text: &str, key: &VerifyingKey) -> Result<Self, ManifestError> {
+        let Some((body, sig_line)) = text.trim_end_matches('\n').rsplit_once('\n') else {
+            return Err(ManifestError::Malformed(1));
+        };
+        let body = format!("{body}\n");
+        let lines = body.lines().count();
+        let sig = sig_line
+            .strip_prefix("sig ")
+            .and_then(crate::parse_hex)
+            .and_then(|sig| Signature::from_slice(&sig).ok())
+            .ok_or(ManifestError::Malformed(lines + 1))?;
+        key.verify(body.as_bytes(), &sig)
+            .map_err(|_| ManifestError::BadSignature)?;
+        let mut lines = body.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(ManifestError::Malformed(1));
+        }
+        let mut entries = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let malformed = || ManifestError::Malformed(i + 2);
+            let mut fields = line.split(' ');
+            let (Some(name), Some(size), Some(digest), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(malformed());
+            };
+            entries.push(Entry {
+                name: name.to_string(),
+                size: size.parse().map_err(|_| malformed())?,
+                digest: crate::parse_hex(digest)
+                    .and_then(|d| <[u8; 32]>::try_from(d.as_slice()).ok())
+                    .ok_or_else(malformed)?,
+            });
+        }
+        Ok(Manifest { entries })
+    }
+
+    /// What changed between this manifest and `now`, by file name.
+    pub fn changes(&self, now: &Manifest) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for entry in &self.entries {
+            match now.entries.iter().find(|e| e.name == entry.name) {
+                None => changes.push(Change::Removed(entry.name.clone())),
+                Some(e) if e != entry => changes.push(Change::Modified(entry.name.clone())),
+                Some(_) => {}
+            }
+        }
+        for entry in &now.entries {
+            if !self.entries.iter().any(|e| e.name == entry.name) {
+                changes.push(Change::Added(entry.name.clone()));
+            }
+        }
+        changes.sort_by(|a, b| change_name(a).cmp(change_name(b)));
+        changes
+    }
+}
+
+fn change_name(change: &Change) -> &str {
+    match change {
+        Change::Added(name) | Change::Removed(name) | Change::Modified(name) => name,
+    }
+}
+
+/// Load the signing key at `path` (32 bytes, hex), creating one (mode
+/// 0600) if there is none. Returns whether it was created.
+pub fn load_or_create_signing_key(path: &Path) -> io::Result<(SigningKey, bool)> {
+    let (secret, created) = load_or_create_key_file(path)?;
+    Ok((SigningKey::from_bytes(&secret), created))
+}
+
+/// A public key given as 64 hex digits (`manifest_pubkey`).
+pub fn parse_public_key(s: &str) -> Option<VerifyingKey> {
+    let bytes = crate::parse_hex(s.trim())?;
+    VerifyingKey::from_bytes(&<[u8; 32]>::try_from(bytes.as_slice()).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sign_then_verify() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = DirStore::new(tmp.path());
+        fs::write(tmp.path().join("alice.passwd"), "$6$x$y\n").unwrap();
+        fs::write(tmp.path().join("@ops.passwd"), "$6$x$z\n").unwrap();
+        fs::write(tmp.path().join("alice.fail"), "1:100\n").unwrap();
+        fs::write(tmp.path().join(CONFIG_FILE), "audit = 1\n").unwrap();
+
+        let (key, created) = load_or_create_signing_key(&tmp.path().join("sign.key")).unwrap();
+        assert!(created);
+        let (again, created) = load_or_create_signing_key(&tmp.path().join("sign.key")).unwrap();
+        assert!(!created);
+        assert_eq!(again.to_bytes(), key.to_bytes());
+        let public = key.verifying_key();
+        assert_eq!(
+            parse_public_key(crate::hex(public.as_bytes()).as_str()),
+            Some(public)
+        );
+
+        let signed = Manifest::scan(&store).unwrap();
+        let names: Vec<_> = signed.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["@ops.passwd", "alice.passwd", CONFIG_FILE]);
+        let text = signed.sign(&key);
+        assert_eq!(Manifest::verify(&text, &public).unwrap(), signed);
+        let other = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        assert!(matches!(
+            Manifest::verify(&text, &other),
+            Err(ManifestError::BadSignature)
+        ));
+        let edited = text.replacen("alice.passwd 7", "alice.passwd 8", 1);
+        assert!(matches!(
+            Manifest::verify(&edited, &public),
+            Err(ManifestError::BadSignature)
+        ));
+
+        // check_pin's own files may change freely
+        fs::write(tmp.path().join("alice.fail"), "2:100\n").unwrap();
+        assert!(signed.changes(&Manifest::scan(&store).unwrap()).is_empty());
+        fs::write(tmp.path().join("alice.passwd"), "$6$x$w\n").unwrap();
+        fs::remove_file(tmp.path().join("@ops.passwd")).unwrap();
+        fs::write(tmp.path().join("bob.decoy"), "$6$x$v\n").unwrap();
+        assert_eq!(
+            signed.changes(&Manifest::scan(&store).unwrap()),
+            [
+                Change::Removed("@ops.passwd".into()),
+                Change::Modified("alice.passwd".into()),
+                Change::Added("bob.decoy".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Prometheus counters in a node_exporter textfile (`metrics_file`).
+//!
+//! Each `check_pin` run adds its attempt to the file under an exclusive
+//! lock on `<file>.lock` and replaces it atomically, so the textfile
+//! collector never reads half a file. The counters live only in the file:
+//! deleting it starts them over, which Prometheus treats as a reset.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+const ATTEMPTS: &str = "pin_auth_attempts_total";
+const LOCKOUTS: &str = "pin_auth_lockouts_total";
+const LAST: &str = "pin_auth_last_attempt_timestamp_seconds";
+
+/// The counters as kept in the file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Attempts by result (`success`, `failure`, `locked`, ...).
+    pub attempts: BTreeMap<String, u64>,
+    pub lockouts: u64,
+    pub last_attempt: u64,
+}
+
+impl Metrics {
+    /// Read back what [`Metrics::render`] wrote; other lines are ignored.
+    pub fn parse(text: &str) -> Self {
+        let mut metrics = Metrics::default();
+        for line in text.lines().filter(|l| !l.starts_with('#')) {
+            let Some((series, value)) = line.rsplit_once(' ') else {
+                continue;
+            };
+            let Ok(value) = value.parse::<u64>() else {
+                continue;
+            };
+            if series == LOCKOUTS {
+                metrics.lockouts = value;
+            } else if series == LAST {
+                metrics.last_attempt = value;
+            } else if let Some(result) = series
+                .strip_prefix(ATTEMPTS)
+                .and_then(|s| s.strip_prefix("{result=\""))
+                .and_then(|s| s.strip_suffix("\"}"))
+            {
+                metrics.attempts.insert(result.to_string(), value);
+            }
+        }
+        metrics
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP {ATTEMPTS} PIN authentication attempts by result.\n# TYPE {ATTEMPTS} counter"
+        );
+        for (result, n) in &self.attempts {
+            let _ = writeln!(out, "{ATTEMPTS}{{result=\"{result}\"}} {n}");
+        }
+        let _ = writeln!(
+            out,
+            "# HELP {LOCKOUTS} Users locked out by failed attempts or decoy PINs.\n# TYPE {LOCKOUTS} counter\n{LOCKOUTS} {}",
+            self.lockouts
+        );
+        let _ = writeln!(
+            out,
+            "# HELP {LAST} Time of the last attempt.\n# TYPE {LAST} gauge\n{LAST} {}",
+            self.last_attempt
+        );
+        out
+    }
+}
+
+/// Count one attempt with `result` at `now` (and a lockout, if it caused
+/// one) in the textfile at `path`.
+pub fn record(path: &Path, result: &str, lockout: bool, now: u64) -> io::Result<()> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+        .open(&lock_path)?;
+    crate::lock::lock_exclusive(&lock, crate::lock::LOCK_TIMEOUT)?;
+    let mut metrics = match crate::store::read_file_nofollow(path) {
+        Ok(text) => Metrics::parse(&text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Metrics::default(),
+        Err(e) => return Err(e),
+    };
+    *metrics.attempts.entry(result.to_string()).or_default() += 1;
+    metrics.lockouts += u64::from(lockout);
+    metrics.last_attempt = now;
+
+    // node_exporter runs unprivileged
+    crate::store::write_atomic(path, metrics.render().as_bytes(), 0o644)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_accumulate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("pin-auth.prom");
+        record(&path, "failure", false, 100).unwrap();
+        record(&path, "failure", true, 101).unwrap();
+        record(&path, "success", false, 102).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("pin_auth_attempts_total{result=\"failure\"} 2\n"));
+        assert!(text.contains("pin_auth_lockouts_total 1\n"));
+        assert!(text.contains("pin_auth_last_attempt_timestamp_seconds 102\n"));
+        let metrics = Metrics::parse(&text);
+        assert_eq!(metrics.attempts["success"], 1);
+        assert_eq!(Metrics::parse(&metrics.render()), metrics);
+    }
+}
@@ -0,0 +1,178 @@
+//! Lockout and decoy-PIN notification: pipe a templated message to a local
+//! mailer.
+//!
+//! The template uses `{name}` placeholders: `{to}`, `{user}`, `{host}`,
+//! `{service}`, `{tty}`, `{rhost}`, `{failcount}` and `{until}`. Unknown
+//! placeholders are left as-is.
+
+use crate::pam::PamContext;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+pub const DEFAULT_COMMAND: &str = "/usr/sbin/sendmail -t -oi";
+
+pub const DEFAULT_TEMPLATE: &str = "\
+To: {to}
+Subject: pin-auth: {user} locked out on {host}
+
+The PIN for user {user} on {host} was locked after {failcount} failed attempts.
+
+Service:      {service}
+TTY:          {tty}
+Remote host:  {rhost}
+Locked until: {until}
+";
+
+/// Sent when one of a user's decoy PINs is entered (`{until}` is only
+/// meaningful with `decoy_lock`, so it is left out).
+pub const DECOY_TEMPLATE: &str = "\
+To: {to}
+Subject: pin-auth: decoy PIN entered for {user} on {host}
+
+A decoy PIN for user {user} on {host} was just entered. Whoever typed it
+knows an old or planted PIN; treat the account as targeted.
+
+Service:      {service}
+TTY:          {tty}
+Remote host:  {rhost}
+Failures:     {failcount}
+";
+
+/// Details of a lockout or decoy attempt, as passed to the template.
+pub struct LockoutNotice<'a> {
+    pub user: &'a str,
+    pub pam: &'a PamContext,
+    pub failcount: u32,
+    /// Epoch seconds; `None` for an indefinite lock.
+    pub until: Option<u64>,
+}
+
+/// Recipients are restricted to a conservative address charset so the value
+/// cannot inject headers into the message.
+pub fn valid_recipient(to: &str) -> bool {
+    !to.is_empty()
+        && to.len() <= 254
+        && to
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c))
+}
+
+/// Fill in `template`. Values are substituted in one pass, so one cannot
+/// introduce another placeholder, and control characters (CR/LF above all)
+/// become `?`: `{rhost}` and `{service}` come partly from the remote side
+/// and must not add header lines.
+pub fn render(template: &str, to: &str, notice: &LockoutNotice<'_>) -> String {
+    let host = nix::unistd::gethostname()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "localhost".to_string());
+    let until = match notice.until {
+        Some(ts) => ts.to_string(),
+        None => "indefinite (until reset)".to_string(),
+    };
+    let failcount = notice.failcount.to_string();
+    let value = |name: &str| -> Option<&str> {
+        Some(match name {
+            "to" => to,
+            "user" => notice.user,
+            "host" => &host,
+            "service" => notice.pam.service.as_deref().unwrap_or("-"),
+            "tty" => notice.pam.tty.as_deref().unwrap_or("-"),
+            "rhost" => notice.pam.rhost.as_deref().unwrap_or("-"),
+            "failcount" => &failcount,
+            "until" => &until,
+            _ => return None,
+        })
+    };
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        match tail
+            .find('}')
+            .and_then(|end| value(&tail[1..end]).map(|v| (end, v)))
+        {
+            Some((end, v)) => {
+                out.extend(v.chars().map(|c| if c.is_control() { '?' } else { c }));
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Spawn `command` (split on whitespace, no shell) with a minimal environment
+/// and write `message` to its stdin. The child is not waited for so a slow
+/// mailer cannot hold up the login.
+pub fn send(command: &str, message: &str) -> io::Result<()> {
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty notify command"))?;
+    let mut child = Command::new(program)
+        .args(parts)
+        .env_clear()
+        .env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_and_recipient() {
+        let pam = PamContext {
+            service: Some("sshd".into()),
+            ..Default::default()
+        };
+        let notice = LockoutNotice {
+            user: "alice",
+            pam: &pam,
+            failcount: 5,
+            until: Some(1700000300),
+        };
+        let msg = render(DEFAULT_TEMPLATE, "root", &notice);
+        assert!(msg.starts_with("To: root\nSubject: pin-auth: alice locked out on "));
+        assert!(msg.contains("after 5 failed attempts"));
+        assert!(msg.contains("Remote host:  -\n"));
+        assert!(msg.contains("Locked until: 1700000300\n"));
+
+        // remote-controlled values cannot add headers or placeholders
+        let pam = PamContext {
+            rhost: Some("evil.example\r\nBcc: x@evil".into()),
+            service: Some("{user}".into()),
+            ..Default::default()
+        };
+        let notice = LockoutNotice {
+            pam: &pam,
+            ..notice
+        };
+        let msg = render(
+            "To: {to}\nX-Rhost: {rhost}\nX-Service: {service}\n{unknown}\n",
+            "root",
+            &notice,
+        );
+        assert_eq!(
+            msg,
+            "To: root\nX-Rhost: evil.example??Bcc: x@evil\nX-Service: {user}\n{unknown}\n"
+        );
+
+        assert!(valid_recipient("ops+pin@example.org"));
+        assert!(!valid_recipient("root\nBcc: x@evil"));
+        assert!(!valid_recipient(""));
+    }
+}
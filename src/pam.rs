@@ -0,0 +1,110 @@
+//! PAM items passed to the helper by `pam_exec`, and the token it writes
+//! to the helper's stdin.
+
+/// `PAM_SERVICE`, `PAM_TTY` and `PAM_RHOST` from the environment `pam_exec`
+/// sets up; empty values are treated as absent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PamContext {
+    pub service: Option<String>,
+    pub tty: Option<String>,
+    pub rhost: Option<String>,
+}
+
+impl PamContext {
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        PamContext {
+            service: var("PAM_SERVICE"),
+            tty: var("PAM_TTY"),
+            rhost: var("PAM_RHOST"),
+        }
+    }
+}
+
+/// How the token on `check_pin`'s stdin ends (`authtok_framing`).
+/// `pam_exec expose_authtok` writes it followed by a NUL; a shell or
+/// another wrapper usually ends it with a newline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AuthtokFraming {
+    /// NUL framing if the input has a NUL, newline framing otherwise.
+    #[default]
+    Auto,
+    /// The token, then a NUL, then nothing.
+    Nul,
+    /// The token, then an optional `\n`.
+    Newline,
+}
+
+impl std::str::FromStr for AuthtokFraming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(AuthtokFraming::Auto),
+            "nul" => Ok(AuthtokFraming::Nul),
+            "newline" => Ok(AuthtokFraming::Newline),
+            _ => Err(format!("{s:?} is not auto, nul or newline")),
+        }
+    }
+}
+
+impl AuthtokFraming {
+    /// The token in `input`, or `None` if `input` is not framed this way:
+    /// a missing NUL, or anything after the terminator.
+    pub fn token(self, input: &str) -> Option<&str> {
+        match self {
+            AuthtokFraming::Auto if input.contains('\0') => AuthtokFraming::Nul.token(input),
+            AuthtokFraming::Auto => AuthtokFraming::Newline.token(input),
+            AuthtokFraming::Nul => match input.split_once('\0') {
+                Some((token, "")) => Some(token),
+                _ => None,
+            },
+            AuthtokFraming::Newline => {
+                let token = input.strip_suffix('\n').unwrap_or(input);
+                (!token.contains(['\n', '\0'])).then_some(token)
+            }
+        }
+    }
+
+    /// [`AuthtokFraming::token`] with whitespace (CR, LF, blanks) dropped
+    /// around the token and before the end of the input
+    /// (`normalize_input`).
+    pub fn normalized_token(self, input: &str) -> Option<&str> {
+        self.token(input.trim_end()).map(str::trim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authtok_framing() {
+        let auto = AuthtokFraming::Auto;
+        assert_eq!(auto.token("2468\0"), Some("2468"));
+        assert_eq!(auto.token("2468\n"), Some("2468"));
+        assert_eq!(auto.token("2468\r\n"), Some("2468\r"));
+        assert_eq!(auto.token("2468"), Some("2468"));
+        assert_eq!(auto.token("\0"), Some(""));
+        assert_eq!(auto.token(""), Some(""));
+        assert_eq!(auto.token("2468\0junk"), None);
+        assert_eq!(auto.token("2468\n1357\n"), None);
+
+        let nul = AuthtokFraming::Nul;
+        assert_eq!(nul.token("2468\0"), Some("2468"));
+        assert_eq!(nul.token("2468\n"), None);
+        assert_eq!(nul.token(""), None);
+
+        let newline = AuthtokFraming::Newline;
+        assert_eq!(newline.token("2468\n"), Some("2468"));
+        assert_eq!(newline.token("2468\0"), None);
+        assert_eq!("NUL".parse(), Ok(AuthtokFraming::Nul));
+
+        assert_eq!(auto.normalized_token(" 2468\r\n"), Some("2468"));
+        assert_eq!(auto.normalized_token("2468\r\n\r\n"), Some("2468"));
+        assert_eq!(auto.normalized_token("2468\r\0"), Some("2468"));
+        assert_eq!(auto.normalized_token("24 68\n"), Some("24 68"));
+        assert_eq!(nul.normalized_token("2468\r\n"), None);
+        assert!("crlf".parse::<AuthtokFraming>().is_err());
+    }
+}
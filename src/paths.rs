@@ -0,0 +1,84 @@
+//! Default locations, fixed at build time.
+//!
+//! Packagers may set these variables for `cargo build`:
+//!
+//! ```text
+//! PIN_AUTH_DIR=/etc/security/pin.d        PIN directory (default /etc/pin.d)
+//! PIN_AUTH_STATE_DIR=/var/lib/pin-auth    default `state_dir` (default: the PIN directory)
+//! PIN_AUTH_CONFIG=/etc/pin-auth.conf      config file (default <PIN dir>/pin-auth.conf)
+//! ```
+//!
+//! The state directory and config file defaults belong to the installed
+//! layout. A debug build pointed elsewhere with `PIN_DIR` keeps both inside
+//! that directory, so tests never touch the system paths.
+
+use crate::config::CONFIG_FILE;
+use std::path::{Path, PathBuf};
+
+/// The PIN directory.
+pub const PIN_DIR: &str = match option_env!("PIN_AUTH_DIR") {
+    Some(dir) => dir,
+    None => "/etc/pin.d",
+};
+
+/// Where state goes when `state_dir` is not configured; `None` keeps it in
+/// the PIN directory.
+pub const STATE_DIR: Option<&str> = option_env!("PIN_AUTH_STATE_DIR");
+
+/// The config file, if not `<PIN dir>/pin-auth.conf`.
+pub const CONFIG: Option<&str> = option_env!("PIN_AUTH_CONFIG");
+
+const fn absolute(path: Option<&str>) -> bool {
+    match path {
+        Some(path) => !path.is_empty() && path.as_bytes()[0] == b'/',
+        None => true,
+    }
+}
+
+const _: () = assert!(
+    absolute(Some(PIN_DIR)),
+    "PIN_AUTH_DIR must be an absolute path"
+);
+const _: () = assert!(absolute(STATE_DIR), "PIN_AUTH_STATE_DIR must be absolute");
+const _: () = assert!(absolute(CONFIG), "PIN_AUTH_CONFIG must be absolute");
+
+/// The PIN directory this run uses. Release builds always use [`PIN_DIR`]
+/// (the binaries run setuid root); debug builds honour `PIN_DIR` from the
+/// environment, for tests.
+pub fn pin_dir() -> String {
+    if cfg!(debug_assertions) {
+        std::env::var("PIN_DIR").unwrap_or_else(|_| PIN_DIR.to_string())
+    } else {
+        PIN_DIR.to_string()
+    }
+}
+
+/// The config file for PIN directory `dir`.
+pub fn config_file(dir: &str) -> PathBuf {
+    match CONFIG {
+        Some(path) if dir == PIN_DIR => PathBuf::from(path),
+        _ => Path::new(dir).join(CONFIG_FILE),
+    }
+}
+
+/// The built-in state directory for PIN directory `dir`, if any.
+pub fn default_state_dir(dir: &str) -> Option<&'static str> {
+    STATE_DIR.filter(|_| dir == PIN_DIR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirected_dirs_are_self_contained() {
+        assert_eq!(
+            config_file("/tmp/pins"),
+            Path::new("/tmp/pins/pin-auth.conf")
+        );
+        assert_eq!(default_state_dir("/tmp/pins"), None);
+        if CONFIG.is_none() {
+            assert_eq!(config_file(PIN_DIR), Path::new(PIN_DIR).join(CONFIG_FILE));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! A PIN that has passed policy and is wiped when dropped.
+
+use crate::policy::{PolicyValidator, PolicyViolation};
+use zeroize::Zeroizing;
+
+/// Secret PIN. The buffer is zeroized on drop, including when construction
+/// fails, and `Debug` never prints the value.
+#[derive(Clone)]
+pub struct Pin(Zeroizing<String>);
+
+impl Pin {
+    /// A new PIN being set: the full policy applies (length, charset, blacklist).
+    pub fn new(
+        secret: impl Into<String>,
+        policy: &PolicyValidator,
+    ) -> Result<Pin, PolicyViolation> {
+        let secret = Zeroizing::new(secret.into());
+        policy.validate(&secret)?;
+        Ok(Pin(secret))
+    }
+
+    /// A candidate to verify: length and charset only, so a PIN set before a
+    /// blacklist entry was added still works.
+    pub fn candidate(
+        secret: impl Into<String>,
+        policy: &PolicyValidator,
+    ) -> Result<Pin, PolicyViolation> {
+        let secret = Zeroizing::new(secret.into());
+        policy.check_format(&secret)?;
+        Ok(Pin(secret))
+    }
+
+    /// No policy at all; for known-answer vectors whose secrets are not PINs.
+    pub(crate) fn unchecked(secret: &str) -> Pin {
+        Pin(Zeroizing::new(secret.to_string()))
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Interop with the `secrecy` crate (feature `secrecy`): accept any
+/// `ExposeSecret<str>`, such as `SecretString`, without an intermediate `String`.
+#[cfg(feature = "secrecy")]
+mod secret {
+    use super::*;
+    use crate::{hash_pin, verify_pin, PinHashError};
+    use secrecy::ExposeSecret;
+
+    impl Pin {
+        pub fn from_secret(
+            secret: &impl ExposeSecret<str>,
+            policy: &PolicyValidator,
+        ) -> Result<Pin, PolicyViolation> {
+            Pin::new(secret.expose_secret(), policy)
+        }
+
+        pub fn candidate_from_secret(
+            secret: &impl ExposeSecret<str>,
+            policy: &PolicyValidator,
+        ) -> Result<Pin, PolicyViolation> {
+            Pin::candidate(secret.expose_secret(), policy)
+        }
+    }
+
+    impl ExposeSecret<str> for Pin {
+        fn expose_secret(&self) -> &str {
+            &self.0
+        }
+    }
+
+    /// [`hash_pin`] for a secret held by the caller.
+    pub fn hash_secret(
+        secret: &impl ExposeSecret<str>,
+        policy: &PolicyValidator,
+    ) -> Result<String, PinHashError> {
+        hash_pin(&Pin::from_secret(secret, policy)?)
+    }
+
+    /// [`verify_pin`] for a secret held by the caller; `false` if the
+    /// candidate does not even have a valid format.
+    pub fn verify_secret(
+        candidate: &impl ExposeSecret<str>,
+        policy: &PolicyValidator,
+        stored: &str,
+    ) -> bool {
+        Pin::candidate_from_secret(candidate, policy).is_ok_and(|pin| verify_pin(&pin, stored))
+    }
+}
+#[cfg(feature = "secrecy")]
+pub use secret::{hash_secret, verify_secret};
+
+impl std::fmt::Debug for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Pin(***)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn construction_enforces_policy() {
+        let policy = PolicyValidator::default().with_blacklist(["1234"]);
+        assert_eq!(
+            Pin::new("12", &policy).unwrap_err(),
+            PolicyViolation::TooShort { min: 4 }
+        );
+        assert_eq!(
+            Pin::new("1234", &policy).unwrap_err(),
+            PolicyViolation::Blacklisted
+        );
+        let pin = Pin::candidate("1234", &policy).unwrap();
+        assert_eq!(pin.expose_secret(), "1234");
+        assert_eq!(format!("{pin:?}"), "Pin(***)");
+    }
+
+    #[cfg(feature = "secrecy")]
+    #[test]
+    fn secret_string_round_trip() {
+        use secrecy::SecretString;
+        let policy = PolicyValidator::default();
+        let secret = SecretString::from("2468".to_string());
+        match hash_secret(&secret, &policy) {
+            Ok(hash) => {
+                assert!(verify_secret(&secret, &policy, &hash));
+                let wrong = SecretString::from("1357".to_string());
+                assert!(!verify_secret(&wrong, &policy, &hash));
+            }
+            Err(crate::PinHashError::BackendUnavailable(_)) => {}
+            Err(e) => panic!("hash: {e:?}"),
+        }
+        let short = SecretString::from("1".to_string());
+        assert!(hash_secret(&short, &policy).is_err());
+    }
+}
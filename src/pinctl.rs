@@ -0,0 +1,1922 @@
+use anyhow::{bail, Result};
+use pin_auth::audit::AuditLog;
+use pin_auth::man::{self, ManPage};
+use pin_auth::ConfigResolver;
+use std::env;
+
+const USAGE: &str = "usage: pinctl <command>
+
+commands:
+  verify-audit                          check the audit log hash chain for edits or truncation
+  audit [--user NAME] [--since 24h]     list recorded attempts (feature `sqlite`)
+  pseudonym USER                        print the log pseudonym used for USER on this host
+  status USER                           show USER's PIN record, fail state and last successful login
+  stats [--json]                        summarize fail counters, lockouts, last logins and hash schemes over all users
+  cleanup [--older-than AGE] [--orphans-only] [--apply]
+                                        list (or with --apply, remove) records of deleted accounts, stale fail files
+                                        and backup copies older than AGE (default 30d)
+  recovery USER                         show how many of USER's recovery codes are left
+  hotp USER [--secret HEX] [--digits N] enroll an HOTP token for USER (feature `hotp`)
+  hotp USER --resync CODE CODE          resynchronize USER's token from two consecutive codes
+  hotp USER --remove                    remove USER's token
+  yubikey USER [--slot N] [--secret HEX] let USER's YubiKey (HMAC-SHA1 slot 1 or 2) stand in for the PIN (feature `yubikey`)
+  yubikey USER --remove                 remove USER's YubiKey
+  fido2 USER [--when MODE] [--device-pin] let a FIDO2 key stand in when USER has no PIN (no_pin), is locked out (locked, default) or both (feature `fido2`)
+  fido2 USER --remove                   remove USER's FIDO2 credential
+  policy USER [KEY=VALUE | KEY=]...     show or set USER's own max_fails, lockout_secs, ... over pin-auth.conf
+  edit USER [--decoy]                   edit USER's PIN record (or decoys) in $EDITOR under a lock, checked before it is saved
+  emergency USER [--revoke]             issue (or revoke) a single-use PIN that works even while USER is locked out
+  sign --key FILE                       sign a manifest of the PIN directory's records with FILE, created if missing (feature `manifest`)
+  verify [--pubkey HEX]                 check the records against the signed manifest (default key: manifest_pubkey)
+  backup --key FILE ARCHIVE             write every file of the PIN and state directories to ARCHIVE, encrypted with FILE (created if missing) (feature `backup`)
+  restore --key FILE ARCHIVE [--force]  put the files from ARCHIVE back; --force overwrites existing ones
+  export [--json] [USER...]             print the records (hashes, decoys, tokens, fail and lock state, last login) of every user, or of USER..., as JSON
+  import FILE|- [--force | --newer]     write the records from an export; --force overwrites existing ones, --newer only older ones
+  import-shadow USER... [--shadow FILE] [--force]
+                                        copy each USER's password hash from /etc/shadow into a PIN record when its
+                                        scheme is supported; --force replaces an existing record
+  sync push|pull HOST|/FILE.json        exchange PINs, decoys and keys with HOST over ssh or with a shared export file; later changes win
+  install [--pam-configs] [--check-pin PATH]
+                                        create the PIN and state directories and a default pin-auth.conf, then print
+                                        the PAM line for check_pin (or with --pam-configs, a pam-auth-update profile)
+  tmpfiles                              print systemd tmpfiles.d lines that create the PIN and state directories
+  check-config                          validate pin-auth.conf and PIN_* settings without changing anything
+  self-test                             check the hashing backends against known-answer vectors";
+
+fn main() -> Result<()> {
+    if env::args().nth(1).as_deref() == Some(man::GENERATE_MAN) {
+        print!("{}", man_page().render());
+        return Ok(());
+    }
+    // Directory is fixed at build time for release builds. In debug/test builds we allow PIN_DIR for test isolation only.
+    let dir = pin_auth::paths::pin_dir();
+    // Settings: <dir>/pin-auth.conf over the (deprecated) environment.
+    let (config, config_error) = match ConfigResolver::load_dir(&dir) {
+        Ok(config) => (config, None),
+        Err(e) => (ConfigResolver::from_env(), Some(e)),
+    };
+    pin_auth::logging::init_with("pinctl", "stderr", &config);
+    // Touches no files; usable unprivileged, e.g. from package scripts.
+    if env::args().nth(1).as_deref() == Some("self-test") {
+        return self_test();
+    }
+    // Built-in paths when the config cannot be read: packagers run it at build time.
+    if env::args().nth(1).as_deref() == Some("tmpfiles") {
+        tmpfiles(&dir, &config);
+        return Ok(());
+    }
+    // Require effective UID 0 (root). In debug builds a test override ALLOW_NON_ROOT=1 permits execution.
+    let euid = nix::unistd::geteuid().as_raw();
+    if euid != 0 {
+        #[cfg(not(debug_assertions))]
+        {
+            tracing::error!("denied: requires root (effective uid 0)");
+            std::process::exit(1);
+        }
+        #[cfg(debug_assertions)]
+        {
+            if env::var("ALLOW_NON_ROOT").ok().as_deref() != Some("1") {
+                tracing::error!(
+                    "denied: requires root (set ALLOW_NON_ROOT=1 in debug to bypass for tests)"
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(e) = config_error {
+        return Err(e.into());
+    }
+
+    let mut args = env::args().skip(1);
+    let command = args.next().unwrap_or_default();
+    if command == "check-config" {
+        return check_config(&dir, &config);
+    }
+    config.report();
+    match command.as_str() {
+        "verify-audit" => verify_audit(&dir, &config),
+        "audit" => audit(&dir, &config, args),
+        "pseudonym" => pseudonym(&dir, &config, args),
+        "status" => status(&dir, &config, args),
+        "stats" => stats(&dir, &config, args),
+        "cleanup" => cleanup(&dir, &config, args),
+        "recovery" => recovery(&dir, &config, args),
+        "emergency" => emergency(&dir, &config, args),
+        "edit" => edit(&dir, &config, args),
+        "policy" => policy(&dir, &config, args),
+        "hotp" => hotp(&dir, &config, args),
+        "yubikey" => yubikey(&dir, &config, args),
+        "fido2" => fido2(&dir, &config, args),
+        "sign" => sign(&dir, &config, args),
+        "backup" => backup(&dir, &config, args),
+        "export" => export(&dir, &config, args),
+        "import" => import(&dir, &config, args),
+        "import-shadow" => import_shadow(&dir, &config, args),
+        "sync" => sync(&dir, &config, args),
+        "restore" => restore(&dir, &config, args),
+        "verify" => verify(&dir, &config, args),
+        "install" => install(&dir, &config, args),
+        "" | "-h" | "--help" | "help" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        other => bail!("unknown command: {other}\n{USAGE}"),
+    }
+}
+
+fn man_page() -> ManPage {
+    ManPage {
+        name: "pinctl",
+        about: "inspect and administer pin-auth records",
+        synopsis: vec!["pinctl COMMAND [ARGS]".into()],
+        description: vec![
+            "pinctl shows and changes the PIN records, fail state, tokens and logs kept in \
+             the PIN directory. Every command but self-test runs as root."
+                .into(),
+        ],
+        lists: vec![("COMMANDS", man::usage_entries(USAGE))],
+    }
+}
+
+fn verify_audit(dir: &str, config: &ConfigResolver) -> Result<()> {
+    use anyhow::Context;
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let log = AuditLog::new(store.path("audit.log"));
+    match log.verify() {
+        Ok(n) => {
+            println!("{}: ok ({n} records)", log.path().display());
+            Ok(())
+        }
+        Err(e) => bail!("{}: {e}", log.path().display()),
+    }
+}
+
+fn self_test() -> Result<()> {
+    use pin_auth::selftest::SCHEMES;
+    match pin_auth::self_test() {
+        Ok(checked) => {
+            for &scheme in SCHEMES {
+                let status = if checked.contains(&scheme) {
+                    "ok"
+                } else {
+                    "not compiled in"
+                };
+                println!("{scheme:?}: {status}");
+            }
+            Ok(())
+        }
+        Err(e) => bail!("self-test failed: {e}"),
+    }
+}
+
+/// Reads the directory metadata and the config file only; reports every
+/// problem rather than stopping at the first.
+fn check_config(dir: &str, config: &ConfigResolver) -> Result<()> {
+    use anyhow::Context;
+    pin_auth::store::resolve_store(dir).context("insecure PIN directory")?;
+    if let Some(state_dir) = config.string("state_dir") {
+        if std::path::Path::new(state_dir).exists() {
+            pin_auth::store::resolve_store(state_dir).context("insecure state directory")?;
+        } else {
+            println!("note: state_dir {state_dir} will be created on first use");
+        }
+    }
+    for (env, key) in config.deprecated() {
+        println!(
+            "note: {env} is deprecated; set {} in {}",
+            key.name,
+            pin_auth::config::CONFIG_FILE
+        );
+    }
+    let problems = config.check();
+    for problem in &problems {
+        println!("{problem}");
+    }
+    match problems.len() {
+        0 => {
+            println!("configuration ok");
+            Ok(())
+        }
+        n => bail!("{n} configuration problem(s)"),
+    }
+}
+
+/// What `install` writes to a PIN directory without a config file.
+const DEFAULT_CONFIG: &str = "\
+# pin-auth settings, `key = value`; see section 6 of the README.
+# Uncomment to change a built-in default.
+#min_len = 4
+#max_len = 6
+#max_fails = 5
+#fail_window = 900
+#lockout_secs = 300
+#audit = 0
+";
+
+/// Sets up a host: the PIN directory and state directory, root's and 0700
+/// (fixed if they exist), and a default config file unless there is one.
+/// The PAM text goes to stdout and notes to stderr, so the output can be
+/// redirected into place.
+fn install(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    let mut pam_configs = false;
+    let mut check_pin = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pam-configs" => pam_configs = true,
+            "--check-pin" => {
+                let path = args.next().context("--check-pin needs a path")?;
+                if !path.starts_with('/') {
+                    bail!("--check-pin: {path} is not an absolute path");
+                }
+                check_pin = Some(path);
+            }
+            other => bail!("unexpected argument: {other}\n{USAGE}"),
+        }
+    }
+    let state_dir = config
+        .string("state_dir")
+        .or_else(|| pin_auth::paths::default_state_dir(dir));
+    for path in [Some(dir), state_dir].into_iter().flatten() {
+        install_dir(std::path::Path::new(path)).with_context(|| format!("setting up {path}"))?;
+    }
+    let config_file = pin_auth::paths::config_file(dir);
+    if config_file.symlink_metadata().is_ok() {
+        eprintln!("kept {}", config_file.display());
+    } else {
+        // the sha-crypt default is not there to fall back on without the feature
+        let scheme = if cfg!(feature = "sha-crypt") { "#" } else { "" };
+        let text = format!(
+            "{DEFAULT_CONFIG}# Argon2id (feature `argon2`) is harder to crack than sha-crypt:\n\
+             {scheme}scheme = argon2id\n"
+        );
+        pin_auth::store::write_atomic(&config_file, text.as_bytes(), 0o600)
+            .with_context(|| format!("writing {}", config_file.display()))?;
+        eprintln!("wrote {}", config_file.display());
+    }
+    // what check_pin will insist on
+    pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+
+    let check_pin = check_pin.unwrap_or_else(|| {
+        env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join("check_pin")))
+            .filter(|path| path.exists())
+            .and_then(|path| path.to_str().map(str::to_string))
+            .unwrap_or_else(|| "/usr/local/sbin/check_pin".to_string())
+    });
+    // OpenPAM's pam_exec has neither seteuid nor quiet
+    let options = if cfg!(target_os = "freebsd") {
+        "expose_authtok"
+    } else {
+        "expose_authtok seteuid quiet"
+    };
+    if pam_configs {
+        eprintln!("save as /usr/share/pam-configs/pin-auth, then run pam-auth-update");
+        println!(
+            "Name: PIN login (pin-auth)\n\
+             Default: no\n\
+             Priority: 512\n\
+             Auth-Type: Primary\n\
+             Auth:\n\
+             \t[success=end default=ignore]\tpam_exec.so {options} {check_pin}"
+        );
+    } else {
+        eprintln!("add to the auth stack in /etc/pam.d/<service>, before the password module:");
+        println!("auth  sufficient  pam_exec.so {options} {check_pin}");
+    }
+    Ok(())
+}
+
+/// systemd-tmpfiles lines for what `install` sets up, for
+/// `/usr/lib/tmpfiles.d/pin-auth.conf`: the directories are created at
+/// boot (a `state_dir` on a fresh `/var` of an image-based system) and
+/// their owner and mode put right. Nothing is ever cleaned up.
+fn tmpfiles(dir: &str, config: &ConfigResolver) {
+    println!("# pin-auth: PIN directory, state directory and config file");
+    println!("d {dir} 0700 root root -");
+    if let Some(state_dir) = config
+        .string("state_dir")
+        .or_else(|| pin_auth::paths::default_state_dir(dir))
+    {
+        println!("d {state_dir} 0700 root root -");
+    }
+    println!(
+        "z {} 0600 root root -",
+        pin_auth::paths::config_file(dir).display()
+    );
+}
+
+/// `path` as a directory of root's with mode 0700, created (with its
+/// parents) if missing. A symlink is refused, as `check_pin` would.
+fn install_dir(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    match path.symlink_metadata() {
+        Ok(md) if md.file_type().is_symlink() => bail!("is a symlink"),
+        Ok(md) if !md.is_dir() => bail!("not a directory"),
+        Ok(_) => eprintln!("kept {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::DirBuilder::new().mode(0o700).create(path)?;
+            eprintln!("created {}", path.display());
+        }
+        Err(e) => return Err(e.into()),
+    }
+    if nix::unistd::geteuid().is_root() {
+        nix::unistd::chown(path, Some(0.into()), Some(0.into()))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))?;
+    Ok(())
+}
+
+fn pseudonym(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    let Some(user) = args.next() else {
+        bail!("usage: pinctl pseudonym USER");
+    };
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let key = pin_auth::logging::load_or_create_key(store.path("log.key"))?;
+    println!("{}", pin_auth::logging::pseudonym(&key, &user));
+    Ok(())
+}
+
+fn status(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::fail::{FailState, LockoutPolicy};
+    use pin_auth::lastlogin::LastLogin;
+    use pin_auth::store::PinStore;
+
+    let Some(user) = args.next() else {
+        bail!("usage: pinctl status USER");
+    };
+    let key = record_key(config, &user)?;
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let immutable =
+        pin_auth::immutable::is_immutable(&store.path(&format!("{key}.passwd"))).unwrap_or(false);
+    let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let record = store
+        .read_file(&format!("{key}.passwd"))
+        .and_then(|raw| pin_auth::record::HashRecord::parse(&raw).map_err(invalid));
+    #[cfg(feature = "toml-records")]
+    let record = match record {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            pin_auth::toml_record::TomlRecord::load(&store, &key)
+                .and_then(|toml| toml.map(|toml| toml.record).ok_or(e))
+        }
+        record => record,
+    };
+    match record {
+        Ok(record) => {
+            let floor = config.parse::<pin_auth::HashFloor>("min_hash");
+            match pin_auth::Scheme::from_hash(&record.hash) {
+                Ok(info) => println!(
+                    "pin:        set ({:?} {}{}{})",
+                    info.scheme,
+                    info.params,
+                    if immutable { ", immutable" } else { "" },
+                    match floor.filter(|floor| !floor.allows(&info)) {
+                        Some(_) => ", below min_hash: set it again",
+                        None => "",
+                    }
+                ),
+                Err(e) => println!("pin:        unreadable ({e})"),
+            }
+            if let Some(ts) = record.changed_at {
+                println!("changed:    {} UTC", pin_auth::format_utc(ts));
+            }
+            if let Some(ts) = record
+                .created_at
+                .filter(|&ts| Some(ts) != record.changed_at)
+            {
+                println!("created:    {} UTC", pin_auth::format_utc(ts));
+            }
+            if !record.flags.is_empty() {
+                println!("flags:      {}", record.flags.join(", "));
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => println!("pin:        not set"),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => println!("pin:        {e}"),
+        Err(e) => return Err(e).context("reading PIN record"),
+    }
+    let fail = match store.read_file(&format!("{key}.fail")) {
+        Ok(raw) => FailState::parse(&raw, now),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => FailState::Clear,
+        Err(e) => return Err(e).context("reading fail state"),
+    };
+    match fail {
+        FailState::Clear => println!("failures:   none"),
+        FailState::Counting { count, first_ts } => println!(
+            "failures:   {count} since {} UTC{}",
+            pin_auth::format_utc(first_ts),
+            // lockout_secs = 0: locked until reset, with no `lock:` entry
+            if count >= LockoutPolicy::from_config(config).max_fails {
+                " (locked)"
+            } else {
+                ""
+            }
+        ),
+        FailState::Locked { until } if until > now => println!(
+            "failures:   locked until {} UTC",
+            pin_auth::format_utc(until)
+        ),
+        FailState::Locked { .. } => println!("failures:   lock expired"),
+    }
+    match LastLogin::load(&store, &key).context("reading last login")? {
+        Some(last) => println!("last login: {}", last.describe()),
+        None => println!("last login: never"),
+    }
+    Ok(())
+}
+
+/// One row per user with a PIN, fail state or login record, plus totals.
+fn stats(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::fail::{FailState, LockoutPolicy};
+    use pin_auth::lastlogin::LastLogin;
+    use pin_auth::store::{PinStore, GROUP_PREFIX};
+    use serde_json::json;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    let mut as_json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => as_json = true,
+            other => bail!("unexpected argument: {other}\n{USAGE}"),
+        }
+    }
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let max_fails = LockoutPolicy::from_config(config).max_fails;
+    let record_key = pin_auth::policy::UsernamePolicy::from_config(config).key;
+    let mut users = BTreeSet::new();
+    for ext in ["passwd", "toml", "fail", "last"] {
+        users.extend(store.names(ext).with_context(|| format!("listing {dir}"))?);
+    }
+    let mut rows = Vec::new();
+    let mut schemes: BTreeMap<String, usize> = BTreeMap::new();
+    // the same by cost parameters, for planning upgrades
+    let mut params: BTreeMap<String, usize> = BTreeMap::new();
+    let (mut failing, mut locked, mut weak) = (0, 0, 0);
+    for user in &users {
+        let info = store
+            .read_hash(user)
+            .ok()
+            .map(|hash| pin_auth::Scheme::from_hash(&hash).ok());
+        let scheme = info.map(|info| {
+            info.map_or_else(
+                || "unknown".to_string(),
+                |info| format!("{:?}", info.scheme),
+            )
+        });
+        if let Some(scheme) = &scheme {
+            *schemes.entry(scheme.clone()).or_default() += 1;
+        }
+        if let Some(info) = info.flatten() {
+            *params.entry(info.to_string()).or_default() += 1;
+            weak += usize::from(info.shortfall(config).is_some());
+        }
+        let (fails, locked_until) = match store
+            .read_file(&format!("{user}.fail"))
+            .map(|raw| FailState::parse(&raw, now))
+            .unwrap_or(FailState::Clear)
+        {
+            FailState::Counting { count, .. } if count >= max_fails => (count, Some(0)),
+            FailState::Counting { count, .. } => (count, None),
+            FailState::Locked { until } if until > now => (max_fails, Some(until)),
+            _ => (0, None),
+        };
+        failing += usize::from(fails > 0);
+        locked += usize::from(locked_until.is_some());
+        let last = LastLogin::load(&store, user).ok().flatten();
+        // `1000` with `record_key = uid`: show whose it is
+        let name = record_key.account_name(user);
+        let info = info.flatten();
+        rows.push((name, scheme, info, fails, locked_until, last));
+    }
+    if as_json {
+        let users: Vec<_> = rows
+            .iter()
+            .map(|(user, scheme, info, fails, locked_until, last)| {
+                let params = info.map(|info| {
+                    let p = info.params;
+                    match (p.sha512_rounds, p.argon2) {
+                        (Some(rounds), _) => json!({ "rounds": rounds }),
+                        (None, Some(c)) => json!({ "m": c.m_cost, "t": c.t_cost, "p": c.p_cost }),
+                        (None, None) => json!({}),
+                    }
+                });
+                json!({
+                    "user": user,
+                    "group": user.starts_with(GROUP_PREFIX),
+                    "scheme": scheme,
+                    "params": params,
+                    "weaker_than_configured": info.is_some_and(|i| i.shortfall(config).is_some()),
+                    "fails": fails,
+                    "locked": locked_until.is_some(),
+                    // 0: until reset
+                    "locked_until": locked_until,
+                    "last_login": last.as_ref().map(|l| l.at),
+                })
+            })
+            .collect();
+        let report = json!({
+            "generated": now,
+            "users": users,
+            "totals": {
+                "users": rows.len(),
+                "failing": failing,
+                "locked": locked,
+                "schemes": schemes,
+                "params": params,
+                "weaker_than_configured": weak,
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "{:<20}  {:<12}  {:<16}  {:>5}  {:<20}  LAST LOGIN (UTC)",
+        "USER", "SCHEME", "PARAMS", "FAILS", "LOCKED UNTIL (UTC)"
+    );
+    for (user, scheme, info, fails, locked_until, last) in &rows {
+        println!(
+            "{:<20}  {:<12}  {:<16}  {:>5}  {:<20}  {}",
+            user,
+            scheme.as_deref().unwrap_or("-"),
+            info.map_or_else(|| "-".to_string(), |info| info.params.to_string()),
+            fails,
+            match locked_until {
+                Some(0) => "until reset".to_string(),
+                Some(until) => pin_auth::format_utc(*until),
+                None => "-".to_string(),
+            },
+            last.as_ref()
+                .map_or_else(|| "never".to_string(), |l| pin_auth::format_utc(l.at))
+        );
+    }
+    let schemes: Vec<String> = schemes.iter().map(|(s, n)| format!("{s} {n}")).collect();
+    println!(
+        "\n{} records, {failing} with failures, {locked} locked; schemes: {}",
+        rows.len(),
+        if schemes.is_empty() {
+            "none".to_string()
+        } else {
+            schemes.join(", ")
+        }
+    );
+    for (params, n) in &params {
+        println!("  {params}: {n}");
+    }
+    if weak > 0 {
+        println!("{weak} hash(es) weaker than the configured scheme and costs");
+    }
+    Ok(())
+}
+
+/// Dry run unless `--apply`: what would go, and why.
+fn cleanup(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::account::group_exists;
+    use pin_auth::fail::LockoutPolicy;
+    use pin_auth::gc;
+
+    let mut older_than = 30 * 24 * 3600;
+    let mut orphans_only = false;
+    let mut apply = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--older-than" => {
+                let v = args.next().context("--older-than needs a value")?;
+                older_than = pin_auth::parse_duration(&v)
+                    .with_context(|| format!("bad duration {v:?} (e.g. 90m, 24h, 7d)"))?;
+            }
+            "--orphans-only" => orphans_only = true,
+            "--apply" => apply = true,
+            other => bail!("unexpected argument: {other}\n{USAGE}"),
+        }
+    }
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let record_key = pin_auth::policy::UsernamePolicy::from_config(config).key;
+    // lookup errors count as existing: never delete on a flaky directory service
+    let user_known = |key: &str| record_key.account_exists(key).unwrap_or(true);
+    let group_known = |group: &str| group_exists(group).unwrap_or(true);
+    let verb = if apply { "removed" } else { "would remove" };
+    let mut count = 0;
+
+    for key in gc::orphaned_records(&store, user_known, group_known).context("listing records")? {
+        for path in gc::record_files(&store, &key) {
+            if apply {
+                pin_auth::immutable::make_mutable(&path)
+                    .and_then(|()| std::fs::remove_file(&path))
+                    .with_context(|| format!("removing {}", path.display()))?;
+            }
+            println!("{verb} {} (account deleted)", path.display());
+            count += 1;
+        }
+        if apply {
+            admin_audit(config, dir, "cleanup", &key)?;
+        }
+    }
+    // with --orphans-only, fail state of existing users is left alone
+    let age = (!orphans_only).then_some(older_than);
+    let policy = LockoutPolicy::from_config(config);
+    for (user, stale) in gc::collect(&store, &policy, age, now, user_known, !apply)
+        .context("collecting fail state")?
+    {
+        println!(
+            "{verb} {} ({})",
+            store.path(&format!("{user}.fail")).display(),
+            stale.reason()
+        );
+        count += 1;
+    }
+    if !orphans_only {
+        for path in gc::old_backups(&store, std::time::Duration::from_secs(older_than))
+            .context("listing backups")?
+        {
+            if apply {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("removing {}", path.display()))?;
+            }
+            println!("{verb} {} (backup)", path.display());
+            count += 1;
+        }
+    }
+    match (count, apply) {
+        (0, _) => println!("nothing to clean up"),
+        (n, false) => println!("{n} files; run again with --apply to remove them"),
+        (n, true) => println!("{n} files removed"),
+    }
+    Ok(())
+}
+
+fn recovery(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::auth::RecoveryCodes;
+
+    let Some(user) = args.next() else {
+        bail!("usage: pinctl recovery USER");
+    };
+    let key = record_key(config, &user)?;
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let codes = RecoveryCodes::load(&store, &key).context("reading recovery codes")?;
+    println!(
+        "{user}: {} of {} recovery codes left",
+        codes.remaining(),
+        codes.codes.len()
+    );
+    Ok(())
+}
+
+/// Write `<user>.emergency`, replacing any outstanding emergency PIN, and
+/// print the PIN once; or remove it with `--revoke`.
+/// Edit USER's record in `$VISUAL`/`$EDITOR`, vipw-style: one edit at a
+/// time, checked before it is saved, saved whole.
+fn edit(dir: &str, config: &ConfigResolver, mut args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::lock::lock_exclusive;
+    use pin_auth::store::{resolve_store_with, PinStore};
+    use std::io::{self, BufRead, Write};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let Some(user) = args.next() else {
+        bail!("usage: pinctl edit USER [--decoy]");
+    };
+    let decoy = match args.next().as_deref() {
+        None => false,
+        Some("--decoy") => true,
+        Some(other) => bail!("unexpected argument: {other}\n{USAGE}"),
+    };
+    let key = record_key(config, &user)?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let name = if decoy {
+        pin_auth::auth::Decoys::file_name(&key)
+    } else if store
+        .path(&format!("{key}.toml"))
+        .symlink_metadata()
+        .is_ok()
+    {
+        format!("{key}.toml")
+    } else {
+        format!("{key}.passwd")
+    };
+    let path = store.path(&name);
+
+    let lock_path = store.path(EDIT_LOCK);
+    let lock = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+        .open(&lock_path)
+        .with_context(|| format!("opening {}", lock_path.display()))?;
+    match lock_exclusive(&lock, std::time::Duration::ZERO) {
+        Ok(()) => {}
+        Err(e) if pin_auth::lock::is_timeout(&e) => {
+            bail!("another pinctl edit is running; try again later")
+        }
+        Err(e) => return Err(e).with_context(|| format!("locking {}", lock_path.display())),
+    }
+
+    let original = match store.read_file(&name) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !decoy => {
+            bail!("no PIN record for {user}; set one with genpin")
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("reading {name}")),
+    };
+    // next to the record (root-only), not in /tmp; `*.tmp` for cleanup
+    let scratch = store.path(&format!("{name}.edit.tmp"));
+    let mut contents = original.clone();
+    let edited = loop {
+        let _ = std::fs::remove_file(&scratch);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+            .open(&scratch)
+            .and_then(|mut f| f.write_all(contents.as_bytes()))
+            .with_context(|| format!("writing {}", scratch.display()))?;
+        let status = run_editor(&scratch);
+        let read = pin_auth::store::read_file_nofollow(&scratch)
+            .with_context(|| format!("reading {}", scratch.display()));
+        let _ = std::fs::remove_file(&scratch);
+        if !status.context("running the editor")?.success() {
+            bail!("editor failed; {name} not changed");
+        }
+        let edited = read?;
+        match check_edited(config, &name, &edited) {
+            Ok(()) => break edited,
+            Err(e) => {
+                eprint!("{name}: {e}\nEdit again? [Y/n] ");
+                let mut answer = String::new();
+                io::stdin().lock().read_line(&mut answer)?;
+                if answer.trim().eq_ignore_ascii_case("n") {
+                    bail!("{name} not changed");
+                }
+                contents = edited;
+            }
+        }
+    };
+    if edited == original {
+        println!("{name}: no changes");
+        return Ok(());
+    }
+    // genpin does not take the lock; do not overwrite what it wrote meanwhile
+    let now = match store.read_file(&name) {
+        Ok(now) => now,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("reading {name}")),
+    };
+    if now != original {
+        bail!("{name} was changed by another process while editing; not saved");
+    }
+    let sealed = store.seal(&name, &edited);
+    replace_file(config, &path, sealed.as_bytes(), 0o600, None)?;
+    admin_audit(config, dir, "edit", &user)?;
+    println!("{} saved", path.display());
+    Ok(())
+}
+
+/// The lock `pinctl edit` holds, in the PIN directory.
+const EDIT_LOCK: &str = "edit.lock";
+
+/// `$VISUAL`, `$EDITOR` or `vi` on `path`, through the shell so the
+/// variable may carry arguments (`EDITOR="code -w"`).
+fn run_editor(path: &std::path::Path) -> std::io::Result<std::process::ExitStatus> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    std::process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(path)
+        .status()
+}
+
+/// Whether `contents` may be saved as `name`: a record that parses, with
+/// hashes this build understands.
+fn check_edited(config: &ConfigResolver, name: &str, contents: &str) -> Result<()> {
+    use pin_auth::Scheme;
+
+    if name.ends_with(".decoy") {
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if !line.is_empty() {
+                Scheme::from_hash(line).map_err(|e| anyhow::anyhow!("line {}: {e}", i + 1))?;
+            }
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "toml-records")]
+    if name.ends_with(".toml") {
+        let record = pin_auth::toml_record::TomlRecord::parse(contents)?;
+        Scheme::from_hash(&record.record.hash)?;
+        config.clone().with_user_settings(record.policy, name)?;
+        return Ok(());
+    }
+    let record = pin_auth::record::HashRecord::parse(contents)?;
+    Scheme::from_hash(&record.hash)?;
+    config.clone().with_user_settings(record.settings(), name)?;
+    Ok(())
+}
+
+/// Shows, or with `KEY=VALUE` arguments changes, the settings kept in a
+/// user's `.passwd` record; `KEY=` goes back to `pin-auth.conf`.
+fn policy(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::config::USER_KEYS;
+    use pin_auth::record::HashRecord;
+    use pin_auth::store::PinStore;
+
+    let Some(user) = args.next() else {
+        bail!("usage: pinctl policy USER [KEY=VALUE | KEY=]...");
+    };
+    let changes = args
+        .map(|arg| match arg.split_once('=') {
+            Some((key, value)) if USER_KEYS.contains(&key) => Ok((
+                key.to_string(),
+                Some(value).filter(|v| !v.is_empty()).map(str::to_string),
+            )),
+            _ => bail!(
+                "{arg}: expected KEY=VALUE with KEY one of {}",
+                USER_KEYS.join(", ")
+            ),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let key = record_key(config, &user)?;
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    if store
+        .path(&format!("{key}.toml"))
+        .symlink_metadata()
+        .is_ok()
+    {
+        bail!("{user} has a TOML record; set [policy] in it with pinctl edit {user}");
+    }
+    let name = format!("{key}.passwd");
+    let original = match store.read_file(&name) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("no PIN record for {user}; set one with genpin")
+        }
+        Err(e) => return Err(e).with_context(|| format!("reading {name}")),
+    };
+    let mut record = HashRecord::parse(&original)?;
+    for (key, value) in &changes {
+        record.set_setting(key, value.as_deref());
+    }
+    let effective = config
+        .clone()
+        .with_user_settings(record.settings(), &name)?;
+    if !changes.is_empty() {
+        let sealed = store.seal(&name, &record.serialize());
+        replace_file(config, &store.path(&name), sealed.as_bytes(), 0o600, None)?;
+        admin_audit(config, dir, "policy", &user)?;
+    }
+    for key in USER_KEYS {
+        let own = record.settings().into_iter().find(|(k, _)| k == key);
+        match (own, effective.string(key)) {
+            (Some((_, value)), _) => println!("{key:<13} {value} ({user})"),
+            (None, Some(value)) => println!("{key:<13} {value}"),
+            (None, None) => println!("{key:<13} default"),
+        }
+    }
+    Ok(())
+}
+
+fn emergency(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::auth::Emergency;
+    use pin_auth::store::{resolve_store_with, PinStore};
+    use std::io;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let Some(user) = args.next() else {
+        bail!("usage: pinctl emergency USER [--revoke]");
+    };
+    let revoke = match args.next().as_deref() {
+        None => false,
+        Some("--revoke") => true,
+        Some(other) => bail!("unexpected argument: {other}\n{USAGE}"),
+    };
+    let key = record_key(config, &user)?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let name = Emergency::file_name(&key);
+    if revoke {
+        match store.remove_file(&name) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                bail!("no emergency PIN outstanding for {user}")
+            }
+            Err(e) => return Err(e).with_context(|| format!("removing {name}")),
+        }
+        admin_audit(config, dir, "revoke_emergency", &user)?;
+        println!("Emergency PIN for {user} revoked");
+        return Ok(());
+    }
+    // check_pin rejects users without a record before looking at anything else.
+    store
+        .read_hash(&key)
+        .with_context(|| format!("no PIN record for {user}"))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let ttl = config
+        .duration("emergency_ttl")
+        .unwrap_or(Emergency::DEFAULT_TTL);
+    let expires = now.saturating_add(ttl);
+    let (secret, record) = Emergency::issue(config, expires).context("hashing emergency PIN")?;
+    store
+        .write_file(&name, &record.serialize())
+        .with_context(|| format!("writing {}", store.path(&name).display()))?;
+    admin_audit(config, dir, "issue_emergency", &user)?;
+    println!(
+        "Emergency PIN for {user} (single use, expires {} UTC):\n\n    {}\n",
+        pin_auth::format_utc(expires),
+        secret.as_str()
+    );
+    Ok(())
+}
+
+/// Enroll, resynchronize or remove `<user>.hotp`.
+#[cfg(feature = "hotp")]
+fn hotp(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::hotp::{self, HotpToken};
+    use pin_auth::store::{resolve_store_with, PinStore};
+
+    let args: Vec<String> = args.collect();
+    let Some((user, opts)) = args.split_first() else {
+        bail!(
+            "usage: pinctl hotp USER [--secret HEX] [--digits N] | --resync CODE CODE | --remove"
+        );
+    };
+    let key = record_key(config, user)?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    // check_pin rewrites the counter while holding the same lock.
+    let _fail = store.open_fail_state(&key).context("locking fail state")?;
+    let name = HotpToken::file_name(&key);
+    match opts {
+        [flag, first, second] if flag == "--resync" => {
+            let token = HotpToken::load(&store, &key)
+                .context("reading HOTP token")?
+                .with_context(|| format!("no HOTP token enrolled for {user}"))?;
+            let counter = token.resync(first, second).with_context(|| {
+                format!(
+                    "no consecutive codes found within {} presses",
+                    hotp::RESYNC_WINDOW
+                )
+            })?;
+            store
+                .write_file(&name, &HotpToken { counter, ..token }.serialize())
+                .with_context(|| format!("writing {name}"))?;
+            admin_audit(config, dir, "resync_hotp", user)?;
+            println!("{user}: HOTP counter set to {counter}");
+        }
+        [flag] if flag == "--remove" => {
+            store
+                .remove_file(&name)
+                .with_context(|| format!("removing {name}"))?;
+            admin_audit(config, dir, "remove_hotp", user)?;
+            println!("HOTP token for {user} removed");
+        }
+        _ => {
+            let mut secret = None;
+            let mut digits = 6;
+            let mut opts = opts.iter();
+            while let Some(opt) = opts.next() {
+                match opt.as_str() {
+                    "--secret" => {
+                        let hex = opts.next().context("--secret needs a value")?;
+                        secret = Some(pin_auth::parse_hex(hex).context("--secret must be hex")?);
+                    }
+                    "--digits" => {
+                        digits = opts
+                            .next()
+                            .and_then(|d| d.parse().ok())
+                            .filter(|d| (6..=8).contains(d))
+                            .context("--digits must be 6, 7 or 8")?;
+                    }
+                    other => bail!("unexpected argument: {other}\n{USAGE}"),
+                }
+            }
+            let generated = secret.is_none();
+            let mut token = HotpToken::generate(digits);
+            if let Some(secret) = secret {
+                token.secret = secret;
+            }
+            store
+                .write_file(&name, &token.serialize())
+                .with_context(|| format!("writing {name}"))?;
+            admin_audit(config, dir, "set_hotp", user)?;
+            println!("HOTP token enrolled for {user} ({digits} digits, counter 0)");
+            if generated {
+                println!("secret (hex):    {}", pin_auth::hex(&token.secret).as_str());
+                println!("secret (base32): {}", token.secret_base32().as_str());
+                println!("{}", token.otpauth_uri(user).as_str());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "hotp"))]
+fn hotp(_dir: &str, _config: &ConfigResolver, _args: impl Iterator<Item = String>) -> Result<()> {
+    bail!("HOTP tokens require building with --features hotp")
+}
+
+/// Enroll or remove `<user>.yubikey`. Without `--secret` a secret is
+/// generated and the ykpersonalize command to program it is printed.
+#[cfg(feature = "yubikey")]
+fn yubikey(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::store::{resolve_store_with, PinStore};
+    use pin_auth::yubikey::{YubikeyRecord, SECRET_LEN};
+
+    let args: Vec<String> = args.collect();
+    let Some((user, opts)) = args.split_first() else {
+        bail!("usage: pinctl yubikey USER [--slot N] [--secret HEX] | --remove");
+    };
+    let key = record_key(config, user)?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let name = YubikeyRecord::file_name(&key);
+    if let [flag] = opts {
+        if flag == "--remove" {
+            store
+                .remove_file(&name)
+                .with_context(|| format!("removing {name}"))?;
+            admin_audit(config, dir, "remove_yubikey", user)?;
+            println!("YubiKey for {user} removed");
+            return Ok(());
+        }
+    }
+    let mut slot = 2;
+    let mut secret = None;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_str() {
+            "--slot" => {
+                slot = opts
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| matches!(s, 1 | 2))
+                    .context("--slot must be 1 or 2")?;
+            }
+            "--secret" => {
+                let hex = opts.next().context("--secret needs a value")?;
+                secret = Some(
+                    pin_auth::parse_hex(hex)
+                        .filter(|s| s.len() == SECRET_LEN)
+                        .context("--secret must be 40 hex digits")?,
+                );
+            }
+            other => bail!("unexpected argument: {other}\n{USAGE}"),
+        }
+    }
+    let generated = secret.is_none();
+    let mut record = YubikeyRecord::generate(slot);
+    if let Some(secret) = secret {
+        record.secret = secret;
+    }
+    store
+        .write_file(&name, &record.serialize())
+        .with_context(|| format!("writing {name}"))?;
+    admin_audit(config, dir, "set_yubikey", user)?;
+    println!("YubiKey slot {slot} enrolled for {user}; an empty PIN asks the key");
+    if !config.flag("yubikey_require_touch").unwrap_or(false) {
+        println!("the key is not asked until yubikey_require_touch = yes is set in pin-auth.conf");
+    }
+    if generated {
+        println!("program the key with:");
+        println!(
+            "    ykpersonalize -{slot} -ochal-resp -ochal-hmac -ohmac-lt64 -ochal-btn-trig -a {}",
+            pin_auth::hex(&record.secret).as_str()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "yubikey"))]
+fn yubikey(
+    _dir: &str,
+    _config: &ConfigResolver,
+    _args: impl Iterator<Item = String>,
+) -> Result<()> {
+    bail!("YubiKey support requires building with --features yubikey")
+}
+
+/// Write `pin-auth.manifest`, signed with the key in `--key`.
+#[cfg(feature = "manifest")]
+fn sign(dir: &str, config: &ConfigResolver, mut args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::manifest::{load_or_create_signing_key, Manifest, MANIFEST_FILE};
+    use pin_auth::store::{resolve_store_with, PinStore};
+
+    let key_path = match (args.next().as_deref(), args.next()) {
+        (Some("--key"), Some(path)) => std::path::PathBuf::from(path),
+        _ => bail!("usage: pinctl sign --key FILE"),
+    };
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let (key, created) = load_or_create_signing_key(&key_path)
+        .with_context(|| format!("signing key {}", key_path.display()))?;
+    let manifest = Manifest::scan(&store).context("reading records")?;
+    store
+        .write_file(MANIFEST_FILE, &manifest.sign(&key))
+        .with_context(|| format!("writing {MANIFEST_FILE}"))?;
+    println!(
+        "{} records signed into {}",
+        manifest.entries.len(),
+        store.path(MANIFEST_FILE).display()
+    );
+    let public = pin_auth::hex(key.verifying_key().as_bytes());
+    if created {
+        println!(
+            "new signing key {}; keep it off this host",
+            key_path.display()
+        );
+    }
+    if config.string("manifest_pubkey") != Some(public.as_str()) {
+        println!("public key: {}", public.as_str());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "manifest"))]
+fn sign(_dir: &str, _config: &ConfigResolver, _args: impl Iterator<Item = String>) -> Result<()> {
+    bail!("manifests require building with --features manifest")
+}
+
+/// Compare the records with `pin-auth.manifest`, failing on any
+/// difference.
+#[cfg(feature = "manifest")]
+fn verify(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::manifest::{parse_public_key, Manifest, MANIFEST_FILE};
+    use pin_auth::store::{resolve_store_with, PinStore};
+
+    let public = match (args.next().as_deref(), args.next()) {
+        (Some("--pubkey"), Some(hex)) => hex,
+        (None, _) => config
+            .string("manifest_pubkey")
+            .context("no public key: pass --pubkey or set manifest_pubkey")?
+            .to_string(),
+        _ => bail!("usage: pinctl verify [--pubkey HEX]"),
+    };
+    let public = parse_public_key(&public).context("public key must be 64 hex digits")?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let path = store.path(MANIFEST_FILE);
+    let text = store
+        .read_file(MANIFEST_FILE)
+        .with_context(|| format!("reading {}", path.display()))?;
+    let signed =
+        Manifest::verify(&text, &public).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    let changes = signed.changes(&Manifest::scan(&store).context("reading records")?);
+    for change in &changes {
+        println!("{change}");
+    }
+    match changes.len() {
+        0 => {
+            println!("{}: ok ({} records)", path.display(), signed.entries.len());
+            Ok(())
+        }
+        n => bail!("{n} record(s) differ from {}", path.display()),
+    }
+}
+
+#[cfg(not(feature = "manifest"))]
+fn verify(_dir: &str, _config: &ConfigResolver, _args: impl Iterator<Item = String>) -> Result<()> {
+    bail!("manifests require building with --features manifest")
+}
+
+/// `--key FILE ARCHIVE`, as `backup` and `restore` take them; `--force`
+/// only for `restore`.
+#[cfg(feature = "backup")]
+fn backup_args(
+    command: &str,
+    args: impl Iterator<Item = String>,
+    force_ok: bool,
+) -> Result<(std::path::PathBuf, std::path::PathBuf, bool)> {
+    let usage = || {
+        let force = if force_ok { " [--force]" } else { "" };
+        anyhow::anyhow!("usage: pinctl {command} --key FILE ARCHIVE{force}")
+    };
+    let mut key = None;
+    let mut archive = None;
+    let mut force = false;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => key = Some(args.next().ok_or_else(usage)?.into()),
+            "--force" if force_ok => force = true,
+            _ if archive.is_none() && !arg.starts_with('-') => archive = Some(arg.into()),
+            _ => return Err(usage()),
+        }
+    }
+    match (key, archive) {
+        (Some(key), Some(archive)) => Ok((key, archive, force)),
+        _ => Err(usage()),
+    }
+}
+
+/// Write an encrypted archive of the PIN and state directories.
+#[cfg(feature = "backup")]
+fn backup(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::backup::Backup;
+    use pin_auth::store::{load_or_create_key_file, resolve_store_with, write_atomic};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let (key_path, archive, _) = backup_args("backup", args, false)?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let (key, created) = load_or_create_key_file(&key_path)
+        .with_context(|| format!("backup key {}", key_path.display()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let backup = Backup::collect(&store, now).context("reading the PIN directory")?;
+    write_atomic(&archive, &backup.seal(&key), 0o600)
+        .with_context(|| format!("writing {}", archive.display()))?;
+    println!(
+        "{} files backed up to {}",
+        backup.files.len(),
+        archive.display()
+    );
+    if created {
+        println!(
+            "new backup key {}; keep a copy apart from the archive",
+            key_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "backup"))]
+fn backup(_dir: &str, _config: &ConfigResolver, _args: impl Iterator<Item = String>) -> Result<()> {
+    bail!("backups require building with --features backup")
+}
+
+/// Put the files of an archive back. Existing files are left alone unless
+/// `--force`.
+#[cfg(feature = "backup")]
+fn restore(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::backup::Backup;
+    use pin_auth::config::CONFIG_FILE;
+    use pin_auth::store::{read_bytes_nofollow, resolve_store_with};
+
+    let (key_path, archive, force) = backup_args("restore", args, true)?;
+    let key = std::fs::read_to_string(&key_path)
+        .ok()
+        .and_then(|raw| pin_auth::parse_hex(raw.trim()))
+        .and_then(|key| <[u8; 32]>::try_from(key.as_slice()).ok())
+        .map(zeroize::Zeroizing::new)
+        .with_context(|| format!("{}: not a backup key", key_path.display()))?;
+    let raw =
+        read_bytes_nofollow(&archive).with_context(|| format!("reading {}", archive.display()))?;
+    let backup =
+        Backup::open(&raw, &key).map_err(|e| anyhow::anyhow!("{}: {e}", archive.display()))?;
+    // Files go where the restored config file puts them (state_dir).
+    let restored;
+    let config = match backup.files.iter().find(|f| f.name == CONFIG_FILE) {
+        Some(file) if force || !std::path::Path::new(dir).join(CONFIG_FILE).exists() => {
+            restored = ConfigResolver::from_env()
+                .with_file(&String::from_utf8_lossy(&file.contents), CONFIG_FILE)
+                .context("config file in archive")?;
+            &restored
+        }
+        _ => config,
+    };
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let existing: Vec<_> = backup
+        .files
+        .iter()
+        .map(|f| store.path(&f.name))
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect();
+    if !force && !existing.is_empty() {
+        for path in &existing {
+            println!("exists: {}", path.display());
+        }
+        bail!(
+            "{} file(s) would be overwritten; pass --force",
+            existing.len()
+        );
+    }
+    for file in &backup.files {
+        replace_file(
+            config,
+            &store.path(&file.name),
+            &file.contents,
+            file.mode,
+            None,
+        )?;
+    }
+    println!(
+        "{} files restored from {} (host {}, backed up {} UTC)",
+        backup.files.len(),
+        archive.display(),
+        backup.host,
+        pin_auth::format_utc(backup.created)
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "backup"))]
+fn restore(
+    _dir: &str,
+    _config: &ConfigResolver,
+    _args: impl Iterator<Item = String>,
+) -> Result<()> {
+    bail!("backups require building with --features backup")
+}
+
+/// Write a whole file as `restore` and `import` do: clearing the immutable
+/// attribute first and, with `immutable_hashes`, setting it again on hashes.
+/// `modified` carries the file's time over from another host.
+fn replace_file(
+    config: &ConfigResolver,
+    path: &std::path::Path,
+    contents: &[u8],
+    mode: u32,
+    modified: Option<u64>,
+) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::immutable::{make_mutable, set_immutable};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    make_mutable(path).with_context(|| format!("clearing immutable on {}", path.display()))?;
+    pin_auth::store::write_atomic(path, contents, mode)
+        .with_context(|| format!("writing {}", path.display()))?;
+    if let Some(ts) = modified {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::libc::O_NOFOLLOW)
+            .open(path)
+            .and_then(|f| {
+                f.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(ts))
+            })
+            .with_context(|| format!("setting the time of {}", path.display()))?;
+    }
+    let hash = matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("passwd" | "decoy")
+    );
+    if hash && config.flag("immutable_hashes").unwrap_or(false) {
+        if let Err(e) = set_immutable(path, true) {
+            tracing::warn!("{}: cannot set immutable: {e}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Print the records of every user (or of USER...) as JSON.
+fn export(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+
+    let mut keys = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => {}
+            _ if arg.starts_with('-') => bail!("usage: pinctl export [--json] [USER...]"),
+            user => keys.push(record_key(config, user)?),
+        }
+    }
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let records = pin_auth::export::collect(&store, &keys).context("reading records")?;
+    if let Some(missing) = keys.iter().find(|k| !records.iter().any(|r| &r.key == *k)) {
+        bail!("no records for {missing}");
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&pin_auth::export::to_json(&records))?
+    );
+    Ok(())
+}
+
+fn parse_export(text: &str, source: &str) -> Result<Vec<pin_auth::export::Record>> {
+    use anyhow::Context;
+    let value: serde_json::Value =
+        serde_json::from_str(text).with_context(|| format!("{source}: not JSON"))?;
+    pin_auth::export::from_json(&value).with_context(|| source.to_string())
+}
+
+/// Write `records` to `store`, each key audited as an import.
+fn write_records(
+    dir: &str,
+    config: &ConfigResolver,
+    store: &pin_auth::store::DirStore,
+    records: &[pin_auth::export::Record],
+) -> Result<()> {
+    for record in records {
+        for (ext, contents) in &record.files {
+            let name = format!("{}.{ext}", record.key);
+            let modified = record.changed.get(ext).copied();
+            let contents = store.seal(&name, contents);
+            replace_file(
+                config,
+                &store.path(&name),
+                contents.as_bytes(),
+                0o600,
+                modified,
+            )?;
+        }
+        admin_audit(config, dir, "import", &record.key)?;
+    }
+    Ok(())
+}
+
+/// Write the files of `incoming` that are newer than the local ones.
+fn write_newer(
+    dir: &str,
+    config: &ConfigResolver,
+    store: &pin_auth::store::DirStore,
+    incoming: &[pin_auth::export::Record],
+) -> Result<()> {
+    use anyhow::Context;
+    let current = pin_auth::export::collect(store, &[]).context("reading records")?;
+    let (updates, conflicts) = pin_auth::export::newer(&current, incoming);
+    for name in &conflicts {
+        println!("conflict: {name} (same time, other contents; kept this host's)");
+    }
+    write_records(dir, config, store, &updates)?;
+    let files: usize = updates.iter().map(|r| r.files.len()).sum();
+    println!("{files} file(s) updated");
+    Ok(())
+}
+
+/// Write the records from a `pinctl export` file (`-` for stdin). Existing
+/// files are left alone unless `--force`; with `--newer`, only older ones
+/// are replaced.
+fn import(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use std::io::Read;
+
+    const IMPORT_USAGE: &str = "usage: pinctl import FILE|- [--force | --newer]";
+    let mut source = None;
+    let mut force = false;
+    let mut newer = false;
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--newer" => newer = true,
+            _ if source.is_none() && (arg == "-" || !arg.starts_with('-')) => source = Some(arg),
+            _ => bail!(IMPORT_USAGE),
+        }
+    }
+    let Some(source) = source.filter(|_| !(force && newer)) else {
+        bail!(IMPORT_USAGE);
+    };
+    let mut text = String::new();
+    if source == "-" {
+        std::io::stdin().read_to_string(&mut text)?;
+    } else {
+        text = std::fs::read_to_string(&source).with_context(|| format!("reading {source}"))?;
+    }
+    let records = parse_export(&text, &source)?;
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    if newer {
+        return write_newer(dir, config, &store, &records);
+    }
+    let existing: Vec<_> = records
+        .iter()
+        .flat_map(|r| r.file_names())
+        .map(|(name, _)| store.path(&name))
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect();
+    if !force && !existing.is_empty() {
+        for path in &existing {
+            println!("exists: {}", path.display());
+        }
+        bail!(
+            "{} file(s) would be overwritten; pass --force",
+            existing.len()
+        );
+    }
+    write_records(dir, config, &store, &records)?;
+    println!("{} records imported from {source}", records.len());
+    Ok(())
+}
+
+/// Where `import-shadow` reads password hashes by default.
+#[cfg(target_os = "freebsd")]
+const SHADOW_FILE: &str = "/etc/master.passwd";
+#[cfg(not(target_os = "freebsd"))]
+const SHADOW_FILE: &str = "/etc/shadow";
+
+/// Copy users' password hashes from the shadow file into PIN records, so
+/// their password serves as the PIN until they set one with `genpin`. Users
+/// that cannot be imported are listed and the others imported.
+fn import_shadow(
+    dir: &str,
+    config: &ConfigResolver,
+    mut args: impl Iterator<Item = String>,
+) -> Result<()> {
+    use anyhow::Context;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const IMPORT_SHADOW_USAGE: &str =
+        "usage: pinctl import-shadow USER... [--shadow FILE] [--force]";
+    let mut users = Vec::new();
+    let mut shadow = SHADOW_FILE.to_string();
+    let mut force = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shadow" => match args.next() {
+                Some(path) => shadow = path,
+                None => bail!(IMPORT_SHADOW_USAGE),
+            },
+            "--force" => force = true,
+            _ if arg.starts_with('-') => bail!(IMPORT_SHADOW_USAGE),
+            _ => users.push(arg),
+        }
+    }
+    if users.is_empty() {
+        bail!(IMPORT_SHADOW_USAGE);
+    }
+    let text = zeroize::Zeroizing::new(
+        std::fs::read_to_string(&shadow).with_context(|| format!("reading {shadow}"))?,
+    );
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut skipped = 0;
+    for user in &users {
+        let imported = match shadow_entry(&text, user) {
+            Some((hash, changed_at)) => {
+                import_shadow_hash(config, &store, user, hash, changed_at, force, now)
+            }
+            None => Err(anyhow::anyhow!("not in {shadow}")),
+        };
+        match imported {
+            Ok(info) => {
+                admin_audit(config, dir, "import_shadow", user)?;
+                println!("{user}: imported ({info})");
+            }
+            Err(e) => {
+                skipped += 1;
+                eprintln!("{user}: not imported: {e:#}");
+            }
+        }
+    }
+    if skipped > 0 {
+        bail!("{skipped} of {} user(s) not imported", users.len());
+    }
+    Ok(())
+}
+
+/// `user`'s hash in a shadow file, and when the password was last changed
+/// (Unix seconds) if the file says.
+fn shadow_entry<'a>(text: &'a str, user: &str) -> Option<(&'a str, Option<u64>)> {
+    text.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next() != Some(user) {
+            return None;
+        }
+        let hash = fields.next()?;
+        // shadow(5) counts days; master.passwd has no such field
+        let changed_at = fields
+            .next()
+            .filter(|_| !cfg!(target_os = "freebsd"))
+            .and_then(|days| days.parse::<u64>().ok())
+            .filter(|&days| days > 0)
+            .map(|days| days * 86_400);
+        Some((hash, changed_at))
+    })
+}
+
+/// Write `hash` as `user`'s PIN record, unless `check_pin` could not use
+/// it or would refuse it.
+fn import_shadow_hash(
+    config: &ConfigResolver,
+    store: &pin_auth::store::DirStore,
+    user: &str,
+    hash: &str,
+    changed_at: Option<u64>,
+    force: bool,
+    now: u64,
+) -> Result<pin_auth::HashInfo> {
+    use pin_auth::record::HashRecord;
+    use pin_auth::store::PinStore;
+
+    if hash.is_empty() || hash.starts_with(['!', '*']) {
+        bail!("no usable password hash (empty, locked or disabled)");
+    }
+    let Ok(info) = pin_auth::Scheme::from_hash(hash) else {
+        let kind = hash.split('$').nth(1).unwrap_or_default();
+        bail!("unsupported hash scheme ${kind}$");
+    };
+    if !info.scheme.is_available() {
+        bail!("{} support not compiled in", info.scheme.name());
+    }
+    if let Some(floor) = config.parse::<pin_auth::HashFloor>("min_hash") {
+        if !floor.allows(&info) {
+            bail!("{info} is below min_hash ({floor})");
+        }
+    }
+    let key = record_key(config, user)?;
+    if store
+        .path(&format!("{key}.toml"))
+        .symlink_metadata()
+        .is_ok()
+    {
+        bail!("has a TOML record");
+    }
+    let name = format!("{key}.passwd");
+    let path = store.path(&name);
+    if !force && path.symlink_metadata().is_ok() {
+        bail!("has a PIN record; pass --force to replace it");
+    }
+    let mut record = HashRecord::new(hash);
+    record.created_at = Some(now);
+    record.changed_at = changed_at;
+    record.scheme = Some(info.scheme.name().to_string());
+    let sealed = store.seal(&name, &record.serialize());
+    replace_file(config, &path, sealed.as_bytes(), 0o600, None)?;
+    // a new secret starts without failures
+    let _ = store.remove_file(&format!("{key}.fail"));
+    Ok(info)
+}
+
+/// Exchange the records an administrator sets (not per-host fail or login
+/// state) with another host over SSH or with an export file on a shared
+/// store; for each file the later change wins.
+fn sync(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::export::{self, shared_only};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let args: Vec<String> = args.collect();
+    let (push, remote) = match args.as_slice() {
+        [way, remote] if !remote.starts_with('-') && (way == "push" || way == "pull") => {
+            (way == "push", remote.as_str())
+        }
+        _ => bail!("usage: pinctl sync push|pull HOST|/SHARED/FILE.json"),
+    };
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let ssh = |command: &[&str]| {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "BatchMode=yes", remote, "pinctl"])
+            .args(command);
+        cmd
+    };
+    if !push {
+        let (text, source) = if remote.starts_with('/') {
+            let text =
+                std::fs::read_to_string(remote).with_context(|| format!("reading {remote}"))?;
+            (text, remote.to_string())
+        } else {
+            let out = ssh(&["export", "--json"])
+                .stderr(Stdio::inherit())
+                .output()
+                .context("running ssh")?;
+            if !out.status.success() {
+                bail!("{remote}: pinctl export failed ({})", out.status);
+            }
+            (String::from_utf8(out.stdout)?, format!("{remote}:"))
+        };
+        let theirs = shared_only(parse_export(&text, &source)?);
+        return write_newer(dir, config, &store, &theirs);
+    }
+    let ours = shared_only(export::collect(&store, &[]).context("reading records")?);
+    if remote.starts_with('/') {
+        let path = std::path::Path::new(remote);
+        let mut theirs = match std::fs::read_to_string(path) {
+            Ok(text) => parse_export(&text, remote)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("reading {remote}")),
+        };
+        let (updates, conflicts) = export::newer(&theirs, &ours);
+        for name in &conflicts {
+            println!("conflict: {name} (same time, other contents; kept {remote}'s)");
+        }
+        let files: usize = updates.iter().map(|r| r.files.len()).sum();
+        export::merge(&mut theirs, updates);
+        let text = serde_json::to_string_pretty(&export::to_json(&theirs))? + "\n";
+        pin_auth::store::write_atomic(path, text.as_bytes(), 0o600)
+            .with_context(|| format!("writing {remote}"))?;
+        println!("{files} file(s) updated in {remote}");
+        return Ok(());
+    }
+    let mut child = ssh(&["import", "-", "--newer"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("running ssh")?;
+    let text = serde_json::to_string(&export::to_json(&ours))?;
+    child
+        .stdin
+        .take()
+        .context("ssh stdin")?
+        .write_all(text.as_bytes())
+        .context("sending records")?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("{remote}: pinctl import failed ({status})");
+    }
+    Ok(())
+}
+
+/// Enroll or remove `<user>.fido2`. Enrolling makes a new resident
+/// credential on the first attached key, which asks for a touch.
+#[cfg(feature = "fido2")]
+fn fido2(dir: &str, config: &ConfigResolver, args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::fido2::{enroll, Fallback, Fido2Record};
+    use pin_auth::store::{resolve_store_with, PinStore};
+
+    let args: Vec<String> = args.collect();
+    let Some((user, opts)) = args.split_first() else {
+        bail!("usage: pinctl fido2 USER [--when no_pin|locked|both] [--device-pin] | --remove");
+    };
+    let key = record_key(config, user)?;
+    let store = resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let name = Fido2Record::file_name(&key);
+    if let [flag] = opts {
+        if flag == "--remove" {
+            store
+                .remove_file(&name)
+                .with_context(|| format!("removing {name}"))?;
+            admin_audit(config, dir, "remove_fido2", user)?;
+            println!("FIDO2 credential for {user} removed");
+            return Ok(());
+        }
+    }
+    let mut when = Fallback::Locked;
+    let mut device_pin = None;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_str() {
+            "--when" => {
+                when = opts
+                    .next()
+                    .and_then(|w| w.parse().ok())
+                    .context("--when must be no_pin, locked or both")?;
+            }
+            "--device-pin" => {
+                device_pin = Some(zeroize::Zeroizing::new(rpassword::prompt_password(
+                    "Security key PIN: ",
+                )?));
+            }
+            other => bail!("unexpected argument: {other}\n{USAGE}"),
+        }
+    }
+    println!("touch the security key...");
+    let record = enroll(user, device_pin.as_deref().map(String::as_str), when)
+        .context("making the FIDO2 credential")?;
+    store
+        .write_file(&name, &record.serialize())
+        .with_context(|| format!("writing {name}"))?;
+    admin_audit(config, dir, "set_fido2", user)?;
+    println!("FIDO2 credential enrolled for {user} (used when: {when})");
+    Ok(())
+}
+
+#[cfg(not(feature = "fido2"))]
+fn fido2(_dir: &str, _config: &ConfigResolver, _args: impl Iterator<Item = String>) -> Result<()> {
+    bail!("FIDO2 support requires building with --features fido2")
+}
+
+/// The key `user`'s files are named after: the name, or its UID with
+/// `record_key = uid`.
+fn record_key(config: &ConfigResolver, user: &str) -> Result<String> {
+    use anyhow::Context;
+    pin_auth::policy::UsernamePolicy::from_config(config)
+        .record_key(user)
+        .with_context(|| format!("invalid username {user:?}"))
+}
+
+/// Append an admin record to the audit log, if enabled.
+fn admin_audit(config: &ConfigResolver, dir: &str, action: &'static str, user: &str) -> Result<()> {
+    use anyhow::Context;
+    if config.flag("audit").unwrap_or(false) {
+        let store =
+            pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+        let log = AuditLog::new(store.path("audit.log"));
+        log.append(&pin_auth::audit::AuditEvent::Admin {
+            action,
+            user,
+            actor_uid: nix::unistd::Uid::current().as_raw(),
+        })
+        .context("writing audit record")?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn audit(dir: &str, config: &ConfigResolver, mut args: impl Iterator<Item = String>) -> Result<()> {
+    use anyhow::Context;
+    use pin_auth::history::History;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let mut user = None;
+    let mut since = 0;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--user" => user = Some(args.next().context("--user needs a value")?),
+            "--since" => {
+                let v = args.next().context("--since needs a value")?;
+                let secs = pin_auth::parse_duration(&v)
+                    .with_context(|| format!("bad duration {v:?} (e.g. 90m, 24h, 7d)"))?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                since = now.saturating_sub(secs);
+            }
+            other => bail!("unexpected argument: {other}\n{USAGE}"),
+        }
+    }
+    let store =
+        pin_auth::store::resolve_store_with(dir, config).context("insecure PIN directory")?;
+    let db = History::open(store.path("history.sqlite")).context("opening history")?;
+    let rows = db
+        .query(user.as_deref(), since)
+        .context("querying history")?;
+    println!(
+        "{:<20}  {:<16}  {:<10}  {:<12}  {:<8}  RHOST",
+        "TIME (UTC)", "USER", "RESULT", "SERVICE", "TTY"
+    );
+    for a in rows {
+        println!(
+            "{:<20}  {:<16}  {:<10}  {:<12}  {:<8}  {}",
+            pin_auth::format_utc(a.ts),
+            a.user,
+            a.result,
+            a.service.as_deref().unwrap_or("-"),
+            a.tty.as_deref().unwrap_or("-"),
+            a.rhost.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn audit(_dir: &str, _config: &ConfigResolver, _args: impl Iterator<Item = String>) -> Result<()> {
+    bail!("attempt history requires building with --features sqlite")
+}
@@ -0,0 +1,877 @@
+//! PIN policy: length limits, a charset (digits unless `charset` says
+//! otherwise) and an optional blacklist; plus the rules for usernames used
+//! as record keys.
+//!
+//! `genpin` applies the full policy when a PIN is set; `check_pin` re-checks
+//! the format (length and charset) of every candidate before hashing it.
+//! [`weakness`] only advises: `genpin` warns about such PINs as they are
+//! typed but accepts them.
+
+use crate::config::ConfigResolver;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+/// Hard upper bound on `min_len`.
+pub const MAX_MIN_LEN: usize = 32;
+
+/// Why a PIN was refused.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum PolicyViolation {
+    #[error("PIN shorter than minimum ({min})")]
+    TooShort { min: usize },
+    #[error("PIN longer than allowed maximum ({max})")]
+    TooLong { max: usize },
+    /// Contains something other than `0-9`.
+    #[error("PIN must contain only digits (0-9)")]
+    NonDigit,
+    /// Contains something outside the configured [`Charset`] (other than
+    /// [`Charset::Digits`], which gives [`PolicyViolation::NonDigit`]).
+    #[error("PIN must contain only {0}")]
+    OutsideCharset(Charset),
+    /// Listed in the blacklist.
+    #[error("PIN is on the blacklist")]
+    Blacklisted,
+    /// The account's UID (`reject_pins = uid`).
+    #[error("PIN is the account's UID")]
+    AccountUid,
+    /// A run along a keyboard or keypad (`reject_pins = keyboard`).
+    #[error("PIN is a row or column of the keypad")]
+    KeyboardRun,
+    /// Digits from the account name, or all of them
+    /// (`reject_pins = username`).
+    #[error("PIN is taken from the username")]
+    FromUsername,
+    /// Below `min_entropy_bits` by [`entropy_bits`].
+    #[error("PIN is too easy to guess (less than {min_bits} bits)")]
+    LowEntropy { min_bits: u32 },
+}
+
+/// The policy itself is unusable.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum PolicyConfigError {
+    /// `min_len` is 0 or above [`MAX_MIN_LEN`].
+    #[error("unreasonable min_len ({0})")]
+    UnreasonableMinLen(usize),
+    #[error("max_len ({max}) is less than min_len ({min})")]
+    MaxBelowMin { min: usize, max: usize },
+    /// Not even a `max_len` PIN of random characters reaches
+    /// `min_entropy_bits`.
+    #[error("min_entropy_bits ({0}) is out of reach with max_len and charset")]
+    UnreachableEntropy(u32),
+}
+
+/// The characters a PIN may contain (`charset`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Charset {
+    /// `0-9`.
+    #[default]
+    Digits,
+    /// `0-9`, `a-f` and `A-F`. Case still matters when the PIN is checked.
+    Hex,
+    /// Printable ASCII, space included.
+    Printable,
+}
+
+impl Charset {
+    /// How many characters there are to choose from.
+    pub fn size(self) -> u32 {
+        match self {
+            Charset::Digits => 10,
+            Charset::Hex => 16,
+            Charset::Printable => 95,
+        }
+    }
+
+    pub fn allows(self, c: char) -> bool {
+        match self {
+            Charset::Digits => c.is_ascii_digit(),
+            Charset::Hex => c.is_ascii_hexdigit(),
+            Charset::Printable => c == ' ' || c.is_ascii_graphic(),
+        }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Charset::Digits => "digits (0-9)",
+            Charset::Hex => "hex digits (0-9, a-f)",
+            Charset::Printable => "printable ASCII",
+        })
+    }
+}
+
+impl std::str::FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "digits" => Ok(Charset::Digits),
+            "hex" => Ok(Charset::Hex),
+            "printable" => Ok(Charset::Printable),
+            _ => Err(format!("{s:?} is not digits, hex or printable")),
+        }
+    }
+}
+
+/// A rough estimate of how many bits of guessing `pin` takes: each
+/// character is worth `log2` of the charset size, except one that repeats
+/// the previous character, continues the previous step (`1234`, `2468`,
+/// `9753`) or repeats the character two or three back (`1212`, `123123`),
+/// which is worth one bit.
+pub fn entropy_bits(pin: &str, charset: Charset) -> f64 {
+    let full = f64::from(charset.size()).log2();
+    let chars: Vec<i32> = pin.chars().map(|c| c as i32).collect();
+    let mut bits = 0.0;
+    for (i, &c) in chars.iter().enumerate() {
+        let back = |n: usize| i.checked_sub(n).map(|j| chars[j]);
+        let predictable = back(1) == Some(c)
+            || i >= 2 && c - chars[i - 1] == chars[i - 1] - chars[i - 2]
+            || back(2) == Some(c)
+            || back(3) == Some(c);
+        bits += if predictable { 1.0 } else { full };
+    }
+    bits
+}
+
+/// Which PINs derived from the account or the keyboard `genpin` refuses
+/// (`reject_pins`, a comma-separated list of `uid`, `keyboard` and
+/// `username`, or `all` / `none`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DerivedPins {
+    pub uid: bool,
+    pub keyboard: bool,
+    pub username: bool,
+}
+
+impl std::str::FromStr for DerivedPins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut checks = DerivedPins::default();
+        for check in s.split(',').map(|c| c.trim().to_ascii_lowercase()) {
+            match check.as_str() {
+                "uid" => checks.uid = true,
+                "keyboard" => checks.keyboard = true,
+                "username" => checks.username = true,
+                "all" => {
+                    checks = DerivedPins {
+                        uid: true,
+                        keyboard: true,
+                        username: true,
+                    }
+                }
+                "none" | "" => {}
+                _ => return Err(format!("{check:?} is not uid, keyboard or username")),
+            }
+        }
+        Ok(checks)
+    }
+}
+
+/// Digit runs along a keyboard or a keypad, either way: the top row, the
+/// rows and columns of a phone keypad and of a numeric keypad.
+const KEYBOARD_RUNS: &[&str] = &[
+    "1234567890",
+    "123456789",
+    "789456123",
+    "147258369",
+    "2580",
+    "7410",
+    "8520",
+];
+
+#[derive(Clone, Debug)]
+pub struct PolicyValidator {
+    min_len: usize,
+    max_len: usize,
+    charset: Charset,
+    /// `min_entropy_bits`; 0 when unset.
+    min_entropy_bits: u32,
+    blacklist: HashSet<String>,
+    derived: DerivedPins,
+    /// The name and UID of the account the PIN is for, if known.
+    account: Option<(String, Option<u32>)>,
+}
+
+impl Default for PolicyValidator {
+    fn default() -> Self {
+        PolicyValidator {
+            min_len: 4,
+            max_len: 6,
+            charset: Charset::Digits,
+            min_entropy_bits: 0,
+            blacklist: HashSet::new(),
+            derived: DerivedPins::default(),
+            account: None,
+        }
+    }
+}
+
+impl PolicyValidator {
+    pub fn new(min_len: usize, max_len: usize) -> Result<Self, PolicyConfigError> {
+        if min_len == 0 || min_len > MAX_MIN_LEN {
+            return Err(PolicyConfigError::UnreasonableMinLen(min_len));
+        }
+        if max_len < min_len {
+            return Err(PolicyConfigError::MaxBelowMin {
+                min: min_len,
+                max: max_len,
+            });
+        }
+        Ok(PolicyValidator {
+            min_len,
+            max_len,
+            ..PolicyValidator::default()
+        })
+    }
+
+    /// [`PolicyValidator::from_config`] over the environment alone.
+    pub fn from_env() -> Result<Self, PolicyConfigError> {
+        Self::from_config(&ConfigResolver::from_env())
+    }
+
+    /// `min_len` / `max_len`, `charset`, `min_entropy_bits` and
+    /// `reject_pins` over the defaults.
+    pub fn from_config(config: &ConfigResolver) -> Result<Self, PolicyConfigError> {
+        let d = PolicyValidator::default();
+        let validator = PolicyValidator::new(
+            config.parse("min_len").unwrap_or(d.min_len),
+            config.parse("max_len").unwrap_or(d.max_len),
+        )?
+        .with_charset(config.parse("charset").unwrap_or(d.charset))
+        .with_derived(config.parse("reject_pins").unwrap_or(d.derived));
+        validator.with_min_entropy(config.parse("min_entropy_bits").unwrap_or(0))
+    }
+
+    /// Refuse new PINs below `bits` by [`entropy_bits`] (0 = no minimum).
+    /// Fails if no PIN within `max_len` could reach it.
+    pub fn with_min_entropy(mut self, bits: u32) -> Result<Self, PolicyConfigError> {
+        let best = self.max_len as f64 * f64::from(self.charset.size()).log2();
+        if f64::from(bits) > best {
+            return Err(PolicyConfigError::UnreachableEntropy(bits));
+        }
+        self.min_entropy_bits = bits;
+        Ok(self)
+    }
+
+    pub fn with_charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    pub fn with_derived(mut self, derived: DerivedPins) -> Self {
+        self.derived = derived;
+        self
+    }
+
+    /// Check new PINs against `name` (a user or group name) and `uid` as
+    /// [`DerivedPins`] says.
+    pub fn for_account(mut self, name: &str, uid: Option<u32>) -> Self {
+        self.account = Some((name.to_string(), uid));
+        self
+    }
+
+    pub fn with_blacklist<I, S>(mut self, pins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.blacklist.extend(pins.into_iter().map(Into::into));
+        self
+    }
+
+    /// Add the PINs listed in `path`, one per line (`#` comments and blank
+    /// lines ignored). A missing file is not an error.
+    pub fn load_blacklist(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self),
+            Err(e) => return Err(e),
+        };
+        let pins: Vec<String> = raw
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        Ok(self.with_blacklist(pins))
+    }
+
+    pub fn min_len(&self) -> usize {
+        self.min_len
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /// Length and charset only; what `check_pin` enforces on candidates.
+    pub fn check_format(&self, pin: &str) -> Result<(), PolicyViolation> {
+        if pin.len() < self.min_len {
+            return Err(PolicyViolation::TooShort { min: self.min_len });
+        }
+        if pin.len() > self.max_len {
+            return Err(PolicyViolation::TooLong { max: self.max_len });
+        }
+        if !pin.chars().all(|c| self.charset.allows(c)) {
+            return Err(match self.charset {
+                Charset::Digits => PolicyViolation::NonDigit,
+                charset => PolicyViolation::OutsideCharset(charset),
+            });
+        }
+        Ok(())
+    }
+
+    /// Full policy for a new PIN.
+    pub fn validate(&self, pin: &str) -> Result<(), PolicyViolation> {
+        self.check_format(pin)?;
+        if self.blacklist.contains(pin) {
+            return Err(PolicyViolation::Blacklisted);
+        }
+        if self.derived.keyboard
+            && pin.len() >= 3
+            && KEYBOARD_RUNS.iter().any(|run| {
+                let reversed: String = run.chars().rev().collect();
+                run.contains(pin) || reversed.contains(pin)
+            })
+        {
+            return Err(PolicyViolation::KeyboardRun);
+        }
+        if self.min_entropy_bits > 0
+            && entropy_bits(pin, self.charset) < f64::from(self.min_entropy_bits)
+        {
+            return Err(PolicyViolation::LowEntropy {
+                min_bits: self.min_entropy_bits,
+            });
+        }
+        let Some((name, uid)) = &self.account else {
+            return Ok(());
+        };
+        if self.derived.uid
+            && uid.is_some_and(|uid| pin.parse::<u64>().ok() == Some(u64::from(uid)))
+        {
+            return Err(PolicyViolation::AccountUid);
+        }
+        // "room1234" choosing 1234, "jdoe1985" choosing 198512
+        if self.derived.username
+            && name
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|digits| digits.len() >= 3 || digits.len() >= pin.len())
+                .any(|digits| digits.contains(pin) || pin.contains(digits))
+        {
+            return Err(PolicyViolation::FromUsername);
+        }
+        Ok(())
+    }
+}
+
+/// An easily guessed PIN the policy still allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum PinWeakness {
+    /// `1111`.
+    #[error("the same digit repeated")]
+    Repeated,
+    /// `1234`, `9876`.
+    #[error("sequential digits")]
+    Sequential,
+    /// `1212`, `123123`: a shorter group of digits repeated.
+    #[error("a repeated pattern")]
+    Pattern,
+}
+
+/// What makes `pin` easy to guess, if anything. Only digits are judged.
+pub fn weakness(pin: &str) -> Option<PinWeakness> {
+    let digits = pin.as_bytes();
+    if digits.len() < 2 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    if digits.iter().all(|&d| d == digits[0]) {
+        return Some(PinWeakness::Repeated);
+    }
+    let steps = |step: i8| digits.windows(2).all(|w| w[1] as i8 - w[0] as i8 == step);
+    if steps(1) || steps(-1) {
+        return Some(PinWeakness::Sequential);
+    }
+    let n = digits.len();
+    if (2..=n / 2)
+        .any(|len| n.is_multiple_of(len) && digits.chunks(len).all(|c| c == &digits[..len]))
+    {
+        return Some(PinWeakness::Pattern);
+    }
+    None
+}
+
+/// Why a username was refused.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum UsernameError {
+    #[error("empty username")]
+    Empty,
+    #[error("username longer than {max}")]
+    TooLong { max: usize },
+    #[error("username contains {0:?}")]
+    InvalidChar(char),
+    #[error("username may not start with {0:?}")]
+    InvalidLeadingChar(char),
+    /// `record_key = uid` and NSS does not know the user.
+    #[error("no such user")]
+    UnknownUser,
+    #[error("looking up the user: {0}")]
+    Lookup(String),
+}
+
+/// What a user's files are named after (`record_key`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordKey {
+    /// `alice.passwd`: the name is the key, so it must be safe in a file name.
+    #[default]
+    Name,
+    /// `1000.passwd`: the UID NSS gives for the name. Any name NSS accepts
+    /// works, and renaming the account keeps its records.
+    Uid,
+    /// `john%2Edoe.passwd`: the name with every byte outside `A-Za-z0-9_-`
+    /// (and a leading `-`) written as `%XX`. Plain names keep their files.
+    Escaped,
+}
+
+impl std::str::FromStr for RecordKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "name" => Ok(RecordKey::Name),
+            "uid" => Ok(RecordKey::Uid),
+            "escaped" => Ok(RecordKey::Escaped),
+            _ => Err(format!("{s:?} is not name, uid or escaped")),
+        }
+    }
+}
+
+impl RecordKey {
+    /// Whether the account behind file key `key` still exists. Numeric keys
+    /// are UIDs in `uid` mode; other keys are names (records written before
+    /// the switch), unescaped in `escaped` mode.
+    pub fn account_exists(self, key: &str) -> io::Result<bool> {
+        match (self, key.parse::<u32>()) {
+            (RecordKey::Uid, Ok(uid)) => Ok(crate::account::name_of(uid)?.is_some()),
+            (RecordKey::Escaped, _) => match unescape_name(key) {
+                Some(name) => crate::account::user_exists(&name),
+                // not written by us: leave it alone
+                None => Ok(true),
+            },
+            _ => crate::account::user_exists(key),
+        }
+    }
+
+    /// The account name behind file key `key`, for display.
+    pub fn account_name(self, key: &str) -> String {
+        match (self, key.parse::<u32>()) {
+            (RecordKey::Uid, Ok(uid)) => crate::account::name_of(uid)
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| format!("uid {uid}")),
+            (RecordKey::Escaped, _) => unescape_name(key).unwrap_or_else(|| key.to_string()),
+            _ => key.to_string(),
+        }
+    }
+}
+
+/// `name` as a file key: bytes outside `A-Za-z0-9_-`, and a leading `-`,
+/// become `%XX`. The result never contains `/` or `.` nor starts with `@`,
+/// and distinct names give distinct keys.
+pub fn escape_name(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    for (i, b) in name.bytes().enumerate() {
+        if b.is_ascii_alphanumeric() || b == b'_' || (b == b'-' && i > 0) {
+            key.push(char::from(b));
+        } else {
+            key.push_str(&format!("%{b:02X}"));
+        }
+    }
+    key
+}
+
+/// The name [`escape_name`] made `key` from; `None` if it could not have.
+pub fn unescape_name(key: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(key.len());
+    let mut rest = key.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    let name = String::from_utf8(bytes).ok()?;
+    (escape_name(&name) == key).then_some(name)
+}
+
+/// Which usernames may be used as PIN record keys. The name becomes part of
+/// a file name, so `/`, NUL, `.` leading, and anything outside printable ASCII
+/// are refused whatever the configuration says. With [`RecordKey::Uid`] or
+/// [`RecordKey::Escaped`] the name never reaches a file name as is, and only
+/// length and control characters are checked (see
+/// [`UsernamePolicy::record_key`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsernamePolicy {
+    pub max_len: usize,
+    /// Allowed in addition to ASCII letters and digits.
+    pub extra_chars: String,
+    pub allow_leading_digit: bool,
+    pub key: RecordKey,
+    /// Lowercase names before keying, for directories (AD, SSSD) where
+    /// `Alice` and `alice` are one principal. Not applied to UID keys: NSS
+    /// resolves those.
+    pub ignore_case: bool,
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        UsernamePolicy {
+            max_len: 32,
+            extra_chars: "_-".to_string(),
+            allow_leading_digit: true,
+            key: RecordKey::Name,
+            ignore_case: false,
+        }
+    }
+}
+
+impl UsernamePolicy {
+    /// [`UsernamePolicy::from_config`] over the environment alone.
+    pub fn from_env() -> Self {
+        Self::from_config(&ConfigResolver::from_env())
+    }
+
+    /// `user_max_len`, `user_extra_chars`, `user_leading_digit` (yes/no)
+    /// `record_key` and `user_ignore_case` over the defaults.
+    pub fn from_config(config: &ConfigResolver) -> Self {
+        let d = UsernamePolicy::default();
+        UsernamePolicy {
+            max_len: config.parse("user_max_len").unwrap_or(d.max_len),
+            extra_chars: config
+                .string("user_extra_chars")
+                .map_or(d.extra_chars, str::to_string),
+            allow_leading_digit: config
+                .flag("user_leading_digit")
+                .unwrap_or(d.allow_leading_digit),
+            key: config.parse("record_key").unwrap_or(d.key),
+            ignore_case: config.flag("user_ignore_case").unwrap_or(d.ignore_case),
+        }
+    }
+
+    /// The key `user`'s files are stored under: the validated name, its
+    /// UID or its [`escape_name`] form; lowercased first with `ignore_case`.
+    pub fn record_key(&self, user: &str) -> Result<String, UsernameError> {
+        let lowered;
+        let user = if self.ignore_case && self.key != RecordKey::Uid {
+            lowered = user.to_lowercase();
+            lowered.as_str()
+        } else {
+            user
+        };
+        if self.key == RecordKey::Name {
+            return self.validate(user).map(|()| user.to_string());
+        }
+        if user.is_empty() {
+            return Err(UsernameError::Empty);
+        }
+        if user.len() > self.max_len {
+            return Err(UsernameError::TooLong { max: self.max_len });
+        }
+        // still logged and audited; NSS rejects NUL itself
+        if let Some(c) = user.chars().find(|c| c.is_control()) {
+            return Err(UsernameError::InvalidChar(c));
+        }
+        if self.key == RecordKey::Escaped {
+            return Ok(escape_name(user));
+        }
+        match crate::account::uid_of(user) {
+            Ok(Some(uid)) => Ok(uid.to_string()),
+            Ok(None) => Err(UsernameError::UnknownUser),
+            Err(e) => Err(UsernameError::Lookup(e.to_string())),
+        }
+    }
+
+    pub fn validate(&self, user: &str) -> Result<(), UsernameError> {
+        let Some(first) = user.chars().next() else {
+            return Err(UsernameError::Empty);
+        };
+        if user.len() > self.max_len {
+            return Err(UsernameError::TooLong { max: self.max_len });
+        }
+        if let Some(c) = user.chars().find(|&c| !self.allowed(c)) {
+            return Err(UsernameError::InvalidChar(c));
+        }
+        let leading_ok = first.is_ascii_alphabetic()
+            || first == '_'
+            || (first.is_ascii_digit() && self.allow_leading_digit);
+        if !leading_ok {
+            return Err(UsernameError::InvalidLeadingChar(first));
+        }
+        Ok(())
+    }
+
+    fn allowed(&self, c: char) -> bool {
+        if c == '/' || !c.is_ascii_graphic() {
+            return false;
+        }
+        c.is_ascii_alphanumeric() || self.extra_chars.contains(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn violations() {
+        let v = PolicyValidator::new(4, 6)
+            .unwrap()
+            .with_blacklist(["1234", "0000"]);
+        assert_eq!(v.validate("2468"), Ok(()));
+        assert_eq!(v.validate("12"), Err(PolicyViolation::TooShort { min: 4 }));
+        assert_eq!(
+            v.validate("1234567"),
+            Err(PolicyViolation::TooLong { max: 6 })
+        );
+        assert_eq!(v.validate("12a4"), Err(PolicyViolation::NonDigit));
+        assert_eq!(v.validate("1234"), Err(PolicyViolation::Blacklisted));
+        assert_eq!(v.check_format("1234"), Ok(()));
+    }
+
+    #[test]
+    fn entropy() {
+        let bits = |pin| entropy_bits(pin, Charset::Digits);
+        assert!(bits("830471") > 19.0);
+        assert!(bits("121212") < 11.0);
+        assert!(bits("123123") < 14.0);
+        assert!(bits("2468") < 9.0);
+        assert!(bits("0000") < 7.0);
+        assert!(entropy_bits("x9!q", Charset::Printable) > 26.0);
+
+        let v = PolicyValidator::default().with_min_entropy(16).unwrap();
+        assert_eq!(v.validate("830471"), Ok(()));
+        assert_eq!(
+            v.validate("121212"),
+            Err(PolicyViolation::LowEntropy { min_bits: 16 })
+        );
+        // only new PINs
+        assert_eq!(v.check_format("121212"), Ok(()));
+        assert_eq!(
+            PolicyValidator::default().with_min_entropy(20).unwrap_err(),
+            PolicyConfigError::UnreachableEntropy(20)
+        );
+        let config = ConfigResolver::default()
+            .with_file("min_entropy_bits = 30\n", "c")
+            .unwrap();
+        assert!(PolicyValidator::from_config(&config).is_err());
+        let config = ConfigResolver::default()
+            .with_file("min_entropy_bits = 30\ncharset = hex\nmax_len = 8\n", "c")
+            .unwrap();
+        assert!(PolicyValidator::from_config(&config).is_ok());
+        // file only: the environment cannot lower the floor
+        let config = ConfigResolver::from_vars([("PIN_MIN_ENTROPY_BITS", "30")]);
+        assert!(PolicyValidator::from_config(&config).is_ok());
+    }
+
+    #[test]
+    fn charsets() {
+        let v = PolicyValidator::default().with_charset(Charset::Hex);
+        assert_eq!(v.validate("beef"), Ok(()));
+        assert_eq!(v.check_format("C0DE"), Ok(()));
+        assert_eq!(
+            v.check_format("c0dz"),
+            Err(PolicyViolation::OutsideCharset(Charset::Hex))
+        );
+        let v = v.with_charset(Charset::Printable);
+        assert_eq!(v.validate("a b!"), Ok(()));
+        assert_eq!(
+            v.validate("ab\tc"),
+            Err(PolicyViolation::OutsideCharset(Charset::Printable))
+        );
+        assert_eq!(
+            v.validate("\u{e9}t\u{e9}"),
+            Err(PolicyViolation::OutsideCharset(Charset::Printable))
+        );
+        let config = ConfigResolver::default()
+            .with_file("charset = hex\n", "c")
+            .unwrap();
+        let v = PolicyValidator::from_config(&config).unwrap();
+        assert_eq!(v.charset(), Charset::Hex);
+        let config = ConfigResolver::from_vars([("PIN_CHARSET", "hex")]);
+        let v = PolicyValidator::from_config(&config).unwrap();
+        assert_eq!(v.charset(), Charset::Digits);
+        assert!("base64".parse::<Charset>().is_err());
+    }
+
+    #[test]
+    fn derived_pins() {
+        let all: DerivedPins = "all".parse().unwrap();
+        let v = PolicyValidator::default()
+            .with_derived(all)
+            .for_account("room1234", Some(1000));
+        assert_eq!(v.validate("1000"), Err(PolicyViolation::AccountUid));
+        assert_eq!(v.validate("001000"), Err(PolicyViolation::AccountUid));
+        assert_eq!(v.validate("1234"), Err(PolicyViolation::KeyboardRun));
+        assert_eq!(v.validate("7410"), Err(PolicyViolation::KeyboardRun));
+        assert_eq!(v.validate("963852"), Err(PolicyViolation::KeyboardRun));
+        assert_eq!(v.validate("2468"), Ok(()));
+        let v = v.for_account("jdoe1985", None);
+        assert_eq!(v.validate("198512"), Err(PolicyViolation::FromUsername));
+        assert_eq!(v.validate("1985"), Err(PolicyViolation::FromUsername));
+        assert_eq!(v.validate("1000"), Ok(()));
+        // a single digit in the name is not enough
+        let v = v.for_account("bob7", None);
+        assert_eq!(v.validate("2479"), Ok(()));
+
+        let some: DerivedPins = "uid, keyboard".parse().unwrap();
+        assert!(some.uid && some.keyboard && !some.username);
+        assert_eq!("none".parse(), Ok(DerivedPins::default()));
+        assert!("birthday".parse::<DerivedPins>().is_err());
+        // off by default
+        let v = PolicyValidator::default().for_account("room1234", Some(1234));
+        assert_eq!(v.validate("1234"), Ok(()));
+    }
+
+    #[test]
+    fn weak_pins() {
+        assert_eq!(weakness("0000"), Some(PinWeakness::Repeated));
+        assert_eq!(weakness("3456"), Some(PinWeakness::Sequential));
+        assert_eq!(weakness("987654"), Some(PinWeakness::Sequential));
+        assert_eq!(weakness("1212"), Some(PinWeakness::Pattern));
+        assert_eq!(weakness("147147"), Some(PinWeakness::Pattern));
+        for fine in ["2468", "9012", "1213", "12a4", "7"] {
+            assert_eq!(weakness(fine), None, "{fine}");
+        }
+    }
+
+    #[test]
+    fn config_and_blacklist_file() {
+        assert_eq!(
+            PolicyValidator::new(0, 6).unwrap_err(),
+            PolicyConfigError::UnreasonableMinLen(0)
+        );
+        assert_eq!(
+            PolicyValidator::new(6, 4).unwrap_err(),
+            PolicyConfigError::MaxBelowMin { min: 6, max: 4 }
+        );
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("pin-blacklist");
+        let v = PolicyValidator::default().load_blacklist(&path).unwrap();
+        assert_eq!(v.validate("1111"), Ok(()));
+        std::fs::write(&path, "# trivial\n1111\n\n 2222 \n").unwrap();
+        let v = v.load_blacklist(&path).unwrap();
+        assert_eq!(v.validate("1111"), Err(PolicyViolation::Blacklisted));
+        assert_eq!(v.validate("2222"), Err(PolicyViolation::Blacklisted));
+    }
+
+    #[test]
+    fn usernames() {
+        let p = UsernamePolicy::default();
+        assert_eq!(p.validate("alice_01"), Ok(()));
+        assert_eq!(p.validate("1backup"), Ok(()));
+        assert_eq!(p.validate(""), Err(UsernameError::Empty));
+        assert_eq!(p.validate("a/b"), Err(UsernameError::InvalidChar('/')));
+        assert_eq!(p.validate("john.doe"), Err(UsernameError::InvalidChar('.')));
+        assert_eq!(
+            p.validate("-rf"),
+            Err(UsernameError::InvalidLeadingChar('-'))
+        );
+        assert_eq!(
+            p.validate(&"a".repeat(33)),
+            Err(UsernameError::TooLong { max: 32 })
+        );
+
+        let p = UsernamePolicy {
+            extra_chars: "._-/".into(),
+            allow_leading_digit: false,
+            ..Default::default()
+        };
+        assert_eq!(p.record_key("john.doe"), Ok("john.doe".to_string()));
+        assert_eq!(p.validate("../x"), Err(UsernameError::InvalidChar('/')));
+        assert_eq!(
+            p.validate(".x"),
+            Err(UsernameError::InvalidLeadingChar('.'))
+        );
+        assert_eq!(
+            p.validate("1backup"),
+            Err(UsernameError::InvalidLeadingChar('1'))
+        );
+    }
+
+    #[test]
+    fn uid_keys() {
+        let p = UsernamePolicy {
+            key: RecordKey::Uid,
+            ..Default::default()
+        };
+        assert_eq!(p.record_key("root"), Ok("0".to_string()));
+        assert_eq!(
+            p.record_key("no-such-user-pin-auth"),
+            Err(UsernameError::UnknownUser)
+        );
+        // only NSS judges the charset
+        assert_eq!(
+            p.record_key("no.such@user.example"),
+            Err(UsernameError::UnknownUser)
+        );
+        assert_eq!(p.record_key("a\nb"), Err(UsernameError::InvalidChar('\n')));
+        assert!(RecordKey::Uid.account_exists("0").unwrap());
+        assert_eq!(RecordKey::Uid.account_name("0"), "root");
+        assert_eq!(RecordKey::Name.account_name("0"), "0");
+    }
+
+    #[test]
+    fn escaped_keys() {
+        let p = UsernamePolicy {
+            key: RecordKey::Escaped,
+            ..Default::default()
+        };
+        for (name, key) in [
+            ("alice", "alice"),
+            ("john.doe", "john%2Edoe"),
+            ("DOMAIN\\user", "DOMAIN%5Cuser"),
+            ("bob@corp.example", "bob%40corp%2Eexample"),
+            ("../etc", "%2E%2E%2Fetc"),
+            ("-rf", "%2Drf"),
+            ("50%", "50%25"),
+            ("jos\u{e9}", "jos%C3%A9"),
+        ] {
+            assert_eq!(p.record_key(name), Ok(key.to_string()));
+            assert_eq!(unescape_name(key).as_deref(), Some(name));
+            assert_eq!(RecordKey::Escaped.account_name(key), name);
+        }
+        // one spelling per name
+        assert_eq!(unescape_name("%61lice"), None);
+        assert_eq!(unescape_name("john.doe"), None);
+        assert_eq!(unescape_name("bad%2"), None);
+        assert_eq!(p.record_key(""), Err(UsernameError::Empty));
+
+        let p = UsernamePolicy {
+            ignore_case: true,
+            ..p
+        };
+        assert_eq!(
+            p.record_key("DOMAIN\\Alice"),
+            Ok("domain%5Calice".to_string())
+        );
+        let p = UsernamePolicy {
+            key: RecordKey::Name,
+            ..p
+        };
+        assert_eq!(p.record_key("Alice"), Ok("alice".to_string()));
+    }
+}
@@ -0,0 +1,146 @@
+//! Python module `pin_auth` (feature `python`; wheels via maturin with
+//! `python-extension`), for provisioning scripts that write PIN records
+//! without running `genpin`.
+//!
+//! ```python
+//! import pin_auth
+//! pin_auth.validate_pin("2468")              # ValueError on a policy violation
+//! record = pin_auth.hash_pin("2468", scheme="argon2id")
+//! assert pin_auth.verify_pin("2468", record)
+//! ```
+//!
+//! Without `min_len`/`max_len` the policy comes from `PIN_MIN_LEN` /
+//! `PIN_MAX_LEN`; without `charset` PINs are digits; without `scheme` the scheme and costs come from
+//! `PIN_SCHEME` / `PIN_ARGON2_*` (`pin-auth.conf` is not read). The blacklist is not
+//! applied; pass it with `blacklist=[...]` if needed.
+
+use crate::policy::{Charset, PolicyValidator};
+use crate::{hash_pin, hash_pin_with, verify_pin, HashParams, Pin, PinHashError, Scheme};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+fn validator(
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    charset: Option<&str>,
+    blacklist: Option<Vec<String>>,
+) -> PyResult<PolicyValidator> {
+    let policy = match (min_len, max_len) {
+        (None, None) => PolicyValidator::from_env(),
+        (min, max) => {
+            let default = PolicyValidator::default();
+            PolicyValidator::new(
+                min.unwrap_or(default.min_len()),
+                max.unwrap_or(default.max_len()),
+            )
+        }
+    }
+    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let policy = match charset {
+        Some(name) => policy.with_charset(parse_charset(name)?),
+        None => policy,
+    };
+    Ok(policy.with_blacklist(blacklist.unwrap_or_default()))
+}
+
+fn parse_charset(name: &str) -> PyResult<Charset> {
+    name.parse()
+        .map_err(|_| PyValueError::new_err(format!("unknown charset {name:?}")))
+}
+
+fn parse_scheme(name: &str) -> PyResult<Scheme> {
+    match name.to_lowercase().as_str() {
+        "sha512" | "sha512crypt" | "sha512-crypt" => Ok(Scheme::Sha512Crypt),
+        "argon2" | "argon2id" => Ok(Scheme::Argon2id),
+        other => Err(PyValueError::new_err(format!("unknown scheme {other:?}"))),
+    }
+}
+
+fn hash_error(e: PinHashError) -> PyErr {
+    match e {
+        PinHashError::Policy(v) => PyValueError::new_err(v.to_string()),
+        other => PyRuntimeError::new_err(other.to_string()),
+    }
+}
+
+/// Check a new PIN against the policy; raises `ValueError` if it fails.
+#[pyfunction]
+#[pyo3(signature = (pin, min_len=None, max_len=None, blacklist=None, charset=None))]
+fn validate_pin(
+    pin: &str,
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    blacklist: Option<Vec<String>>,
+    charset: Option<&str>,
+) -> PyResult<()> {
+    validator(min_len, max_len, charset, blacklist)?
+        .validate(pin)
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Validate and hash a new PIN, returning the `<user>.passwd` contents
+/// (without the trailing newline).
+#[pyfunction(name = "hash_pin")]
+#[pyo3(signature = (pin, scheme=None, min_len=None, max_len=None, blacklist=None, charset=None))]
+fn py_hash_pin(
+    py: Python<'_>,
+    pin: &str,
+    scheme: Option<&str>,
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    blacklist: Option<Vec<String>>,
+    charset: Option<&str>,
+) -> PyResult<String> {
+    let pin = Pin::new(pin, &validator(min_len, max_len, charset, blacklist)?)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let scheme = scheme.map(parse_scheme).transpose()?;
+    py.allow_threads(|| match scheme {
+        Some(scheme) => hash_pin_with(&pin, scheme, &HashParams::default()),
+        None => hash_pin(&pin),
+    })
+    .map_err(hash_error)
+}
+
+/// `True` if `pin` matches the stored hash. A malformed hash, or a candidate
+/// outside the configured length/charset, is `False`.
+#[pyfunction(name = "verify_pin")]
+#[pyo3(signature = (pin, stored, charset=None))]
+fn py_verify_pin(py: Python<'_>, pin: &str, stored: &str, charset: Option<&str>) -> bool {
+    let mut policy = PolicyValidator::from_env().unwrap_or_default();
+    if let Some(charset) = charset.and_then(|name| name.parse().ok()) {
+        policy = policy.with_charset(charset);
+    }
+    let Ok(candidate) = Pin::candidate(pin, &policy) else {
+        return false;
+    };
+    py.allow_threads(|| verify_pin(&candidate, stored.trim()))
+}
+
+#[pymodule]
+#[pyo3(name = "pin_auth")]
+fn pin_auth_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(validate_pin, m)?)?;
+    m.add_function(wrap_pyfunction!(py_hash_pin, m)?)?;
+    m.add_function(wrap_pyfunction!(py_verify_pin, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_and_scheme_arguments() {
+        assert!(validate_pin("2468", None, None, None, None).is_ok());
+        assert!(validate_pin("246", Some(4), Some(6), None, None).is_err());
+        assert!(validate_pin("12345678", Some(4), Some(8), None, None).is_ok());
+        assert!(validate_pin("2468", Some(4), None, Some(vec!["2468".into()]), None).is_err());
+        assert!(validate_pin("beef", None, None, None, None).is_err());
+        assert!(validate_pin("beef", None, None, None, Some("hex")).is_ok());
+        assert!(validator(None, None, Some("base64"), None).is_err());
+        assert!(validator(Some(8), Some(4), None, None).is_err());
+        assert_eq!(parse_scheme("Argon2id").unwrap(), Scheme::Argon2id);
+        assert_eq!(parse_scheme("sha512").unwrap(), Scheme::Sha512Crypt);
+        assert!(parse_scheme("md5").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! The contents of a `<user>.passwd` file.
+//!
+//! A record is the hash on its first line, then optional `key=value`
+//! lines `genpin` writes so expiry and auditing need no sidecar files:
+//!
+//! ```text
+//! $argon2id$v=19$m=19456,t=2,p=1$...
+//! created_at=1700000000
+//! changed_at=1710000000
+//! scheme=argon2id
+//! flags=...
+//! max_fails=3
+//! ```
+//!
+//! Lines naming a key in [`USER_KEYS`] (`max_fails`, `lockout_secs`, ...)
+//! are that user's settings over `pin-auth.conf`, as `pinctl policy` sets
+//! them; see [`HashRecord::settings`].
+//!
+//! Keys are read leniently for forward compatibility: unknown keys are
+//! kept as they are and a value that does not parse is ignored. Anything
+//! else in the file (a second hash, text after the hash, a stray editor
+//! buffer) makes it corrupt rather than being ignored: `check_pin` logs
+//! and audits `result=corrupt_record` and refuses, instead of verifying
+//! against whatever happens to come first.
+//!
+//! [`USER_KEYS`]: crate::config::USER_KEYS
+
+use crate::config::USER_KEYS;
+
+/// Why a `.passwd` file is not a record.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum RecordError {
+    #[error("corrupt record: empty")]
+    Empty,
+    /// Whitespace inside the hash line.
+    #[error("corrupt record: line 1: unexpected content after the hash")]
+    TrailingContent,
+    /// A later line that is not `key=value` (1-based line number).
+    #[error("corrupt record: line {0}: unexpected content")]
+    ExtraLine(usize),
+    /// A `<user>.toml` record (feature `toml-records`) that does not
+    /// parse, or one next to a `.passwd` file.
+    #[error("corrupt record: {0}")]
+    Toml(String),
+}
+
+/// A parsed `.passwd` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HashRecord {
+    pub hash: String,
+    /// When the user's first PIN was set (Unix seconds).
+    pub created_at: Option<u64>,
+    /// When the current PIN was set (Unix seconds).
+    pub changed_at: Option<u64>,
+    /// The scheme `genpin` hashed with ([`crate::Scheme::name`]); the hash
+    /// itself is authoritative.
+    pub scheme: Option<String>,
+    /// Markers for other tools, written as `flags=a,b`.
+    pub flags: Vec<String>,
+    /// Keys this version does not know, in file order, so a rewrite keeps
+    /// them.
+    pub other: Vec<(String, String)>,
+}
+
+impl HashRecord {
+    pub fn new(hash: impl Into<String>) -> Self {
+        HashRecord {
+            hash: hash.into(),
+            ..HashRecord::default()
+        }
+    }
+
+    /// Parse a file: one line holding the hash, with or without a final
+    /// newline (`\r\n` too), then `key=value` lines. Blanks around the
+    /// hash and blank lines are allowed.
+    pub fn parse(contents: &str) -> Result<Self, RecordError> {
+        let mut lines = contents.lines();
+        let hash = lines.next().map(str::trim).unwrap_or_default();
+        if hash.is_empty() {
+            return Err(RecordError::Empty);
+        }
+        if hash.contains(char::is_whitespace) {
+            return Err(RecordError::TrailingContent);
+        }
+        let mut record = HashRecord::new(hash);
+        for (i, line) in lines.enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=').filter(|(key, _)| valid_key(key)) else {
+                return Err(RecordError::ExtraLine(i + 2));
+            };
+            let value = value.trim();
+            match key {
+                "created_at" => record.created_at = value.parse().ok(),
+                "changed_at" => record.changed_at = value.parse().ok(),
+                "scheme" => record.scheme = (!value.is_empty()).then(|| value.to_string()),
+                "flags" => {
+                    record.flags = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|flag| !flag.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => record.other.push((key.to_string(), value.to_string())),
+            }
+        }
+        Ok(record)
+    }
+
+    /// The file contents.
+    pub fn serialize(&self) -> String {
+        let mut out = format!("{}\n", self.hash);
+        if let Some(ts) = self.created_at {
+            out.push_str(&format!("created_at={ts}\n"));
+        }
+        if let Some(ts) = self.changed_at {
+            out.push_str(&format!("changed_at={ts}\n"));
+        }
+        if let Some(scheme) = &self.scheme {
+            out.push_str(&format!("scheme={scheme}\n"));
+        }
+        if !self.flags.is_empty() {
+            out.push_str(&format!("flags={}\n", self.flags.join(",")));
+        }
+        for (key, value) in &self.other {
+            out.push_str(&format!("{key}={value}\n"));
+        }
+        out
+    }
+
+    /// Whether `flag` is set.
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    /// The per-user settings among the other keys, for
+    /// [`crate::ConfigResolver::with_user_settings`].
+    pub fn settings(&self) -> Vec<(String, String)> {
+        self.other
+            .iter()
+            .filter(|(key, _)| USER_KEYS.contains(&key.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Set the per-user setting `key` (one of [`USER_KEYS`]), or remove it
+    /// with `None`.
+    pub fn set_setting(&mut self, key: &str, value: Option<&str>) {
+        match (self.other.iter_mut().find(|(k, _)| k == key), value) {
+            (Some((_, old)), Some(value)) => *old = value.to_string(),
+            (None, Some(value)) => self.other.push((key.to_string(), value.to_string())),
+            (_, None) => self.other.retain(|(k, _)| k != key),
+        }
+    }
+}
+
+/// Lowercase ASCII letters, digits and `_`, as the keys above.
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_parse() {
+        let ok = |s: &str| HashRecord::parse(s).map(|r| r.hash);
+        assert_eq!(ok("$6$x$y\n"), Ok("$6$x$y".to_string()));
+        assert_eq!(ok("$6$x$y"), Ok("$6$x$y".to_string()));
+        assert_eq!(ok("  $6$x$y\r\n"), Ok("$6$x$y".to_string()));
+        assert_eq!(ok(""), Err(RecordError::Empty));
+        assert_eq!(ok("\n$6$x$y\n"), Err(RecordError::Empty));
+        assert_eq!(ok("$6$x$y junk\n"), Err(RecordError::TrailingContent));
+        assert_eq!(ok("$6$x$y\n$6$x$z\n"), Err(RecordError::ExtraLine(2)));
+        assert_eq!(ok("$6$x$y\n\n"), Ok("$6$x$y".to_string()));
+        assert_eq!(ok("$6$x$y\n\nx\n"), Err(RecordError::ExtraLine(3)));
+        assert_eq!(ok("$6$x$y\nCreated=1\n"), Err(RecordError::ExtraLine(2)));
+        let record = HashRecord::new("$6$x$y");
+        assert_eq!(HashRecord::parse(&record.serialize()), Ok(record));
+    }
+
+    #[test]
+    fn metadata_lines() {
+        let record = HashRecord::parse(
+            "$6$x$y\ncreated_at=100\nchanged_at=soon\nscheme=sha512crypt\n\
+             flags=a, b,\nexpires_at=300\n",
+        )
+        .unwrap();
+        assert_eq!(record.created_at, Some(100));
+        // lenient: an unparsable value is dropped, not an error
+        assert_eq!(record.changed_at, None);
+        assert_eq!(record.scheme.as_deref(), Some("sha512crypt"));
+        assert_eq!(record.flags, ["a", "b"]);
+        assert!(record.has_flag("b"));
+        assert_eq!(record.other, [("expires_at".into(), "300".into())]);
+        assert_eq!(
+            record.serialize(),
+            "$6$x$y\ncreated_at=100\nscheme=sha512crypt\nflags=a,b\nexpires_at=300\n"
+        );
+        assert_eq!(HashRecord::parse(&record.serialize()), Ok(record));
+    }
+
+    #[test]
+    fn per_user_settings() {
+        let mut record = HashRecord::parse(
+            "$6$x$y
+max_fails=3
+expires_at=300
+",
+        )
+        .unwrap();
+        assert_eq!(record.settings(), [("max_fails".into(), "3".into())]);
+        record.set_setting("lockout_secs", Some("900"));
+        record.set_setting("max_fails", Some("10"));
+        assert_eq!(
+            record.serialize(),
+            "$6$x$y\nmax_fails=10\nexpires_at=300\nlockout_secs=900\n"
+        );
+        record.set_setting("max_fails", None);
+        assert_eq!(record.settings(), [("lockout_secs".into(), "900".into())]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Hash records encrypted at rest under a host key (feature `encryption`,
+//! `encryption_key_file`), so a stolen disk or a copied image does not
+//! even give away the PIN hashes to crack offline.
+//!
+//! [`DirStore`](crate::store::DirStore) seals every file holding a hash or
+//! a secret (`.passwd`, `.toml`, `.decoy`, `.emergency`, `.recovery` and
+//! `.hotp`) as it writes them and opens them as it reads them. Plain files
+//! are still read, so records are sealed one by one as they are next
+//! written; once all are, `require_sealed` refuses plain ones, so write
+//! access to the directories is not enough to plant a hash.
+//! A sealed file is one line, AES-256-GCM with the file name as associated
+//! data, so a record cannot be copied over another user's:
+//!
+//! ```text
+//! sealed:1:<12-byte nonce, hex>:<ciphertext and tag, hex>
+//! ```
+//!
+//! The key is 32 bytes, raw or as hex. It should not live on the same disk
+//! unprotected: typically it is a systemd credential sealed to the TPM
+//! (`systemd-creds encrypt --with-key=tpm2`) that a unit decrypts to
+//! `/run` at boot.
+
+use crate::store::SEALED_PREFIX;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::io;
+use std::path::Path;
+
+/// Extensions of the files that are sealed.
+pub const SEALED_EXTS: &[&str] = &["passwd", "toml", "decoy", "emergency", "recovery", "hotp"];
+const NONCE_LEN: usize = 12;
+
+/// The host key, ready to seal and open records.
+#[derive(Clone)]
+pub struct RecordCipher(Aes256Gcm);
+
+impl std::fmt::Debug for RecordCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RecordCipher(..)")
+    }
+}
+
+impl RecordCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        RecordCipher(Aes256Gcm::new(key.into()))
+    }
+
+    /// Load the key at `path`: 32 bytes, or 64 hex digits.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let raw = zeroize::Zeroizing::new(crate::store::read_bytes_nofollow(path)?);
+        let key = match <[u8; 32]>::try_from(raw.as_slice()) {
+            Ok(key) => zeroize::Zeroizing::new(key),
+            Err(_) => std::str::from_utf8(&raw)
+                .ok()
+                .and_then(|s| crate::parse_hex(s.trim()))
+                .and_then(|k| <[u8; 32]>::try_from(k.as_slice()).ok())
+                .map(zeroize::Zeroizing::new)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: not a 32-byte key", path.display()),
+                    )
+                })?,
+        };
+        Ok(Self::new(&key))
+    }
+
+    /// `contents` of the file `name`, sealed.
+    pub fn seal(&self, name: &str, contents: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut nonce);
+        let sealed = self
+            .0
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: contents.as_bytes(),
+                    aad: name.as_bytes(),
+                },
+            )
+            .expect("AES-GCM encryption of an in-memory buffer");
+        format!(
+            "{SEALED_PREFIX}{}:{}\n",
+            crate::hex(&nonce).as_str(),
+            crate::hex(&sealed).as_str()
+        )
+    }
+
+    /// The contents of the sealed file `name`. Fails with
+    /// [`io::ErrorKind::InvalidData`] for another key, a renamed file or
+    /// damage.
+    pub fn open(&self, name: &str, sealed: &str) -> io::Result<String> {
+        let invalid =
+            |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{name}: {msg}"));
+        let (nonce, data) = sealed
+            .trim_end()
+            .strip_prefix(SEALED_PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .and_then(|(nonce, data)| Some((crate::parse_hex(nonce)?, crate::parse_hex(data)?)))
+            .filter(|(nonce, _)| nonce.len() == NONCE_LEN)
+            .ok_or_else(|| invalid("malformed sealed record"))?;
+        let plain = self
+            .0
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &data,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid("cannot decrypt (wrong key, renamed or damaged)"))?;
+        String::from_utf8(plain).map_err(|_| invalid("sealed record is not text"))
+    }
+}
+
+/// Whether the file `name` is sealed when written.
+pub fn is_sealed_name(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, ext)| SEALED_EXTS.contains(&ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{DirStore, PinStore};
+    use std::fs;
+
+    #[test]
+    fn sealed_records_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key_path = tmp.path().join("host.key");
+        fs::write(&key_path, format!("{}\n", "ab".repeat(32))).unwrap();
+        let cipher = RecordCipher::load(&key_path).unwrap();
+        let store = DirStore::new(tmp.path()).with_cipher(cipher.clone());
+
+        store.write_file("alice.passwd", "$6$x$y\n").unwrap();
+        store.write_file("alice.hotp", "0 00\n").unwrap();
+        let raw = fs::read_to_string(tmp.path().join("alice.passwd")).unwrap();
+        assert!(raw.starts_with(SEALED_PREFIX), "{raw}");
+        assert!(!raw.contains("$6$"));
+        assert_eq!(store.read_hash("alice").unwrap(), "$6$x$y");
+        let raw = fs::read_to_string(tmp.path().join("alice.hotp")).unwrap();
+        assert!(raw.starts_with(SEALED_PREFIX), "{raw}");
+        assert_eq!(store.read_file("alice.hotp").unwrap(), "0 00\n");
+
+        // still readable before it is next written
+        fs::write(tmp.path().join("bob.passwd"), "$6$x$z\n").unwrap();
+        assert_eq!(store.read_hash("bob").unwrap(), "$6$x$z");
+
+        // bound to the name, and to the key
+        fs::copy(
+            tmp.path().join("alice.passwd"),
+            tmp.path().join("bob.passwd"),
+        )
+        .unwrap();
+        assert!(store.read_hash("bob").is_err());
+        let other = DirStore::new(tmp.path()).with_cipher(RecordCipher::new(&[1; 32]));
+        assert!(other.read_hash("alice").is_err());
+        let err = DirStore::new(tmp.path()).read_hash("alice").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn require_sealed_refuses_plain_records() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = DirStore::new(tmp.path())
+            .with_cipher(RecordCipher::new(&[7; 32]))
+            .require_sealed();
+        store.write_file("alice.passwd", "$6$x$y\n").unwrap();
+        store.write_file("alice.recovery", "$6$a$b\n").unwrap();
+        assert_eq!(store.read_hash("alice").unwrap(), "$6$x$y");
+        assert_eq!(store.read_file("alice.recovery").unwrap(), "$6$a$b\n");
+
+        // a hash planted in the clear
+        for name in ["alice.passwd", "alice.emergency", "alice.hotp"] {
+            fs::write(tmp.path().join(name), "$6$evil$evil\n").unwrap();
+            let err = store.read_file(name).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{name}");
+        }
+        assert!(store.read_hash("alice").is_err());
+        // state that is not secret stays plain
+        store.write_file("alice.fail", "1 2\n").unwrap();
+        assert_eq!(store.read_file("alice.fail").unwrap(), "1 2\n");
+    }
+
+    #[cfg(feature = "toml-records")]
+    #[test]
+    fn toml_records_are_sealed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = DirStore::new(tmp.path()).with_cipher(RecordCipher::new(&[7; 32]));
+
+        store
+            .write_file(
+                "alice.toml",
+                "hash = \"$6$x$y\"\n\n[policy]\nmax_fails = 3\n",
+            )
+            .unwrap();
+        let raw = fs::read_to_string(tmp.path().join("alice.toml")).unwrap();
+        assert!(raw.starts_with(SEALED_PREFIX), "{raw}");
+        assert!(!raw.contains("$6$") && !raw.contains("max_fails"));
+        assert_eq!(store.read_hash("alice").unwrap(), "$6$x$y");
+        let record = crate::toml_record::TomlRecord::load(&store, "alice")
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.record.hash, "$6$x$y");
+
+        // bound to the name like a .passwd file
+        fs::copy(tmp.path().join("alice.toml"), tmp.path().join("bob.toml")).unwrap();
+        assert!(store.read_hash("bob").is_err());
+    }
+}
@@ -0,0 +1,875 @@
+//! Where PIN records and fail state live.
+//!
+//! [`PinStore`] is what the verification flow needs from storage; [`DirStore`]
+//! is the on-disk layout under `/etc/pin.d`:
+//!
+//! ```text
+//! <dir>/<user>.passwd   hash, then key=value lines (see `record::HashRecord`)
+//! <dir>/<user>.toml     hash, flags and policy instead (see `toml_record`)
+//! <dir>/@<group>.passwd  hash shared by the members of <group>
+//! <dir>/<user>.fail     fail counter / lock (see `fail::FailState`)
+//! <dir>/<user>.decoy    decoy PIN hashes (see `auth::Decoys`)
+//! <dir>/<user>.emergency  single-use emergency PIN (see `auth::Emergency`)
+//! <dir>/<user>.recovery   printed recovery codes (see `auth::RecoveryCodes`)
+//! <dir>/<name>          auxiliary files (lockout-notify.tmpl, pin-blacklist, ...)
+//! ```
+//!
+//! Files that change after provisioning ([`STATE_EXTS`], [`STATE_FILES`])
+//! move to `state_dir` when it is set, so the PIN directory can be
+//! read-only (image-based systems); see [`resolve_store_with`]. With
+//! `encryption_key_file`, hash files are encrypted at rest (`crate::seal`).
+//!
+//! [`MemoryStore`] keeps the same records in memory, for embedding and tests.
+
+use crate::fail::{FailFile, FailStore};
+use crate::record::HashRecord;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+pub trait PinStore {
+    /// The stored hash for `user` (trimmed). `NotFound` if there is none;
+    /// `InvalidData` wrapping a [`crate::record::RecordError`] if the record
+    /// is corrupt.
+    fn read_hash(&self, user: &str) -> io::Result<String>;
+    /// Fail state for `user`, locked against concurrent attempts until dropped.
+    fn open_fail_state(&self, user: &str) -> io::Result<Box<dyn FailStore>>;
+    /// An auxiliary file by name.
+    fn read_file(&self, name: &str) -> io::Result<String>;
+    /// Delete an auxiliary file, e.g. a used emergency PIN. `NotFound` if
+    /// there was none.
+    fn remove_file(&self, name: &str) -> io::Result<()>;
+    /// Replace an auxiliary file as a whole (readers see the old or the new
+    /// contents, never a mix).
+    fn write_file(&self, name: &str, contents: &str) -> io::Result<()>;
+}
+
+/// Extensions of the files kept per user (or `@group`) in a [`DirStore`].
+pub const RECORD_EXTS: &[&str] = &[
+    "passwd",
+    "fail",
+    "decoy",
+    "emergency",
+    "recovery",
+    "hotp",
+    "yubikey",
+    "fido2",
+    "last",
+    "toml",
+];
+
+/// Per-user files that `check_pin` rewrites: fail state, last login, and
+/// the single-use secrets it crosses off.
+pub const STATE_EXTS: &[&str] = &["fail", "last", "emergency", "recovery", "hotp"];
+/// Shared files that grow or are created at run time.
+pub const STATE_FILES: &[&str] = &["audit.log", "history.sqlite", "log.key", "state-gc.stamp"];
+
+/// How a record encrypted at rest starts (`crate::seal`).
+pub const SEALED_PREFIX: &str = "sealed:1:";
+
+fn is_state(name: &str) -> bool {
+    STATE_FILES.contains(&name)
+        || Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| STATE_EXTS.contains(&ext))
+}
+
+/// Files in one directory (state possibly in a second), opened with
+/// `O_NOFOLLOW`.
+#[derive(Clone, Debug)]
+pub struct DirStore {
+    dir: PathBuf,
+    state_dir: PathBuf,
+    #[cfg(feature = "encryption")]
+    cipher: Option<crate::seal::RecordCipher>,
+    #[cfg(feature = "encryption")]
+    require_sealed: bool,
+}
+
+impl DirStore {
+    /// No checks; see [`resolve_store`] for the validated constructor.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        DirStore {
+            state_dir: dir.clone(),
+            dir,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "encryption")]
+            require_sealed: false,
+        }
+    }
+
+    /// Keep state files in `state_dir` instead of the PIN directory.
+    pub fn with_state_dir(self, state_dir: impl Into<PathBuf>) -> Self {
+        DirStore {
+            state_dir: state_dir.into(),
+            ..self
+        }
+    }
+
+    /// Encrypt hash files with `cipher` as they are written.
+    #[cfg(feature = "encryption")]
+    pub fn with_cipher(self, cipher: crate::seal::RecordCipher) -> Self {
+        DirStore {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+
+    /// Refuse plain files where a sealed one belongs, so that whoever can
+    /// write the directory cannot swap in a hash or secret of their own.
+    #[cfg(feature = "encryption")]
+    pub fn require_sealed(self) -> Self {
+        DirStore {
+            require_sealed: true,
+            ..self
+        }
+    }
+
+    /// `contents` as the file `name` is stored: sealed if it is a hash file
+    /// and there is a key, else unchanged. For writers that place the file
+    /// themselves rather than through [`PinStore::write_file`].
+    pub fn seal(&self, name: &str, contents: &str) -> String {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            if crate::seal::is_sealed_name(name) {
+                return cipher.seal(name, contents);
+            }
+        }
+        let _ = name;
+        contents.to_string()
+    }
+
+    fn unseal(&self, name: &str, raw: String) -> io::Result<String> {
+        if !raw.starts_with(SEALED_PREFIX) {
+            #[cfg(feature = "encryption")]
+            if self.require_sealed && crate::seal::is_sealed_name(name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{name} is not encrypted; require_sealed is set"),
+                ));
+            }
+            return Ok(raw);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.open(name, &raw);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{name} is encrypted; encryption_key_file is not set"),
+        ))
+    }
+
+    /// Where the file `name` lives.
+    pub fn path(&self, name: &str) -> PathBuf {
+        if is_state(name) {
+            self.state_dir.join(name)
+        } else {
+            self.dir.join(name)
+        }
+    }
+
+    /// Record keys with a `.<ext>` file, e.g. every user with a PIN for
+    /// `"passwd"`, sorted. Shared `@group` records are included.
+    pub fn names(&self, ext: &str) -> io::Result<Vec<String>> {
+        let dir = if STATE_EXTS.contains(&ext) {
+            &self.state_dir
+        } else {
+            &self.dir
+        };
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(ext) {
+                continue;
+            }
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                names.push(stem.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The same as [`DirStore::dir`] unless `state_dir` is set.
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+}
+
+impl PinStore for DirStore {
+    fn read_hash(&self, user: &str) -> io::Result<String> {
+        #[cfg(feature = "toml-records")]
+        if let Some(record) = crate::toml_record::TomlRecord::load(self, user)? {
+            return Ok(record.record.hash);
+        }
+        let contents = self.read_file(&format!("{user}.passwd"))?;
+        HashRecord::parse(&contents)
+            .map(|record| record.hash)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn open_fail_state(&self, user: &str) -> io::Result<Box<dyn FailStore>> {
+        Ok(Box::new(FailFile::open(
+            self.path(&format!("{user}.fail")),
+        )?))
+    }
+
+    fn read_file(&self, name: &str) -> io::Result<String> {
+        self.unseal(name, read_file_nofollow(&self.path(name))?)
+    }
+
+    fn remove_file(&self, name: &str) -> io::Result<()> {
+        // unlink never follows a symlink
+        fs::remove_file(self.path(name))
+    }
+
+    fn write_file(&self, name: &str, contents: &str) -> io::Result<()> {
+        write_atomic(
+            &self.path(name),
+            self.seal(name, contents).as_bytes(),
+            0o600,
+        )
+    }
+}
+
+/// Records held in memory; nothing touches the filesystem.
+///
+/// Fail state and auxiliary files are shared between clones and, as with [`DirStore`], a user's
+/// fail state can be open only once at a time: `open_fail_state` blocks until
+/// the previous handle is dropped.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    hashes: HashMap<String, String>,
+    files: Arc<Mutex<HashMap<String, String>>>,
+    fail: Arc<FailTable>,
+}
+
+#[derive(Debug, Default)]
+struct FailTable {
+    inner: Mutex<FailTableInner>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct FailTableInner {
+    states: HashMap<String, String>,
+    open: HashSet<String>,
+}
+
+impl FailTable {
+    fn lock(&self) -> MutexGuard<'_, FailTableInner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `user`'s hash, as `genpin` would write it.
+    pub fn with_hash(mut self, user: &str, hash: impl Into<String>) -> Self {
+        self.set_hash(user, hash);
+        self
+    }
+
+    /// Add an auxiliary file such as `lockout-notify.tmpl`.
+    pub fn with_file(self, name: &str, contents: impl Into<String>) -> Self {
+        self.files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_string(), contents.into());
+        self
+    }
+
+    pub fn set_hash(&mut self, user: &str, hash: impl Into<String>) {
+        self.hashes.insert(user.to_string(), hash.into());
+    }
+
+    pub fn remove_hash(&mut self, user: &str) -> Option<String> {
+        self.hashes.remove(user)
+    }
+
+    /// The serialized fail state for `user` (see `fail::FailState`), if any.
+    pub fn fail_state(&self, user: &str) -> Option<String> {
+        self.fail.lock().states.get(user).cloned()
+    }
+}
+
+impl PinStore for MemoryStore {
+    fn read_hash(&self, user: &str) -> io::Result<String> {
+        self.hashes
+            .get(user)
+            .map(|h| h.trim().to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no PIN for {user}")))
+    }
+
+    fn open_fail_state(&self, user: &str) -> io::Result<Box<dyn FailStore>> {
+        let mut table = self.fail.lock();
+        while table.open.contains(user) {
+            table = self
+                .fail
+                .released
+                .wait(table)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        table.open.insert(user.to_string());
+        Ok(Box::new(MemoryFailHandle {
+            user: user.to_string(),
+            table: Arc::clone(&self.fail),
+        }))
+    }
+
+    fn read_file(&self, name: &str) -> io::Result<String> {
+        self.files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, name.to_string()))
+    }
+
+    fn remove_file(&self, name: &str) -> io::Result<()> {
+        self.files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+            .map(drop)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, name.to_string()))
+    }
+
+    fn write_file(&self, name: &str, contents: &str) -> io::Result<()> {
+        self.files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_string(), contents.to_string());
+        Ok(())
+    }
+}
+
+/// One user's fail state in a [`MemoryStore`], held open until dropped.
+struct MemoryFailHandle {
+    user: String,
+    table: Arc<FailTable>,
+}
+
+impl FailStore for MemoryFailHandle {
+    fn load(&mut self) -> io::Result<String> {
+        Ok(self
+            .table
+            .lock()
+            .states
+            .get(&self.user)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn save(&mut self, serialized: &str) -> io::Result<()> {
+        let mut table = self.table.lock();
+        if serialized.is_empty() {
+            table.states.remove(&self.user);
+        } else {
+            table
+                .states
+                .insert(self.user.clone(), serialized.to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MemoryFailHandle {
+    fn drop(&mut self) {
+        self.table.lock().open.remove(&self.user);
+        self.table.released.notify_all();
+    }
+}
+
+/// Prefix of a group-owned record's key: `@operators.passwd`. Usernames may
+/// not start with it.
+pub const GROUP_PREFIX: char = '@';
+
+/// The first of `groups` (by name) with a shared record, and its hash. Used
+/// for a user without a record of their own; fail state stays per user.
+pub fn read_group_hash(store: &dyn PinStore, groups: &[String]) -> Option<(String, String)> {
+    let mut groups: Vec<&String> = groups.iter().collect();
+    groups.sort();
+    groups.into_iter().find_map(|group| {
+        store
+            .read_hash(&format!("{GROUP_PREFIX}{group}"))
+            .ok()
+            .map(|hash| (group.clone(), hash))
+    })
+}
+
+/// Validate `input` as the PIN directory and return a store over it.
+///
+/// When running as root the path must be absolute and not itself a symlink,
+/// and every directory from `/` down to it (after resolving symlinks in the
+/// parents) must be owned by root and not group/world writable. Sticky
+/// parents such as `/tmp` are tolerated; the PIN directory itself never is.
+/// Non-root (test) runs skip the checks.
+pub fn resolve_store(input: &str) -> io::Result<DirStore> {
+    let path = Path::new(input);
+    if nix::unistd::geteuid().is_root() {
+        if !path.is_absolute() {
+            return Err(denied(path, "PIN_DIR must be absolute under root"));
+        }
+        let md = fs::symlink_metadata(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("stat {path:?}: {e}")))?;
+        if md.file_type().is_symlink() {
+            return Err(denied(path, "PIN_DIR may not be a symlink"));
+        }
+        check_component(path, &md, true)?;
+        for parent in fs::canonicalize(path)?.ancestors().skip(1) {
+            check_component(parent, &fs::metadata(parent)?, false)?;
+        }
+    }
+    Ok(DirStore::new(path))
+}
+
+/// [`resolve_store`], with state in `state_dir` if the configuration (or
+/// the build, [`crate::paths::STATE_DIR`]) sets it. A missing state
+/// directory is created (0700, not its parents); then it is checked like
+/// the PIN directory. Hash files are sealed with the key in
+/// `encryption_key_file` if that is set, and with `require_sealed` plain
+/// ones are refused.
+pub fn resolve_store_with(input: &str, config: &crate::ConfigResolver) -> io::Result<DirStore> {
+    #[allow(unused_mut)]
+    let mut store = resolve_store(input)?;
+    #[cfg(feature = "encryption")]
+    if let Some(path) = config.string("encryption_key_file") {
+        let cipher = crate::seal::RecordCipher::load(Path::new(path))
+            .map_err(|e| io::Error::new(e.kind(), format!("encryption_key_file: {e}")))?;
+        store = store.with_cipher(cipher);
+    }
+    if config.flag("require_sealed") == Some(true) {
+        #[cfg(feature = "encryption")]
+        if store.cipher.is_some() {
+            store = store.require_sealed();
+        }
+        #[cfg(feature = "encryption")]
+        let keyed = store.require_sealed;
+        #[cfg(not(feature = "encryption"))]
+        let keyed = false;
+        if !keyed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "require_sealed is set but encryption_key_file is not",
+            ));
+        }
+    }
+    let Some(state_dir) = config
+        .string("state_dir")
+        .or_else(|| crate::paths::default_state_dir(input))
+    else {
+        return Ok(store);
+    };
+    match fs::DirBuilder::new().mode(0o700).create(state_dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("creating {state_dir}: {e}"),
+            ))
+        }
+    }
+    Ok(store.with_state_dir(resolve_store(state_dir)?.dir))
+}
+
+fn denied(path: &Path, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{}: {msg}", path.display()),
+    )
+}
+
+fn check_component(path: &Path, md: &fs::Metadata, leaf: bool) -> io::Result<()> {
+    if !md.is_dir() {
+        return Err(denied(path, "not a directory"));
+    }
+    if md.uid() != 0 {
+        return Err(denied(path, "must be owned by root"));
+    }
+    let mode = md.mode();
+    let sticky = mode & 0o1000 != 0;
+    // group/world write bits
+    if mode & 0o022 != 0 && (leaf || !sticky) {
+        return Err(denied(path, "must not be group/world writable"));
+    }
+    Ok(())
+}
+
+/// Replace `path` with a file holding `contents`, with permissions `mode`
+/// whatever the umask, so that readers find the old file or the whole new
+/// one, never a part.
+///
+/// On Linux the data goes into an unnamed `O_TMPFILE` inode, which is
+/// linked in as `<path>.tmp` only once written and synced. Where the file
+/// system or kernel lacks `O_TMPFILE`, `<path>.tmp` is created and written
+/// in place. Either way it is then renamed over `path`.
+pub fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    #[cfg(target_os = "linux")]
+    if let Some(mut f) = unnamed_file(path, mode)? {
+        f.set_permissions(fs::Permissions::from_mode(mode))?;
+        f.write_all(contents)?;
+        f.sync_all()?;
+        if link_unnamed(&f, &tmp).is_ok() {
+            return fs::rename(&tmp, path);
+        }
+    }
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+        .open(&tmp)?;
+    // an old `<path>.tmp` keeps its mode
+    f.set_permissions(fs::Permissions::from_mode(mode))?;
+    f.write_all(contents)?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// An `O_TMPFILE` in `path`'s directory; `None` if that is not supported.
+#[cfg(target_os = "linux")]
+fn unnamed_file(path: &Path, mode: u32) -> io::Result<Option<fs::File>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let opened = OpenOptions::new()
+        .write(true)
+        .mode(mode)
+        .custom_flags(nix::libc::O_TMPFILE | nix::libc::O_CLOEXEC)
+        .open(dir);
+    match opened {
+        Ok(f) => Ok(Some(f)),
+        // EISDIR: a kernel that predates O_TMPFILE and saw only O_DIRECTORY
+        Err(e) => match e.raw_os_error() {
+            Some(nix::libc::EOPNOTSUPP | nix::libc::EISDIR | nix::libc::EINVAL) => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Give unnamed file `f` the name `tmp`, replacing a leftover one.
+#[cfg(target_os = "linux")]
+fn link_unnamed(f: &fs::File, tmp: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+
+    let to_c = |p: &Path| {
+        CString::new(p.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in path"))
+    };
+    let target = to_c(tmp)?;
+    // AT_EMPTY_PATH needs CAP_DAC_READ_SEARCH before Linux 6.10; the
+    // /proc link works for anyone, if /proc is mounted.
+    let by_proc = to_c(Path::new(&format!("/proc/self/fd/{}", f.as_raw_fd())))?;
+    let link = || {
+        let linked = unsafe {
+            nix::libc::linkat(
+                f.as_raw_fd(),
+                c"".as_ptr(),
+                nix::libc::AT_FDCWD,
+                target.as_ptr(),
+                nix::libc::AT_EMPTY_PATH,
+            ) == 0
+                || nix::libc::linkat(
+                    nix::libc::AT_FDCWD,
+                    by_proc.as_ptr(),
+                    nix::libc::AT_FDCWD,
+                    target.as_ptr(),
+                    nix::libc::AT_SYMLINK_FOLLOW,
+                ) == 0
+        };
+        if linked {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    };
+    match link() {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            fs::remove_file(tmp)?;
+            link()
+        }
+        linked => linked,
+    }
+}
+
+pub fn read_file_nofollow(path: &Path) -> io::Result<String> {
+    let mut f = OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+        .open(path)?;
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    Ok(buf)
+}
+
+pub fn read_bytes_nofollow(path: &Path) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+        .open(path)?
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Load a 32-byte key kept as hex at `path`, creating a random one (mode
+/// 0600) if there is none. Returns whether it was created.
+pub fn load_or_create_key_file(path: &Path) -> io::Result<(zeroize::Zeroizing<[u8; 32]>, bool)> {
+    let open = |create: bool| {
+        OpenOptions::new()
+            .read(true)
+            .write(create)
+            .create_new(create)
+            .mode(0o600)
+            .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_CLOEXEC)
+            .open(path)
+    };
+    match open(true) {
+        Ok(mut f) => {
+            let mut key = zeroize::Zeroizing::new([0u8; 32]);
+            rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, key.as_mut());
+            writeln!(f, "{}", crate::hex(key.as_ref()).as_str())?;
+            f.sync_all()?;
+            Ok((key, true))
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let mut raw = zeroize::Zeroizing::new(String::new());
+            open(false)?.read_to_string(&mut raw)?;
+            let key = crate::parse_hex(raw.trim())
+                .and_then(|k| <[u8; 32]>::try_from(k.as_slice()).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is not 64 hex digits", path.display()),
+                    )
+                })?;
+            Ok((zeroize::Zeroizing::new(key), false))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_store_layout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = resolve_store(tmp.path().to_str().unwrap()).unwrap();
+        assert_eq!(
+            store.read_hash("alice").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        fs::write(tmp.path().join("alice.passwd"), "$6$x$y\n").unwrap();
+        assert_eq!(store.read_hash("alice").unwrap(), "$6$x$y");
+
+        let mut fail = store.open_fail_state("alice").unwrap();
+        fail.save("1:100\n").unwrap();
+        drop(fail);
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("alice.fail")).unwrap(),
+            "1:100\n"
+        );
+
+        std::os::unix::fs::symlink("/etc/hostname", tmp.path().join("link")).unwrap();
+        assert!(store.read_file("link").is_err());
+        assert_eq!(store.names("passwd").unwrap(), ["alice"]);
+        store.remove_file("link").unwrap();
+        store.write_file("alice.recovery", "used 1\n").unwrap();
+        assert_eq!(store.read_file("alice.recovery").unwrap(), "used 1\n");
+        assert_eq!(
+            store.remove_file("link").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn atomic_writes_replace_whole_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("alice.passwd");
+        write_atomic(&path, b"$6$old\n", 0o600).unwrap();
+        // left behind by an interrupted write
+        fs::write(tmp.path().join("alice.passwd.tmp"), "$6$half").unwrap();
+        write_atomic(&path, b"$6$new\n", 0o644).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "$6$new\n");
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o644);
+        let names: Vec<_> = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["alice.passwd"]);
+    }
+
+    #[test]
+    fn state_dir_layout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (etc, var) = (tmp.path().join("etc"), tmp.path().join("var"));
+        fs::create_dir(&etc).unwrap();
+        fs::write(
+            etc.join("pin-auth.conf"),
+            format!("state_dir = {}\n", var.display()),
+        )
+        .unwrap();
+        let config = crate::ConfigResolver::load_dir(etc.to_str().unwrap()).unwrap();
+        let store = resolve_store_with(etc.to_str().unwrap(), &config).unwrap();
+        assert!(var.is_dir(), "state dir not created");
+        fs::write(etc.join("alice.passwd"), "$6$x$y\n").unwrap();
+        assert_eq!(store.read_hash("alice").unwrap(), "$6$x$y");
+        store
+            .open_fail_state("alice")
+            .unwrap()
+            .save("1:100\n")
+            .unwrap();
+        store.write_file("alice.recovery", "used 1\n").unwrap();
+        assert!(var.join("alice.fail").exists() && var.join("alice.recovery").exists());
+        assert_eq!(store.names("fail").unwrap(), ["alice"]);
+        assert_eq!(store.names("passwd").unwrap(), ["alice"]);
+        assert_eq!(store.path("audit.log"), var.join("audit.log"));
+        assert_eq!(store.path("pin-blacklist"), etc.join("pin-blacklist"));
+        let mut files: Vec<_> = fs::read_dir(&etc)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["alice.passwd", "pin-auth.conf"]);
+    }
+
+    #[test]
+    fn memory_store_auth_flow() {
+        use crate::auth::{evaluate_attempt, load_fail_state, Outcome};
+        use crate::fail::LockoutPolicy;
+        use crate::policy::PolicyValidator;
+        use crate::{hash_pin_with, HashParams, Pin, Scheme};
+
+        let validator = PolicyValidator::default();
+        let pin = Pin::new("2468", &validator).unwrap();
+        let Some(hash) = [Scheme::Argon2id, Scheme::Sha512Crypt]
+            .into_iter()
+            .find_map(|scheme| hash_pin_with(&pin, scheme, &HashParams::default()).ok())
+        else {
+            return; // no backend compiled in
+        };
+        let store = MemoryStore::new()
+            .with_hash("alice", format!("{hash}\n"))
+            .with_file("lockout-notify.tmpl", "To: {to}\n");
+        assert_eq!(store.read_hash("alice").unwrap(), hash);
+        assert_eq!(
+            store.read_hash("bob").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(store.read_file("lockout-notify.tmpl").is_ok());
+
+        let policy = LockoutPolicy {
+            max_fails: 2,
+            ..LockoutPolicy::default()
+        };
+        let attempt = |candidate: &str| {
+            let stored = store.read_hash("alice").unwrap();
+            let mut tracker = load_fail_state(&store, "alice", policy, Default::default()).unwrap();
+            evaluate_attempt(&mut tracker, &validator, candidate.into(), &stored)
+        };
+        assert!(matches!(attempt("1111"), Outcome::Failure { count: 1, .. }));
+        assert!(store.fail_state("alice").unwrap().starts_with("1:"));
+        assert_eq!(attempt("2468"), Outcome::Success);
+        assert_eq!(store.fail_state("alice"), None);
+        attempt("1111");
+        assert!(matches!(
+            attempt("1111"),
+            Outcome::Failure { locked: true, .. }
+        ));
+        // a clone shares the fail state
+        let clone = store.clone();
+        let mut tracker = load_fail_state(&clone, "alice", policy, Default::default()).unwrap();
+        assert!(matches!(
+            evaluate_attempt(&mut tracker, &validator, "2468".into(), &hash),
+            Outcome::Locked { .. }
+        ));
+    }
+
+    #[test]
+    fn group_records() {
+        let store = MemoryStore::new()
+            .with_hash("@operators", "$6$ops\n")
+            .with_hash("@lab", "$6$lab\n");
+        let groups = |names: &[&str]| names.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            read_group_hash(&store, &groups(&["wheel", "operators", "lab"])),
+            Some(("lab".to_string(), "$6$lab".to_string()))
+        );
+        assert_eq!(
+            read_group_hash(&store, &groups(&["operators"])).map(|(g, _)| g),
+            Some("operators".to_string())
+        );
+        assert_eq!(read_group_hash(&store, &groups(&["wheel"])), None);
+        assert!(crate::policy::UsernamePolicy::default()
+            .validate("@operators")
+            .is_err());
+    }
+
+    #[test]
+    fn memory_fail_state_is_exclusive() {
+        let store = MemoryStore::new();
+        let first = store.open_fail_state("alice").unwrap();
+        let other = store.clone();
+        let waiter = std::thread::spawn(move || {
+            let mut fail = other.open_fail_state("alice").unwrap();
+            fail.load().unwrap()
+        });
+        let mut first = first;
+        first.save("1:100\n").unwrap();
+        drop(first);
+        assert_eq!(waiter.join().unwrap(), "1:100\n");
+    }
+
+    #[test]
+    fn insecure_components_rejected() {
+        use std::os::unix::fs::PermissionsExt;
+        if !nix::unistd::geteuid().is_root() {
+            return; // checks only apply to root
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = tmp.path().join("parent");
+        let dir = parent.join("pin.d");
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(resolve_store(dir.to_str().unwrap()).is_ok());
+
+        fs::set_permissions(&parent, fs::Permissions::from_mode(0o777)).unwrap();
+        let err = resolve_store(dir.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("parent: must not be"), "{err}");
+        // sticky parent is fine
+        fs::set_permissions(&parent, fs::Permissions::from_mode(0o1777)).unwrap();
+        assert!(resolve_store(dir.to_str().unwrap()).is_ok());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o1777)).unwrap();
+        assert!(resolve_store(dir.to_str().unwrap()).is_err());
+
+        let link = tmp.path().join("link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        assert!(resolve_store(link.to_str().unwrap()).is_err());
+    }
+}
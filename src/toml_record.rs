@@ -0,0 +1,155 @@
+//! `<user>.toml` records (feature `toml-records`): the hash, flags and
+//! per-user policy in one declarative file, for PIN directories managed by
+//! configuration management rather than `genpin`.
+//!
+//! ```toml
+//! hash = "$argon2id$v=19$m=19456,t=2,p=1$..."
+//! changed_at = 1710000000
+//! flags = ["kiosk"]
+//!
+//! [policy]
+//! max_fails = 3
+//! lockout_secs = 900
+//! ```
+//!
+//! [`DirStore`] reads the hash from here when the key has no `.passwd`
+//! file; having both is a corrupt record. `[policy]` takes the keys in
+//! [`USER_KEYS`], which `check_pin` applies over `pin-auth.conf` for that
+//! user. Other top-level keys are ignored, as unknown `key=value` lines
+//! are in `.passwd` files.
+//!
+//! [`USER_KEYS`]: crate::config::USER_KEYS
+
+use crate::record::{HashRecord, RecordError};
+use crate::store::{DirStore, PinStore};
+use std::io;
+use toml::{Table, Value};
+
+/// A parsed `<user>.toml` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TomlRecord {
+    /// The hash and metadata, as a `.passwd` file would hold them.
+    pub record: HashRecord,
+    /// `[policy]` settings as config values (`true` is `1`).
+    pub policy: Vec<(String, String)>,
+}
+
+impl TomlRecord {
+    pub fn file_name(key: &str) -> String {
+        format!("{key}.toml")
+    }
+
+    pub fn parse(text: &str) -> Result<Self, RecordError> {
+        let corrupt = |msg: String| RecordError::Toml(msg);
+        let table: Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| corrupt(e.message().to_string()))?;
+        let hash = table
+            .get("hash")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|hash| !hash.is_empty() && !hash.contains(char::is_whitespace))
+            .ok_or_else(|| corrupt("hash must be a non-empty string".to_string()))?;
+        let time = |key: &str| {
+            table
+                .get(key)
+                .and_then(Value::as_integer)
+                .and_then(|ts| u64::try_from(ts).ok())
+        };
+        let mut record = HashRecord::new(hash);
+        record.created_at = time("created_at");
+        record.changed_at = time("changed_at");
+        record.flags = table
+            .get("flags")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|flag| Some(flag.as_str()?.to_string()))
+            .collect();
+        let mut policy = Vec::new();
+        if let Some(settings) = table.get("policy") {
+            let settings = settings
+                .as_table()
+                .ok_or_else(|| corrupt("policy must be a table".to_string()))?;
+            for (name, value) in settings {
+                let value = match value {
+                    Value::Integer(n) => n.to_string(),
+                    Value::Boolean(b) => u8::from(*b).to_string(),
+                    Value::String(s) => s.clone(),
+                    _ => return Err(corrupt(format!("policy.{name}: not a plain value"))),
+                };
+                policy.push((name.clone(), value));
+            }
+        }
+        Ok(TomlRecord { record, policy })
+    }
+
+    /// `key`'s TOML record, if it has one. Fails with
+    /// [`io::ErrorKind::InvalidData`] wrapping a [`RecordError`] if it does
+    /// not parse or `key` also has a `.passwd` file.
+    pub fn load(store: &DirStore, key: &str) -> io::Result<Option<Self>> {
+        let text = match store.read_file(&Self::file_name(key)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let corrupt = |e: RecordError| io::Error::new(io::ErrorKind::InvalidData, e);
+        if store
+            .path(&format!("{key}.passwd"))
+            .symlink_metadata()
+            .is_ok()
+        {
+            return Err(corrupt(RecordError::Toml(format!(
+                "both {key}.passwd and {key}.toml"
+            ))));
+        }
+        Self::parse(&text).map(Some).map_err(corrupt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigResolver;
+    use std::fs;
+
+    #[test]
+    fn toml_records() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = DirStore::new(tmp.path());
+        fs::write(
+            tmp.path().join("alice.toml"),
+            "hash = \"$6$x$y\"\nchanged_at = 100\nflags = [\"kiosk\"]\nfuture = 1\n\n\
+             [policy]\nmax_fails = 3\ndecoy_lock = true\n",
+        )
+        .unwrap();
+        assert_eq!(store.read_hash("alice").unwrap(), "$6$x$y");
+        let record = TomlRecord::load(&store, "alice").unwrap().unwrap();
+        assert_eq!(record.record.changed_at, Some(100));
+        assert!(record.record.has_flag("kiosk"));
+        let config = ConfigResolver::from_vars([("PIN_MAX_FAILS", "5")])
+            .with_user_settings(record.policy, "alice.toml")
+            .unwrap();
+        assert_eq!(config.parse::<u32>("max_fails"), Some(3));
+        assert_eq!(config.flag("decoy_lock"), Some(true));
+        assert!(TomlRecord::load(&store, "bob").unwrap().is_none());
+
+        let refused = |policy: &str| {
+            ConfigResolver::default()
+                .with_user_settings(TomlRecord::parse(policy).unwrap().policy, "bob.toml")
+                .is_err()
+        };
+        assert!(refused("hash = \"x\"\n[policy]\nstate_dir = \"/tmp\"\n"));
+        assert!(refused("hash = \"x\"\n[policy]\nmax_fails = -1\n"));
+
+        for bad in ["hash = 1\n", "hash = \"$6$x$y\" junk\n", "hash = \"a b\"\n"] {
+            assert!(matches!(TomlRecord::parse(bad), Err(RecordError::Toml(_))));
+        }
+        fs::write(tmp.path().join("alice.passwd"), "$6$x$z\n").unwrap();
+        let err = store.read_hash("alice").unwrap_err();
+        assert!(
+            err.get_ref().is_some_and(|e| e.is::<RecordError>()),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Argon2 cost calibration against a verification time budget (feature
+//! `argon2`). `genpin` uses it when `argon2_target_ms` is set; the chosen
+//! costs end up in the PHC string, so `check_pin` needs no configuration.
+
+use crate::{
+    hash_pin_with, host, Argon2Costs, ConfigResolver, HashParams, Pin, PinHashError, Scheme,
+};
+use std::time::{Duration, Instant};
+
+/// Starting point and floor: the argon2 crate default (OWASP minimum).
+/// `p_cost` is replaced by [`host::argon2_lanes`] when calibrating.
+pub const TUNE_BASE: Argon2Costs = crate::ARGON2_DEFAULT_COSTS;
+
+/// Upper bound on tuned memory (KiB): 1 GiB.
+pub const TUNE_MAX_M_COST: u32 = 1 << 20;
+
+/// [`target_from_config`] over the environment alone.
+pub fn target_from_env() -> Option<Duration> {
+    target_from_config(&ConfigResolver::from_env())
+}
+
+/// `argon2_target_ms`: verification budget in milliseconds, if set and > 0.
+pub fn target_from_config(config: &ConfigResolver) -> Option<Duration> {
+    config
+        .parse::<u64>("argon2_target_ms")
+        .filter(|&ms| ms > 0)
+        .map(Duration::from_millis)
+}
+
+/// Memory cost that should take about `budget`, given that `m_cost` took
+/// `elapsed`. Argon2 time is close to linear in memory at fixed `t`/`p`.
+/// Rounded down to whole MiB and kept within [`TUNE_BASE`, `TUNE_MAX_M_COST`].
+pub fn scaled_m_cost(m_cost: u32, elapsed: Duration, budget: Duration) -> u32 {
+    let elapsed = elapsed.as_nanos().max(1);
+    let scaled = u128::from(m_cost) * budget.as_nanos() / elapsed;
+    let scaled = u32::try_from(scaled).unwrap_or(u32::MAX) / 1024 * 1024;
+    scaled.clamp(TUNE_BASE.m_cost, TUNE_MAX_M_COST)
+}
+
+fn time_hash(costs: Argon2Costs) -> Result<Duration, PinHashError> {
+    let params = HashParams {
+        argon2: Some(costs),
+        ..HashParams::default()
+    };
+    let pin = Pin::unchecked("0000");
+    let start = Instant::now();
+    hash_pin_with(&pin, Scheme::Argon2id, &params)?;
+    Ok(start.elapsed())
+}
+
+/// Benchmark Argon2id on this host and return costs whose hash (and so
+/// verification) takes about `budget`. Only `m_cost` is scaled; if even
+/// [`TUNE_BASE`] exceeds the budget, its costs are returned.
+pub fn calibrate_argon2(budget: Duration) -> Result<Argon2Costs, PinHashError> {
+    let mut start = Argon2Costs {
+        p_cost: host::argon2_lanes(),
+        ..TUNE_BASE
+    };
+    // Never probe, or settle on, more memory than the cgroup allows.
+    let headroom = host::memory_headroom();
+    let cap = |costs: &mut Argon2Costs| {
+        if let Some(h) = headroom {
+            costs.m_cost = costs.m_cost.min(host::max_m_cost(h, costs.p_cost));
+        }
+    };
+    cap(&mut start);
+    let base = time_hash(start)?;
+    if base >= budget {
+        return Ok(start);
+    }
+    let mut costs = Argon2Costs {
+        m_cost: scaled_m_cost(start.m_cost, base, budget),
+        ..start
+    };
+    cap(&mut costs);
+    // One correction pass: cache effects make large sizes slower than linear.
+    let measured = time_hash(costs)?;
+    if measured > budget + budget / 10 {
+        costs.m_cost = scaled_m_cost(costs.m_cost, measured, budget);
+    }
+    cap(&mut costs);
+    tracing::debug!(?costs, ?base, ?measured, "calibrated argon2");
+    Ok(costs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        let ms = Duration::from_millis;
+        // twice the time available: twice the memory, whole MiB
+        assert_eq!(scaled_m_cost(65_536, ms(100), ms(200)), 131_072);
+        assert_eq!(scaled_m_cost(20_000, ms(100), ms(250)), 49_152);
+        // clamps
+        assert_eq!(scaled_m_cost(65_536, ms(100), ms(10)), TUNE_BASE.m_cost);
+        assert_eq!(scaled_m_cost(65_536, ms(1), ms(100_000)), TUNE_MAX_M_COST);
+        assert_eq!(
+            scaled_m_cost(u32::MAX, Duration::ZERO, ms(1)),
+            TUNE_MAX_M_COST
+        );
+
+        let tiny = calibrate_argon2(Duration::from_nanos(1)).unwrap();
+        assert_eq!(
+            (tiny.m_cost, tiny.t_cost),
+            (TUNE_BASE.m_cost, TUNE_BASE.t_cost)
+        );
+    }
+}
@@ -0,0 +1,483 @@
+//! YubiKey HMAC-SHA1 challenge-response as an alternative to the PIN
+//! (feature `yubikey`), for keys programmed the ykpers way:
+//!
+//! ```text
+//! ykpersonalize -2 -ochal-resp -ochal-hmac -ohmac-lt64 -ochal-btn-trig -a <40 hex digits>
+//! ```
+//!
+//! `<user>.yubikey` holds `<slot> <hex secret>`. `check_pin` sends a fresh
+//! random challenge to each attached YubiKey (Linux hidraw, Yubico vendor
+//! ID) and compares the response with the HMAC it computes from the stored
+//! secret. Keys programmed with or without `-ohmac-lt64` both work. The
+//! transport is the keyboard interface's 8-byte feature reports, framed as
+//! in ykpers' `ykcore`.
+//!
+//! Whoever can start a PAM conversation can make `check_pin` ask the keys
+//! attached to the host, including over `sshd`; see [`session_refusal`]
+//! for when it does.
+
+use crate::pam::PamContext;
+use crate::store::PinStore;
+use crate::ConfigResolver;
+use hmac::{Hmac, Mac};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Yubico's USB vendor ID.
+pub const VENDOR_YUBICO: u32 = 0x1050;
+/// HMAC-SHA1 secret length a YubiKey slot takes.
+pub const SECRET_LEN: usize = 20;
+/// Challenge bytes `check_pin` sends (padded to 64 on the wire).
+pub const CHALLENGE_LEN: usize = 32;
+/// How long to wait for a key configured to require a touch.
+pub const TOUCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+const SLOT_CHAL_HMAC1: u8 = 0x30;
+const SLOT_CHAL_HMAC2: u8 = 0x38;
+const SLOT_WRITE_FLAG: u8 = 0x80;
+const RESP_PENDING_FLAG: u8 = 0x40;
+const RESP_TIMEOUT_WAIT_FLAG: u8 = 0x20;
+const DUMMY_REPORT_WRITE: u8 = 0x8f;
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1150);
+const CRC_OK_RESIDUAL: u16 = 0xf0b8;
+
+/// The 8-byte feature reports of a YubiKey's OTP interface.
+pub trait FeatureReports {
+    fn get_report(&mut self) -> io::Result<[u8; 8]>;
+    fn set_report(&mut self, report: &[u8; 8]) -> io::Result<()>;
+}
+
+/// A `/dev/hidrawN` device.
+pub struct Hidraw(File);
+
+impl Hidraw {
+    pub fn open(path: &std::path::Path) -> io::Result<Self> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_CLOEXEC)
+            .open(path)
+            .map(Hidraw)
+    }
+
+    fn feature_ioctl(&self, nr: u64, buf: &mut [u8; 9]) -> io::Result<()> {
+        // _IOC(_IOC_READ | _IOC_WRITE, 'H', nr, len): HIDIOCSFEATURE / HIDIOCGFEATURE
+        let request = (3 << 30) | ((buf.len() as u64) << 16) | (u64::from(b'H') << 8) | nr;
+        if unsafe { nix::libc::ioctl(self.0.as_raw_fd(), request as _, buf.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl FeatureReports for Hidraw {
+    fn get_report(&mut self) -> io::Result<[u8; 8]> {
+        // report ID 0 first
+        let mut buf = [0u8; 9];
+        self.feature_ioctl(0x07, &mut buf)?;
+        let mut report = [0u8; 8];
+        report.copy_from_slice(&buf[1..]);
+        Ok(report)
+    }
+
+    fn set_report(&mut self, report: &[u8; 8]) -> io::Result<()> {
+        let mut buf = [0u8; 9];
+        buf[1..].copy_from_slice(report);
+        self.feature_ioctl(0x06, &mut buf)
+    }
+}
+
+/// hidraw devices with Yubico's vendor ID, from sysfs.
+pub fn find_keys() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir("/sys/class/hidraw") else {
+        return Vec::new();
+    };
+    let mut keys: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            fs::read_to_string(entry.path().join("device/uevent"))
+                .is_ok_and(|uevent| uevent_vendor(&uevent) == Some(VENDOR_YUBICO))
+        })
+        .map(|entry| PathBuf::from("/dev").join(entry.file_name()))
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Vendor ID from a HID `uevent` (`HID_ID=<bus>:<vendor>:<product>`).
+pub fn uevent_vendor(uevent: &str) -> Option<u32> {
+    let id = uevent.lines().find_map(|l| l.strip_prefix("HID_ID="))?;
+    let vendor = id.split(':').nth(1)?;
+    u32::from_str_radix(vendor, 16).ok()
+}
+
+/// CRC-16 as ykpers computes it (reflected 0x8408, initial 0xffff).
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |mut crc, &b| {
+        crc ^= u16::from(b);
+        for _ in 0..8 {
+            let carry = crc & 1;
+            crc >>= 1;
+            if carry != 0 {
+                crc ^= 0x8408;
+            }
+        }
+        crc
+    })
+}
+
+/// Poll until the status byte has all of `mask` set (`set`) or clear.
+fn wait_status(
+    dev: &mut dyn FeatureReports,
+    mask: u8,
+    set: bool,
+    timeout: Duration,
+) -> io::Result<[u8; 8]> {
+    let start = Instant::now();
+    let mut timeout = timeout;
+    let mut pause = Duration::from_millis(1);
+    loop {
+        let report = dev.get_report()?;
+        let status = report[7];
+        if (status & mask == mask) == set {
+            return Ok(report);
+        }
+        if set && status & RESP_TIMEOUT_WAIT_FLAG != 0 && timeout < TOUCH_TIMEOUT {
+            tracing::info!("touch the YubiKey");
+            timeout = TOUCH_TIMEOUT;
+        }
+        if start.elapsed() >= timeout {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "YubiKey timed out"));
+        }
+        thread::sleep(pause);
+        pause = (pause * 2).min(Duration::from_millis(250));
+    }
+}
+
+/// Send `challenge` to HMAC slot `slot` (1 or 2) and return the 20-byte
+/// response.
+pub fn challenge_response(
+    dev: &mut dyn FeatureReports,
+    slot: u8,
+    challenge: &[u8; 64],
+) -> io::Result<[u8; 20]> {
+    let command = match slot {
+        1 => SLOT_CHAL_HMAC1,
+        2 => SLOT_CHAL_HMAC2,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "slot must be 1 or 2",
+            ))
+        }
+    };
+    // payload, slot command, CRC (LE), 3 filler bytes: ten reports of 7
+    let mut frame = [0u8; 70];
+    frame[..64].copy_from_slice(challenge);
+    frame[64] = command;
+    frame[65..67].copy_from_slice(&crc16(challenge).to_le_bytes());
+    for (seq, chunk) in frame.chunks(7).enumerate() {
+        // all-zero chunks in the middle are implied
+        if seq > 0 && seq < 9 && chunk.iter().all(|&b| b == 0) {
+            continue;
+        }
+        let mut report = [0u8; 8];
+        report[..7].copy_from_slice(chunk);
+        report[7] = SLOT_WRITE_FLAG | seq as u8;
+        wait_status(dev, SLOT_WRITE_FLAG, false, WRITE_TIMEOUT)?;
+        dev.set_report(&report)?;
+    }
+
+    let first = wait_status(dev, RESP_PENDING_FLAG, true, WRITE_TIMEOUT)?;
+    let mut response = Vec::with_capacity(28);
+    response.extend_from_slice(&first[..7]);
+    let done = loop {
+        let report = dev.get_report()?;
+        if report[7] & RESP_PENDING_FLAG == 0 {
+            break false;
+        }
+        // the sequence number wraps to 0 after the last part
+        if report[7] & 0x1f == 0 {
+            break true;
+        }
+        response.extend_from_slice(&report[..7]);
+        if response.len() >= 28 {
+            break true;
+        }
+    };
+    // leave read mode
+    let mut reset = [0u8; 8];
+    reset[7] = DUMMY_REPORT_WRITE;
+    dev.set_report(&reset)?;
+    if !done || response.len() < 22 || crc16(&response[..22]) != CRC_OK_RESIDUAL {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad YubiKey response",
+        ));
+    }
+    let mut out = [0u8; 20];
+    out.copy_from_slice(&response[..20]);
+    Ok(out)
+}
+
+/// `challenge` padded to the 64 bytes the key takes. The padding differs
+/// from the last challenge byte, so a key in variable-length mode
+/// (`-ohmac-lt64`) strips it again.
+pub fn pad_challenge(challenge: &[u8; CHALLENGE_LEN]) -> [u8; 64] {
+    let mut padded = [challenge[CHALLENGE_LEN - 1] ^ 0xff; 64];
+    padded[..CHALLENGE_LEN].copy_from_slice(challenge);
+    padded
+}
+
+/// One user's enrolled key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YubikeyRecord {
+    /// HMAC slot: 1 or 2.
+    pub slot: u8,
+    pub secret: Zeroizing<Vec<u8>>,
+}
+
+impl YubikeyRecord {
+    /// File name of `user`'s record.
+    pub fn file_name(user: &str) -> String {
+        format!("{user}.yubikey")
+    }
+
+    /// A record for `slot` with a new random secret.
+    pub fn generate(slot: u8) -> Self {
+        let mut secret = Zeroizing::new(vec![0u8; SECRET_LEN]);
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret);
+        YubikeyRecord { slot, secret }
+    }
+
+    /// `user`'s record from `store`; `None` if not enrolled.
+    pub fn load(store: &dyn PinStore, user: &str) -> io::Result<Option<Self>> {
+        let raw = match store.read_file(&Self::file_name(user)) {
+            Ok(raw) => Zeroizing::new(raw),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut fields = raw.split_whitespace();
+        let slot = fields
+            .next()
+            .and_then(|s| s.parse().ok())
+            .filter(|s| matches!(s, 1 | 2));
+        let secret = fields
+            .next()
+            .and_then(crate::parse_hex)
+            .filter(|s| s.len() == SECRET_LEN);
+        match (slot, secret, fields.next()) {
+            (Some(slot), Some(secret), None) => Ok(Some(YubikeyRecord { slot, secret })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed YubiKey record",
+            )),
+        }
+    }
+
+    /// The record as written to `<user>.yubikey`.
+    pub fn serialize(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{} {}\n",
+            self.slot,
+            crate::hex(&self.secret).as_str()
+        ))
+    }
+
+    /// Whether `response` answers `challenge`, in either HMAC input mode.
+    pub fn accepts(&self, challenge: &[u8; CHALLENGE_LEN], response: &[u8; 20]) -> bool {
+        let hmac = |message: &[u8]| {
+            let mut mac = <Hmac<sha1::Sha1> as Mac>::new_from_slice(&self.secret)
+                .expect("HMAC takes any key length");
+            mac.update(message);
+            mac.finalize().into_bytes()
+        };
+        let variable = hmac(challenge);
+        let fixed = hmac(&pad_challenge(challenge));
+        // both computed, so timing does not tell the modes apart
+        let a = crate::constant_time_eq(&variable, response);
+        let b = crate::constant_time_eq(&fixed, response);
+        a | b
+    }
+
+    /// Challenge every attached YubiKey in turn. `Ok(true)` once one answers
+    /// correctly, `Ok(false)` if keys answered but none correctly, and an
+    /// error if no key could be asked.
+    pub fn authenticate(&self) -> io::Result<bool> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no YubiKey attached");
+        let mut answered = false;
+        for path in find_keys() {
+            let mut challenge = [0u8; CHALLENGE_LEN];
+            rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut challenge);
+            // the FIDO interface shares the vendor ID but has no feature reports
+            let response = Hidraw::open(&path).and_then(|mut dev| {
+                challenge_response(&mut dev, self.slot, &pad_challenge(&challenge))
+            });
+            match response {
+                Ok(response) if self.accepts(&challenge, &response) => return Ok(true),
+                Ok(_) => answered = true,
+                Err(e) => {
+                    tracing::debug!(error = %e, path = %path.display(), "no YubiKey response");
+                    last_error = e;
+                }
+            }
+        }
+        if answered {
+            Ok(false)
+        } else {
+            Err(last_error)
+        }
+    }
+}
+
+/// Why an empty PIN may not ask the attached keys in the session `pam`
+/// describes, or `None` if it may. Only with `yubikey_require_touch`, the
+/// administrator's word that the keys need a touch; never for a remote
+/// session (`PAM_RHOST` set), where someone else may be at the machine;
+/// and with `yubikey_services`, only for the PAM services listed.
+pub fn session_refusal(config: &ConfigResolver, pam: &PamContext) -> Option<&'static str> {
+    if !config.flag("yubikey_require_touch").unwrap_or(false) {
+        return Some("yubikey_require_touch is not set");
+    }
+    if pam.rhost.is_some() {
+        return Some("remote session");
+    }
+    let listed = |list: &str| {
+        list.split(',')
+            .map(str::trim)
+            .any(|service| pam.service.as_deref() == Some(service))
+    };
+    match config.string("yubikey_services") {
+        Some(list) if !listed(list) => Some("service not in yubikey_services"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just enough of a YubiKey's OTP interface to answer one challenge.
+    struct FakeKey {
+        secret: Vec<u8>,
+        variable: bool,
+        frame: [u8; 70],
+        response: Vec<[u8; 8]>,
+    }
+
+    impl FeatureReports for FakeKey {
+        fn get_report(&mut self) -> io::Result<[u8; 8]> {
+            Ok(if self.response.is_empty() {
+                [0; 8]
+            } else {
+                self.response.remove(0)
+            })
+        }
+
+        fn set_report(&mut self, report: &[u8; 8]) -> io::Result<()> {
+            if report[7] == DUMMY_REPORT_WRITE {
+                self.response.clear();
+                return Ok(());
+            }
+            let seq = usize::from(report[7] & 0x0f);
+            self.frame[seq * 7..seq * 7 + 7].copy_from_slice(&report[..7]);
+            if seq == 9 {
+                let payload = &self.frame[..64];
+                assert_eq!(self.frame[64], SLOT_CHAL_HMAC2);
+                assert_eq!(self.frame[65..67], crc16(payload).to_le_bytes());
+                let mut len = 64;
+                if self.variable {
+                    while len > 1 && payload[len - 2] == payload[63] {
+                        len -= 1;
+                    }
+                    len -= 1;
+                }
+                let mut mac = <Hmac<sha1::Sha1> as Mac>::new_from_slice(&self.secret).unwrap();
+                mac.update(&payload[..len]);
+                let mut data = mac.finalize().into_bytes().to_vec();
+                data.extend_from_slice(&(!crc16(&data)).to_le_bytes());
+                data.resize(28, 0);
+                for (i, chunk) in data.chunks(7).enumerate() {
+                    let mut r = [0u8; 8];
+                    r[..7].copy_from_slice(chunk);
+                    r[7] = RESP_PENDING_FLAG | i as u8;
+                    self.response.push(r);
+                }
+                self.response.push([0, 0, 0, 0, 0, 0, 0, RESP_PENDING_FLAG]);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn challenge_response_round_trip() {
+        let record = YubikeyRecord::generate(2);
+        let challenge = [0x5a; CHALLENGE_LEN];
+        for variable in [true, false] {
+            let mut key = FakeKey {
+                secret: record.secret.to_vec(),
+                variable,
+                frame: [0; 70],
+                response: Vec::new(),
+            };
+            let response = challenge_response(&mut key, 2, &pad_challenge(&challenge)).unwrap();
+            assert!(record.accepts(&challenge, &response), "variable={variable}");
+            assert!(!YubikeyRecord::generate(2).accepts(&challenge, &response));
+        }
+    }
+
+    #[test]
+    fn records_and_sysfs() {
+        let record = YubikeyRecord::generate(1);
+        let store = crate::store::MemoryStore::new()
+            .with_file("alice.yubikey", record.serialize().as_str())
+            .with_file("bob.yubikey", "3 00\n");
+        assert_eq!(YubikeyRecord::load(&store, "alice").unwrap(), Some(record));
+        assert!(YubikeyRecord::load(&store, "bob").is_err());
+        assert_eq!(YubikeyRecord::load(&store, "carol").unwrap(), None);
+
+        let uevent = "DRIVER=hid-generic\nHID_ID=0003:00001050:00000407\nHID_NAME=Yubico YubiKey OTP+FIDO+CCID\n";
+        assert_eq!(uevent_vendor(uevent), Some(VENDOR_YUBICO));
+        assert_eq!(uevent_vendor("HID_ID=garbage"), None);
+        // a frame with its inverted CRC appended leaves the fixed residual
+        let mut data = b"response".to_vec();
+        data.extend_from_slice(&(!crc16(&data)).to_le_bytes());
+        assert_eq!(crc16(&data), CRC_OK_RESIDUAL);
+    }
+
+    #[test]
+    fn only_local_sessions_ask_the_keys() {
+        let session = |service: &str, rhost: Option<&str>| PamContext {
+            service: Some(service.to_string()),
+            tty: None,
+            rhost: rhost.map(str::to_string),
+        };
+        let config = |text: &str| {
+            ConfigResolver::default()
+                .with_file(text, "pin-auth.conf")
+                .unwrap()
+        };
+        let unset = config("");
+        assert!(session_refusal(&unset, &session("login", None)).is_some());
+        let touch = config("yubikey_require_touch = yes\n");
+        assert_eq!(session_refusal(&touch, &session("login", None)), None);
+        assert_eq!(
+            session_refusal(&touch, &session("sshd", Some("203.0.113.7"))),
+            Some("remote session")
+        );
+        let listed =
+            config("yubikey_require_touch = yes\nyubikey_services = login, gdm-password\n");
+        assert_eq!(
+            session_refusal(&listed, &session("gdm-password", None)),
+            None
+        );
+        assert!(session_refusal(&listed, &session("sudo", None)).is_some());
+        assert!(session_refusal(&listed, &session("login", Some("::1"))).is_some());
+    }
+}
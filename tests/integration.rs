@@ -19,13 +19,21 @@ fn end_to_end_pin_dir() {
     // generate pin
     let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
         .env("PIN_DIR", dir)
-        .env("GENPIN_NONINTERACTIVE", "2468")
         .env("PIN_MAX_LEN", "6")
         .env("PIN_SCHEME", TEST_SCHEME)
-        .arg("alice")
+        .args(["alice", "--pin-stdin"])
+        .stdin(Stdio::piped())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
-        .status()
+        .spawn()
+        .map(|mut c| {
+            c.stdin
+                .as_mut()
+                .unwrap()
+                .write_all(b"2468\n2468\n")
+                .unwrap();
+            c.wait().unwrap()
+        })
         .unwrap();
     assert!(status.success(), "genpin failed");
     let stored = dir.join("alice.passwd");
@@ -77,7 +85,11 @@ fn end_to_end_pin_dir() {
             c.wait().unwrap()
         })
         .unwrap();
-    assert!(!locked.success(), "lockout did not trigger");
+    assert_eq!(
+        locked.code(),
+        Some(pin_auth::exit::EXIT_LOCKED),
+        "lockout did not trigger"
+    );
 
     let bad = Command::new(env!("CARGO_BIN_EXE_check_pin"))
         .env("PAM_USER", "alice")
@@ -115,25 +127,56 @@ fn no_username_noop() {
 }
 
 #[test]
-fn timed_lockout_expires() {
+fn config_file_overrides_env() {
     let tmp = tempfile::tempdir().unwrap();
     let dir = tmp.path();
-    // create PIN
+    fs::write(dir.join("pin-auth.conf"), "# policy\nmin_len = 6\n").unwrap();
+    let genpin = |pin: &str| {
+        Command::new(env!("CARGO_BIN_EXE_genpin"))
+            .env("PIN_DIR", dir)
+            .env("GENPIN_NONINTERACTIVE", pin)
+            .env("PIN_MIN_LEN", "4")
+            .env("PIN_SCHEME", TEST_SCHEME)
+            .arg("alice")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+    };
+    assert!(!genpin("2468").success(), "file min_len ignored");
+    assert!(genpin("246813").success(), "six digit PIN rejected");
+
+    fs::write(dir.join("pin-auth.conf"), "min_len 6\n").unwrap();
+    assert!(!genpin("246813").success(), "broken config accepted");
+}
+
+#[test]
+fn lockout_sends_notification() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let mail = dir.join("mail.out");
     let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
         .env("PIN_DIR", dir)
-        .env("GENPIN_NONINTERACTIVE", "1111")
-        .arg("bob")
+        .env("GENPIN_NONINTERACTIVE", "4321")
+        .env("PIN_SCHEME", TEST_SCHEME)
+        .arg("carol")
+        .stdout(Stdio::null())
         .status()
         .unwrap();
     assert!(status.success());
 
-    // Trigger failures threshold=2 lockout=2s
     for _ in 0..2 {
         let bad = Command::new(env!("CARGO_BIN_EXE_check_pin"))
-            .env("PAM_USER", "bob")
+            .env("PAM_USER", "carol")
+            .env("PAM_SERVICE", "login")
             .env("PIN_DIR", dir)
+            .env("PIN_SCHEME", TEST_SCHEME)
             .env("PIN_MAX_FAILS", "2")
-            .env("PIN_LOCKOUT_SECS", "2")
+            .env("PIN_NOTIFY_MAIL", "root")
+            .env(
+                "PIN_NOTIFY_COMMAND",
+                format!("cp /dev/stdin {}", mail.display()),
+            )
             .stdin(Stdio::piped())
             .spawn()
             .map(|mut c| {
@@ -143,35 +186,588 @@ fn timed_lockout_expires() {
             .unwrap();
         assert!(!bad.success());
     }
-    // Should still be locked even with correct PIN
-    let locked = Command::new(env!("CARGO_BIN_EXE_check_pin"))
-        .env("PAM_USER", "bob")
+    // The mailer is not waited for; give it a moment.
+    let mut body = String::new();
+    for _ in 0..50 {
+        body = fs::read_to_string(&mail).unwrap_or_default();
+        if body.ends_with('\n') {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(body.starts_with("To: root\n"), "no notification: {body:?}");
+    assert!(body.contains("carol locked out"));
+    assert!(body.contains("Service:      login"));
+}
+
+#[test]
+fn decoy_pin_alerts_and_locks() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let mail = dir.join("mail.out");
+    fs::write(
+        dir.join("pin-auth.conf"),
+        format!("decoy_lock = yes\nnotify_mail = root\nscheme = {TEST_SCHEME}\n"),
+    )
+    .unwrap();
+    let genpin = |pin: &str, decoy: bool| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_genpin"));
+        cmd.env("PIN_DIR", dir)
+            .env("GENPIN_NONINTERACTIVE", pin)
+            .arg("dave");
+        if decoy {
+            cmd.arg("--decoy");
+        }
+        cmd.stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+    };
+    assert!(genpin("2468", false).success());
+    assert!(!genpin("2468", true).success(), "decoy equal to the PIN");
+    assert!(genpin("1357", true).success());
+    assert!(!genpin("1357", false).success(), "PIN equal to a decoy");
+
+    let check = |pin: &str| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "dave")
+            .env("PIN_DIR", dir)
+            .env(
+                "PIN_NOTIFY_COMMAND",
+                format!("cp /dev/stdin {}", mail.display()),
+            )
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(format!("{pin}\n").as_bytes())
+                    .unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    // locked at once; the real PIN no longer works
+    assert_eq!(check("1357").code(), Some(2));
+    assert_eq!(check("2468").code(), Some(2));
+    let mut body = String::new();
+    for _ in 0..50 {
+        body = fs::read_to_string(&mail).unwrap_or_default();
+        if body.ends_with('\n') {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(body.contains("decoy PIN entered for dave"), "{body:?}");
+}
+
+#[test]
+fn emergency_pin_unlocks_once() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    fs::write(
+        dir.join("pin-auth.conf"),
+        format!("max_fails = 1\nlockout_secs = 0\nscheme = {TEST_SCHEME}\n"),
+    )
+    .unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .arg("erin")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let check = |pin: &str| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "erin")
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(format!("{pin}\n").as_bytes())
+                    .unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    assert_eq!(check("0000").code(), Some(2));
+    assert_eq!(check("2468").code(), Some(2));
+
+    let out = Command::new(env!("CARGO_BIN_EXE_pinctl"))
+        .env("PIN_DIR", dir)
+        .args(["emergency", "erin"])
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "pinctl emergency failed");
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let pin = stdout
+        .split_whitespace()
+        .find(|w| w.len() == 12 && w.bytes().all(|b| b.is_ascii_digit()))
+        .expect("no emergency PIN printed")
+        .to_string();
+    assert!(check(&pin).success(), "emergency PIN rejected");
+    assert!(!dir.join("erin.emergency").exists());
+    assert!(!check(&pin).success(), "emergency PIN accepted twice");
+}
+
+#[test]
+fn recovery_codes_unlock_when_locked() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    fs::write(
+        dir.join("pin-auth.conf"),
+        format!("max_fails = 1\nrecovery_codes = 2\nscheme = {TEST_SCHEME}\n"),
+    )
+    .unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .arg("frank")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let codes: Vec<&str> = stdout
+        .split_whitespace()
+        .filter(|w| w.len() == 14 && w.matches('-').count() == 2)
+        .collect();
+    assert_eq!(codes.len(), 2, "{stdout}");
+    let check = |pin: &str| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "frank")
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(format!("{pin}\n").as_bytes())
+                    .unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    // only while locked
+    assert_eq!(check(codes[0]).code(), Some(3));
+    assert_eq!(check("0000").code(), Some(2));
+    assert!(check(codes[0]).success(), "recovery code rejected");
+    assert_eq!(check("0000").code(), Some(2));
+    assert_eq!(check(codes[0]).code(), Some(2), "recovery code reused");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_pinctl"))
+        .env("PIN_DIR", dir)
+        .args(["recovery", "frank"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "frank: 1 of 2 recovery codes left\n"
+    );
+}
+
+#[cfg(feature = "hotp")]
+#[test]
+fn hotp_codes_replace_the_pin_once() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .env("PIN_SCHEME", TEST_SCHEME)
+        .arg("gina")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // RFC 4226 test secret "12345678901234567890"
+    let status = Command::new(env!("CARGO_BIN_EXE_pinctl"))
+        .env("PIN_DIR", dir)
+        .args([
+            "hotp",
+            "gina",
+            "--secret",
+            "3132333435363738393031323334353637383930",
+        ])
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let check = |code: &str| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "gina")
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(format!("{code}\n").as_bytes())
+                    .unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    assert!(check("287082").success(), "code one press ahead rejected");
+    assert!(!check("287082").success(), "code replayed");
+    assert!(!check("755224").success(), "older code accepted");
+    assert!(check("359152").success());
+    assert!(check("2468").success(), "PIN no longer accepted");
+}
+
+#[test]
+fn group_record_is_shared_with_per_user_fail_state() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    fs::write(
+        dir.join("pin-auth.conf"),
+        format!("max_fails = 3\nscheme = {TEST_SCHEME}\n"),
+    )
+    .unwrap();
+    // root's primary group exists everywhere this runs
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .arg("@root")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "genpin @root failed");
+    assert!(dir.join("@root.passwd").exists());
+    let check = |pin: &str| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "root")
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(format!("{pin}\n").as_bytes())
+                    .unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    assert_eq!(check("0000").code(), Some(1));
+    assert!(dir.join("root.fail").exists());
+    assert!(!dir.join("@root.fail").exists());
+    assert!(check("2468").success(), "shared PIN rejected");
+}
+
+#[test]
+fn require_group_refuses_non_members() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .env("PIN_SCHEME", TEST_SCHEME)
+        .arg("root")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let check = |group: &str| {
+        fs::write(
+            dir.join("pin-auth.conf"),
+            format!("require_group = {group}\nscheme = {TEST_SCHEME}\n"),
+        )
+        .unwrap();
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "root")
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                // refused before the input is read: the pipe may be closed
+                let _ = c.stdin.as_mut().unwrap().write_all(b"2468\n");
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    assert_eq!(check("no-such-group-pin-auth").code(), Some(5));
+    assert!(
+        !dir.join("root.fail").exists(),
+        "refusal counted as failure"
+    );
+    assert!(check("root").success());
+}
+
+#[test]
+fn uid_record_keys() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    fs::write(
+        dir.join("pin-auth.conf"),
+        format!("record_key = uid\nmax_fails = 3\nscheme = {TEST_SCHEME}\n"),
+    )
+    .unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
         .env("PIN_DIR", dir)
-        .env("PIN_MAX_FAILS", "2")
-        .env("PIN_LOCKOUT_SECS", "2")
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .arg("root")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "genpin failed");
+    assert!(dir.join("0.passwd").exists());
+    assert!(!dir.join("root.passwd").exists());
+    let check = |pin: &str| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "root")
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(format!("{pin}\n").as_bytes())
+                    .unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    assert_eq!(check("0000").code(), Some(1));
+    assert!(dir.join("0.fail").exists());
+    assert!(check("2468").success(), "PIN under the UID rejected");
+}
+
+#[test]
+fn unknown_users_look_like_a_wrong_pin() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    fs::write(
+        dir.join("pin-auth.conf"),
+        format!("require_known_user = 1\naudit = 1\nmax_fails = 2\nscheme = {TEST_SCHEME}\n"),
+    )
+    .unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .arg("root")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let check = |user: &str, pin: &[u8]| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", user)
+            .env("PIN_DIR", dir)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                // an invalid name is refused before the input is read
+                let _ = c.stdin.as_mut().unwrap().write_all(pin);
+                c.wait().unwrap()
+            })
+            .unwrap()
+            .code()
+    };
+    assert_eq!(check("root", b"2468\n"), Some(0));
+    // an unknown name, a known user without a record and a wrong PIN: the
+    // same answers, up to the lockout
+    for user in ["no-such-user-pin-auth", "nobody", "root"] {
+        let codes: Vec<_> = (0..3).map(|_| check(user, b"1357\n")).collect();
+        assert_eq!(codes, [Some(1), Some(2), Some(2)], "{user}");
+    }
+    assert!(!dir.join("no-such-user-pin-auth.fail").exists());
+    let audit = fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("\"result\":\"unknown_user\""), "{audit}");
+    assert!(audit.contains("\"result\":\"no_record\""), "{audit}");
+    assert_eq!(check("../etc", b"2468\n"), Some(4));
+    let audit = fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("\"result\":\"invalid_user\""), "{audit}");
+}
+
+#[test]
+fn pinctl_edit_checks_before_saving() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("GENPIN_NONINTERACTIVE", "2468")
+        .env("PIN_SCHEME", TEST_SCHEME)
+        .arg("hana")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let edit = |editor: &str| {
+        Command::new(env!("CARGO_BIN_EXE_pinctl"))
+            .env("PIN_DIR", dir)
+            .env("EDITOR", editor)
+            .env_remove("VISUAL")
+            .args(["edit", "hana"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                // "Edit again?" after a bad save
+                c.stdin.as_mut().unwrap().write_all(b"n\n").unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    let record = dir.join("hana.passwd");
+    assert!(edit("sed -i '$a flags=kiosk'").success());
+    let saved = fs::read_to_string(&record).unwrap();
+    assert!(saved.ends_with("flags=kiosk\n"), "{saved}");
+
+    assert!(!edit("sed -i '$a not a key'").success());
+    assert_eq!(fs::read_to_string(&record).unwrap(), saved);
+    assert!(!edit("false").success());
+    assert_eq!(fs::read_to_string(&record).unwrap(), saved);
+    assert!(!dir.join("hana.passwd.edit.tmp").exists());
+}
+
+#[test]
+fn pinctl_install_sets_up_a_fresh_host() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path().join("pin.d");
+    let install = || {
+        Command::new(env!("CARGO_BIN_EXE_pinctl"))
+            .env("PIN_DIR", &dir)
+            .args(["install", "--check-pin", "/sbin/check_pin"])
+            .stderr(Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let out = install();
+    assert!(out.status.success());
+    let pam = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        pam.starts_with("auth  sufficient  pam_exec.so expose_authtok"),
+        "{pam}"
+    );
+    assert!(pam.ends_with(" /sbin/check_pin\n"), "{pam}");
+    let mode = |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&dir), 0o700);
+    let conf = dir.join("pin-auth.conf");
+    assert_eq!(mode(&conf), 0o600);
+
+    // a second run fixes the mode and keeps the settings
+    fs::write(&conf, "max_fails = 3\n").unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(install().status.success());
+    assert_eq!(mode(&dir), 0o700);
+    assert_eq!(fs::read_to_string(&conf).unwrap(), "max_fails = 3\n");
+}
+
+#[test]
+fn per_user_max_fails_from_the_record() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let status = Command::new(env!("CARGO_BIN_EXE_genpin"))
+        .env("PIN_DIR", dir)
+        .env("PIN_SCHEME", TEST_SCHEME)
+        .args(["ines", "--pin-stdin"])
         .stdin(Stdio::piped())
+        .stdout(Stdio::null())
         .spawn()
         .map(|mut c| {
-            c.stdin.as_mut().unwrap().write_all(b"1111\n").unwrap();
+            c.stdin.as_mut().unwrap().write_all(b"2468\n").unwrap();
             c.wait().unwrap()
         })
         .unwrap();
-    assert!(!locked.success(), "lockout not in effect");
+    assert!(status.success());
+    let policy = Command::new(env!("CARGO_BIN_EXE_pinctl"))
+        .env("PIN_DIR", dir)
+        .args(["policy", "ines", "max_fails=1"])
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(policy.success());
+    let check = |pin: &[u8]| {
+        Command::new(env!("CARGO_BIN_EXE_check_pin"))
+            .env("PAM_USER", "ines")
+            .env("PIN_DIR", dir)
+            .env("PIN_MAX_FAILS", "5")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|mut c| {
+                c.stdin.as_mut().unwrap().write_all(pin).unwrap();
+                c.wait().unwrap()
+            })
+            .unwrap()
+    };
+    assert!(!check(b"0000\n").success());
+    // locked after one failure, not five
+    assert_eq!(check(b"2468\n").code(), Some(pin_auth::exit::EXIT_LOCKED));
+}
 
-    thread::sleep(Duration::from_secs(3)); // allow lockout to expire
+#[test]
+fn pinctl_import_shadow_keeps_the_password_working() {
+    use pin_auth::policy::PolicyValidator;
+    use pin_auth::{hash_pin_with, HashParams, Pin, Scheme};
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path().join("pin.d");
+    fs::create_dir(&dir).unwrap();
+    fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o700)).unwrap();
+    let hash = [Scheme::Argon2id, Scheme::Sha512Crypt]
+        .into_iter()
+        .find_map(|s| {
+            let pin = Pin::new("2468", &PolicyValidator::default()).unwrap();
+            hash_pin_with(&pin, s, &HashParams::default()).ok()
+        })
+        .unwrap();
+    let shadow = tmp.path().join("shadow");
+    fs::write(
+        &shadow,
+        format!("ana:{hash}:19700:0:99999:7:::\nbo:!{hash}:19700:0:99999:7:::\n"),
+    )
+    .unwrap();
+    let import = |users: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pinctl"))
+            .env("PIN_DIR", &dir)
+            .arg("import-shadow")
+            .args(users)
+            .arg("--shadow")
+            .arg(&shadow)
+            .output()
+            .unwrap()
+    };
+    let out = import(&["ana", "bo"]);
+    // a locked password is not carried over
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("bo: not imported"));
+    assert!(!dir.join("bo.passwd").exists());
+    let record = fs::read_to_string(dir.join("ana.passwd")).unwrap();
+    assert!(record.starts_with(&format!("{hash}\n")), "{record}");
+    // the password's age, from shadow's days since the epoch
+    assert!(record.contains("\nchanged_at=1702080000\n"), "{record}");
+    // an existing record needs --force
+    assert!(!import(&["ana"]).status.success());
 
-    // Now correct PIN should succeed again
-    let ok = Command::new(env!("CARGO_BIN_EXE_check_pin"))
-        .env("PAM_USER", "bob")
-        .env("PIN_DIR", dir)
-        .env("PIN_MAX_FAILS", "2")
-        .env("PIN_LOCKOUT_SECS", "2")
+    let check = Command::new(env!("CARGO_BIN_EXE_check_pin"))
+        .env("PAM_USER", "ana")
+        .env("PIN_DIR", &dir)
         .stdin(Stdio::piped())
+        .stderr(Stdio::null())
         .spawn()
         .map(|mut c| {
-            c.stdin.as_mut().unwrap().write_all(b"1111\n").unwrap();
+            c.stdin.as_mut().unwrap().write_all(b"2468\n").unwrap();
             c.wait().unwrap()
         })
         .unwrap();
-    assert!(ok.success(), "lockout did not expire");
+    assert!(check.success());
 }
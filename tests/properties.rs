@@ -0,0 +1,102 @@
+//! Property tests: hash/verify round trips, verification of arbitrary
+//! stored data, and agreement between the set (`genpin`) and verify
+//! (`check_pin`) policy paths.
+use pin_auth::auth::{evaluate_attempt, Outcome};
+use pin_auth::fail::{FailTracker, LockoutPolicy, MemoryFailStore};
+use pin_auth::policy::{PolicyValidator, MAX_MIN_LEN};
+use pin_auth::{verify_pin_outcome, Pin, Scheme, VerifyOutcome};
+use proptest::prelude::*;
+
+fn policy() -> impl Strategy<Value = PolicyValidator> {
+    (1..=MAX_MIN_LEN)
+        .prop_flat_map(|min| (Just(min), min..=MAX_MIN_LEN))
+        .prop_map(|(min, max)| PolicyValidator::new(min, max).unwrap())
+}
+
+/// Mostly `$`-prefixed strings shaped like the supported formats, with
+/// costs kept small so a parsable one still verifies quickly.
+fn stored_hash() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "\\$6\\$(rounds=[0-9]{0,4}\\$)?[./A-Za-z0-9]{0,16}\\$[./A-Za-z0-9]{0,86}",
+        "\\$argon2id\\$(v=[0-9]{1,2}\\$)?m=[0-9]{1,3},t=[0-2],p=[0-2]\\$[A-Za-z0-9+/]{0,24}(\\$[A-Za-z0-9+/]{0,44})?",
+        "\\$[a-z0-9]{0,8}(\\$[^$]{0,12}){0,4}",
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[cfg(any(feature = "sha-crypt", feature = "argon2"))]
+    #[test]
+    fn hash_verify_round_trip(pin in "[0-9]{4,12}", other in "[0-9]{4,12}") {
+        use pin_auth::{hash_pin_with, HashParamsBuilder};
+        let validator = PolicyValidator::new(4, 12).unwrap();
+        let pin = Pin::new(pin, &validator).unwrap();
+        let candidates = [
+            #[cfg(feature = "sha-crypt")]
+            (Scheme::Sha512Crypt, HashParamsBuilder::new().sha512_rounds(1000).build().unwrap()),
+            #[cfg(feature = "argon2")]
+            (Scheme::Argon2id, HashParamsBuilder::new()
+                .memory_kib(256)
+                .time_cost(1)
+                .parallelism(1)
+                .build()
+                .unwrap()),
+        ];
+        for (scheme, params) in candidates {
+            let stored = hash_pin_with(&pin, scheme, &params).unwrap();
+            let info = Scheme::from_hash(&stored).unwrap();
+            prop_assert_eq!(info.scheme, scheme);
+            prop_assert_eq!(verify_pin_outcome(&pin, &stored), VerifyOutcome::Match);
+            let expected = if other == pin.expose_secret() {
+                VerifyOutcome::Match
+            } else {
+                VerifyOutcome::Mismatch
+            };
+            let other = Pin::candidate(other.as_str(), &validator).unwrap();
+            prop_assert_eq!(verify_pin_outcome(&other, &stored), expected);
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_stored_never_matches(pin in "[0-9]{1,32}", stored in stored_hash()) {
+        let pin = Pin::candidate(pin, &PolicyValidator::new(1, MAX_MIN_LEN).unwrap()).unwrap();
+        let outcome = verify_pin_outcome(&pin, &stored);
+        prop_assert_ne!(outcome, VerifyOutcome::Match);
+        if Scheme::from_hash(&stored).is_err() {
+            prop_assert_eq!(outcome, VerifyOutcome::MalformedHash);
+        }
+    }
+
+    /// Whatever `genpin` accepts, `check_pin` accepts; without a blacklist
+    /// the two agree exactly, and a blacklisted PIN still verifies.
+    #[test]
+    fn set_and_verify_policies_agree(
+        validator in policy(),
+        pin in prop_oneof!["[0-9]{0,40}", ".{0,40}"],
+        blacklisted in any::<bool>(),
+    ) {
+        let set = Pin::new(pin.as_str(), &validator).is_ok();
+        let verify = Pin::candidate(pin.as_str(), &validator).is_ok();
+        prop_assert_eq!(set, verify);
+        if blacklisted {
+            let validator = validator.with_blacklist([pin.as_str()]);
+            prop_assert!(Pin::new(pin.as_str(), &validator).is_err());
+            prop_assert_eq!(Pin::candidate(pin.as_str(), &validator).is_ok(), verify);
+        }
+    }
+
+    /// `check_pin` never counts a malformed candidate against the user.
+    #[test]
+    fn bad_input_is_not_a_failure(validator in policy(), pin in ".{0,40}") {
+        let mut tracker =
+            FailTracker::new(MemoryFailStore::default(), LockoutPolicy::default()).unwrap();
+        let valid = validator.check_format(&pin).is_ok();
+        let outcome = evaluate_attempt(&mut tracker, &validator, pin, "$6$x$y");
+        prop_assert_eq!(outcome == Outcome::BadInput, !valid);
+        prop_assert_eq!(tracker.count(), u32::from(valid));
+    }
+}